@@ -0,0 +1,205 @@
+//! Error responses from the NovelAI API.
+//!
+//! Failed requests come back as
+//! `{ "statusCode": 402, "message": "...", "error": "Payment Required" }`.
+//! The message is pulled out and common failures are given a code, so the
+//! frontend can branch on `error_code` instead of matching message text.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Error bodies are logged up to this many characters; proxies can answer
+// with whole HTML pages
+const MAX_LOGGED_BODY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NaiErrorKind {
+    /// Not enough Anlas for the request
+    InsufficientAnlas,
+    /// Another generation is still running on this account
+    ConcurrentLimit,
+    /// Too many requests
+    RateLimited,
+    /// Invalid or unsupported parameters
+    BadRequest,
+    /// Token expired or invalid
+    Unauthorized,
+    /// NAI-side failure (5xx)
+    ServerError,
+    /// NAI couldn't be reached, so nothing was checked
+    Offline,
+    Unknown,
+}
+
+impl NaiErrorKind {
+    fn hint(self) -> &'static str {
+        match self {
+            NaiErrorKind::InsufficientAnlas => "Anlas 부족",
+            NaiErrorKind::ConcurrentLimit => {
+                "동시 생성 한도 초과 - 이전 생성이 끝난 뒤 다시 시도하세요"
+            }
+            NaiErrorKind::RateLimited => "요청이 너무 많습니다 - 잠시 후 다시 시도하세요",
+            NaiErrorKind::BadRequest => "잘못된 파라미터",
+            NaiErrorKind::Unauthorized => "유효하지 않은 API 토큰",
+            NaiErrorKind::ServerError => "NovelAI 서버 오류",
+            NaiErrorKind::Offline => "오프라인 - 네트워크 연결을 확인하세요",
+            NaiErrorKind::Unknown => "API 오류",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NaiError {
+    pub status: u16,
+    pub kind: NaiErrorKind,
+    /// NAI's own message, or the raw body when it isn't JSON
+    pub message: String,
+}
+
+impl fmt::Display for NaiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{} ({})", self.kind.hint(), self.status)
+        } else {
+            write!(
+                f,
+                "{} ({}): {}",
+                self.kind.hint(),
+                self.status,
+                self.message
+            )
+        }
+    }
+}
+
+/// The JSON body NAI sends with a failed request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NaiApiError {
+    #[serde(default, rename = "statusCode")]
+    pub status_code: Option<u16>,
+    /// Human-readable explanation, e.g. "Not enough Anlas"
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Short HTTP reason, e.g. "Payment Required"
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl NaiApiError {
+    /// The text worth showing: `message`, else `error`.
+    fn user_message(self) -> Option<String> {
+        self.message
+            .or(self.error)
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+    }
+}
+
+fn classify(status: u16, message: &str) -> NaiErrorKind {
+    let message = message.to_lowercase();
+    if message.contains("anlas") || status == 402 {
+        NaiErrorKind::InsufficientAnlas
+    } else if message.contains("concurrent") {
+        NaiErrorKind::ConcurrentLimit
+    } else if status == 429 {
+        NaiErrorKind::RateLimited
+    } else if status == 401 {
+        NaiErrorKind::Unauthorized
+    } else if status == 400 || status == 422 {
+        NaiErrorKind::BadRequest
+    } else if status >= 500 {
+        NaiErrorKind::ServerError
+    } else {
+        NaiErrorKind::Unknown
+    }
+}
+
+/// `body` cut to `MAX_LOGGED_BODY` characters, noting how much was left out.
+fn truncate_for_log(body: &str) -> String {
+    match body.char_indices().nth(MAX_LOGGED_BODY) {
+        Some((cut, _)) => format!("{}... ({} bytes total)", &body[..cut], body.len()),
+        None => body.to_string(),
+    }
+}
+
+/// Builds a `NaiError` from a non-2xx response. Only the body's `message`
+/// (or `error`) reaches the user; the raw body goes to the log, truncated.
+/// Bodies that aren't NAI's JSON fall back to the raw text.
+pub fn parse_api_error(status: u16, body: &str) -> NaiError {
+    log::warn!(
+        "NAI request failed ({}): {}",
+        status,
+        truncate_for_log(body)
+    );
+
+    let parsed = serde_json::from_str::<NaiApiError>(body).unwrap_or_default();
+    // Proxies in between can change the HTTP status; NAI's own code is the
+    // one that says what went wrong
+    let code = parsed.status_code.unwrap_or(status);
+    let message = parsed
+        .user_message()
+        .unwrap_or_else(|| body.trim().to_string());
+
+    NaiError {
+        status,
+        kind: classify(code, &message),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anlas_wins_over_the_status() {
+        assert_eq!(
+            classify(429, "Not enough Anlas"),
+            NaiErrorKind::InsufficientAnlas
+        );
+        assert_eq!(classify(401, "anlas"), NaiErrorKind::InsufficientAnlas);
+        assert_eq!(classify(402, ""), NaiErrorKind::InsufficientAnlas);
+        assert_eq!(
+            classify(429, "Concurrent generation is locked"),
+            NaiErrorKind::ConcurrentLimit
+        );
+        assert_eq!(classify(429, ""), NaiErrorKind::RateLimited);
+        assert_eq!(classify(401, ""), NaiErrorKind::Unauthorized);
+        assert_eq!(classify(422, ""), NaiErrorKind::BadRequest);
+        assert_eq!(classify(503, ""), NaiErrorKind::ServerError);
+        assert_eq!(classify(418, ""), NaiErrorKind::Unknown);
+    }
+
+    #[test]
+    fn body_status_code_overrides_the_http_status() {
+        let error = parse_api_error(
+            500,
+            r#"{"statusCode": 402, "message": "Payment Required ", "error": "x"}"#,
+        );
+        assert_eq!(error.status, 500);
+        assert_eq!(error.kind, NaiErrorKind::InsufficientAnlas);
+        assert_eq!(error.message, "Payment Required");
+
+        let error = parse_api_error(502, r#"{"statusCode": 401, "error": "Unauthorized"}"#);
+        assert_eq!(error.kind, NaiErrorKind::Unauthorized);
+        assert_eq!(error.message, "Unauthorized");
+    }
+
+    #[test]
+    fn non_json_bodies_fall_back_to_the_text() {
+        let error = parse_api_error(502, "  Bad Gateway\n");
+        assert_eq!(error.kind, NaiErrorKind::ServerError);
+        assert_eq!(error.message, "Bad Gateway");
+        assert_eq!(error.to_string(), "NovelAI 서버 오류 (502): Bad Gateway");
+    }
+
+    #[test]
+    fn long_bodies_are_truncated_in_the_log() {
+        assert_eq!(truncate_for_log("short"), "short");
+        let body = "가".repeat(MAX_LOGGED_BODY + 10);
+        let logged = truncate_for_log(&body);
+        assert!(logged.starts_with(&"가".repeat(MAX_LOGGED_BODY)));
+        assert!(logged.ends_with(&format!("... ({} bytes total)", body.len())));
+    }
+}
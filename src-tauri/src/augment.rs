@@ -0,0 +1,92 @@
+//! Director tools (`/ai/augment-image`): line art, sketch, colorize, emotion,
+//! declutter and background removal. Each takes one image and answers with a
+//! ZIP like `generate-image`, so tools can be chained by feeding one output
+//! into the next step.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const AUGMENT_URL: &str = "https://image.novelai.net/ai/augment-image";
+
+// Same limits as for generation
+const FREE_PIXEL_LIMIT: u64 = 1024 * 1024;
+const BASE_COST: u32 = 5;
+const MAX_DEFRY: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AugmentTool {
+    #[serde(rename = "lineart")]
+    LineArt,
+    #[serde(rename = "sketch")]
+    Sketch,
+    #[serde(rename = "colorize")]
+    Colorize,
+    #[serde(rename = "emotion")]
+    Emotion,
+    #[serde(rename = "declutter")]
+    Declutter,
+    #[serde(rename = "bg-removal")]
+    BgRemoval,
+}
+
+impl AugmentTool {
+    /// The `req_type` NAI expects
+    pub fn req_type(self) -> &'static str {
+        match self {
+            AugmentTool::LineArt => "lineart",
+            AugmentTool::Sketch => "sketch",
+            AugmentTool::Colorize => "colorize",
+            AugmentTool::Emotion => "emotion",
+            AugmentTool::Declutter => "declutter",
+            AugmentTool::BgRemoval => "bg-removal",
+        }
+    }
+
+    /// Colorize and emotion are steered by a prompt and `defry`; the others
+    /// ignore both.
+    fn takes_prompt(self) -> bool {
+        matches!(self, AugmentTool::Colorize | AugmentTool::Emotion)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AugmentStep {
+    pub tool: AugmentTool,
+    /// Colorize: what to paint. Emotion: the mood, e.g. `happy;;`.
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// How far the result may drift from the input, 0 (closest) to 5
+    #[serde(default)]
+    pub defry: Option<u8>,
+}
+
+/// Request body for one step on an image of `width` x `height`.
+pub fn build_payload(step: &AugmentStep, image: &str, width: u32, height: u32) -> Value {
+    let mut payload = json!({
+        "req_type": step.tool.req_type(),
+        "width": width,
+        "height": height,
+        "image": image,
+    });
+    if step.tool.takes_prompt() {
+        payload["prompt"] = json!(step.prompt.clone().unwrap_or_default());
+        payload["defry"] = json!(step.defry.unwrap_or(0).min(MAX_DEFRY));
+    }
+    payload
+}
+
+/// Estimated Anlas for one step, priced like a generation of the same size.
+/// With unlimited generation, tools up to 1024x1024 are free except
+/// background removal, which is always charged (at three times the base).
+pub fn estimate_cost(tool: AugmentTool, width: u32, height: u32, unlimited: bool) -> u32 {
+    let pixels = width as u64 * height as u64;
+    if unlimited && pixels <= FREE_PIXEL_LIMIT && tool != AugmentTool::BgRemoval {
+        return 0;
+    }
+    let cost = BASE_COST * pixels.div_ceil(FREE_PIXEL_LIMIT).max(1) as u32;
+    if tool == AugmentTool::BgRemoval {
+        cost * 3
+    } else {
+        cost
+    }
+}
@@ -0,0 +1,135 @@
+//! Where auto-saved generations go.
+//!
+//! Results can be sorted into subfolders of the save folder by the local
+//! date (`2024-06-01/`), the model (`nai-diffusion-4-5-full/`) or both
+//! (`2024-06-01/nai-diffusion-4-5-full/`). Folder and file names go through
+//! `export::sanitize_name`, so they are valid on every OS.
+
+use crate::export::sanitize_name;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrganizeBy {
+    #[default]
+    None,
+    Date,
+    Model,
+    DateModel,
+}
+
+impl OrganizeBy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "none" | "" => Ok(OrganizeBy::None),
+            "date" => Ok(OrganizeBy::Date),
+            "model" => Ok(OrganizeBy::Model),
+            "date_model" => Ok(OrganizeBy::DateModel),
+            _ => Err(format!("알 수 없는 폴더 분류 방식: {}", value)),
+        }
+    }
+}
+
+/// Folder for a result of `model` generated at `now`, inside `base`.
+pub fn target_dir(
+    base: &Path,
+    organize_by: OrganizeBy,
+    model: &str,
+    now: DateTime<Local>,
+) -> PathBuf {
+    let date = now.format("%Y-%m-%d").to_string();
+    let model = match sanitize_name(model) {
+        name if name.is_empty() => "unknown".to_string(),
+        name => name,
+    };
+    let mut dir = base.to_path_buf();
+    match organize_by {
+        OrganizeBy::None => {}
+        OrganizeBy::Date => dir.push(date),
+        OrganizeBy::Model => dir.push(model),
+        OrganizeBy::DateModel => {
+            dir.push(date);
+            dir.push(model);
+        }
+    }
+    dir
+}
+
+/// Writes `bytes` to `{dir}/{name}.{extension}`, or `{name}_2`, `{name}_3`,
+/// ... when that file already exists. Without a name the time is used, e.g.
+/// `20240601_153012_042`. The file is created with `create_new`, so two saves
+/// racing for the same name never overwrite each other.
+pub fn write_new(
+    dir: &Path,
+    name: Option<&str>,
+    extension: &str,
+    now: DateTime<Local>,
+    bytes: &[u8],
+) -> io::Result<PathBuf> {
+    let base = name
+        .map(sanitize_name)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| now.format("%Y%m%d_%H%M%S_%3f").to_string());
+    let mut path = dir.join(format!("{}.{}", base, extension));
+    let mut n = 2;
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                if let Err(e) = file.write_all(bytes) {
+                    drop(file);
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                path = dir.join(format!("{}_{}.{}", base, n, extension));
+                n += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn existing_files_are_never_overwritten() {
+        let dir = std::env::temp_dir().join(format!("nais2-autosave-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now();
+
+        let first = write_new(&dir, Some("image"), "png", now, b"first").unwrap();
+        let second = write_new(&dir, Some("image"), "png", now, b"second").unwrap();
+        let third = write_new(&dir, Some("image"), "png", now, b"third").unwrap();
+
+        assert_eq!(first, dir.join("image.png"));
+        assert_eq!(second, dir.join("image_2.png"));
+        assert_eq!(third, dir.join("image_3.png"));
+        assert_eq!(fs::read(&first).unwrap(), b"first");
+        assert_eq!(fs::read(&second).unwrap(), b"second");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn organize_by_builds_subfolders() {
+        let now = Local::now();
+        let date = now.format("%Y-%m-%d").to_string();
+        let base = Path::new("out");
+        assert_eq!(target_dir(base, OrganizeBy::None, "m", now), base);
+        assert_eq!(
+            target_dir(base, OrganizeBy::DateModel, "nai-diffusion-4-5-full", now),
+            base.join(&date).join("nai-diffusion-4-5-full")
+        );
+        assert_eq!(
+            target_dir(base, OrganizeBy::Model, "", now),
+            base.join("unknown")
+        );
+    }
+}
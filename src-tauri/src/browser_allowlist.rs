@@ -0,0 +1,133 @@
+//! Which sites the embedded browser may show.
+//!
+//! An entry is a bare host name such as `novelai.net`. It matches that host
+//! and every subdomain of it (`image.novelai.net`), split at a dot, so
+//! `novelai.net` matches neither `evilnovelai.net` nor
+//! `novelai.net.example.com`. A leading `*.` is accepted and means the
+//! same thing. Only `http`/`https` URLs can be allowed, and ports are
+//! ignored. `about:blank` is always allowed since webviews start there.
+
+use tauri::Url;
+
+pub const DEFAULT_DOMAINS: [&str; 3] = ["novelai.net", "danbooru.donmai.us", "safebooru.donmai.us"];
+
+/// Checks one entry and brings it to the form `is_allowed` compares with:
+/// lowercase, punycode, no trailing dot.
+pub fn normalize_domain(entry: &str) -> Result<String, String> {
+    let trimmed = entry.trim().trim_end_matches('.');
+    let host = trimmed.strip_prefix("*.").unwrap_or(trimmed);
+    if host.is_empty()
+        || host.contains(['/', ':', '*', '?', '#', '@'])
+        || host.contains(char::is_whitespace)
+    {
+        return Err(format!("올바른 도메인이 아닙니다: {}", entry));
+    }
+    Url::parse(&format!("https://{}/", host))
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .ok_or_else(|| format!("올바른 도메인이 아닙니다: {}", entry))
+}
+
+/// Normalizes a whole list, dropping repeats. An empty list would block
+/// every page, so it is refused.
+pub fn normalize_list(domains: &[String]) -> Result<Vec<String>, String> {
+    let mut list = Vec::new();
+    for entry in domains {
+        let domain = normalize_domain(entry)?;
+        if !list.contains(&domain) {
+            list.push(domain);
+        }
+    }
+    if list.is_empty() {
+        return Err("허용 도메인이 비어있습니다".to_string());
+    }
+    Ok(list)
+}
+
+pub fn default_list() -> Vec<String> {
+    DEFAULT_DOMAINS.iter().map(|d| d.to_string()).collect()
+}
+
+fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+pub fn is_allowed(domains: &[String], url: &Url) -> bool {
+    match url.scheme() {
+        "about" => url.as_str() == "about:blank",
+        "http" | "https" => url.host_str().is_some_and(|host| {
+            let host = host.trim_end_matches('.').to_lowercase();
+            domains.iter().any(|domain| host_matches(&host, domain))
+        }),
+        _ => false,
+    }
+}
+
+/// `Ok` when `url` may be opened, otherwise the error the commands return.
+pub fn check(domains: &[String], url: &Url) -> Result<(), String> {
+    if is_allowed(domains, url) {
+        Ok(())
+    } else {
+        Err(format!(
+            "허용되지 않은 주소입니다: {}",
+            url.host_str().unwrap_or(url.as_str())
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(url: &str) -> bool {
+        is_allowed(&default_list(), &Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn subdomains_match_only_at_a_dot() {
+        assert!(allowed("https://novelai.net/image"));
+        assert!(allowed("https://image.novelai.net/"));
+        assert!(allowed("http://IMAGE.NovelAI.net.:8080/"));
+        assert!(!allowed("https://evilnovelai.net/"));
+        assert!(!allowed("https://novelai.net.example.com/"));
+    }
+
+    #[test]
+    fn only_web_urls_and_about_blank() {
+        assert!(allowed("about:blank"));
+        assert!(!allowed("about:config"));
+        assert!(!allowed("file:///etc/passwd"));
+        assert!(!allowed("javascript:alert(1)"));
+        assert!(check(
+            &default_list(),
+            &Url::parse("https://example.com/").unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn entries_are_normalized() {
+        assert_eq!(normalize_domain("*.NovelAI.net.").unwrap(), "novelai.net");
+        assert_eq!(normalize_domain(" example.com ").unwrap(), "example.com");
+        for entry in [
+            "",
+            "*.",
+            "https://a.com",
+            "a.com/path",
+            "a b.com",
+            "user@a.com",
+        ] {
+            assert!(normalize_domain(entry).is_err(), "{:?}", entry);
+        }
+    }
+
+    #[test]
+    fn lists_are_deduplicated_and_never_empty() {
+        let list = normalize_list(&["a.com".to_string(), "*.A.com.".to_string()]).unwrap();
+        assert_eq!(list, vec!["a.com"]);
+        assert!(normalize_list(&[]).is_err());
+    }
+}
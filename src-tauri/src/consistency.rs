@@ -0,0 +1,230 @@
+//! Compares what was sent to NAI with what NAI wrote into the result, to
+//! catch settings that weren't applied as requested.
+//!
+//! NAI records the settings it used in the image's `Comment` JSON. Only
+//! fields present on both sides are compared, since the recorded set
+//! differs between models. The model isn't compared: `Source` only names
+//! the version (see `nai_settings::model_from_source`).
+
+use crate::metadata::EmbeddedMetadata;
+use crate::uc_presets;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Keys under `parameters` that NAI records under the same name, with the
+/// name reported for them
+const FIELDS: [(&str, &str); 12] = [
+    ("steps", "steps"),
+    ("scale", "scale"),
+    ("sampler", "sampler"),
+    ("noise_schedule", "noise_schedule"),
+    ("cfg_rescale", "cfg_rescale"),
+    ("width", "width"),
+    ("height", "height"),
+    ("sm", "sm"),
+    ("sm_dyn", "sm_dyn"),
+    ("ucPreset", "uc_preset"),
+    ("skip_cfg_above_sigma", "skip_cfg_above_sigma"),
+    ("strength", "strength"),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldMismatch {
+    /// Seed of the image the mismatch was found in
+    pub seed: i64,
+    pub field: String,
+    pub requested: Value,
+    pub actual: Value,
+}
+
+/// Equal as JSON, with numbers compared loosely (`5` and `5.0`, or floats
+/// that went through a round trip).
+fn same(requested: &Value, actual: &Value) -> bool {
+    match (requested.as_f64(), actual.as_f64()) {
+        (Some(a), Some(b)) => (a - b).abs() <= 1e-6 * a.abs().max(1.0),
+        _ => requested == actual,
+    }
+}
+
+/// The sent negative prompt already has the UC preset in front. NAI
+/// usually records it that way too, but `uc` may also hold only the user's
+/// part, so that counts as a match as well.
+fn same_uc(payload: &Value, requested: &Value, actual: &Value) -> bool {
+    if same(requested, actual) {
+        return true;
+    }
+    let (Some(requested), Some(actual)) = (requested.as_str(), actual.as_str()) else {
+        return false;
+    };
+    let model = payload["model"].as_str().unwrap_or_default();
+    let Some(preset_id) = payload["parameters"]["ucPreset"].as_u64() else {
+        return false;
+    };
+    uc_presets::apply_uc_preset(model, preset_id as u32, actual) == requested
+}
+
+/// The fields of `payload` that `meta` records differently. `seed` is the
+/// seed of this image, which differs from the payload's for every sample
+/// after the first.
+pub fn verify_result_consistency(
+    payload: &Value,
+    seed: i64,
+    meta: &EmbeddedMetadata,
+) -> Vec<FieldMismatch> {
+    let Some(Value::Object(recorded)) = meta.comment() else {
+        return Vec::new();
+    };
+    let parameters = &payload["parameters"];
+    let requested_seed = Value::from(seed);
+    let pairs = FIELDS
+        .iter()
+        .map(|(key, field)| (*field, parameters.get(*key), recorded.get(*key)))
+        .chain([
+            ("seed", Some(&requested_seed), recorded.get("seed")),
+            ("prompt", payload.get("input"), recorded.get("prompt")),
+            (
+                "negative_prompt",
+                parameters.get("negative_prompt"),
+                recorded.get("uc"),
+            ),
+        ]);
+
+    let mut mismatches = Vec::new();
+    for (field, requested, actual) in pairs {
+        let (Some(requested), Some(actual)) = (requested, actual) else {
+            continue;
+        };
+        let matches = if field == "negative_prompt" {
+            same_uc(payload, requested, actual)
+        } else {
+            same(requested, actual)
+        };
+        if !matches {
+            mismatches.push(FieldMismatch {
+                seed,
+                field: field.to_string(),
+                requested: requested.clone(),
+                actual: actual.clone(),
+            });
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::MetadataSource;
+    use serde_json::json;
+
+    const MODEL: &str = "nai-diffusion-4-5-full";
+
+    fn recorded(comment: Value) -> EmbeddedMetadata {
+        EmbeddedMetadata {
+            fields: vec![
+                (
+                    "Source".to_string(),
+                    "NovelAI Diffusion V4.5 4BDE2A90".to_string(),
+                ),
+                ("Comment".to_string(), comment.to_string()),
+            ],
+            source: MetadataSource::TextChunk,
+        }
+    }
+
+    fn payload(negative: &str) -> Value {
+        json!({
+            "input": "1girl, smile",
+            "model": MODEL,
+            "parameters": {
+                "steps": 28,
+                "scale": 5,
+                "sampler": "k_euler_ancestral",
+                "width": 832,
+                "height": 1216,
+                "seed": 1000,
+                "ucPreset": 0,
+                "negative_prompt": negative,
+            }
+        })
+    }
+
+    fn fields(mismatches: &[FieldMismatch]) -> Vec<&str> {
+        mismatches.iter().map(|m| m.field.as_str()).collect()
+    }
+
+    #[test]
+    fn numbers_compare_loosely() {
+        let meta = recorded(json!({
+            "prompt": "1girl, smile",
+            "steps": 28.0,
+            "scale": 5.0,
+            "width": 832,
+            "height": 1216,
+            "seed": 1000,
+        }));
+        assert!(verify_result_consistency(&payload(""), 1000, &meta).is_empty());
+
+        let meta = recorded(json!({ "steps": 23, "scale": 5.0000001 }));
+        let mismatches = verify_result_consistency(&payload(""), 1000, &meta);
+        assert_eq!(
+            mismatches,
+            vec![FieldMismatch {
+                seed: 1000,
+                field: "steps".to_string(),
+                requested: json!(28),
+                actual: json!(23),
+            }]
+        );
+    }
+
+    #[test]
+    fn later_samples_use_their_own_seed() {
+        let payload = payload("");
+        for i in 0..3 {
+            let meta = recorded(json!({ "seed": 1000 + i }));
+            assert!(verify_result_consistency(&payload, 1000 + i, &meta).is_empty());
+        }
+        let meta = recorded(json!({ "seed": 1000 }));
+        let mismatches = verify_result_consistency(&payload, 1001, &meta);
+        assert_eq!(fields(&mismatches), ["seed"]);
+        assert_eq!(mismatches[0].seed, 1001);
+    }
+
+    #[test]
+    fn fields_missing_on_either_side_are_skipped() {
+        let meta = recorded(json!({ "sm": true, "cfg_rescale": 0.2, "steps": 28 }));
+        assert!(verify_result_consistency(&payload(""), 1000, &meta).is_empty());
+
+        let mut sent = payload("");
+        sent["parameters"].as_object_mut().unwrap().remove("steps");
+        let meta = recorded(json!({ "steps": 23 }));
+        assert!(verify_result_consistency(&sent, 1000, &meta).is_empty());
+
+        assert!(verify_result_consistency(
+            &sent,
+            1000,
+            &EmbeddedMetadata {
+                fields: Vec::new(),
+                source: MetadataSource::TextChunk,
+            }
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn uc_with_or_without_the_preset_matches() {
+        let sent = uc_presets::apply_uc_preset(MODEL, 0, "bad hands");
+        assert_ne!(sent, "bad hands");
+        let payload = payload(&sent);
+
+        let meta = recorded(json!({ "uc": sent, "ucPreset": 0 }));
+        assert!(verify_result_consistency(&payload, 1000, &meta).is_empty());
+        let meta = recorded(json!({ "uc": "bad hands", "ucPreset": 0 }));
+        assert!(verify_result_consistency(&payload, 1000, &meta).is_empty());
+
+        let meta = recorded(json!({ "uc": "bad feet" }));
+        let mismatches = verify_result_consistency(&payload, 1000, &meta);
+        assert_eq!(fields(&mismatches), ["negative_prompt"]);
+    }
+}
@@ -0,0 +1,225 @@
+//! Duplicate detection for a gallery folder.
+//!
+//! Images are compared by a 64-bit difference hash (dHash), so re-encodes and
+//! resizes still match. Images whose NAI metadata (seed + settings payload)
+//! is identical are marked as exact duplicates.
+
+use crate::metadata;
+use image::imageops::FilterType;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const DEFAULT_MAX_DISTANCE: u32 = 6;
+const IMAGE_EXTENSIONS: [&str; 4] = ["png", "webp", "jpg", "jpeg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKind {
+    /// Every file has the same seed and generation settings
+    Exact,
+    /// Visually similar within the distance threshold
+    Similar,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateFile {
+    pub path: String,
+    /// Hamming distance between this file's dHash and the representative's
+    pub distance: u32,
+    /// `1 - distance / 64`
+    pub similarity: f64,
+    /// Same metadata as the representative
+    pub exact: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    /// The oldest file in the group, i.e. the one to keep
+    pub representative: String,
+    /// Every other member; these are the deletion candidates
+    pub duplicates: Vec<DuplicateFile>,
+}
+
+struct HashedImage {
+    path: PathBuf,
+    modified: SystemTime,
+    dhash: u64,
+    metadata_key: Option<u64>,
+}
+
+/// 9x8 grayscale thumbnail; each bit says whether a pixel is brighter than
+/// its right neighbour.
+fn dhash(image: &image::DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// Hash of the seed plus the whole settings payload, or `None` without NAI
+/// metadata.
+fn metadata_key(bytes: &[u8]) -> Option<u64> {
+    let comment = metadata::read_embedded_metadata(bytes)?.comment()?;
+    let mut hasher = DefaultHasher::new();
+    comment.get("seed").map(|s| s.to_string()).hash(&mut hasher);
+    comment.to_string().hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+fn hash_image(path: &Path) -> Option<HashedImage> {
+    let bytes = std::fs::read(path).ok()?;
+    let image = match image::load_from_memory(&bytes) {
+        Ok(image) => image,
+        Err(e) => {
+            log::debug!("Skipping {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let modified = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    Some(HashedImage {
+        path: path.to_path_buf(),
+        modified,
+        dhash: dhash(&image),
+        metadata_key: metadata_key(&bytes),
+    })
+}
+
+/// Hashes `paths` on all available cores.
+fn hash_all(paths: Vec<PathBuf>) -> Vec<HashedImage> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4);
+    let chunk_size = paths.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter_map(|p| hash_image(p))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (ra, rb) = (find_root(parent, a), find_root(parent, b));
+    if ra != rb {
+        parent[rb] = ra;
+    }
+}
+
+/// Groups the images in `dir` (not recursive) whose dHash is within
+/// `max_distance` bits of each other or whose metadata matches exactly.
+/// Groups are returned largest first.
+pub fn find_duplicates(dir: &Path, max_distance: u32) -> Result<Vec<DuplicateGroup>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("폴더 읽기 오류: {}", e))?;
+    let paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+
+    let images = hash_all(paths);
+    let mut parent: Vec<usize> = (0..images.len()).collect();
+
+    let mut by_metadata: HashMap<u64, usize> = HashMap::new();
+    for (i, image) in images.iter().enumerate() {
+        if let Some(key) = image.metadata_key {
+            match by_metadata.get(&key) {
+                Some(&first) => union(&mut parent, first, i),
+                None => {
+                    by_metadata.insert(key, i);
+                }
+            }
+        }
+    }
+    for i in 0..images.len() {
+        for j in i + 1..images.len() {
+            if (images[i].dhash ^ images[j].dhash).count_ones() <= max_distance {
+                union(&mut parent, i, j);
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..images.len() {
+        let root = find_root(&mut parent, i);
+        members.entry(root).or_default().push(i);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = members
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by_key(|&i| (images[i].modified, images[i].path.clone()));
+            let rep = &images[group[0]];
+            let duplicates: Vec<DuplicateFile> = group[1..]
+                .iter()
+                .map(|&i| {
+                    let image = &images[i];
+                    let distance = (image.dhash ^ rep.dhash).count_ones();
+                    DuplicateFile {
+                        path: image.path.to_string_lossy().to_string(),
+                        distance,
+                        similarity: 1.0 - distance as f64 / 64.0,
+                        exact: rep.metadata_key.is_some() && image.metadata_key == rep.metadata_key,
+                    }
+                })
+                .collect();
+            DuplicateGroup {
+                kind: if duplicates.iter().all(|d| d.exact) {
+                    DuplicateKind::Exact
+                } else {
+                    DuplicateKind::Similar
+                },
+                representative: rep.path.to_string_lossy().to_string(),
+                duplicates,
+            }
+        })
+        .collect();
+
+    groups.sort_by(|a, b| {
+        b.duplicates
+            .len()
+            .cmp(&a.duplicates.len())
+            .then_with(|| a.representative.cmp(&b.representative))
+    });
+    Ok(groups)
+}
@@ -0,0 +1,181 @@
+//! Bundles images and their generation settings into a single ZIP.
+//!
+//! Entries are written to the archive one at a time straight into the output
+//! file, so only the image being processed is held decoded in memory.
+
+use crate::metadata::{self, ConvertTarget, MetadataEmbed, MetadataSource};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+#[derive(Debug, Deserialize)]
+pub struct ExportItem {
+    /// File name without extension; falls back to `image_{index}`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Base64 image data (PNG, WebP or JPEG), data URL prefix allowed.
+    pub image: String,
+    /// Generation settings. When omitted, the settings already embedded in
+    /// the image are used.
+    #[serde(default)]
+    pub metadata: Option<Value>,
+}
+
+/// Where the settings JSON ends up inside the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportMetadataMode {
+    /// `{name}.json` next to each PNG
+    #[default]
+    Sidecar,
+    /// `Comment` text chunk inside the PNG
+    Embed,
+    Both,
+    None,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportProgress {
+    pub current: usize,
+    pub total: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportZipResult {
+    pub path: String,
+    pub exported: usize,
+}
+
+/// Keeps only characters that are safe in file names on every OS.
+pub fn sanitize_name(name: &str) -> String {
+    let cleaned: String = name
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    cleaned.trim_matches('.').trim().to_string()
+}
+
+/// Returns `base`, or `base_2`, `base_3`, ... if it was already taken.
+fn unique_name(used: &mut HashSet<String>, base: &str) -> String {
+    let mut name = base.to_string();
+    let mut n = 2;
+    // Compare case-insensitively since Windows/macOS file systems do
+    while !used.insert(name.to_lowercase()) {
+        name = format!("{}_{}", base, n);
+        n += 1;
+    }
+    name
+}
+
+/// Converts the item to PNG (keeping any metadata it already carries) and
+/// works out which settings JSON belongs to it.
+fn prepare_png(
+    item: &ExportItem,
+    mode: ExportMetadataMode,
+) -> Result<(Vec<u8>, Option<Value>), String> {
+    let bytes = metadata::decode_image_base64(&item.image)?;
+    let embedded = metadata::read_embedded_metadata(&bytes);
+    let settings = item
+        .metadata
+        .clone()
+        .or_else(|| embedded.as_ref().and_then(|m| m.comment()));
+
+    let mut png = if bytes.starts_with(b"\x89PNG") {
+        bytes
+    } else {
+        metadata::convert_image_bytes(&bytes, ConvertTarget::Png, MetadataEmbed::default())?
+    };
+
+    // Only add a Comment chunk if the PNG doesn't already have one
+    let has_text_chunk = embedded
+        .as_ref()
+        .is_some_and(|m| m.source != MetadataSource::StealthAlpha && m.get("Comment").is_some());
+    if matches!(mode, ExportMetadataMode::Embed | ExportMetadataMode::Both) && !has_text_chunk {
+        if let Some(settings) = &settings {
+            let fields = vec![("Comment".to_string(), settings.to_string())];
+            png = metadata::insert_png_text_chunks(&png, &fields)?;
+        }
+    }
+
+    Ok((png, settings))
+}
+
+/// Writes `items` to a ZIP at `out_path`. `compression_level` is the deflate
+/// level (0 = store only, 9 = smallest); `on_progress` runs after each item.
+pub fn export_images_zip(
+    items: Vec<ExportItem>,
+    out_path: &Path,
+    mode: ExportMetadataMode,
+    compression_level: Option<i64>,
+    mut on_progress: impl FnMut(ExportProgress),
+) -> Result<ExportZipResult, String> {
+    if items.is_empty() {
+        return Err("내보낼 이미지가 없습니다".to_string());
+    }
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("폴더 생성 오류: {}", e))?;
+    }
+
+    let file = File::create(out_path).map_err(|e| format!("파일 생성 오류: {}", e))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+
+    let options = match compression_level.map(|l| l.clamp(0, 9)) {
+        Some(0) => SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        level => SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .compression_level(level),
+    };
+
+    let total = items.len();
+    let mut used = HashSet::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let base = item
+            .name
+            .as_deref()
+            .map(sanitize_name)
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| format!("image_{}", index + 1));
+        let name = unique_name(&mut used, &base);
+
+        let (png, settings) = prepare_png(&item, mode).map_err(|e| format!("{}: {}", name, e))?;
+
+        zip.start_file(format!("{}.png", name), options)
+            .and_then(|_| zip.write_all(&png).map_err(Into::into))
+            .map_err(|e| format!("ZIP 쓰기 오류: {}", e))?;
+
+        if matches!(mode, ExportMetadataMode::Sidecar | ExportMetadataMode::Both) {
+            if let Some(settings) = settings {
+                let json = serde_json::to_vec_pretty(&settings).map_err(|e| e.to_string())?;
+                zip.start_file(format!("{}.json", name), options)
+                    .and_then(|_| zip.write_all(&json).map_err(Into::into))
+                    .map_err(|e| format!("ZIP 쓰기 오류: {}", e))?;
+            }
+        }
+
+        on_progress(ExportProgress {
+            current: index + 1,
+            total,
+            name,
+        });
+    }
+
+    zip.finish()
+        .and_then(|mut w| w.flush().map_err(Into::into))
+        .map_err(|e| format!("ZIP 쓰기 오류: {}", e))?;
+
+    Ok(ExportZipResult {
+        path: out_path.to_string_lossy().to_string(),
+        exported: total,
+    })
+}
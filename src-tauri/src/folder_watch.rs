@@ -0,0 +1,171 @@
+//! Watching folders for images made elsewhere (other tools, the NAI site)
+//! so they show up in the gallery.
+//!
+//! File events only mark a path as pending. A pending file is handed over
+//! once it has gone `SETTLE_TIME` without events, its size hasn't changed
+//! since the last one, and the bytes look like a finished image (PNG ends
+//! with `IEND`, JPEG with `FFD9`, WebP matches its RIFF size). A file that
+//! is still being written just stays pending. Every path is handed over at
+//! most once; files already in a folder when it is watched are left alone.
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SUPPORTED_EXTENSIONS: [&str; 4] = ["png", "webp", "jpg", "jpeg"];
+const SETTLE_TIME: Duration = Duration::from_millis(1000);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct FolderWatch {
+    watcher: Mutex<RecommendedWatcher>,
+    folders: Mutex<BTreeSet<PathBuf>>,
+}
+
+fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Whether `bytes` hold a whole image rather than the start of one.
+fn is_complete(bytes: &[u8]) -> bool {
+    match image::guess_format(bytes) {
+        Ok(image::ImageFormat::Png) => {
+            bytes.len() >= 12 && &bytes[bytes.len() - 8..][..4] == b"IEND"
+        }
+        // Some writers pad after the end marker
+        Ok(image::ImageFormat::Jpeg) => bytes.windows(2).rev().take(16).any(|w| w == [0xFF, 0xD9]),
+        Ok(image::ImageFormat::WebP) => {
+            let size = bytes
+                .get(4..8)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+            size.is_some_and(|size| size + 8 <= bytes.len())
+        }
+        _ => false,
+    }
+}
+
+/// Waits for pending files to settle and passes finished ones to `on_ready`.
+fn run_debounce(rx: mpsc::Receiver<PathBuf>, on_ready: impl Fn(PathBuf, Vec<u8>)) {
+    let mut pending: HashMap<PathBuf, (Instant, Option<u64>)> = HashMap::new();
+    let mut imported: HashSet<PathBuf> = HashSet::new();
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(path) => {
+                if !imported.contains(&path) {
+                    let size = std::fs::metadata(&path).ok().map(|m| m.len());
+                    pending.insert(path, (Instant::now(), size));
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let mut settled = Vec::new();
+        pending.retain(|path, (last_event, size)| {
+            if now.duration_since(*last_event) < SETTLE_TIME {
+                return true;
+            }
+            match std::fs::metadata(path) {
+                // Removed or renamed away before it settled
+                Err(_) => false,
+                Ok(meta) if Some(meta.len()) != *size => {
+                    *last_event = now;
+                    *size = Some(meta.len());
+                    true
+                }
+                Ok(_) => {
+                    settled.push(path.clone());
+                    false
+                }
+            }
+        });
+
+        for path in settled {
+            match std::fs::read(&path) {
+                Ok(bytes) if is_complete(&bytes) => {
+                    imported.insert(path.clone());
+                    on_ready(path, bytes);
+                }
+                // Not finished after all; the next write marks it again
+                Ok(_) => {}
+                Err(e) => log::warn!("Failed to read watched file {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+impl FolderWatch {
+    /// Starts the watcher. `on_ready` runs on a background thread with each
+    /// new image's path and bytes.
+    pub fn start(on_ready: impl Fn(PathBuf, Vec<u8>) + Send + 'static) -> Result<Self, String> {
+        let (tx, rx) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                return;
+            }
+            for path in event.paths.into_iter().filter(|p| is_supported(p)) {
+                let _ = tx.send(path);
+            }
+        })
+        .map_err(|e| format!("폴더 감시를 시작할 수 없습니다: {}", e))?;
+
+        std::thread::spawn(move || run_debounce(rx, on_ready));
+        Ok(FolderWatch {
+            watcher: Mutex::new(watcher),
+            folders: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    /// Watches `folder` (not its subfolders). Returns the canonical path,
+    /// which is what `unwatch` and `folders` use.
+    pub fn watch(&self, folder: &Path) -> Result<PathBuf, String> {
+        let folder = folder
+            .canonicalize()
+            .ok()
+            .filter(|p| p.is_dir())
+            .ok_or_else(|| format!("폴더를 찾을 수 없습니다: {}", folder.display()))?;
+        let mut folders = self.folders.lock().map_err(|e| e.to_string())?;
+        if !folders.contains(&folder) {
+            self.watcher
+                .lock()
+                .map_err(|e| e.to_string())?
+                .watch(&folder, RecursiveMode::NonRecursive)
+                .map_err(|e| format!("폴더 감시 오류: {}", e))?;
+            folders.insert(folder.clone());
+        }
+        Ok(folder)
+    }
+
+    pub fn unwatch(&self, folder: &Path) -> Result<(), String> {
+        let folder = folder
+            .canonicalize()
+            .unwrap_or_else(|_| folder.to_path_buf());
+        let mut folders = self.folders.lock().map_err(|e| e.to_string())?;
+        if !folders.remove(&folder) {
+            return Err(format!("감시 중인 폴더가 아닙니다: {}", folder.display()));
+        }
+        // The folder may be gone already, which also ends the watch
+        let _ = self
+            .watcher
+            .lock()
+            .map_err(|e| e.to_string())?
+            .unwatch(&folder);
+        Ok(())
+    }
+
+    pub fn folders(&self) -> Vec<PathBuf> {
+        self.folders
+            .lock()
+            .map(|folders| folders.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
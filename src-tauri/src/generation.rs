@@ -0,0 +1,619 @@
+//! Image generation payloads for `image.novelai.net/ai/generate-image`.
+//!
+//! Mirrors the request the frontend builds in `novelai-api.ts` so the Rust
+//! backend can run generations without going through the webview's fetch.
+
+use crate::{models, prompt, uc_presets};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+/// NAI accepts seeds in the u32 range.
+pub const MAX_SEED: i64 = 4_294_967_295;
+/// Most images NAI returns for one request (`n_samples`).
+pub const MAX_SAMPLES: u32 = 4;
+
+/// Character positions are picked on a 5x5 grid in NAI's UI, and only the
+/// cell centers are ever sent: 0.1, 0.3, 0.5, 0.7, 0.9 on each axis.
+const POSITION_GRID: [f64; 5] = [0.1, 0.3, 0.5, 0.7, 0.9];
+/// Sent for every character when positions are left to the AI.
+pub const DEFAULT_POSITION: CharacterPosition = CharacterPosition { x: 0.5, y: 0.5 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CharacterPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
+fn snap_axis(value: f64) -> f64 {
+    let value = if value.is_finite() {
+        value.clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    // Five equal cells; 1.0 belongs to the last one
+    let cell = ((value * 5.0) as usize).min(POSITION_GRID.len() - 1);
+    POSITION_GRID[cell]
+}
+
+impl CharacterPosition {
+    /// The grid cell center NAI expects for this position, or the default
+    /// center when `use_coords` is off.
+    pub fn snapped(self, use_coords: bool) -> Self {
+        if !use_coords {
+            return DEFAULT_POSITION;
+        }
+        CharacterPosition {
+            x: snap_axis(self.x),
+            y: snap_axis(self.y),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CharacterPrompt {
+    pub prompt: String,
+    #[serde(default)]
+    pub negative: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub position: CharacterPosition,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Same shape as the frontend's `GenerationParams`. Vibe and character
+/// reference images are expected to be pre-encoded / pre-processed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParams {
+    pub prompt: String,
+    #[serde(default)]
+    pub negative_prompt: String,
+    pub model: String,
+    pub width: u32,
+    pub height: u32,
+    pub steps: u32,
+    pub cfg_scale: f64,
+    #[serde(default)]
+    pub cfg_rescale: f64,
+    pub sampler: String,
+    pub scheduler: String,
+    #[serde(default)]
+    pub smea: bool,
+    #[serde(default)]
+    pub smea_dyn: bool,
+    #[serde(default)]
+    pub variety: bool,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    /// Index into the model's official UC presets (see `uc_presets`). When
+    /// set, the preset text is prepended to `negative_prompt`.
+    #[serde(default, alias = "ucPreset")]
+    pub uc_preset: Option<u32>,
+    /// Run prompts through `prompt::normalize_prompt` before sending. Off by
+    /// default so results can be compared against the unnormalized prompt.
+    #[serde(default)]
+    pub normalize_prompt: bool,
+    /// Whether character positions are sent. Defaults to on whenever there
+    /// is at least one character prompt.
+    #[serde(default, alias = "useCoords")]
+    pub use_coords: Option<bool>,
+    /// Images per request (1-4). NAI gives sample `i` the seed `seed + i`.
+    #[serde(default, alias = "nSamples")]
+    pub n_samples: Option<u32>,
+    /// Seed of NAI's secondary noise (`extra_noise_seed`), which otherwise
+    /// follows `seed`. Keeping `seed` and changing this gives small
+    /// variations on one composition.
+    #[serde(default, alias = "variationSeed")]
+    pub variation_seed: Option<u32>,
+    /// How far a variation may move away from the base image, 0-1
+    #[serde(default, alias = "variationStrength")]
+    pub variation_strength: Option<f64>,
+
+    // Character Reference
+    #[serde(rename = "charImages", default)]
+    pub char_images: Vec<String>,
+    #[serde(rename = "charInfo", default)]
+    pub char_info: Vec<f64>,
+    #[serde(rename = "charStrength", default)]
+    pub char_strength: Vec<f64>,
+
+    // Vibe Transfer (pre-encoded vibes only)
+    #[serde(rename = "preEncodedVibes", default)]
+    pub pre_encoded_vibes: Vec<String>,
+    #[serde(rename = "vibeInfo", default)]
+    pub vibe_info: Vec<f64>,
+    #[serde(rename = "vibeStrength", default)]
+    pub vibe_strength: Vec<f64>,
+
+    // Character Prompts (V4 char_captions)
+    #[serde(rename = "characterPrompts", default)]
+    pub character_prompts: Vec<CharacterPrompt>,
+
+    // I2I / Inpainting
+    #[serde(rename = "sourceImage", default)]
+    pub source_image: Option<String>,
+    #[serde(default)]
+    pub strength: Option<f64>,
+    #[serde(default)]
+    pub noise: Option<f64>,
+    #[serde(default)]
+    pub mask: Option<String>,
+
+    /// Settings from an imported NAI file that NAIS doesn't use, kept so
+    /// exporting writes them back (see `nai_settings`)
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra: serde_json::Map<String, Value>,
+}
+
+impl GenerationParams {
+    pub fn is_v4(&self) -> bool {
+        self.model.contains("diffusion-4")
+    }
+
+    /// Within Opus's free generation limits: one image of at most 1024x1024
+    /// (by area) with 28 steps or fewer.
+    pub fn is_opus_free(&self) -> bool {
+        self.width as u64 * self.height as u64 <= 1024 * 1024
+            && self.steps <= 28
+            && self.samples() == 1
+    }
+
+    /// Estimated Anlas for one request, following the official site's
+    /// formula: a per-image price from the area and steps (SMEA costs extra
+    /// on V3, img2img scales with strength), at least 2 per image. Free when
+    /// `unlimited` and within `is_opus_free`.
+    pub fn estimate_anlas(&self, unlimited: bool) -> u32 {
+        if unlimited && self.is_opus_free() {
+            return 0;
+        }
+        let pixels = self.width as f64 * self.height as f64;
+        let mut per_image =
+            2.951823174884865e-6 * pixels + 5.753298233447344e-7 * pixels * self.steps as f64;
+        if !self.is_v4() && self.smea {
+            per_image *= if self.smea_dyn { 1.4 } else { 1.2 };
+        }
+        if self.source_image.is_some() {
+            per_image *= self.strength.unwrap_or(0.7);
+        }
+        (per_image.ceil() as u32).max(2) * self.samples()
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.n_samples.unwrap_or(1).clamp(1, MAX_SAMPLES)
+    }
+
+    /// Copy with the prompt, negative prompt and character prompts
+    /// normalized for this model.
+    pub fn normalized(&self) -> Self {
+        let mut params = self.clone();
+        params.prompt = prompt::normalize_prompt(&self.prompt, &self.model);
+        params.negative_prompt = prompt::normalize_prompt(&self.negative_prompt, &self.model);
+        for character in &mut params.character_prompts {
+            character.prompt = prompt::normalize_prompt(&character.prompt, &self.model);
+            character.negative = prompt::normalize_prompt(&character.negative, &self.model);
+        }
+        params
+    }
+
+    /// The negative prompt actually sent: the UC preset followed by the
+    /// user's own negative prompt.
+    pub fn final_negative_prompt(&self) -> String {
+        match self.uc_preset {
+            Some(id) => uc_presets::apply_uc_preset(&self.model, id, &self.negative_prompt),
+            None => self.negative_prompt.clone(),
+        }
+    }
+}
+
+fn strip_data_url(data: &str) -> &str {
+    match data.find(";base64,") {
+        Some(idx) if data.starts_with("data:") => &data[idx + ";base64,".len()..],
+        _ => data,
+    }
+}
+
+/// Builds the full request body (`input`, `model`, `action`, `parameters`)
+/// for one generation with the given seed.
+pub fn build_payload(params: &GenerationParams, seed: i64) -> Value {
+    let is_v4 = params.is_v4();
+    let negative_prompt = params.final_negative_prompt();
+
+    // Models without character prompts (V3) get neither captions nor positions
+    let supports_characters =
+        models::find(&params.model).is_some_and(|model| model.features.character_prompts);
+    let characters: Vec<&CharacterPrompt> = params
+        .character_prompts
+        .iter()
+        .filter(|c| supports_characters && c.enabled && !c.prompt.trim().is_empty())
+        .collect();
+    let use_coords = !characters.is_empty() && params.use_coords.unwrap_or(true);
+
+    let mut char_captions = Vec::new();
+    let mut char_negative_captions = Vec::new();
+    for character in characters {
+        let position = character.position.snapped(use_coords);
+        let centers = json!([{ "x": position.x, "y": position.y }]);
+        char_captions.push(json!({
+            "char_caption": character.prompt,
+            "centers": centers,
+        }));
+        if !character.negative.trim().is_empty() {
+            char_negative_captions.push(json!({
+                "char_caption": character.negative,
+                "centers": centers,
+            }));
+        }
+    }
+
+    let mut parameters = json!({
+        "width": params.width,
+        "height": params.height,
+        "n_samples": params.samples(),
+        "seed": seed,
+        "extra_noise_seed": params.variation_seed.map_or(seed, i64::from),
+        "sampler": params.sampler,
+        "steps": params.steps,
+        "scale": params.cfg_scale,
+        "negative_prompt": negative_prompt,
+        "cfg_rescale": params.cfg_rescale,
+        "noise_schedule": params.scheduler,
+        "params_version": 3,
+        "legacy": false,
+        "legacy_v3_extend": false,
+        // V4 models don't support SMEA
+        "sm": !is_v4 && params.smea,
+        "sm_dyn": !is_v4 && params.smea && params.smea_dyn,
+        "dynamic_thresholding": false,
+        "skip_cfg_above_sigma": if params.variety { json!(19) } else { Value::Null },
+        "add_original_image": true,
+        "legacy_uc": false,
+        "prefer_brownian": true,
+        "ucPreset": params.uc_preset.unwrap_or(0),
+        "use_coords": use_coords,
+        "reference_image_multiple": params.pre_encoded_vibes,
+        "reference_information_extracted_multiple": params.vibe_info,
+        "reference_strength_multiple": params.vibe_strength,
+        "director_reference_images": params.char_images,
+        "director_reference_information_extracted": params.char_info,
+        "director_reference_strength_values": params.char_strength,
+        "director_reference_secondary_strength_values":
+            params.char_strength.iter().map(|_| 0).collect::<Vec<_>>(),
+        "v4_prompt": {
+            "caption": {
+                "base_caption": params.prompt,
+                "char_captions": char_captions,
+            },
+            "use_coords": use_coords,
+            "use_order": true,
+        },
+        "v4_negative_prompt": {
+            "caption": {
+                "base_caption": negative_prompt,
+                "char_captions": char_negative_captions,
+            },
+            "use_coords": use_coords,
+            "use_order": false,
+            "legacy_uc": false,
+        },
+    });
+    let obj = parameters.as_object_mut().expect("parameters is an object");
+
+    if params.variation_seed.is_some() {
+        if let Some(strength) = params.variation_strength {
+            obj.insert("variation_strength".into(), json!(strength));
+        }
+    }
+    if params.pre_encoded_vibes.len() > 1 {
+        obj.insert("normalize_reference_strength_multiple".into(), json!(true));
+    }
+    if !params.char_images.is_empty() {
+        let descriptions: Vec<Value> = params
+            .char_images
+            .iter()
+            .map(|_| {
+                json!({
+                    "caption": { "base_caption": "character", "char_captions": [] },
+                    "legacy_uc": false,
+                })
+            })
+            .collect();
+        obj.insert(
+            "director_reference_descriptions".into(),
+            json!(descriptions),
+        );
+    }
+
+    let mut action = "generate";
+    let mut model = params.model.clone();
+
+    if let Some(source) = &params.source_image {
+        obj.insert("image".into(), json!(strip_data_url(source)));
+        let strength = params.strength.unwrap_or(0.7);
+
+        if let Some(mask) = &params.mask {
+            action = "infill";
+            if !model.contains("inpainting") {
+                model.push_str("-inpainting");
+            }
+            obj.insert("strength".into(), json!(0.7));
+            obj.insert(
+                "img2img".into(),
+                json!({ "strength": strength, "color_correct": true }),
+            );
+            obj.insert("inpaintImg2ImgStrength".into(), json!(strength));
+            obj.insert("mask".into(), json!(strip_data_url(mask)));
+
+            // The inpainting model rejects director references
+            for key in [
+                "director_reference_images",
+                "director_reference_information_extracted",
+                "director_reference_strength_values",
+                "director_reference_secondary_strength_values",
+                "director_reference_descriptions",
+            ] {
+                obj.remove(key);
+            }
+        } else {
+            action = "img2img";
+            obj.insert("strength".into(), json!(strength));
+            obj.insert("noise".into(), json!(params.noise.unwrap_or(0.0)));
+        }
+    }
+
+    json!({
+        "input": params.prompt,
+        "model": model,
+        "action": action,
+        "parameters": parameters,
+    })
+}
+
+/// Adds `extra` to `payload` without replacing anything: new keys are
+/// inserted, objects on both sides are merged the same way, and every other
+/// key that already exists is kept. Returns the paths of those kept keys
+/// (e.g. `parameters.steps`).
+pub fn merge_extra_params(payload: &mut Value, extra: &Value) -> Vec<String> {
+    fn merge(
+        target: &mut Map<String, Value>,
+        extra: &Map<String, Value>,
+        prefix: &str,
+        conflicts: &mut Vec<String>,
+    ) {
+        for (key, value) in extra {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            match (target.get_mut(key), value) {
+                (None, _) => {
+                    target.insert(key.clone(), value.clone());
+                }
+                (Some(Value::Object(existing)), Value::Object(value)) => {
+                    merge(existing, value, &path, conflicts)
+                }
+                (Some(_), _) => conflicts.push(path),
+            }
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    if let (Value::Object(target), Value::Object(extra)) = (payload, extra) {
+        merge(target, extra, "", &mut conflicts);
+    }
+    conflicts
+}
+
+// Every place the seed shows up in a payload
+const SEED_KEYS: [&str; 2] = ["seed", "extra_noise_seed"];
+
+/// Writes `value` as JSON with object keys sorted, `null` fields dropped
+/// (the same as leaving them out) and whole-number floats written as
+/// integers, so equal settings always produce the same text.
+fn write_canonical(value: &Value, include_seed: bool, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map
+                .iter()
+                .filter(|(k, v)| !v.is_null() && (include_seed || !SEED_KEYS.contains(&k.as_str())))
+                .map(|(k, _)| k)
+                .collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&map[key], include_seed, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, include_seed, out);
+            }
+            out.push(']');
+        }
+        Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < 1e15 => {
+                out.push_str(&(f as i64).to_string())
+            }
+            _ => out.push_str(&n.to_string()),
+        },
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+/// SHA-256 (hex) of the canonical form of a generation payload. Two
+/// payloads with the same fingerprint ask NAI for exactly the same thing;
+/// without `include_seed`, images that differ only by seed match too.
+pub fn payload_fingerprint(payload: &Value, include_seed: bool) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut canonical = String::new();
+    write_canonical(payload, include_seed, &mut canonical);
+    format!("{:x}", Sha256::digest(canonical.as_bytes()))
+}
+
+/// Settings in `payload` that can make the same seed give different images,
+/// as user-facing hints. Empty when nothing known applies.
+pub fn nondeterminism_hints(payload: &Value) -> Vec<String> {
+    let p = &payload["parameters"];
+    let is_on = |key: &str| p[key].as_bool().unwrap_or(false);
+    let is_set = |key: &str| p[key].as_array().is_some_and(|a| !a.is_empty());
+    let mut hints = Vec::new();
+
+    if is_on("sm") || is_on("sm_dyn") {
+        hints.push("SMEA/DYN이 켜져 있습니다. 끄고 다시 확인해 보세요".to_string());
+    }
+    if is_on("dynamic_thresholding") {
+        hints.push("Dynamic thresholding이 켜져 있습니다".to_string());
+    }
+    let sampler = p["sampler"].as_str().unwrap_or_default();
+    if sampler.contains("ancestral") || sampler.contains("sde") {
+        hints.push(format!(
+            "{} 샘플러는 단계마다 노이즈를 더합니다. k_euler 등으로 비교해 보세요",
+            sampler
+        ));
+    }
+    if !p["skip_cfg_above_sigma"].is_null() {
+        hints.push("Variety+가 켜져 있습니다".to_string());
+    }
+    if p["noise"].as_f64().is_some_and(|n| n > 0.0) {
+        hints.push("img2img 노이즈가 0보다 큽니다".to_string());
+    }
+    if is_set("reference_image_multiple") || is_set("director_reference_images") {
+        hints.push(
+            "Vibe/캐릭터 참조 이미지는 서버에서 처리되어 결과가 달라질 수 있습니다".to_string(),
+        );
+    }
+    hints
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedMode {
+    /// A different random seed for every image
+    #[default]
+    Random,
+    /// The same seed for every image
+    Fixed,
+    /// `base`, `base + 1`, `base + 2`, ...
+    Incremental,
+}
+
+/// Seed of the `index`-th image of an `n_samples` request.
+pub fn sample_seed(seed: i64, index: usize) -> i64 {
+    (seed + index as i64) % (MAX_SEED + 1)
+}
+
+pub fn random_seed() -> i64 {
+    rand::thread_rng().gen_range(0..=MAX_SEED)
+}
+
+/// Resolves the seed for each of `count` requests of `samples` images. A
+/// missing base seed is replaced by a random one (`Random` ignores it
+/// entirely). NAI gives the `i`-th sample `seed + i`, so incremental seeds
+/// step by `samples` to keep every image's seed distinct, wrapping around
+/// at `MAX_SEED`.
+pub fn resolve_seeds(
+    base_seed: Option<i64>,
+    count: usize,
+    samples: u32,
+    mode: SeedMode,
+) -> Vec<i64> {
+    let base = base_seed
+        .map(|s| s.rem_euclid(MAX_SEED + 1))
+        .unwrap_or_else(random_seed);
+
+    match mode {
+        SeedMode::Fixed => vec![base; count],
+        SeedMode::Incremental => (0..count as i64)
+            .map(|i| (base + i * i64::from(samples.max(1))) % (MAX_SEED + 1))
+            .collect(),
+        SeedMode::Random => {
+            let mut seen = HashSet::with_capacity(count);
+            let mut seeds = Vec::with_capacity(count);
+            while seeds.len() < count {
+                let seed = random_seed();
+                if seen.insert(seed) {
+                    seeds.push(seed);
+                }
+            }
+            seeds
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental_seeds_skip_over_samples() {
+        let seeds = resolve_seeds(Some(100), 2, 4, SeedMode::Incremental);
+        assert_eq!(seeds, vec![100, 104]);
+        let images: HashSet<i64> = seeds
+            .iter()
+            .flat_map(|&seed| (0..4).map(move |i| sample_seed(seed, i)))
+            .collect();
+        assert_eq!(images.len(), 8);
+
+        // Wraps around at the top of the seed range
+        assert_eq!(
+            resolve_seeds(Some(MAX_SEED - 1), 2, 4, SeedMode::Incremental),
+            vec![MAX_SEED - 1, 2]
+        );
+        assert_eq!(
+            resolve_seeds(Some(7), 3, 1, SeedMode::Incremental),
+            vec![7, 8, 9]
+        );
+        assert_eq!(resolve_seeds(Some(7), 2, 4, SeedMode::Fixed), vec![7, 7]);
+    }
+
+    fn snap(x: f64, y: f64) -> (f64, f64) {
+        let position = CharacterPosition { x, y }.snapped(true);
+        (position.x, position.y)
+    }
+
+    #[test]
+    fn grid_values_are_kept() {
+        for x in POSITION_GRID {
+            for y in POSITION_GRID {
+                assert_eq!(snap(x, y), (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn positions_snap_to_their_cell_center() {
+        assert_eq!(snap(0.0, 0.19), (0.1, 0.1));
+        assert_eq!(snap(0.2, 0.39), (0.3, 0.3));
+        assert_eq!(snap(0.45, 0.55), (0.5, 0.5));
+        assert_eq!(snap(0.61, 0.8), (0.7, 0.9));
+        assert_eq!(snap(1.0, 0.95), (0.9, 0.9));
+    }
+
+    #[test]
+    fn out_of_range_positions_are_clamped() {
+        assert_eq!(snap(-0.5, 1.5), (0.1, 0.9));
+        assert_eq!(snap(-100.0, 100.0), (0.1, 0.9));
+        assert_eq!(snap(f64::NAN, f64::INFINITY), (0.5, 0.5));
+    }
+
+    #[test]
+    fn without_coords_every_position_is_the_center() {
+        let position = CharacterPosition { x: 0.1, y: 0.9 }.snapped(false);
+        assert_eq!(position, DEFAULT_POSITION);
+    }
+}
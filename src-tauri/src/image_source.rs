@@ -0,0 +1,129 @@
+//! Image inputs and outputs that can be files instead of base64.
+//!
+//! Large images are expensive to pass between JS and Rust as base64 strings.
+//! Commands that take an `ImageSource` also accept `{ "path": "..." }`, which
+//! Rust reads itself, and commands with a `return_path` argument write their
+//! result there and return the path instead of the image.
+//!
+//! A plain string is still read as base64, so existing callers keep working.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "SourceRepr")]
+pub enum ImageSource {
+    /// Base64 data, with or without a `data:` URL prefix
+    Base64(String),
+    Path(String),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TaggedSource {
+    Base64(String),
+    Path(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SourceRepr {
+    Plain(String),
+    Tagged(TaggedSource),
+}
+
+impl From<SourceRepr> for ImageSource {
+    fn from(repr: SourceRepr) -> Self {
+        match repr {
+            SourceRepr::Plain(data) | SourceRepr::Tagged(TaggedSource::Base64(data)) => {
+                ImageSource::Base64(data)
+            }
+            SourceRepr::Tagged(TaggedSource::Path(path)) => ImageSource::Path(path),
+        }
+    }
+}
+
+fn io_error_message(path: &Path, e: &std::io::Error) -> String {
+    match e.kind() {
+        ErrorKind::NotFound => format!("파일을 찾을 수 없습니다: {}", path.display()),
+        ErrorKind::PermissionDenied => format!("파일 접근 권한이 없습니다: {}", path.display()),
+        _ => format!("파일 읽기 오류 ({}): {}", path.display(), e),
+    }
+}
+
+/// Reads an image file, telling a missing file, a permission problem and a
+/// folder apart.
+pub async fn read_image_file(path: &str) -> Result<Vec<u8>, String> {
+    let path = Path::new(path);
+    let meta = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| io_error_message(path, &e))?;
+    if meta.is_dir() {
+        return Err(format!("파일이 아니라 폴더입니다: {}", path.display()));
+    }
+    tokio::fs::read(path)
+        .await
+        .map_err(|e| io_error_message(path, &e))
+}
+
+impl ImageSource {
+    /// The raw image bytes.
+    pub async fn read(&self) -> Result<Vec<u8>, String> {
+        match self {
+            ImageSource::Base64(data) => crate::metadata::decode_image_base64(data),
+            ImageSource::Path(path) => read_image_file(path).await,
+        }
+    }
+
+    /// The image as base64, for APIs that want it that way. Base64 input is
+    /// passed through as-is.
+    pub async fn into_base64(self) -> Result<String, String> {
+        match self {
+            ImageSource::Base64(data) => Ok(data),
+            ImageSource::Path(path) => Ok(STANDARD.encode(read_image_file(&path).await?)),
+        }
+    }
+}
+
+/// Writes `bytes` to `path`, creating missing folders, and returns the path.
+pub async fn write_image_file(path: &str, bytes: &[u8]) -> Result<String, String> {
+    let path = PathBuf::from(path);
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("폴더 생성 오류: {}", e))?;
+    }
+    tokio::fs::write(&path, bytes)
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::PermissionDenied => format!("파일 쓰기 권한이 없습니다: {}", path.display()),
+            _ => format!("파일 저장 오류 ({}): {}", path.display(), e),
+        })?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// The result of an image command: written to `return_path` (returning the
+/// path) when one was given, otherwise base64.
+pub async fn deliver(bytes: Vec<u8>, return_path: Option<String>) -> Result<String, String> {
+    match return_path {
+        Some(path) => write_image_file(&path, &bytes).await,
+        None => Ok(STANDARD.encode(bytes)),
+    }
+}
+
+/// Like `deliver`, for results that are already base64: `(image_data, path)`
+/// with exactly one of them set.
+pub async fn deliver_base64(
+    data: String,
+    return_path: Option<String>,
+) -> Result<(Option<String>, Option<String>), String> {
+    match return_path {
+        Some(path) => {
+            let bytes = crate::metadata::decode_image_base64(&data)?;
+            Ok((None, Some(write_image_file(&path, &bytes).await?)))
+        }
+        None => Ok((Some(data), None)),
+    }
+}
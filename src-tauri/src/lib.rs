@@ -1,27 +1,134 @@
+mod api_error;
+mod augment;
+mod autosave;
+mod browser_allowlist;
+mod consistency;
+mod duplicates;
+mod export;
+mod folder_watch;
+mod generation;
+mod image_source;
+mod metadata;
+mod models;
+mod nai_settings;
+mod pipeline;
+mod postprocess;
+mod presets;
+mod prompt;
+mod prompt_convert;
+mod prompt_lint;
+mod queue;
+mod request_log;
+mod request_schema;
+mod request_stats;
+mod result_files;
+mod session_report;
+mod settings_migration;
+mod share;
+mod stream;
+mod tagger;
+mod uc_presets;
+mod url_import;
+mod wildcards;
+
+use api_error::{parse_api_error, NaiErrorKind};
+use export::{ExportItem, ExportMetadataMode, ExportZipResult};
+use generation::{GenerationParams, SeedMode};
+use image_source::ImageSource;
+use metadata::{ConvertTarget, MetadataEmbed, MetadataResult};
+use presets::{PresetImportResult, PresetMeta, PresetParams, PromptPreset};
 use serde::{Deserialize, Serialize};
+use stream::StreamEvent;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VerifyTokenResult {
     pub valid: bool,
     pub tier: Option<String>,
+    /// Raw tier number from the API, for tiers `tier` can't name
+    #[serde(default)]
+    pub tier_level: Option<i32>,
+    /// Whether the subscription includes free (Opus-style) image generation
+    #[serde(default)]
+    pub unlimited_generation: bool,
     pub error: Option<String>,
+    /// Machine-readable reason when NAI rejected the request (see `api_error`)
+    #[serde(default)]
+    pub error_code: Option<NaiErrorKind>,
+    /// Network unreachable. `valid` is false since nothing was checked;
+    /// `tier` is the one last seen for this token, if any
+    #[serde(default)]
+    pub offline: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnlasResult {
     pub success: bool,
     pub fixed: Option<i64>,
     pub purchased: Option<i64>,
     pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<NaiErrorKind>,
+    /// Network unreachable; the balance is the last one fetched online with
+    /// the same token
+    #[serde(default)]
+    pub offline: bool,
 }
 
 #[derive(Debug, Deserialize)]
 struct SubscriptionResponse {
     tier: Option<i32>,
+    #[serde(default)]
+    active: Option<bool>,
+    #[serde(default)]
+    perks: Option<SubscriptionPerks>,
     #[serde(rename = "trainingStepsLeft")]
     training_steps_left: Option<TrainingSteps>,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubscriptionPerks {
+    #[serde(rename = "unlimitedImageGeneration", default)]
+    unlimited_image_generation: bool,
+}
+
+/// NAI subscription tier numbers. Anything not listed here is a tier NAI
+/// added later and is reported as `unknown(n)` instead of being mistaken
+/// for Paper.
+fn tier_name(tier: i32) -> String {
+    match tier {
+        0 => "paper".to_string(),
+        1 => "tablet".to_string(),
+        2 => "scroll".to_string(),
+        3 => "opus".to_string(),
+        n => format!("unknown({})", n),
+    }
+}
+
+/// Kept separate from `tier_name` so free-generation checks don't depend on
+/// display names.
+fn is_opus(tier: i32) -> bool {
+    tier == 3
+}
+
+impl SubscriptionResponse {
+    /// Inactive or missing subscriptions count as Paper.
+    fn tier_number(&self) -> i32 {
+        match (self.tier, self.active) {
+            (_, Some(false)) | (None, _) => 0,
+            (Some(n), _) => n,
+        }
+    }
+
+    /// Opus-style free generation. The `perks` flag is preferred so a new
+    /// tier with the same perk is still recognized.
+    fn unlimited_generation(&self) -> bool {
+        match &self.perks {
+            Some(perks) => perks.unlimited_image_generation,
+            None => is_opus(self.tier_number()),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct TrainingSteps {
     #[serde(rename = "fixedTrainingStepsLeft")]
@@ -30,16 +137,417 @@ struct TrainingSteps {
     purchased_training_steps: Option<i64>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkErrorKind {
+    Timeout,
+    Connect,
+    Dns,
+    Tls,
+    Other,
+}
+
+impl NetworkErrorKind {
+    fn from_reqwest(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return NetworkErrorKind::Timeout;
+        }
+
+        // reqwest doesn't expose DNS/TLS failures directly, so look at the source chain
+        let mut chain = String::new();
+        let mut source: Option<&dyn std::error::Error> = Some(e);
+        while let Some(err) = source {
+            chain.push_str(&err.to_string().to_lowercase());
+            chain.push('\n');
+            source = err.source();
+        }
+
+        if chain.contains("dns error")
+            || chain.contains("failed to lookup address")
+            || chain.contains("name or service not known")
+            || chain.contains("no such host")
+        {
+            NetworkErrorKind::Dns
+        } else if chain.contains("certificate")
+            || chain.contains("tls")
+            || chain.contains("handshake")
+        {
+            NetworkErrorKind::Tls
+        } else if e.is_connect() {
+            NetworkErrorKind::Connect
+        } else {
+            NetworkErrorKind::Other
+        }
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            NetworkErrorKind::Timeout => "요청 시간 초과 - 네트워크 상태를 확인하고 다시 시도하세요",
+            NetworkErrorKind::Connect => "서버에 연결할 수 없음 - 프록시/방화벽 설정을 확인하세요",
+            NetworkErrorKind::Dns => "도메인을 찾을 수 없음 - 인터넷 연결과 DNS 설정을 확인하세요",
+            NetworkErrorKind::Tls => {
+                "TLS 인증서 오류 - 회사/학교 네트워크의 보안 프록시가 HTTPS를 가로채고 있는지 확인하세요"
+            }
+            NetworkErrorKind::Other => "네트워크 요청 실패",
+        }
+    }
+
+    /// DNS and connect failures mean there's no route to NAI at all, as
+    /// opposed to a request that reached the server and failed.
+    fn is_offline(self) -> bool {
+        matches!(self, NetworkErrorKind::Dns | NetworkErrorKind::Connect)
+    }
+}
+
+fn network_error_message(e: &reqwest::Error) -> String {
+    let kind = NetworkErrorKind::from_reqwest(e);
+    if kind.is_offline() {
+        return format!("오프라인: {}", kind.hint());
+    }
+    format!("네트워크 오류: {} ({})", kind.hint(), e)
+}
+
+fn is_offline_error(e: &reqwest::Error) -> bool {
+    NetworkErrorKind::from_reqwest(e).is_offline()
+}
+
+/// Short connect timeout so a dead connection fails in seconds instead of
+/// hanging on the OS default. Generation itself can still take a while, so
+/// only the connect phase is bounded.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Sent with every request so NAI (and Hugging Face) can tell this client
+/// apart from scripts.
+const DEFAULT_USER_AGENT: &str = concat!(
+    "NAIS/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/sunanakgo/NAIS2)"
+);
+
+/// The app-wide HTTP client, created once in `setup`. Sharing it keeps
+/// connections (and TLS sessions) alive between requests, and keeps the
+/// timeouts and default headers in one place. Only `set_user_agent`
+/// replaces it.
+pub struct AppHttpClient(pub Mutex<reqwest::Client>);
+
+fn build_http_client(user_agent: &str) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .user_agent(user_agent)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .gzip(true)
+        .brotli(true)
+        .build()
+}
+
+/// Resolves host names for `DownloadHttpClient`, leaving out addresses on
+/// this machine or the local network (`url_import::is_public_ip`). Checking
+/// at connect time also covers redirects and names that resolve to
+/// something else on a second lookup.
+struct PublicOnlyResolver;
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<std::net::SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| url_import::is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(url_import::INTERNAL_ADDRESS_ERROR.into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+const MAX_REDIRECTS: usize = 10;
+
+/// Client for images at arbitrary URLs (`import_from_url`). Unlike
+/// `AppHttpClient` it never connects to loopback, private or link-local
+/// addresses, also not after a redirect. Replaced along with it by
+/// `set_user_agent`.
+pub struct DownloadHttpClient(pub Mutex<reqwest::Client>);
+
+fn build_download_client(user_agent: &str) -> reqwest::Result<reqwest::Client> {
+    let redirects = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Err(e) = url_import::check_public_host(attempt.url()) {
+            attempt.error(e)
+        } else {
+            attempt.follow()
+        }
+    });
+    reqwest::Client::builder()
+        .user_agent(user_agent)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .gzip(true)
+        .brotli(true)
+        .redirect(redirects)
+        .dns_resolver(Arc::new(PublicOnlyResolver))
+        .build()
+}
+
+fn download_client(app: &AppHandle) -> reqwest::Client {
+    let state = app.state::<DownloadHttpClient>();
+    let client = state.0.lock().unwrap_or_else(|e| e.into_inner());
+    client.clone()
+}
+
+/// The managed `AppHttpClient`. Async commands that return a plain result
+/// struct can't borrow `State`, so they go through the handle instead.
+/// Cloning a `reqwest::Client` is cheap.
+fn http_client(app: &AppHandle) -> reqwest::Client {
+    let state = app.state::<AppHttpClient>();
+    let client = state.0.lock().unwrap_or_else(|e| e.into_inner());
+    client.clone()
+}
+
+/// Rebuilds the shared client with `user_agent`; an empty string restores
+/// the default. Requests already in flight finish on the old client.
+#[tauri::command]
+fn set_user_agent(app: AppHandle, user_agent: String) -> Result<(), String> {
+    let user_agent = match user_agent.trim() {
+        "" => DEFAULT_USER_AGENT,
+        ua => ua,
+    };
+    let client =
+        build_http_client(user_agent).map_err(|e| format!("HTTP 클라이언트 생성 오류: {}", e))?;
+    let downloads = build_download_client(user_agent)
+        .map_err(|e| format!("HTTP 클라이언트 생성 오류: {}", e))?;
+    let state = app.state::<AppHttpClient>();
+    *state.0.lock().unwrap_or_else(|e| e.into_inner()) = client;
+    let state = app.state::<DownloadHttpClient>();
+    *state.0.lock().unwrap_or_else(|e| e.into_inner()) = downloads;
+    Ok(())
+}
+
+/// Bodies at least this large (or of unknown size) are read chunk by chunk
+/// with `download_progress` events instead of in a single `bytes()` call.
+const STREAM_THRESHOLD: u64 = 1024 * 1024;
+const PROGRESS_INTERVAL: u64 = 256 * 1024;
+/// Most a Content-Length header may reserve up front; the buffer grows past
+/// it as data actually arrives.
+const MAX_PREALLOCATE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize)]
+struct DownloadProgress {
+    received: u64,
+    /// `None` when the server sent no Content-Length (or the body is
+    /// compressed, in which case the length isn't the decoded size)
+    total: Option<u64>,
+}
+
+/// Reads a response body. Small bodies with a known length are read in one
+/// go; everything else is streamed into a buffer sized up front from
+/// Content-Length (at most `MAX_PREALLOCATE`), emitting `download_progress` roughly every 256 KiB.
+async fn read_body(app: &AppHandle, mut response: reqwest::Response) -> reqwest::Result<Vec<u8>> {
+    let log_id = request_log::id_of(&response);
+    let total = response.content_length();
+    if total.is_some_and(|len| len < STREAM_THRESHOLD) {
+        let body = response.bytes().await?.to_vec();
+        request_log::body(app, log_id, &body);
+        return Ok(body);
+    }
+
+    let capacity = total.unwrap_or(STREAM_THRESHOLD).min(MAX_PREALLOCATE);
+    let mut body = Vec::with_capacity(capacity as usize);
+    let mut last_emit = 0u64;
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        let received = body.len() as u64;
+        if received - last_emit >= PROGRESS_INTERVAL {
+            last_emit = received;
+            let _ = app.emit("download_progress", DownloadProgress { received, total });
+        }
+    }
+
+    let _ = app.emit(
+        "download_progress",
+        DownloadProgress {
+            received: body.len() as u64,
+            total: total.or(Some(body.len() as u64)),
+        },
+    );
+    request_log::body(app, log_id, &body);
+    Ok(body)
+}
+
+/// Any HTTP response, even an error status, means NAI is reachable.
+#[tauri::command]
+async fn check_connectivity(app: AppHandle) -> bool {
+    http_client(&app)
+        .head("https://image.novelai.net")
+        .timeout(CONNECTIVITY_TIMEOUT)
+        .send()
+        .await
+        .is_ok()
+}
+
+// Set once a NAI request comes back 401 so the frontend is only told once;
+// cleared when a new token is saved.
+static TOKEN_INVALIDATED: AtomicBool = AtomicBool::new(false);
+
+/// Shared status check for every NAI response. A 401 means the token expired
+/// or was revoked: drop the cached subscription and emit `token_invalidated`
+/// (once per token) so the frontend can ask the user to log in again.
+fn check_auth_status(app: &AppHandle, status: reqwest::StatusCode) {
+    if status != reqwest::StatusCode::UNAUTHORIZED {
+        return;
+    }
+    if TOKEN_INVALIDATED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    log::warn!("NovelAI returned 401, token invalidated");
+    if let Ok(store) = app.store(AUTH_STORE_FILE) {
+        store.delete(AUTH_STORE_SUBSCRIPTION_KEY);
+        if let Err(e) = store.save() {
+            log::warn!("Failed to clear subscription cache: {}", e);
+        }
+    }
+    if let Err(e) = app.emit("token_invalidated", ()) {
+        log::warn!("Failed to emit token_invalidated: {}", e);
+    }
+}
+
+// Last successful subscription lookup, served back when offline
+const AUTH_STORE_SUBSCRIPTION_KEY: &str = "subscription_cache";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedSubscription {
+    /// `token_hash` of the token the rest was fetched with
+    #[serde(default)]
+    token_hash: Option<String>,
+    tier: Option<String>,
+    #[serde(default)]
+    tier_level: Option<i32>,
+    #[serde(default)]
+    unlimited_generation: bool,
+    fixed: Option<i64>,
+    purchased: Option<i64>,
+}
+
+fn load_cached_subscription(app: &AppHandle) -> Option<CachedSubscription> {
+    let store = app.store(AUTH_STORE_FILE).ok()?;
+    store
+        .get(AUTH_STORE_SUBSCRIPTION_KEY)
+        .and_then(|v| serde_json::from_value(v).ok())
+}
+
+/// Identifies a token in the cache without storing it.
+fn token_hash(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(normalize_token(token).as_bytes()))
+}
+
+/// The cache, if it was filled with `token`.
+fn cached_subscription_for(app: &AppHandle, token: &str) -> Option<CachedSubscription> {
+    let hash = token_hash(token);
+    load_cached_subscription(app).filter(|c| c.token_hash.as_deref() == Some(hash.as_str()))
+}
+
+/// Updates the cache for `token`; a cache filled with another token is
+/// started over.
+fn update_cached_subscription(
+    app: &AppHandle,
+    token: &str,
+    update: impl FnOnce(&mut CachedSubscription),
+) {
+    let Ok(store) = app.store(AUTH_STORE_FILE) else {
+        return;
+    };
+    let mut cached = cached_subscription_for(app, token).unwrap_or_else(|| CachedSubscription {
+        token_hash: Some(token_hash(token)),
+        ..Default::default()
+    });
+    update(&mut cached);
+    match serde_json::to_value(&cached) {
+        Ok(value) => store.set(AUTH_STORE_SUBSCRIPTION_KEY, value),
+        Err(e) => {
+            log::warn!("Failed to serialize subscription cache: {}", e);
+            return;
+        }
+    }
+    if let Err(e) = store.save() {
+        log::warn!("Failed to save subscription cache: {}", e);
+    }
+}
+
+// Shorter than any NAI token; persistent tokens are `pst-` plus 64 characters
+const MIN_TOKEN_LENGTH: usize = 32;
+
+/// Cleans up a pasted token: line breaks, surrounding whitespace and quotes
+/// and a `Bearer ` prefix are removed, in any combination.
+fn normalize_token(raw: &str) -> String {
+    let mut token: String = raw
+        .chars()
+        .filter(|c| !matches!(c, '\r' | '\n' | '\t'))
+        .collect();
+    loop {
+        let trimmed = token.trim().trim_matches(|c| matches!(c, '"' | '\'' | '`'));
+        let stripped = match trimmed.get(..7) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("bearer ") => &trimmed[7..],
+            _ => trimmed,
+        };
+        if stripped.len() == token.len() {
+            return token;
+        }
+        token = stripped.to_string();
+    }
+}
+
+/// Rejects tokens that can't be valid before they are sent anywhere.
+/// Expects a token that went through `normalize_token`.
+fn check_token_format(token: &str) -> Result<(), String> {
+    if token.is_empty() {
+        return Err("토큰이 비어 있습니다".to_string());
+    }
+    if let Some(c) = token
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(if c.is_whitespace() {
+            "토큰 중간에 공백이 있습니다. 토큰만 정확히 복사했는지 확인하세요".to_string()
+        } else {
+            format!("토큰에 사용할 수 없는 문자가 있습니다: '{}'", c)
+        });
+    }
+    if token.len() < MIN_TOKEN_LENGTH {
+        return Err(format!(
+            "토큰이 너무 짧습니다 ({}자). 전체를 복사했는지 확인하세요",
+            token.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Checks `token` against the subscription endpoint. The token is usually
+/// one being entered, not the saved one, so a 401 only fails the check and
+/// doesn't emit `token_invalidated`.
 #[tauri::command]
-async fn verify_token(token: String) -> VerifyTokenResult {
-    let client = reqwest::Client::new();
+async fn verify_token(app: AppHandle, token: String) -> VerifyTokenResult {
+    let token = normalize_token(&token);
+    if let Err(e) = check_token_format(&token) {
+        return VerifyTokenResult {
+            valid: false,
+            tier: None,
+            tier_level: None,
+            unlimited_generation: false,
+            error: Some(e),
+            offline: false,
+            error_code: None,
+        };
+    }
+    let client = http_client(&app);
 
-    let result = client
+    let request = client
         .get("https://api.novelai.net/user/subscription")
-        .header("Authorization", format!("Bearer {}", token.trim()))
-        .header("Content-Type", "application/json")
-        .send()
-        .await;
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Content-Type", "application/json");
+    let result = send_logged(&app, &client, request).await;
 
     match result {
         Ok(response) => {
@@ -47,59 +555,106 @@ async fn verify_token(token: String) -> VerifyTokenResult {
             if status.is_success() {
                 match response.json::<SubscriptionResponse>().await {
                     Ok(data) => {
-                        let tier_name = match data.tier {
-                            Some(3) => Some("opus".to_string()),
-                            Some(2) => Some("scroll".to_string()),
-                            Some(1) => Some("tablet".to_string()),
-                            _ => Some("paper".to_string()),
-                        };
+                        let tier_level = data.tier_number();
+                        let tier_name = Some(tier_name(tier_level));
+                        let unlimited_generation = data.unlimited_generation();
+                        update_cached_subscription(&app, &token, |c| {
+                            c.tier = tier_name.clone();
+                            c.tier_level = Some(tier_level);
+                            c.unlimited_generation = unlimited_generation;
+                        });
                         VerifyTokenResult {
                             valid: true,
                             tier: tier_name,
+                            tier_level: Some(tier_level),
+                            unlimited_generation,
                             error: None,
+                            offline: false,
+                            error_code: None,
                         }
                     }
                     Err(e) => VerifyTokenResult {
                         valid: false,
                         tier: None,
+                        tier_level: None,
+                        unlimited_generation: false,
                         error: Some(format!("JSON 파싱 오류: {}", e)),
+                        offline: false,
+                        error_code: None,
                     },
                 }
             } else if status.as_u16() == 401 {
                 VerifyTokenResult {
                     valid: false,
                     tier: None,
+                    tier_level: None,
+                    unlimited_generation: false,
                     error: Some("유효하지 않은 API 토큰".to_string()),
+                    offline: false,
+                    error_code: Some(NaiErrorKind::Unauthorized),
                 }
             } else {
+                let body = error_text(&app, response).await;
+                let error = parse_api_error(status.as_u16(), &body);
                 VerifyTokenResult {
                     valid: false,
                     tier: None,
-                    error: Some(format!("API 오류: {}", status.as_u16())),
+                    tier_level: None,
+                    unlimited_generation: false,
+                    error: Some(error.to_string()),
+                    offline: false,
+                    error_code: Some(error.kind),
                 }
             }
         }
-        Err(e) => VerifyTokenResult {
-            valid: false,
-            tier: None,
-            error: Some(format!("네트워크 오류: {}", e)),
-        },
+        Err(e) => {
+            let offline = is_offline_error(&e);
+            // Nothing was verified: the token stays unconfirmed, and only the
+            // tier last seen for this same token is shown
+            match cached_subscription_for(&app, &token).filter(|c| offline && c.tier.is_some()) {
+                Some(cached) => VerifyTokenResult {
+                    valid: false,
+                    tier: cached.tier,
+                    tier_level: cached.tier_level,
+                    unlimited_generation: cached.unlimited_generation,
+                    error: Some("오프라인(캐시됨)".to_string()),
+                    offline: true,
+                    error_code: Some(NaiErrorKind::Offline),
+                },
+                None => VerifyTokenResult {
+                    valid: false,
+                    tier: None,
+                    tier_level: None,
+                    unlimited_generation: false,
+                    error: Some(network_error_message(&e)),
+                    offline,
+                    error_code: offline.then_some(NaiErrorKind::Offline),
+                },
+            }
+        }
     }
 }
 
 #[tauri::command]
-async fn get_anlas_balance(token: String) -> AnlasResult {
-    let client = reqwest::Client::new();
+async fn get_anlas_balance(app: AppHandle, token: String) -> AnlasResult {
+    fetch_anlas_balance(&app, &token).await
+}
 
-    let result = client
+async fn fetch_anlas_balance(app: &AppHandle, token: &str) -> AnlasResult {
+    let client = http_client(app);
+
+    let request = client
         .get("https://api.novelai.net/user/subscription")
-        .header("Authorization", format!("Bearer {}", token.trim()))
-        .header("Content-Type", "application/json")
-        .send()
-        .await;
+        .header(
+            "Authorization",
+            format!("Bearer {}", normalize_token(token)),
+        )
+        .header("Content-Type", "application/json");
+    let result = send_logged(app, &client, request).await;
 
     match result {
         Ok(response) => {
+            check_auth_status(app, response.status());
             if response.status().is_success() {
                 match response.json::<SubscriptionResponse>().await {
                     Ok(data) => {
@@ -111,11 +666,17 @@ async fn get_anlas_balance(token: String) -> AnlasResult {
                             .training_steps_left
                             .as_ref()
                             .and_then(|t| t.purchased_training_steps);
+                        update_cached_subscription(app, token, |c| {
+                            c.fixed = fixed;
+                            c.purchased = purchased;
+                        });
                         AnlasResult {
                             success: true,
                             fixed,
                             purchased,
                             error: None,
+                            offline: false,
+                            error_code: None,
                         }
                     }
                     Err(e) => AnlasResult {
@@ -123,219 +684,4220 @@ async fn get_anlas_balance(token: String) -> AnlasResult {
                         fixed: None,
                         purchased: None,
                         error: Some(format!("JSON 파싱 오류: {}", e)),
+                        offline: false,
+                        error_code: None,
                     },
                 }
             } else {
+                let status = response.status().as_u16();
+                let body = error_text(app, response).await;
+                let error = parse_api_error(status, &body);
                 AnlasResult {
                     success: false,
                     fixed: None,
                     purchased: None,
-                    error: Some(format!("API 오류: {}", response.status().as_u16())),
+                    error: Some(error.to_string()),
+                    offline: false,
+                    error_code: Some(error.kind),
                 }
             }
         }
-        Err(e) => AnlasResult {
-            success: false,
-            fixed: None,
-            purchased: None,
-            error: Some(format!("네트워크 오류: {}", e)),
-        },
+        Err(e) => {
+            let offline = is_offline_error(&e);
+            let cached = cached_subscription_for(app, token)
+                .filter(|c| offline && (c.fixed.is_some() || c.purchased.is_some()));
+            match cached {
+                Some(cached) => AnlasResult {
+                    success: true,
+                    fixed: cached.fixed,
+                    purchased: cached.purchased,
+                    error: Some("오프라인(캐시됨)".to_string()),
+                    offline: true,
+                    error_code: None,
+                },
+                None => AnlasResult {
+                    success: false,
+                    fixed: None,
+                    purchased: None,
+                    error: Some(network_error_message(&e)),
+                    offline,
+                    error_code: None,
+                },
+            }
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct UpscaleResult {
-    pub success: bool,
-    pub image_data: Option<String>,
-    pub error: Option<String>,
+// NAI's balance only changes when something is generated, so polling faster
+// than this just burns API calls
+const MIN_ANLAS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_ANLAS_POLL_INTERVAL: Duration = Duration::from_secs(600);
+// Applied while the main window isn't focused
+const BACKGROUND_POLL_FACTOR: u32 = 4;
+
+// Bumped to stop the running poll loop; each loop exits once it sees a
+// generation other than the one it started with.
+static ANLAS_POLL_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Payload of `anlas_changed`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnlasBalance {
+    pub fixed: Option<i64>,
+    pub purchased: Option<i64>,
 }
 
-#[derive(Debug, Serialize)]
-struct UpscalePayload {
-    image: String,
-    width: i32,
-    height: i32,
-    scale: i32,
+fn stop_anlas_poll_loop() {
+    ANLAS_POLL_GENERATION.fetch_add(1, Ordering::SeqCst);
 }
 
-#[tauri::command]
-async fn upscale_image(
-    token: String,
-    image: String,
-    width: i32,
-    height: i32,
-    scale: i32,
-) -> UpscaleResult {
-    let client = reqwest::Client::new();
+fn main_window_focused(app: &AppHandle) -> bool {
+    app.get_webview_window("main")
+        .and_then(|w| w.is_focused().ok())
+        .unwrap_or(true)
+}
 
-    let payload = UpscalePayload {
-        image,
-        width,
-        height,
-        scale,
-    };
+/// Polls `token`'s balance every `interval_secs` (at least 30) and emits
+/// `anlas_changed` only when it differs from the last one seen. The
+/// interval doubles after each failed lookup (up to 10 minutes) and is
+/// stretched while the app is in the background. Polling stops on its own
+/// when the token is rejected, or when a token is saved or deleted; the
+/// frontend then starts it again with the new token.
+#[tauri::command]
+fn start_anlas_polling(app: AppHandle, token: String, interval_secs: u64) {
+    let token = normalize_token(&token);
+    let generation = ANLAS_POLL_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let base = Duration::from_secs(interval_secs).max(MIN_ANLAS_POLL_INTERVAL);
 
-    let result = client
-        .post("https://api.novelai.net/ai/upscale")
-        .header("Authorization", format!("Bearer {}", token.trim()))
-        .header("Content-Type", "application/json")
-        .json(&payload)
-        .send()
-        .await;
+    tauri::async_runtime::spawn(async move {
+        let mut last: Option<AnlasBalance> = None;
+        let mut failures = 0u32;
+        while ANLAS_POLL_GENERATION.load(Ordering::SeqCst) == generation {
+            let result = fetch_anlas_balance(&app, &token).await;
+            if result.error_code == Some(NaiErrorKind::Unauthorized) {
+                break;
+            }
 
-    match result {
-        Ok(response) => {
-            if response.status().is_success() {
-                // Response is a ZIP file containing the image
-                match response.bytes().await {
-                    Ok(bytes) => {
-                        // Use zip crate to extract
-                        match extract_image_from_zip(&bytes) {
-                            Ok(base64_image) => UpscaleResult {
-                                success: true,
-                                image_data: Some(base64_image),
-                                error: None,
-                            },
-                            Err(e) => UpscaleResult {
-                                success: false,
-                                image_data: None,
-                                error: Some(format!("ZIP 처리 오류: {}", e)),
-                            },
-                        }
-                    }
-                    Err(e) => UpscaleResult {
-                        success: false,
-                        image_data: None,
-                        error: Some(format!("응답 읽기 오류: {}", e)),
-                    },
+            if result.success && !result.offline {
+                failures = 0;
+                let balance = AnlasBalance {
+                    fixed: result.fixed,
+                    purchased: result.purchased,
+                };
+                if last.as_ref() != Some(&balance) {
+                    let _ = app.emit("anlas_changed", balance.clone());
+                    last = Some(balance);
                 }
             } else {
-                let status = response.status().as_u16();
-                let error_text = response.text().await.unwrap_or_default();
-                UpscaleResult {
-                    success: false,
-                    image_data: None,
-                    error: Some(format!("API 오류 {}: {}", status, error_text)),
-                }
+                failures = failures.saturating_add(1);
+            }
+
+            let mut interval = base.saturating_mul(1 << failures.min(5));
+            if !main_window_focused(&app) {
+                interval = interval.saturating_mul(BACKGROUND_POLL_FACTOR);
             }
+            tokio::time::sleep(interval.min(MAX_ANLAS_POLL_INTERVAL)).await;
         }
-        Err(e) => UpscaleResult {
-            success: false,
-            image_data: None,
-            error: Some(format!("네트워크 오류: {}", e)),
-        },
-    }
+        log::debug!("Anlas polling stopped");
+    });
 }
 
-fn extract_image_from_zip(zip_bytes: &[u8]) -> Result<String, String> {
-    use base64::{engine::general_purpose::STANDARD, Engine as _};
-    use std::io::{Cursor, Read};
-    use zip::ZipArchive;
+#[tauri::command]
+fn stop_anlas_polling() {
+    stop_anlas_poll_loop();
+}
 
-    let cursor = Cursor::new(zip_bytes);
-    let mut archive = ZipArchive::new(cursor).map_err(|e| e.to_string())?;
+/// The balance last read for `token` (every lookup caches it), so paid
+/// requests don't need a lookup of their own beforehand.
+fn last_known_anlas(app: &AppHandle, token: &str) -> Option<i64> {
+    cached_subscription_for(app, token)
+        .filter(|c| c.fixed.is_some() || c.purchased.is_some())
+        .map(|c| c.fixed.unwrap_or(0) + c.purchased.unwrap_or(0))
+}
 
-    if archive.is_empty() {
-        return Err("ZIP 파일이 비어있습니다".to_string());
+/// Only called after a request failed with a server error: reads the
+/// balance live and compares it with `before` (`last_known_anlas` from
+/// before the request) less `spent_since` for requests known to have
+/// succeeded in between. This is a best guess: anything else that spent
+/// Anlas meanwhile (the site, another device) also reads as charged. `None`
+/// when either balance is unknown.
+async fn check_anlas_charged(
+    app: &AppHandle,
+    token: &str,
+    before: Option<i64>,
+    spent_since: i64,
+) -> Option<bool> {
+    let expected = before? - spent_since;
+    let result = fetch_anlas_balance(app, token).await;
+    if !result.success || result.offline {
+        return None;
+    }
+    let after = result.fixed.unwrap_or(0) + result.purchased.unwrap_or(0);
+    let charged = after < expected;
+    if charged {
+        log::warn!(
+            "Request failed with a server error but about {} Anlas were charged",
+            expected - after
+        );
     }
+    Some(charged)
+}
+
+fn has_unlimited_generation(app: &AppHandle) -> bool {
+    load_cached_subscription(app).is_some_and(|c| c.unlimited_generation)
+}
 
-    let mut file = archive.by_index(0).map_err(|e| e.to_string())?;
-    let mut contents = Vec::new();
-    file.read_to_end(&mut contents).map_err(|e| e.to_string())?;
+// Background re-check of the frontend's token, so an expired subscription or
+// a revoked token shows up before the next generation fails with a 401
+const DEFAULT_TOKEN_CHECK_MINUTES: u64 = 15;
+const AUTH_STORE_CHECK_INTERVAL_KEY: &str = "token_check_interval_minutes";
+// 0 turns the worker off
+static TOKEN_CHECK_MINUTES: AtomicU64 = AtomicU64::new(DEFAULT_TOKEN_CHECK_MINUTES);
 
-    Ok(STANDARD.encode(&contents))
+fn token_check_wakeup() -> &'static tokio::sync::Notify {
+    static WAKEUP: std::sync::OnceLock<tokio::sync::Notify> = std::sync::OnceLock::new();
+    WAKEUP.get_or_init(tokio::sync::Notify::new)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RemoveBackgroundResult {
-    pub success: bool,
-    pub image_data: Option<String>,
-    pub error: Option<String>,
+/// The token the worker checks, as last handed over by the frontend. Only
+/// kept in memory.
+fn checked_token() -> &'static Mutex<Option<String>> {
+    static TOKEN: std::sync::OnceLock<Mutex<Option<String>>> = std::sync::OnceLock::new();
+    TOKEN.get_or_init(|| Mutex::new(None))
 }
 
-#[tauri::command]
-async fn remove_background(image_base64: String) -> RemoveBackgroundResult {
-    use base64::{engine::general_purpose::STANDARD, Engine as _};
+/// Started once from `setup`. Every `TOKEN_CHECK_MINUTES` the token passed
+/// to `set_token_check_interval` is verified quietly and the result is
+/// emitted as `subscription-updated`. A 401 means this saved token was
+/// revoked and goes through `check_auth_status`, which emits
+/// `token_invalidated`. Network failures
+/// are skipped until the next round, and nothing is sent while there is no
+/// token or the current one is already known to be bad.
+fn spawn_token_check_worker(app: AppHandle) {
+    if let Ok(store) = app.store(AUTH_STORE_FILE) {
+        if let Some(minutes) = store
+            .get(AUTH_STORE_CHECK_INTERVAL_KEY)
+            .and_then(|v| v.as_u64())
+        {
+            TOKEN_CHECK_MINUTES.store(minutes, Ordering::SeqCst);
+        }
+    }
 
-    // Decode base64 image
-    let image_bytes = match STANDARD.decode(&image_base64) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            return RemoveBackgroundResult {
-                success: false,
-                image_data: None,
-                error: Some(format!("Base64 디코딩 오류: {}", e)),
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let minutes = TOKEN_CHECK_MINUTES.load(Ordering::SeqCst);
+            // Disabled: wait until an interval is set
+            if minutes == 0 {
+                token_check_wakeup().notified().await;
+                continue;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(minutes.saturating_mul(60))) => {}
+                // Interval changed; start over with the new one
+                _ = token_check_wakeup().notified() => continue,
+            }
+
+            if TOKEN_INVALIDATED.load(Ordering::SeqCst) {
+                continue;
+            }
+            let token = checked_token().lock().ok().and_then(|t| t.clone());
+            let Some(token) = token else {
+                continue;
+            };
+            let result = verify_token(app.clone(), token).await;
+            if result.valid && !result.offline {
+                let _ = app.emit("subscription-updated", result);
+            } else if result.error_code == Some(NaiErrorKind::Unauthorized) {
+                check_auth_status(&app, reqwest::StatusCode::UNAUTHORIZED);
+            } else if result.error_code.is_none() {
+                log::debug!("Background token check skipped: {:?}", result.error);
             }
         }
-    };
+    });
+}
+
+/// Sets the token the background worker re-checks and how often, in
+/// minutes; 0 turns it off. Takes effect immediately. The interval is kept
+/// across restarts, the token isn't; `delete_token` forgets it.
+#[tauri::command]
+fn set_token_check_interval(app: AppHandle, token: String, minutes: u64) -> Result<(), String> {
+    let token = normalize_token(&token);
+    if let Ok(mut checked) = checked_token().lock() {
+        *checked = Some(token).filter(|t| !t.is_empty());
+    }
+    TOKEN_CHECK_MINUTES.store(minutes, Ordering::SeqCst);
+    token_check_wakeup().notify_one();
+
+    let store = app.store(AUTH_STORE_FILE).map_err(|e| e.to_string())?;
+    store.set(AUTH_STORE_CHECK_INTERVAL_KEY, minutes);
+    store.save().map_err(|e| e.to_string())
+}
+
+// Token storage: OS keyring first, plaintext store only as a fallback
+const KEYRING_SERVICE: &str = "com.sunakgo.nais2";
+const KEYRING_TOKEN_USER: &str = "novelai-token";
+const AUTH_STORE_FILE: &str = "auth.json";
+const AUTH_STORE_TOKEN_KEY: &str = "token";
+
+fn token_keyring_entry() -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_TOKEN_USER).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn save_token(app: AppHandle, token: String) -> Result<(), String> {
+    let token = normalize_token(&token);
+    check_token_format(&token)?;
+    let store = app.store(AUTH_STORE_FILE).map_err(|e| e.to_string())?;
+
+    match token_keyring_entry()
+        .and_then(|entry| entry.set_password(&token).map_err(|e| e.to_string()))
+    {
+        Ok(()) => {
+            // Never leave a plaintext copy behind once the keyring has it
+            store.delete(AUTH_STORE_TOKEN_KEY);
+        }
+        Err(e) => {
+            log::warn!(
+                "Keyring unavailable, storing token in plaintext store: {}",
+                e
+            );
+            store.set(AUTH_STORE_TOKEN_KEY, token);
+        }
+    }
+    // The cache belongs to whichever token was verified last
+    store.delete(AUTH_STORE_SUBSCRIPTION_KEY);
+    TOKEN_INVALIDATED.store(false, Ordering::SeqCst);
+    stop_anlas_poll_loop();
+
+    store.save().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn load_token(app: AppHandle) -> Result<Option<String>, String> {
+    match token_keyring_entry().and_then(|entry| entry.get_password().map_err(|e| e.to_string())) {
+        // Tokens saved before normalization may still carry a prefix or quotes
+        Ok(token) => return Ok(Some(normalize_token(&token))),
+        Err(e) => log::debug!("Keyring lookup failed, checking plaintext store: {}", e),
+    }
+
+    let store = app.store(AUTH_STORE_FILE).map_err(|e| e.to_string())?;
+    Ok(store
+        .get(AUTH_STORE_TOKEN_KEY)
+        .and_then(|v| v.as_str().map(normalize_token))
+        .filter(|s| !s.is_empty()))
+}
+
+#[tauri::command]
+async fn delete_token(app: AppHandle) -> Result<(), String> {
+    if let Ok(entry) = token_keyring_entry() {
+        match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => log::warn!("Failed to delete token from keyring: {}", e),
+        }
+    }
+
+    if let Ok(mut checked) = checked_token().lock() {
+        *checked = None;
+    }
+    let store = app.store(AUTH_STORE_FILE).map_err(|e| e.to_string())?;
+    store.delete(AUTH_STORE_TOKEN_KEY);
+    store.delete(AUTH_STORE_SUBSCRIPTION_KEY);
+    stop_anlas_poll_loop();
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Moves a token `save_token` had to keep in the plaintext store (the
+/// keyring was unavailable at the time) into the OS keyring. If it still is,
+/// the token is kept and this is retried on the next launch.
+///
+/// Tokens older versions kept in the frontend's `nais2-auth` storage are
+/// moved by the frontend through `save_token` when it loads.
+fn migrate_token_storage(app: &AppHandle) -> Result<(), String> {
+    let store = app.store(AUTH_STORE_FILE).map_err(|e| e.to_string())?;
+
+    let plaintext = store
+        .get(AUTH_STORE_TOKEN_KEY)
+        .and_then(|v| v.as_str().map(|s| s.trim().to_string()))
+        .filter(|s| !s.is_empty());
+
+    let Some(token) = plaintext else {
+        return Ok(());
+    };
+    let result = token_keyring_entry()
+        .and_then(|entry| entry.set_password(&token).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        log::warn!(
+            "Keyring unavailable, keeping plaintext token in {}: {}",
+            AUTH_STORE_FILE,
+            e
+        );
+        return Ok(());
+    }
+    store.delete(AUTH_STORE_TOKEN_KEY);
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Copies the store files as they are into
+/// `backups/settings-v{version}-{time}` in the app data folder.
+fn backup_store_files(app: &AppHandle, version: u32) -> Result<std::path::PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    let backup = dir.join("backups").join(format!(
+        "settings-v{}-{}",
+        version,
+        chrono::Local::now().format("%Y%m%d_%H%M%S")
+    ));
+    std::fs::create_dir_all(&backup).map_err(|e| format!("백업 폴더 생성 실패: {}", e))?;
+    for file in settings_migration::FILES {
+        let source = dir.join(file);
+        if source.is_file() {
+            std::fs::copy(&source, backup.join(file))
+                .map_err(|e| format!("설정 백업 실패 ({}): {}", file, e))?;
+        }
+    }
+    Ok(backup)
+}
+
+/// Brings the store files up to the current schema (see
+/// `settings_migration`). Runs at startup before anything reads them; the
+/// files are backed up first, and a schema newer than this build is left
+/// untouched.
+fn migrate_settings(app: &AppHandle) -> Result<(), String> {
+    let mut stores = settings_migration::Stores::new();
+    for file in settings_migration::FILES {
+        let store = app.store(file).map_err(|e| e.to_string())?;
+        if !store.is_empty() {
+            stores.insert(file.to_string(), store.entries().into_iter().collect());
+        }
+    }
+    if !settings_migration::needs_migration(&stores)? {
+        return Ok(());
+    }
+
+    let from = settings_migration::stored_version(&stores);
+    let before = stores.clone();
+    if !before.is_empty() {
+        let backup = backup_store_files(app, from)?;
+        log::info!("Backed up settings to {}", backup.display());
+    }
+    let applied = settings_migration::migrate(&mut stores)?;
+    for (file, entries) in &stores {
+        if before.get(file) == Some(entries) {
+            continue;
+        }
+        let store = app.store(file.as_str()).map_err(|e| e.to_string())?;
+        store.clear();
+        for (key, value) in entries {
+            store.set(key.clone(), value.clone());
+        }
+        store.save().map_err(|e| e.to_string())?;
+    }
+    for step in applied {
+        log::info!("Settings migration: {}", step);
+    }
+    log::info!(
+        "Settings schema {} -> {}",
+        from,
+        settings_migration::CURRENT_VERSION
+    );
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpscaleResult {
+    pub success: bool,
+    pub image_data: Option<String>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<NaiErrorKind>,
+    /// After a server error: a best guess at whether NAI still took Anlas
+    /// for the failed request (see `check_anlas_charged`). `None` when it
+    /// wasn't checked or the balance couldn't be read.
+    #[serde(default)]
+    pub anlas_charged: Option<bool>,
+    /// Set when the given width/height didn't match the image
+    #[serde(default)]
+    pub warning: Option<String>,
+    /// Where the result was written when `return_path` was given
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Size of the upscaled image, so the frontend can lay it out early
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct UpscalePayload {
+    image: String,
+    width: i32,
+    height: i32,
+    scale: i32,
+}
+
+/// Upscale factors NAI accepts; anything else is rejected with a 400.
+const SUPPORTED_UPSCALE_SCALES: [i32; 2] = [2, 4];
+/// Largest output NAI will produce (a 1024x1024 source at 4x).
+const MAX_UPSCALE_OUTPUT_PIXELS: i64 = 4096 * 4096;
+
+/// Checks `scale` and the output size before anything is sent, so a
+/// request that NAI would refuse doesn't cost a round trip.
+fn validate_upscale(width: i32, height: i32, scale: i32) -> Result<(), String> {
+    if !SUPPORTED_UPSCALE_SCALES.contains(&scale) {
+        return Err(format!(
+            "지원하지 않는 배율: {} (지원: {:?})",
+            scale, SUPPORTED_UPSCALE_SCALES
+        ));
+    }
+    if width <= 0 || height <= 0 {
+        return Err(format!("잘못된 이미지 크기: {}x{}", width, height));
+    }
+    let output = width as i64 * height as i64 * (scale as i64).pow(2);
+    if output > MAX_UPSCALE_OUTPUT_PIXELS {
+        return Err(format!(
+            "출력 크기가 최대치를 {}px 초과합니다 ({}x{} x{} = {}px, 최대 {}px)",
+            output - MAX_UPSCALE_OUTPUT_PIXELS,
+            width,
+            height,
+            scale,
+            output,
+            MAX_UPSCALE_OUTPUT_PIXELS
+        ));
+    }
+    Ok(())
+}
+
+/// Upscale factors `upscale_image` accepts.
+#[tauri::command]
+fn supported_upscale_scales() -> Vec<i32> {
+    SUPPORTED_UPSCALE_SCALES.to_vec()
+}
+
+/// Settings to write back into an upscaled image: `original_metadata` when
+/// given, else whatever the input carries, and whether the input also had
+/// stealth pnginfo.
+async fn upscale_source_metadata(
+    image: &str,
+    original_metadata: Option<String>,
+) -> Option<(Vec<(String, String)>, bool)> {
+    let bytes = metadata::decode_image_base64(image).ok()?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let stealth = metadata::has_stealth_metadata(&bytes);
+        let fields = match original_metadata {
+            Some(text) => metadata::fields_from_json(&text)?,
+            None => metadata::read_embedded_metadata(&bytes)?.fields,
+        };
+        Some((fields, stealth))
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Puts the source's settings back into an upscaled PNG, with the upscale
+/// recorded in the history.
+async fn restore_upscale_metadata(
+    image: String,
+    mut fields: Vec<(String, String)>,
+    stealth: bool,
+    scale: i32,
+) -> Result<String, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let png = metadata::decode_image_base64(&image)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let (width, height) = postprocess::header_dimensions(&png)?;
+        let step = serde_json::json!({
+            "action": "upscale",
+            "scale": scale,
+            "width": width,
+            "height": height,
+            "date": chrono::Local::now().to_rfc3339(),
+        });
+        metadata::record_postprocess(&mut fields, "upscaled", step);
+        let png = metadata::embed_png_metadata(&png, &fields, stealth)?;
+        Ok(STANDARD.encode(png))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// NAI's upscaler returns a bare PNG, so the input's generation settings
+/// (or `original_metadata`, the `fields` or settings JSON from
+/// `parse_metadata`) are written back into the result, marked `upscaled`.
+/// Stealth pnginfo in the input is rewritten too. With `return_mode: "file"`
+/// (and no `return_path`) the result is written to a temporary file whose
+/// path is returned instead of base64.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn upscale_image(
+    app: AppHandle,
+    token: String,
+    image: ImageSource,
+    width: Option<i32>,
+    height: Option<i32>,
+    scale: i32,
+    return_path: Option<String>,
+    original_metadata: Option<String>,
+    return_mode: Option<String>,
+    verify_output: Option<bool>,
+) -> UpscaleResult {
+    let (width_arg, height_arg) = (width, height);
+    let failure = |error: String| UpscaleResult {
+        success: false,
+        image_data: None,
+        error: Some(error),
+        error_code: Some(NaiErrorKind::BadRequest),
+        anlas_charged: None,
+        warning: None,
+        path: None,
+        width: None,
+        height: None,
+    };
+
+    let return_mode = match result_files::ReturnMode::parse(return_mode.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => {
+            return UpscaleResult {
+                error_code: None,
+                ..failure(e)
+            }
+        }
+    };
+    let image = match image.into_base64().await {
+        Ok(image) => image,
+        Err(e) => {
+            return UpscaleResult {
+                error_code: None,
+                ..failure(e)
+            }
+        }
+    };
+    let source_metadata = upscale_source_metadata(&image, original_metadata).await;
+    let image = match normalize_orientation_base64(image.clone()).await {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            log::warn!("Orientation normalization failed, using image as-is: {}", e);
+            image
+        }
+    };
+    // The size sent must match the image exactly, so it's always read from
+    // the image itself; the arguments are only checked against it
+    let actual = match metadata::decode_image_base64(&image) {
+        Ok(bytes) => {
+            tauri::async_runtime::spawn_blocking(move || postprocess::verified_dimensions(&bytes))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r)
+        }
+        Err(e) => Err(e),
+    };
+    let (width, height) = match actual {
+        Ok((w, h)) => (w as i32, h as i32),
+        Err(e) => return failure(e),
+    };
+    let warning = match (width_arg, height_arg) {
+        (Some(w), Some(h)) if (w, h) != (width, height) => {
+            log::warn!(
+                "Upscale size mismatch: got {}x{}, image is {}x{}",
+                w,
+                h,
+                width,
+                height
+            );
+            Some(format!(
+                "전달된 크기({}x{})가 실제 이미지 크기({}x{})와 달라 실제 크기를 사용했습니다",
+                w, h, width, height
+            ))
+        }
+        _ => None,
+    };
+    if let Err(e) = validate_upscale(width, height, scale) {
+        return failure(e);
+    }
+    // Opus upscales up to 640x640 for free
+    let free = has_unlimited_generation(&app) && width * height <= 640 * 640;
+    let balance_before = if free {
+        None
+    } else {
+        last_known_anlas(&app, &token)
+    };
+    let client = http_client(&app);
+
+    let payload = UpscalePayload {
+        image,
+        width,
+        height,
+        scale,
+    };
+
+    let request = client
+        .post("https://api.novelai.net/ai/upscale")
+        .header(
+            "Authorization",
+            format!("Bearer {}", normalize_token(&token)),
+        )
+        .header("Content-Type", "application/json")
+        .json(&payload);
+    let result = until_exit(send_logged(&app, &client, request))
+        .await
+        .and_then(|r| r.map_err(|e| network_error_message(&e)));
+
+    match result {
+        Ok(response) => {
+            check_auth_status(&app, response.status());
+            if response.status().is_success() {
+                // Response is a ZIP file containing the image
+                let body = until_exit(read_body(&app, response))
+                    .await
+                    .and_then(|r| r.map_err(|e| format!("응답 읽기 오류: {}", e)));
+                match body {
+                    Ok(bytes) => {
+                        // Use zip crate to extract
+                        let extracted = match extract_image_from_zip(&bytes) {
+                            Ok(image) => response_image_size(&image, verify_output.unwrap_or(true))
+                                .await
+                                .map(|size| (image, size)),
+                            Err(e) => Err(format!("ZIP 처리 오류: {}", e)),
+                        };
+                        let delivered = match extracted {
+                            Ok((base64_image, size)) => {
+                                let base64_image = match source_metadata {
+                                    Some((fields, stealth)) => restore_upscale_metadata(
+                                        base64_image.clone(),
+                                        fields,
+                                        stealth,
+                                        scale,
+                                    )
+                                    .await
+                                    .unwrap_or_else(|e| {
+                                        log::warn!(
+                                            "Could not restore metadata after upscale: {}",
+                                            e
+                                        );
+                                        base64_image
+                                    }),
+                                    None => base64_image,
+                                };
+                                let return_path = match return_path {
+                                    None if return_mode == result_files::ReturnMode::File => {
+                                        result_file_path(&app).await.map(Some)
+                                    }
+                                    path => Ok(path),
+                                };
+                                match return_path {
+                                    Ok(path) => image_source::deliver_base64(base64_image, path)
+                                        .await
+                                        .map(|delivered| (delivered, size)),
+                                    Err(e) => Err(e),
+                                }
+                            }
+                            Err(e) => Err(e),
+                        };
+                        match delivered {
+                            Ok(((image_data, path), size)) => UpscaleResult {
+                                success: true,
+                                image_data,
+                                error: None,
+                                error_code: None,
+                                anlas_charged: None,
+                                warning,
+                                path,
+                                width: size.map(|(w, _)| w),
+                                height: size.map(|(_, h)| h),
+                            },
+                            Err(e) => UpscaleResult {
+                                success: false,
+                                image_data: None,
+                                error: Some(e),
+                                error_code: None,
+                                anlas_charged: None,
+                                warning,
+                                path: None,
+                                width: None,
+                                height: None,
+                            },
+                        }
+                    }
+                    Err(e) => UpscaleResult {
+                        success: false,
+                        image_data: None,
+                        error: Some(e),
+                        error_code: None,
+                        anlas_charged: None,
+                        warning,
+                        path: None,
+                        width: None,
+                        height: None,
+                    },
+                }
+            } else {
+                let status = response.status().as_u16();
+                let body = error_text(&app, response).await;
+                let error = parse_api_error(status, &body);
+                let anlas_charged = if error.kind == NaiErrorKind::ServerError {
+                    check_anlas_charged(&app, &token, balance_before, 0).await
+                } else {
+                    None
+                };
+                UpscaleResult {
+                    success: false,
+                    image_data: None,
+                    error: Some(error.to_string()),
+                    error_code: Some(error.kind),
+                    anlas_charged,
+                    warning,
+                    path: None,
+                    width: None,
+                    height: None,
+                }
+            }
+        }
+        Err(e) => UpscaleResult {
+            success: false,
+            image_data: None,
+            error: Some(e),
+            error_code: None,
+            anlas_charged: None,
+            warning,
+            path: None,
+            width: None,
+            height: None,
+        },
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AugmentPipelineResult {
+    pub success: bool,
+    /// Output of the last step that succeeded
+    pub image_data: Option<String>,
+    /// With `keep_intermediates`, the output of every successful step in order
+    pub intermediates: Vec<String>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<NaiErrorKind>,
+    /// Index of the step that failed
+    #[serde(default)]
+    pub failed_step: Option<usize>,
+    /// Estimated Anlas spent by the steps that went through
+    pub anlas_cost: u32,
+    /// Where the result was written when `return_path` was given
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// Runs one Director tool step and returns its output image as base64.
+async fn request_augment(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    token: &str,
+    payload: &serde_json::Value,
+) -> Result<String, RequestError> {
+    let bytes = until_exit(async {
+        let response =
+            send_generation(app, client, augment::AUGMENT_URL, token, payload, None).await?;
+        read_body(app, response)
+            .await
+            .map_err(|e| RequestError::from(format!("응답 읽기 오류: {}", e)))
+    })
+    .await??;
+    // Background removal answers with several images; the first is the cut-out
+    extract_image_from_zip(&bytes).map_err(|e| format!("ZIP 처리 오류: {}", e).into())
+}
+
+/// Applies Director tools one after another (e.g. line art, then colorize,
+/// then background removal), each step working on the previous step's
+/// output. Stops at the first failure and returns what was done so far with
+/// the index of the failing step.
+#[tauri::command]
+async fn augment_pipeline(
+    app: AppHandle,
+    token: String,
+    steps: Vec<augment::AugmentStep>,
+    input: ImageSource,
+    keep_intermediates: Option<bool>,
+    return_path: Option<String>,
+) -> AugmentPipelineResult {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let mut result = AugmentPipelineResult {
+        success: false,
+        image_data: None,
+        intermediates: Vec::new(),
+        error: None,
+        error_code: None,
+        failed_step: None,
+        anlas_cost: 0,
+        path: None,
+    };
+    if steps.is_empty() {
+        result.error = Some("적용할 단계가 없습니다".to_string());
+        return result;
+    }
+    let mut image = match input.read().await {
+        Ok(bytes) => STANDARD.encode(bytes),
+        Err(e) => {
+            result.error = Some(e);
+            return result;
+        }
+    };
+
+    let client = http_client(&app);
+    let unlimited = has_unlimited_generation(&app);
+    let keep_intermediates = keep_intermediates.unwrap_or(false);
+    for (index, step) in steps.iter().enumerate() {
+        let size = metadata::decode_image_base64(&image)
+            .and_then(|bytes| postprocess::header_dimensions(&bytes));
+        let (width, height) = match size {
+            Ok(size) => size,
+            Err(e) => {
+                result.error = Some(e);
+                result.failed_step = Some(index);
+                return result;
+            }
+        };
+
+        let payload = augment::build_payload(step, &image, width, height);
+        match request_augment(&app, &client, &token, &payload).await {
+            Ok(output) => {
+                result.anlas_cost += augment::estimate_cost(step.tool, width, height, unlimited);
+                if keep_intermediates {
+                    result.intermediates.push(output.clone());
+                }
+                image = output;
+                result.image_data = Some(image.clone());
+            }
+            Err(e) => {
+                log::warn!(
+                    "Augment step {} ({}) failed: {}",
+                    index,
+                    step.tool.req_type(),
+                    e.message
+                );
+                result.error = Some(e.message);
+                result.error_code = e.code;
+                result.failed_step = Some(index);
+                return result;
+            }
+        }
+    }
+
+    if let Some(return_path) = return_path {
+        match image_source::deliver_base64(image, Some(return_path)).await {
+            Ok((_, path)) => {
+                result.image_data = None;
+                result.path = path;
+            }
+            Err(e) => {
+                result.error = Some(e);
+                return result;
+            }
+        }
+    }
+    result.success = true;
+    result
+}
+
+// Far above any real NAI output; stops a malformed or hostile archive from
+// inflating into gigabytes
+const MAX_ZIP_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+const ZIP_IMAGE_EXTENSIONS: [&str; 4] = ["png", "webp", "jpg", "jpeg"];
+
+fn is_image_bytes(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\x89PNG\r\n\x1a\n")
+        || bytes.starts_with(&[0xFF, 0xD8, 0xFF])
+        || (bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP")
+}
+
+/// The number in an entry name like `image_3.png`, if any.
+fn zip_entry_index(name: &str) -> Option<u64> {
+    let stem = std::path::Path::new(name).file_stem()?.to_str()?;
+    let digits: String = stem
+        .chars()
+        .rev()
+        .take_while(char::is_ascii_digit)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    digits.parse().ok()
+}
+
+/// Every image entry of a NAI response ZIP, base64-encoded, in sample order
+/// (`image_0.png`, `image_1.png`, ... for batches). Entries are only
+/// taken as images when both the extension and the magic bytes agree, so
+/// metadata files and empty entries are skipped.
+fn extract_images_from_zip(zip_bytes: &[u8]) -> Result<Vec<String>, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    let cursor = Cursor::new(zip_bytes);
+    let mut archive = ZipArchive::new(cursor).map_err(|e| e.to_string())?;
+
+    if archive.is_empty() {
+        return Err("ZIP 파일이 비어있습니다".to_string());
+    }
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| {
+            std::path::Path::new(name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ZIP_IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .map(str::to_string)
+        .collect();
+    // `image_10` must come after `image_9`, i.e. NAI's sample order
+    names.sort_by_key(|name| (zip_entry_index(name), name.clone()));
+
+    let mut images = Vec::with_capacity(names.len());
+    for name in names {
+        let file = archive.by_name(&name).map_err(|e| e.to_string())?;
+        if file.is_dir() || file.size() == 0 {
+            continue;
+        }
+        if file.size() > MAX_ZIP_ENTRY_SIZE {
+            return Err(format!(
+                "ZIP 항목이 너무 큽니다: {} ({} bytes)",
+                name,
+                file.size()
+            ));
+        }
+
+        // The declared size can lie, so cap the actual read as well
+        let mut contents = Vec::with_capacity(file.size() as usize);
+        file.take(MAX_ZIP_ENTRY_SIZE + 1)
+            .read_to_end(&mut contents)
+            .map_err(|e| e.to_string())?;
+        if contents.len() as u64 > MAX_ZIP_ENTRY_SIZE {
+            return Err(format!("ZIP 항목이 너무 큽니다: {}", name));
+        }
+        if !is_image_bytes(&contents) {
+            log::warn!("Skipping non-image ZIP entry: {}", name);
+            continue;
+        }
+        images.push(STANDARD.encode(&contents));
+    }
+
+    if images.is_empty() {
+        return Err("ZIP에 이미지가 없습니다".to_string());
+    }
+    Ok(images)
+}
+
+/// The first image of a NAI response ZIP.
+fn extract_image_from_zip(zip_bytes: &[u8]) -> Result<String, String> {
+    extract_images_from_zip(zip_bytes).map(|mut images| images.swap_remove(0))
+}
+
+/// Size of an image NAI sent back (base64). With `verify` it is decoded in
+/// full, so a truncated or corrupt response fails here rather than when the
+/// frontend renders it. Otherwise only the header is read, and an unreadable
+/// one just leaves the size unknown.
+async fn response_image_size(image: &str, verify: bool) -> Result<Option<(u32, u32)>, String> {
+    let corrupt = |e: String| format!("손상된 응답 이미지: {}", e);
+    let bytes = metadata::decode_image_base64(image).map_err(corrupt)?;
+    if !verify {
+        return Ok(postprocess::header_dimensions(&bytes).ok());
+    }
+    tauri::async_runtime::spawn_blocking(move || postprocess::decode(&bytes))
+        .await
+        .map_err(|e| e.to_string())?
+        .map(|(image, _)| Some((image.width(), image.height())))
+        .map_err(corrupt)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemoveBackgroundResult {
+    pub success: bool,
+    pub image_data: Option<String>,
+    pub error: Option<String>,
+    /// Where the result was written when `return_path` was given
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Stopped by `cancel_remove_background` while the model was loading
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+impl RemoveBackgroundResult {
+    fn failure(error: String) -> Self {
+        RemoveBackgroundResult {
+            success: false,
+            image_data: None,
+            error: Some(error),
+            path: None,
+            cancelled: false,
+        }
+    }
+}
+
+const RMBG_URL: &str = "https://router.huggingface.co/hf-inference/models/briaai/RMBG-1.4";
+/// Longest total wait for a cold-started model before giving up.
+const MAX_RMBG_LOADING_WAIT: u64 = 60;
+
+static RMBG_CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Payload of `rmbg-progress`, sent every second while the model loads.
+#[derive(Debug, Clone, Serialize)]
+pub struct RmbgProgress {
+    /// Seconds left until the next attempt
+    pub waiting_seconds: u64,
+}
+
+/// `estimated_time` (seconds) from HF's 503 "model is currently loading"
+/// body, or `None` when the 503 is something else.
+fn hf_loading_estimate(body: &str) -> Option<f64> {
+    #[derive(Deserialize)]
+    struct Loading {
+        estimated_time: f64,
+    }
+    serde_json::from_str::<Loading>(body)
+        .ok()
+        .map(|l| l.estimated_time)
+        .filter(|t| t.is_finite())
+}
+
+/// Removes the background with RMBG-1.4 on the Hugging Face Inference API.
+/// A cold model answers 503 with an estimated load time; the request is
+/// retried after that long (emitting `rmbg-progress` meanwhile) until 60
+/// seconds of waiting in total, and `cancel_remove_background` stops it.
+/// The model's matte is scaled back to the input size and applied to the
+/// original pixels, softened by `feather` (blur radius in pixels), so the
+/// PNG returned has the input's resolution.
+#[tauri::command]
+async fn remove_background(
+    app: AppHandle,
+    image_base64: ImageSource,
+    return_path: Option<String>,
+    feather: Option<f64>,
+) -> RemoveBackgroundResult {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    RMBG_CANCELLED.store(false, Ordering::SeqCst);
+    let image_bytes = match image_base64.read().await {
+        Ok(bytes) => bytes,
+        Err(e) => return RemoveBackgroundResult::failure(e),
+    };
+
+    let client = http_client(&app);
+
+    // Use Hugging Face Inference API (free tier available)
+    // Note: For production, consider getting an HF API token
+    let mut waited = 0;
+    let response = loop {
+        let sent = until_exit(
+            client
+                .post(RMBG_URL)
+                .header("Content-Type", "application/octet-stream")
+                .body(image_bytes.clone())
+                .send(),
+        )
+        .await
+        .and_then(|r| r.map_err(|e| network_error_message(&e)));
+        let response = match sent {
+            Ok(response) => response,
+            Err(e) => return RemoveBackgroundResult::failure(e),
+        };
+        if response.status() != reqwest::StatusCode::SERVICE_UNAVAILABLE {
+            break response;
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        let Some(estimate) = hf_loading_estimate(&body) else {
+            return RemoveBackgroundResult::failure(format!("API 오류 503: {}", body));
+        };
+        let wait = (estimate.ceil() as u64).max(1);
+        if waited + wait > MAX_RMBG_LOADING_WAIT {
+            return RemoveBackgroundResult::failure(format!(
+                "모델 로딩 대기 시간 초과 ({}초 대기, 예상 {}초 더 필요)",
+                waited, wait
+            ));
+        }
+        log::info!("RMBG model loading, retrying in {}s", wait);
+        for remaining in (1..=wait).rev() {
+            if RMBG_CANCELLED.load(Ordering::SeqCst) {
+                return RemoveBackgroundResult {
+                    cancelled: true,
+                    ..RemoveBackgroundResult::failure("취소되었습니다".to_string())
+                };
+            }
+            let _ = app.emit(
+                "rmbg-progress",
+                RmbgProgress {
+                    waiting_seconds: remaining,
+                },
+            );
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        waited += wait;
+    };
+
+    if !response.status().is_success() {
+        let status = response.status().as_u16();
+        let error_text = response.text().await.unwrap_or_default();
+        return RemoveBackgroundResult::failure(format!("API 오류 {}: {}", status, error_text));
+    }
+    let body = until_exit(read_body(&app, response))
+        .await
+        .and_then(|r| r.map_err(|e| format!("응답 읽기 오류: {}", e)));
+    let matte = match body {
+        Ok(bytes) => bytes,
+        Err(e) => return RemoveBackgroundResult::failure(e),
+    };
+    let feather = feather.unwrap_or(0.0);
+    let cutout = tauri::async_runtime::spawn_blocking(move || {
+        postprocess::apply_cutout(&image_bytes, &matte, feather)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+    let bytes = match cutout {
+        Ok(bytes) => bytes,
+        Err(e) => return RemoveBackgroundResult::failure(format!("배경 합성 오류: {}", e)),
+    };
+    match return_path {
+        Some(path) => match image_source::write_image_file(&path, &bytes).await {
+            Ok(path) => RemoveBackgroundResult {
+                success: true,
+                image_data: None,
+                error: None,
+                path: Some(path),
+                cancelled: false,
+            },
+            Err(e) => RemoveBackgroundResult::failure(e),
+        },
+        None => RemoveBackgroundResult {
+            success: true,
+            image_data: Some(format!("data:image/png;base64,{}", STANDARD.encode(&bytes))),
+            error: None,
+            path: None,
+            cancelled: false,
+        },
+    }
+}
+
+/// Stops a `remove_background` that is waiting for the model to load.
+#[tauri::command]
+fn cancel_remove_background() {
+    RMBG_CANCELLED.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratedImage {
+    pub seed: i64,
+    pub image_data: Option<String>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<NaiErrorKind>,
+    /// See `UpscaleResult::anlas_charged`
+    #[serde(default)]
+    pub anlas_charged: Option<bool>,
+    /// `payload_fingerprint` of the request, seed included. Samples of one
+    /// request share it.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// The result file with `return_mode: "file"`; `image_data` is empty then
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// The `variation_seed` used, when there was one
+    #[serde(default)]
+    pub variation_seed: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerateResult {
+    pub success: bool,
+    pub image_data: Option<String>,
+    pub seed: Option<i64>,
+    pub images: Vec<GeneratedImage>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub error_code: Option<NaiErrorKind>,
+    /// Stopped early because NAI couldn't be reached
+    #[serde(default)]
+    pub offline: bool,
+    /// `Some(true)` if any failed request was still charged
+    #[serde(default)]
+    pub anlas_charged: Option<bool>,
+    /// `payload_fingerprint` without the seed; the history stores it to find
+    /// images made with the same settings
+    #[serde(default)]
+    pub settings_fingerprint: Option<String>,
+    /// `path` of the first image
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Extra headers or params that were left out
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Settings NAI recorded in the images differently from the request
+    /// (`verify_consistency`)
+    #[serde(default)]
+    pub mismatches: Vec<consistency::FieldMismatch>,
+}
+
+impl GenerateResult {
+    fn failure(error: String) -> Self {
+        GenerateResult {
+            success: false,
+            image_data: None,
+            seed: None,
+            images: Vec::new(),
+            error: Some(error),
+            error_code: None,
+            offline: false,
+            anlas_charged: None,
+            settings_fingerprint: None,
+            path: None,
+            warnings: Vec::new(),
+            mismatches: Vec::new(),
+        }
+    }
+
+    /// Sums up the images of one or more requests: the first success is the
+    /// main result, otherwise the first error.
+    fn from_images(
+        images: Vec<GeneratedImage>,
+        offline: bool,
+        settings_fingerprint: Option<String>,
+        warnings: Vec<String>,
+    ) -> Self {
+        let first_ok = images
+            .iter()
+            .find(|img| img.image_data.is_some() || img.path.is_some());
+        GenerateResult {
+            success: first_ok.is_some(),
+            image_data: first_ok.and_then(|img| img.image_data.clone()),
+            seed: first_ok.map(|img| img.seed),
+            error: if first_ok.is_some() {
+                None
+            } else {
+                images.iter().find_map(|img| img.error.clone())
+            },
+            error_code: if first_ok.is_some() {
+                None
+            } else {
+                images.iter().find_map(|img| img.error_code)
+            },
+            anlas_charged: images
+                .iter()
+                .filter_map(|img| img.anlas_charged)
+                .reduce(|a, b| a || b),
+            path: first_ok.and_then(|img| img.path.clone()),
+            images,
+            offline,
+            settings_fingerprint,
+            warnings,
+            mismatches: Vec::new(),
+        }
+    }
+}
+
+struct RequestError {
+    message: String,
+    code: Option<NaiErrorKind>,
+    offline: bool,
+}
+
+impl From<String> for RequestError {
+    fn from(message: String) -> Self {
+        RequestError {
+            message,
+            code: None,
+            offline: false,
+        }
+    }
+}
+
+// Set once the app starts exiting; requests started after that fail at once.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+// Requests running through `until_exit`, so exit can wait for them to stop.
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+/// How long exit waits for cancelled requests to wind down.
+const EXIT_GRACE: Duration = Duration::from_secs(2);
+
+fn exit_signal() -> &'static tokio::sync::Notify {
+    static SIGNAL: std::sync::OnceLock<tokio::sync::Notify> = std::sync::OnceLock::new();
+    SIGNAL.get_or_init(tokio::sync::Notify::new)
+}
+
+struct InFlightGuard;
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs `future` until it finishes or the app exits. On exit the future is
+/// dropped, which closes its connection and runs its cleanup, and an error
+/// is returned instead.
+async fn until_exit<F: std::future::Future>(future: F) -> Result<F::Output, String> {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    let _guard = InFlightGuard;
+    let exiting = exit_signal().notified();
+    tokio::pin!(exiting);
+    // Registers for the signal before checking the flag, so an exit between
+    // the two isn't missed
+    exiting.as_mut().enable();
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return Err("앱이 종료되어 요청이 취소되었습니다".to_string());
+    }
+    tokio::select! {
+        output = future => Ok(output),
+        _ = exiting => Err("앱이 종료되어 요청이 취소되었습니다".to_string()),
+    }
+}
+
+/// Cancels every request in `until_exit` and waits up to `EXIT_GRACE` for
+/// them to finish dropping. Called from the exit handler, so it blocks.
+fn cancel_requests_for_exit() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    cancel_streams(None);
+    RMBG_CANCELLED.store(true, Ordering::SeqCst);
+    exit_signal().notify_waiters();
+
+    let deadline = std::time::Instant::now() + EXIT_GRACE;
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 {
+        if std::time::Instant::now() >= deadline {
+            log::warn!(
+                "Exiting with {} request(s) still running",
+                IN_FLIGHT.load(Ordering::SeqCst)
+            );
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Sends `request`, recording it in the request log when that is on.
+async fn send_logged(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    request: reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let request = request.build()?;
+    let id = request_log::request(app, &request);
+    let token = stats_token(&request);
+    match client.execute(request).await {
+        Ok(mut response) => {
+            request_log::response(app, id, &mut response);
+            if let Some(token) = token {
+                let status = response.status();
+                let outcome = if status.is_success() {
+                    request_stats::Outcome::Success
+                } else if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                    request_stats::Outcome::RateLimited
+                } else {
+                    request_stats::Outcome::Failed
+                };
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+                    .map(std::time::Duration::from_secs);
+                request_stats::record(&token, outcome, retry_after);
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            request_log::error(app, id, &e.to_string());
+            if let Some(token) = token {
+                request_stats::record(&token, request_stats::Outcome::Failed, None);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// The token of a request to NAI, for `request_stats`. Other hosts aren't
+/// counted.
+fn stats_token(request: &reqwest::Request) -> Option<String> {
+    let host = request.url().host_str()?;
+    if host != "novelai.net" && !host.ends_with(".novelai.net") {
+        return None;
+    }
+    let auth = request
+        .headers()
+        .get(reqwest::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    auth.strip_prefix("Bearer ").map(normalize_token)
+}
+
+/// Request counts of the last minute and hour for `token` (all tokens when
+/// omitted), with how long to wait after a recent 429. Kept in memory only.
+#[tauri::command]
+fn get_request_stats(token: Option<String>) -> request_stats::RequestStats {
+    request_stats::get(token.map(|t| normalize_token(&t)).as_deref())
+}
+
+/// This session's generations for a day's notes or a bug report: requests,
+/// images that succeeded or failed, estimated Anlas spent, 429s, average
+/// generation time and models used, along with the app version and OS.
+/// `format` is `markdown` (default) or `json`. Tokens, prompts and paths are
+/// never included.
+#[tauri::command]
+fn export_session_report(app: AppHandle, format: Option<String>) -> Result<String, String> {
+    let report = session_report::report(
+        app.package_info().version.to_string(),
+        request_stats::get(None).rate_limited_count,
+    );
+    match format.as_deref().unwrap_or("markdown") {
+        "markdown" | "md" => Ok(session_report::to_markdown(&report)),
+        "json" => serde_json::to_string_pretty(&report).map_err(|e| e.to_string()),
+        other => Err(format!("지원하지 않는 리포트 형식: {}", other)),
+    }
+}
+
+/// Reads an error response's text, logging it like any other body.
+async fn error_text(app: &AppHandle, response: reqwest::Response) -> String {
+    let id = request_log::id_of(&response);
+    let body = response.text().await.unwrap_or_default();
+    request_log::body(app, id, body.as_bytes());
+    body
+}
+
+/// Turns the request log on or off. Off again after a restart.
+#[tauri::command]
+fn set_request_logging(enabled: bool) {
+    request_log::set_enabled(enabled);
+}
+
+/// Where the request log is written, whether or not it exists yet.
+#[tauri::command]
+fn get_request_log_path(app: AppHandle) -> Result<String, String> {
+    request_log::path(&app).map(|path| path.to_string_lossy().to_string())
+}
+
+/// Posts a generation payload to `url`. Non-2xx responses are turned into a
+/// coded `RequestError`.
+async fn send_generation(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    url: &str,
+    token: &str,
+    payload: &serde_json::Value,
+    extra_headers: Option<&reqwest::header::HeaderMap>,
+) -> Result<reqwest::Response, RequestError> {
+    let mut request = client.post(url);
+    if let Some(headers) = extra_headers {
+        request = request.headers(headers.clone());
+    }
+    let request = request
+        .header(
+            "Authorization",
+            format!("Bearer {}", normalize_token(token)),
+        )
+        .header("Content-Type", "application/json")
+        .json(payload);
+    let response = send_logged(app, client, request)
+        .await
+        .map_err(|e| RequestError {
+            message: network_error_message(&e),
+            code: None,
+            offline: is_offline_error(&e),
+        })?;
+
+    check_auth_status(app, response.status());
+    if !response.status().is_success() {
+        let status = response.status().as_u16();
+        let body = error_text(app, response).await;
+        let error = parse_api_error(status, &body);
+        return Err(RequestError {
+            message: error.to_string(),
+            code: Some(error.kind),
+            offline: false,
+        });
+    }
+    Ok(response)
+}
+
+async fn request_generation(
+    app: &AppHandle,
+    client: &reqwest::Client,
+    token: &str,
+    payload: &serde_json::Value,
+    extra_headers: Option<&reqwest::header::HeaderMap>,
+) -> Result<Vec<String>, RequestError> {
+    let bytes = until_exit(async {
+        let response = send_generation(
+            app,
+            client,
+            "https://image.novelai.net/ai/generate-image",
+            token,
+            payload,
+            extra_headers,
+        )
+        .await?;
+        read_body(app, response)
+            .await
+            .map_err(|e| RequestError::from(format!("응답 읽기 오류: {}", e)))
+    })
+    .await??;
+    extract_images_from_zip(&bytes).map_err(|e| format!("ZIP 처리 오류: {}", e).into())
+}
+
+/// Snaps a dragged character position to the grid cell center NAI uses,
+/// clamping to 0-1 first. With `use_coords` off, returns the default center.
+/// The payload builder snaps the same way, so the UI shows what is sent.
+#[tauri::command]
+fn snap_character_position(x: f64, y: f64, use_coords: Option<bool>) -> (f64, f64) {
+    let position = generation::CharacterPosition { x, y }.snapped(use_coords.unwrap_or(true));
+    (position.x, position.y)
+}
+
+/// SHA-256 of a generation payload in canonical form (sorted keys, defaults
+/// filled in), for spotting identical requests. Takes either a full request
+/// body or the frontend's `GenerationParams`, which go through
+/// `build_payload` first. The seed is left out unless `include_seed` is set.
+#[tauri::command]
+fn payload_fingerprint(
+    payload: serde_json::Value,
+    include_seed: Option<bool>,
+) -> Result<String, String> {
+    let payload = if payload.get("parameters").is_some() {
+        payload
+    } else {
+        let params: GenerationParams =
+            serde_json::from_value(payload).map_err(|e| format!("파라미터 파싱 오류: {}", e))?;
+        let seed = params.seed.unwrap_or(0);
+        generation::build_payload(&params, seed)
+    };
+    Ok(generation::payload_fingerprint(
+        &payload,
+        include_seed.unwrap_or(false),
+    ))
+}
+
+/// Developer aid for bug reports: the request NAIS sends for `action`
+/// (`generate`, `img2img`, `inpaint`, `upscale` or `augment`) with default
+/// settings and placeholder prompts and images, plus the type of each field.
+/// Contains no token or user data.
+#[tauri::command]
+fn dump_request_schema(action: String) -> Result<String, String> {
+    request_schema::dump(&action)
+}
+
+/// Reads settings exported from (or copied out of) the official NovelAI
+/// site. Missing settings are filled with defaults and listed in `warnings`.
+#[tauri::command]
+fn import_nai_settings(json: String) -> Result<nai_settings::NaiSettingsImport, String> {
+    let value = serde_json::from_str(&json).map_err(|e| format!("JSON 파싱 오류: {}", e))?;
+    nai_settings::import(value)
+}
+
+/// Reads the generation settings out of an image (text chunks, EXIF or
+/// stealth alpha alike) as `generate_image` params, for "generate again like
+/// this one". `lost` lists what the image doesn't carry, such as reference
+/// images.
+#[tauri::command]
+async fn params_from_image(image: ImageSource) -> Result<nai_settings::ImageParams, String> {
+    let bytes = image.read().await?;
+    let meta =
+        tauri::async_runtime::spawn_blocking(move || metadata::read_embedded_metadata(&bytes))
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "이미지에 메타데이터가 없습니다".to_string())?;
+    nai_settings::from_metadata(&meta)
+}
+
+/// Writes `params` as a settings JSON the official site understands.
+#[tauri::command]
+fn export_nai_settings(params: GenerationParams) -> Result<String, String> {
+    nai_settings::export(&params)
+}
+
+/// Applies prompt normalization when requested and fixes the orientation of
+/// the img2img source.
+async fn prepare_params(params: GenerationParams) -> GenerationParams {
+    let mut params = if params.normalize_prompt {
+        params.normalized()
+    } else {
+        params
+    };
+    if let Some(source) = params.source_image.take() {
+        match normalize_orientation_base64(source.clone()).await {
+            Ok(normalized) => params.source_image = Some(normalized),
+            Err(e) => {
+                log::warn!(
+                    "Orientation normalization failed, using source as-is: {}",
+                    e
+                );
+                params.source_image = Some(source);
+            }
+        }
+    }
+    params
+}
+
+/// A new file in `result_files::dir` for one result.
+async fn result_file_path(app: &AppHandle) -> Result<String, String> {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || result_files::next_path(&app))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Writes every generated image to a result file and returns the path in
+/// place of the base64. An image that can't be written keeps its base64.
+async fn deliver_as_files(app: &AppHandle, images: &mut [GeneratedImage]) {
+    for image in images.iter_mut() {
+        let Some(data) = image.image_data.take() else {
+            continue;
+        };
+        let written = match result_file_path(app).await {
+            Ok(path) => image_source::deliver_base64(data.clone(), Some(path)).await,
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok((_, path)) => image.path = path,
+            Err(e) => {
+                log::warn!("Could not write result file, returning base64: {}", e);
+                image.image_data = Some(data);
+            }
+        }
+    }
+}
+
+// Set by NAIS itself; extra headers can't replace them
+const RESERVED_HEADERS: [&str; 5] = [
+    "authorization",
+    "content-type",
+    "content-length",
+    "host",
+    "accept-encoding",
+];
+
+/// Extra request headers for experiments. Reserved headers are skipped
+/// with a warning; malformed ones are an error.
+fn extra_header_map(
+    headers: &HashMap<String, String>,
+) -> Result<(reqwest::header::HeaderMap, Vec<String>), String> {
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    let mut map = reqwest::header::HeaderMap::new();
+    let mut skipped = Vec::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("잘못된 헤더 이름: {}", name))?;
+        if RESERVED_HEADERS.contains(&name.as_str()) {
+            skipped.push(format!("'{}' 헤더는 바꿀 수 없어 무시했습니다", name));
+            continue;
+        }
+        let value =
+            HeaderValue::from_str(value).map_err(|_| format!("잘못된 헤더 값: {}", name))?;
+        map.append(name, value);
+    }
+    Ok((map, skipped))
+}
+
+/// Settings NAI recorded in `image_data` differently from `payload`. An
+/// image whose metadata can't be read has nothing to compare.
+async fn result_mismatches(
+    image_data: &str,
+    payload: &serde_json::Value,
+    seed: i64,
+) -> Vec<consistency::FieldMismatch> {
+    let Ok(bytes) = metadata::decode_image_base64(image_data) else {
+        return Vec::new();
+    };
+    let meta =
+        tauri::async_runtime::spawn_blocking(move || metadata::read_embedded_metadata(&bytes))
+            .await
+            .ok()
+            .flatten();
+    let Some(meta) = meta else {
+        log::debug!("Seed {}: no metadata to check the result against", seed);
+        return Vec::new();
+    };
+    let mismatches = consistency::verify_result_consistency(payload, seed, &meta);
+    for mismatch in &mismatches {
+        log::warn!(
+            "Seed {}: requested {} = {} but NAI recorded {}",
+            seed,
+            mismatch.field,
+            mismatch.requested,
+            mismatch.actual
+        );
+    }
+    mismatches
+}
+
+/// Adds one request's images to the session report.
+fn record_generation(model: &str, images: &[GeneratedImage], duration: Duration, anlas: u32) {
+    let failed: Vec<&GeneratedImage> = images.iter().filter(|i| i.error.is_some()).collect();
+    session_report::record(session_report::Generation {
+        model,
+        succeeded: (images.len() - failed.len()) as u32,
+        failed: failed.len() as u32,
+        error_code: failed.first().and_then(|image| image.error_code),
+        duration,
+        anlas,
+    });
+}
+
+/// Optional settings of `generate_image`; everything left out uses its
+/// default.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GenerateOptions {
+    /// Number of requests, default 1
+    pub count: Option<usize>,
+    pub seed_mode: Option<SeedMode>,
+    /// `"base64"` (default) or `"file"`
+    pub return_mode: Option<String>,
+    pub extra_headers: Option<HashMap<String, String>>,
+    pub extra_params: Option<serde_json::Value>,
+    /// Default true
+    pub verify_output: Option<bool>,
+    /// Default true
+    pub verify_consistency: Option<bool>,
+}
+
+/// Runs `count` requests (default 1) of `params.n_samples` images each. Each
+/// request gets its own seed from `resolve_seeds`, and every image is
+/// returned with the seed that reproduces it (`seed + i` for the `i`-th
+/// sample of a request). With `return_mode: "file"` the images come back as
+/// temporary PNG paths for `convertFileSrc` rather than base64.
+///
+/// `extra_headers` and `extra_params` are for trying out NAI features NAIS
+/// doesn't know yet: the headers are added to every request and the params
+/// are merged into the request body, never replacing what is already there.
+/// Anything left out is listed in `warnings`.
+///
+/// With `verify_consistency` (on by default) each image's metadata is
+/// compared with the request, and settings NAI didn't apply as sent are
+/// listed in `mismatches`. The images are returned either way.
+#[tauri::command]
+async fn generate_image(
+    app: AppHandle,
+    token: String,
+    params: GenerationParams,
+    options: Option<GenerateOptions>,
+) -> GenerateResult {
+    let GenerateOptions {
+        count,
+        seed_mode,
+        return_mode,
+        extra_headers,
+        extra_params,
+        verify_output,
+        verify_consistency,
+    } = options.unwrap_or_default();
+    if let Err(e) = models::validate(&params) {
+        return GenerateResult::failure(e);
+    }
+    let return_mode = match result_files::ReturnMode::parse(return_mode.as_deref()) {
+        Ok(mode) => mode,
+        Err(e) => return GenerateResult::failure(e),
+    };
+    let mut warnings = Vec::new();
+    let extra_headers = match extra_headers.map(|h| extra_header_map(&h)).transpose() {
+        Ok(headers) => headers.map(|(headers, skipped)| {
+            warnings.extend(skipped);
+            headers
+        }),
+        Err(e) => return GenerateResult::failure(e),
+    };
+    if extra_params.as_ref().is_some_and(|p| !p.is_object()) {
+        return GenerateResult::failure("extra_params는 JSON 객체여야 합니다".to_string());
+    }
+    let count = count.unwrap_or(1).max(1);
+    let verify = verify_output.unwrap_or(true);
+    let check_consistency = verify_consistency.unwrap_or(true);
+    // With an explicit seed, a single generation must use exactly that seed
+    let mode = seed_mode.unwrap_or(if params.seed.is_some() {
+        SeedMode::Incremental
+    } else {
+        SeedMode::Random
+    });
+    let seeds = generation::resolve_seeds(params.seed, count, params.samples(), mode);
+    let params = prepare_params(params).await;
+
+    let client = http_client(&app);
+    let mut images = Vec::with_capacity(count);
+    let mut mismatches = Vec::new();
+    let mut offline = false;
+    let unlimited = has_unlimited_generation(&app);
+    let track_anlas = !(unlimited && params.is_opus_free());
+    let cost = params.estimate_anlas(unlimited);
+    let mut balance_before = if track_anlas {
+        last_known_anlas(&app, &token)
+    } else {
+        None
+    };
+    // Estimated spending of the requests that succeeded since `balance_before`
+    let mut spent_since = 0;
+    let mut settings_fingerprint = None;
+    for seed in seeds {
+        let mut payload = generation::build_payload(&params, seed);
+        if let Some(extra) = &extra_params {
+            let kept = generation::merge_extra_params(&mut payload, extra);
+            // The same for every seed
+            if settings_fingerprint.is_none() {
+                warnings.extend(kept.into_iter().map(|key| {
+                    format!("extra_params의 '{}'는 이미 있는 필드라 무시했습니다", key)
+                }));
+            }
+        }
+        let fingerprint = generation::payload_fingerprint(&payload, true);
+        if settings_fingerprint.is_none() {
+            settings_fingerprint = Some(generation::payload_fingerprint(&payload, false));
+        }
+        let started = std::time::Instant::now();
+        let first_image = images.len();
+        let charged;
+        let give_up =
+            match request_generation(&app, &client, &token, &payload, extra_headers.as_ref()).await
+            {
+                Ok(samples) => {
+                    spent_since += i64::from(cost);
+                    charged = true;
+                    for (i, image_data) in samples.into_iter().enumerate() {
+                        let mut image = GeneratedImage {
+                            seed: generation::sample_seed(seed, i),
+                            image_data: None,
+                            error: None,
+                            error_code: None,
+                            anlas_charged: None,
+                            fingerprint: Some(fingerprint.clone()),
+                            path: None,
+                            width: None,
+                            height: None,
+                            variation_seed: params.variation_seed,
+                        };
+                        match response_image_size(&image_data, verify).await {
+                            Ok(size) => {
+                                if check_consistency {
+                                    mismatches.extend(
+                                        result_mismatches(&image_data, &payload, image.seed).await,
+                                    );
+                                }
+                                image.image_data = Some(image_data);
+                                image.width = size.map(|(w, _)| w);
+                                image.height = size.map(|(_, h)| h);
+                            }
+                            Err(e) => {
+                                log::warn!("Seed {}: {}", image.seed, e);
+                                image.error = Some(e);
+                                image.error_code = Some(NaiErrorKind::ServerError);
+                            }
+                        }
+                        images.push(image);
+                    }
+                    false
+                }
+                Err(e) => {
+                    offline = e.offline;
+                    let anlas_charged = if track_anlas && e.code == Some(NaiErrorKind::ServerError)
+                    {
+                        let charged =
+                            check_anlas_charged(&app, &token, balance_before, spent_since).await;
+                        // The check read the balance, which is the new baseline
+                        balance_before = last_known_anlas(&app, &token);
+                        spent_since = 0;
+                        charged
+                    } else {
+                        None
+                    };
+                    charged = anlas_charged == Some(true);
+                    images.push(GeneratedImage {
+                        seed,
+                        image_data: None,
+                        error: Some(e.message),
+                        error_code: e.code,
+                        anlas_charged,
+                        fingerprint: Some(fingerprint),
+                        path: None,
+                        width: None,
+                        height: None,
+                        variation_seed: params.variation_seed,
+                    });
+                    matches!(
+                        e.code,
+                        Some(NaiErrorKind::Unauthorized | NaiErrorKind::InsufficientAnlas)
+                    )
+                }
+            };
+        record_generation(
+            &params.model,
+            &images[first_image..],
+            started.elapsed(),
+            if charged { cost } else { 0 },
+        );
+        // No point trying the remaining seeds without a connection, a valid
+        // token or enough Anlas
+        if offline || give_up {
+            break;
+        }
+    }
+
+    if return_mode == result_files::ReturnMode::File {
+        deliver_as_files(&app, &mut images).await;
+    }
+    let mut result = GenerateResult::from_images(images, offline, settings_fingerprint, warnings);
+    result.mismatches = mismatches;
+    result
+}
+
+/// Variations on one image: the base seed (`params.seed`, random when
+/// unset) stays fixed and each of the `count` images gets its own
+/// `variation_seed`, counting up from `params.variation_seed` when that is
+/// set. Each image reports its seeds so any of them can be made again.
+#[tauri::command]
+async fn generate_variations(
+    app: AppHandle,
+    token: String,
+    params: GenerationParams,
+    count: usize,
+    return_mode: Option<String>,
+) -> GenerateResult {
+    let mut check = params.clone();
+    check.variation_seed.get_or_insert(0);
+    if let Err(e) = models::validate(&check) {
+        return GenerateResult::failure(e);
+    }
+    let count = count.max(1);
+    let base_seed = generation::resolve_seeds(params.seed, 1, 1, SeedMode::Fixed)[0];
+    let variation_mode = match params.variation_seed {
+        Some(_) => SeedMode::Incremental,
+        None => SeedMode::Random,
+    };
+    let variation_seeds = generation::resolve_seeds(
+        params.variation_seed.map(i64::from),
+        count,
+        1,
+        variation_mode,
+    );
+
+    let mut images = Vec::with_capacity(count);
+    let mut offline = false;
+    let mut settings_fingerprint = None;
+    let mut warnings = Vec::new();
+    let mut mismatches = Vec::new();
+    for variation_seed in variation_seeds {
+        let mut params = params.clone();
+        params.seed = Some(base_seed);
+        params.variation_seed = Some(variation_seed as u32);
+        params.n_samples = Some(1);
+        let options = GenerateOptions {
+            return_mode: return_mode.clone(),
+            ..Default::default()
+        };
+        let result = generate_image(app.clone(), token.clone(), params, Some(options)).await;
+        offline = result.offline;
+        settings_fingerprint = settings_fingerprint.or(result.settings_fingerprint);
+        warnings.extend(result.warnings);
+        mismatches.extend(result.mismatches);
+        let give_up = !result.success
+            && matches!(
+                result.error_code,
+                Some(NaiErrorKind::Unauthorized | NaiErrorKind::InsufficientAnlas)
+            );
+        images.extend(result.images);
+        if offline || give_up {
+            break;
+        }
+    }
+    let mut result = GenerateResult::from_images(images, offline, settings_fingerprint, warnings);
+    result.mismatches = mismatches;
+    result
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReproResult {
+    pub success: bool,
+    /// Nothing was generated; only `warning` and `official_settings` are set
+    pub dry_run: bool,
+    pub seed: i64,
+    /// Both runs gave the same pixels
+    pub identical: Option<bool>,
+    /// Both files were byte for byte the same. NAI writes the generation
+    /// time into the metadata, so this can be false with identical pixels.
+    pub bytes_identical: Option<bool>,
+    /// Pixel hash of each run
+    pub hashes: Vec<String>,
+    /// Settings that may explain a difference
+    pub hints: Vec<String>,
+    /// The params in NAI's settings format, to compare with the official site
+    pub official_settings: Option<String>,
+    pub warning: Option<String>,
+    pub error: Option<String>,
+    pub error_code: Option<NaiErrorKind>,
+}
+
+/// Generates the same payload twice with one seed and compares the results,
+/// to tell NAIS-side differences from server-side nondeterminism. This costs
+/// two generations of Anlas unless it falls under Opus's free limits;
+/// `dry_run` only returns that warning and the settings without generating.
+#[tauri::command]
+async fn verify_reproducibility(
+    app: AppHandle,
+    token: String,
+    params: GenerationParams,
+    dry_run: Option<bool>,
+) -> ReproResult {
+    use sha2::{Digest, Sha256};
+
+    let seed = params.seed.unwrap_or_else(generation::random_seed);
+    let mut params = prepare_params(params).await;
+    params.seed = Some(seed);
+    params.n_samples = None;
+    let payload = generation::build_payload(&params, seed);
+
+    let free = has_unlimited_generation(&app) && params.is_opus_free();
+    let mut result = ReproResult {
+        success: false,
+        dry_run: dry_run.unwrap_or(false),
+        seed,
+        identical: None,
+        bytes_identical: None,
+        hashes: Vec::new(),
+        hints: Vec::new(),
+        official_settings: nai_settings::export(&params).ok(),
+        warning: (!free).then(|| "이미지를 두 번 생성하므로 Anlas가 2회분 소모됩니다".to_string()),
+        error: models::validate(&params).err(),
+        error_code: None,
+    };
+    if result.error.is_some() {
+        return result;
+    }
+    if result.dry_run {
+        result.success = true;
+        return result;
+    }
+
+    let client = http_client(&app);
+    let mut images = Vec::with_capacity(2);
+    for _ in 0..2 {
+        match request_generation(&app, &client, &token, &payload, None).await {
+            Ok(mut samples) => images.push(samples.swap_remove(0)),
+            Err(e) => {
+                result.error = Some(e.message);
+                result.error_code = e.code;
+                return result;
+            }
+        }
+    }
+
+    let decoded: Result<Vec<Vec<u8>>, String> = images
+        .iter()
+        .map(|image| metadata::decode_image_base64(image))
+        .collect();
+    let hashed = match decoded {
+        Ok(files) => tauri::async_runtime::spawn_blocking(move || {
+            files
+                .iter()
+                .map(|bytes| {
+                    let pixels = postprocess::pixel_hash(bytes)?;
+                    Ok((pixels, format!("{:x}", Sha256::digest(bytes))))
+                })
+                .collect::<Result<Vec<_>, String>>()
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r),
+        Err(e) => Err(e),
+    };
+    let hashes = match hashed {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            result.error = Some(e);
+            return result;
+        }
+    };
+
+    let identical = hashes[0].0 == hashes[1].0;
+    result.success = true;
+    result.identical = Some(identical);
+    result.bytes_identical = Some(hashes[0].1 == hashes[1].1);
+    result.hashes = hashes.into_iter().map(|(pixels, _)| pixels).collect();
+    if !identical {
+        result.hints = generation::nondeterminism_hints(&payload);
+        if result.hints.is_empty() {
+            result
+                .hints
+                .push("알려진 비결정 요인이 없습니다. 서버 측 차이일 수 있습니다".to_string());
+        }
+    }
+    result
+}
+
+/// Cancel state of one `generate_image_stream`: the flag, and a `Notify`
+/// that wakes the request waiting on it.
+#[derive(Default)]
+struct StreamFlag {
+    cancelled: AtomicBool,
+    wakeup: tokio::sync::Notify,
+}
+
+impl StreamFlag {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.wakeup.notify_waiters();
+    }
+}
+
+/// Cancel flags of the running `generate_image_stream` calls, by stream id.
+fn stream_cancellations() -> &'static Mutex<HashMap<String, Arc<StreamFlag>>> {
+    static STREAMS: std::sync::OnceLock<Mutex<HashMap<String, Arc<StreamFlag>>>> =
+        std::sync::OnceLock::new();
+    STREAMS.get_or_init(Default::default)
+}
+
+/// One stream's cancel flag, registered under its id while the stream runs.
+/// Set by `cancel_generation_stream`.
+struct StreamCancel {
+    id: String,
+    flag: Arc<StreamFlag>,
+}
+
+impl StreamCancel {
+    fn register(id: String) -> Self {
+        let flag = Arc::new(StreamFlag::default());
+        if SHUTTING_DOWN.load(Ordering::SeqCst) {
+            flag.cancel();
+        }
+        if let Ok(mut streams) = stream_cancellations().lock() {
+            streams.insert(id.clone(), flag.clone());
+        }
+        StreamCancel { id, flag }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.flag.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Runs `future` until it finishes or the stream is cancelled, whichever
+    /// comes first. Dropping the request closes its connection, so a stalled
+    /// stream or a regular request stops right away.
+    async fn run<T>(
+        &self,
+        future: impl std::future::Future<Output = Result<T, RequestError>>,
+    ) -> Result<T, RequestError> {
+        let cancelled = self.flag.wakeup.notified();
+        tokio::pin!(cancelled);
+        // Registers for the wakeup before checking the flag, so a cancel
+        // between the two isn't missed
+        cancelled.as_mut().enable();
+        if self.is_cancelled() {
+            return Err("생성이 취소되었습니다".to_string().into());
+        }
+        tokio::select! {
+            output = future => output,
+            _ = cancelled => Err("생성이 취소되었습니다".to_string().into()),
+        }
+    }
+}
+
+impl Drop for StreamCancel {
+    fn drop(&mut self) {
+        if let Ok(mut streams) = stream_cancellations().lock() {
+            // A newer stream may have reused the id
+            if streams
+                .get(&self.id)
+                .is_some_and(|flag| Arc::ptr_eq(flag, &self.flag))
+            {
+                streams.remove(&self.id);
+            }
+        }
+    }
+}
+
+/// Cancels the stream `id`, or every stream without one.
+fn cancel_streams(id: Option<&str>) {
+    if let Ok(streams) = stream_cancellations().lock() {
+        for (stream_id, flag) in streams.iter() {
+            if id.map_or(true, |id| id == stream_id) {
+                flag.cancel();
+            }
+        }
+    }
+}
+
+/// Payload of `generation-preview`. Progress is `step / total_steps`.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationPreview {
+    pub stream_id: String,
+    pub step: u32,
+    pub total_steps: u32,
+    /// Base64 JPEG
+    pub image_data: String,
+}
+
+/// Payload of `generation-complete`.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationComplete {
+    pub stream_id: String,
+    pub seed: i64,
+    pub image_data: String,
+    /// `false` when the model has no streaming support and the regular
+    /// endpoint was used instead
+    pub streamed: bool,
+}
+
+/// Payload of `generation-error`.
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationStreamError {
+    pub stream_id: String,
+    pub message: String,
+    pub error_code: Option<NaiErrorKind>,
+    pub cancelled: bool,
+    pub offline: bool,
+}
+
+async fn request_generation_stream(
+    app: &AppHandle,
+    token: &str,
+    mut payload: serde_json::Value,
+    total_steps: u32,
+    stream_id: &str,
+) -> Result<String, RequestError> {
+    payload["parameters"]["stream"] = serde_json::Value::from("sse");
+    let mut response = send_generation(
+        app,
+        &http_client(app),
+        "https://image.novelai.net/ai/generate-image-stream",
+        token,
+        &payload,
+        None,
+    )
+    .await?;
+
+    let mut parser = stream::SseParser::default();
+    loop {
+        let chunk = response
+            .chunk()
+            .await
+            .map_err(|e| format!("응답 읽기 오류: {}", e))?;
+        let events = match &chunk {
+            Some(chunk) => parser.feed(chunk),
+            None => parser.finish().into_iter().collect(),
+        };
+
+        for event in events {
+            match event {
+                StreamEvent::Intermediate { step, image } => {
+                    let _ = app.emit(
+                        "generation-preview",
+                        GenerationPreview {
+                            stream_id: stream_id.to_string(),
+                            step,
+                            total_steps,
+                            image_data: image,
+                        },
+                    );
+                }
+                StreamEvent::Final { image } => return Ok(image),
+                StreamEvent::Error { message } => {
+                    return Err(format!("스트리밍 오류: {}", message).into());
+                }
+            }
+        }
+
+        if chunk.is_none() {
+            return Err("최종 이미지 없이 스트림이 끝났습니다".to_string().into());
+        }
+    }
+}
+
+/// Generates one image and streams the intermediate steps as
+/// `generation-preview` events, then sends `generation-complete` (or
+/// `generation-error`). Models without streaming support fall back to the
+/// regular endpoint, so only the completion event is sent for them.
+/// Every event carries `stream_id` (a new one when none is passed), which
+/// `cancel_generation_stream` takes to stop just this stream.
+#[tauri::command]
+async fn generate_image_stream(
+    app: AppHandle,
+    token: String,
+    params: GenerationParams,
+    stream_id: Option<String>,
+) -> GenerateResult {
+    static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+    let stream_id = stream_id
+        .unwrap_or_else(|| format!("stream-{}", NEXT_STREAM_ID.fetch_add(1, Ordering::SeqCst)));
+    let cancel = StreamCancel::register(stream_id.clone());
+    if let Err(e) = models::validate(&params) {
+        let _ = app.emit(
+            "generation-error",
+            GenerationStreamError {
+                stream_id,
+                message: e.clone(),
+                error_code: None,
+                cancelled: false,
+                offline: false,
+            },
+        );
+        return GenerateResult::failure(e);
+    }
+    let seed = params.seed.unwrap_or_else(generation::random_seed);
+    let mut params = prepare_params(params).await;
+    // Previews are for a single image
+    params.n_samples = None;
+    let payload = generation::build_payload(&params, seed);
+    let fingerprint = generation::payload_fingerprint(&payload, true);
+    let settings_fingerprint = Some(generation::payload_fingerprint(&payload, false));
+
+    let streamed = stream::supports_streaming(&params.model);
+    let unlimited = has_unlimited_generation(&app);
+    let track_anlas = !(unlimited && params.is_opus_free());
+    let balance_before = if track_anlas {
+        last_known_anlas(&app, &token)
+    } else {
+        None
+    };
+    let started = std::time::Instant::now();
+    let result = if streamed {
+        until_exit(cancel.run(request_generation_stream(
+            &app,
+            &token,
+            payload,
+            params.steps,
+            &cancel.id,
+        )))
+        .await
+        .unwrap_or_else(|e| Err(e.into()))
+    } else {
+        let client = http_client(&app);
+        cancel
+            .run(request_generation(&app, &client, &token, &payload, None))
+            .await
+            .map(|mut images| images.swap_remove(0))
+    };
+    let anlas_charged = match &result {
+        Err(e) if track_anlas && e.code == Some(NaiErrorKind::ServerError) => {
+            check_anlas_charged(&app, &token, balance_before, 0).await
+        }
+        _ => None,
+    };
+    let cost = params.estimate_anlas(unlimited);
+    session_report::record(session_report::Generation {
+        model: &params.model,
+        succeeded: u32::from(result.is_ok()),
+        failed: u32::from(result.is_err()),
+        error_code: result.as_ref().err().and_then(|e| e.code),
+        duration: started.elapsed(),
+        anlas: if result.is_ok() || anlas_charged == Some(true) {
+            cost
+        } else {
+            0
+        },
+    });
+
+    match result {
+        Ok(image_data) => {
+            let _ = app.emit(
+                "generation-complete",
+                GenerationComplete {
+                    stream_id,
+                    seed,
+                    image_data: image_data.clone(),
+                    streamed,
+                },
+            );
+            GenerateResult {
+                success: true,
+                image_data: Some(image_data.clone()),
+                seed: Some(seed),
+                images: vec![GeneratedImage {
+                    seed,
+                    image_data: Some(image_data),
+                    error: None,
+                    error_code: None,
+                    anlas_charged: None,
+                    fingerprint: Some(fingerprint),
+                    path: None,
+                    width: None,
+                    height: None,
+                    variation_seed: None,
+                }],
+                error: None,
+                error_code: None,
+                anlas_charged: None,
+                offline: false,
+                settings_fingerprint,
+                path: None,
+                warnings: Vec::new(),
+                mismatches: Vec::new(),
+            }
+        }
+        Err(e) => {
+            let _ = app.emit(
+                "generation-error",
+                GenerationStreamError {
+                    stream_id,
+                    message: e.message.clone(),
+                    error_code: e.code,
+                    cancelled: cancel.is_cancelled(),
+                    offline: e.offline,
+                },
+            );
+            GenerateResult {
+                success: false,
+                image_data: None,
+                seed: Some(seed),
+                images: vec![GeneratedImage {
+                    seed,
+                    image_data: None,
+                    error: Some(e.message.clone()),
+                    error_code: e.code,
+                    anlas_charged,
+                    fingerprint: Some(fingerprint),
+                    path: None,
+                    width: None,
+                    height: None,
+                    variation_seed: None,
+                }],
+                error: Some(e.message),
+                error_code: e.code,
+                anlas_charged,
+                offline: e.offline,
+                settings_fingerprint,
+                path: None,
+                warnings: Vec::new(),
+                mismatches: Vec::new(),
+            }
+        }
+    }
+}
+
+/// Stops the `generate_image_stream` with `stream_id` (every running one
+/// when omitted) right away, also while it waits for the server.
+#[tauri::command]
+fn cancel_generation_stream(stream_id: Option<String>) {
+    cancel_streams(stream_id.as_deref());
+}
+
+#[derive(Default)]
+struct GenerationQueueState {
+    queue: Mutex<queue::GenerationQueue>,
+    wakeup: tokio::sync::Notify,
+}
+
+/// Payload of `queue-item-complete`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueItemComplete {
+    pub id: u64,
+    pub result: GenerateResult,
+}
+
+fn emit_queue_status(app: &AppHandle) {
+    let state = app.state::<GenerationQueueState>();
+    let status = state.queue.lock().map(|queue| queue.status());
+    if let Ok(status) = status {
+        let _ = app.emit("queue-updated", status);
+    }
+}
+
+fn wildcard_dir(app: &AppHandle) -> Result<std::path::PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(dir.join("wildcards"))
+}
+
+/// `wildcards::expand` with files from the wildcard folder, each read once.
+fn expand_with_files(app: &AppHandle, texts: &mut [&mut String], seed: u32) -> Result<(), String> {
+    let dir = wildcard_dir(app)?;
+    let mut files: HashMap<String, Vec<String>> = HashMap::new();
+    let mut load = |name: &str| match files.get(name) {
+        Some(lines) => Ok(lines.clone()),
+        None => {
+            let lines = wildcards::read_file(&dir, name)?;
+            files.insert(name.to_string(), lines.clone());
+            Ok(lines)
+        }
+    };
+    for text in texts {
+        **text = wildcards::expand(text, seed, &mut load)?;
+    }
+    Ok(())
+}
+
+/// Expands the wildcards in every prompt of `params` with its seed. A
+/// missing seed is fixed to a random one first, so the image's seed alone
+/// is enough to get the same prompts again.
+fn expand_params_wildcards(app: &AppHandle, params: &mut GenerationParams) -> Result<(), String> {
+    let seed = *params.seed.get_or_insert_with(generation::random_seed);
+    let mut texts: Vec<&mut String> = vec![&mut params.prompt, &mut params.negative_prompt];
+    for character in &mut params.character_prompts {
+        texts.push(&mut character.prompt);
+        texts.push(&mut character.negative);
+    }
+    expand_with_files(app, &mut texts, seed as u32)
+}
+
+/// Expands `{a|b|c}` choices and `__file__` references in `prompt` (see
+/// `wildcards`). The same seed always gives the same prompt.
+#[tauri::command]
+fn expand_wildcards(app: AppHandle, prompt: String, seed: u32) -> Result<String, String> {
+    let mut prompt = prompt;
+    expand_with_files(&app, &mut [&mut prompt], seed)?;
+    Ok(prompt)
+}
+
+/// The folder `__name__` wildcards are read from (`name.txt`), created if
+/// it doesn't exist yet.
+#[tauri::command]
+fn get_wildcard_dir(app: AppHandle) -> Result<String, String> {
+    let dir = wildcard_dir(&app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir.to_string_lossy().to_string())
+}
+
+/// Runs queued generations one after another, emitting
+/// `queue-item-complete` for each and `queue-updated` whenever the queue
+/// changes.
+fn spawn_queue_worker(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let state = app.state::<GenerationQueueState>();
+            let next = state.queue.lock().ok().and_then(|mut q| q.start_next());
+            let Some(item) = next else {
+                state.wakeup.notified().await;
+                continue;
+            };
+            emit_queue_status(&app);
+
+            // Each item draws its own wildcards, from its own seed
+            let mut params = item.params;
+            let result = match expand_params_wildcards(&app, &mut params) {
+                Ok(()) => generate_image(app.clone(), item.token, params, None).await,
+                Err(e) => GenerateResult::failure(e),
+            };
+            if let Ok(mut queue) = state.queue.lock() {
+                queue.finish();
+            }
+            let _ = app.emit(
+                "queue-item-complete",
+                QueueItemComplete {
+                    id: item.id,
+                    result,
+                },
+            );
+            emit_queue_status(&app);
+        }
+    });
+}
+
+/// Adds a generation to the queue and returns its id. Higher `priority`
+/// (default 0) runs sooner; equal priorities run first come, first served.
+#[tauri::command]
+fn enqueue_generation(
+    app: AppHandle,
+    state: tauri::State<'_, GenerationQueueState>,
+    token: String,
+    params: GenerationParams,
+    priority: Option<i32>,
+) -> Result<u64, String> {
+    models::validate(&params)?;
+    let id =
+        state
+            .queue
+            .lock()
+            .map_err(|e| e.to_string())?
+            .push(token, params, priority.unwrap_or(0));
+    state.wakeup.notify_one();
+    emit_queue_status(&app);
+    Ok(id)
+}
+
+/// Moves a waiting item to `new_index` in the run order (0 = next). The
+/// item takes the priority of the item it lands behind (at the front, the
+/// one it lands before), which `queue-updated` reports.
+#[tauri::command]
+fn reorder_queue(
+    app: AppHandle,
+    state: tauri::State<'_, GenerationQueueState>,
+    id: u64,
+    new_index: usize,
+) -> Result<(), String> {
+    state
+        .queue
+        .lock()
+        .map_err(|e| e.to_string())?
+        .reorder(id, new_index)?;
+    emit_queue_status(&app);
+    Ok(())
+}
+
+#[tauri::command]
+fn set_queue_priority(
+    app: AppHandle,
+    state: tauri::State<'_, GenerationQueueState>,
+    id: u64,
+    priority: i32,
+) -> Result<(), String> {
+    state
+        .queue
+        .lock()
+        .map_err(|e| e.to_string())?
+        .set_priority(id, priority)?;
+    emit_queue_status(&app);
+    Ok(())
+}
+
+/// The running item and the waiting ones in the order they will run.
+#[tauri::command]
+fn get_queue_status(
+    state: tauri::State<'_, GenerationQueueState>,
+) -> Result<queue::QueueStatus, String> {
+    Ok(state.queue.lock().map_err(|e| e.to_string())?.status())
+}
+
+/// Runs one pipeline step on `input` (base64) and returns its output image.
+async fn run_pipeline_step(
+    app: &AppHandle,
+    token: &str,
+    step: &pipeline::PipelineStep,
+    input: Option<String>,
+) -> Result<String, String> {
+    use pipeline::PipelineStep;
+
+    let missing = || "결과 이미지가 없습니다".to_string();
+    if let PipelineStep::Generate { params, use_input } = step {
+        let mut params = (**params).clone();
+        if *use_input {
+            params.source_image = Some(input.ok_or_else(|| "입력 이미지가 없습니다".to_string())?);
+        }
+        let result = generate_image(app.clone(), token.to_string(), params, None).await;
+        return match result.success {
+            true => result.image_data.ok_or_else(missing),
+            false => Err(result.error.unwrap_or_else(missing)),
+        };
+    }
+
+    let input = ImageSource::Base64(input.ok_or_else(|| "입력 이미지가 없습니다".to_string())?);
+    let (image_data, error) = match step {
+        PipelineStep::Generate { .. } => unreachable!("handled above"),
+        PipelineStep::Upscale { scale } => {
+            let result = upscale_image(
+                app.clone(),
+                token.to_string(),
+                input,
+                None,
+                None,
+                *scale,
+                None,
+                None,
+                None,
+                None,
+            )
+            .await;
+            (result.image_data, result.error)
+        }
+        PipelineStep::Augment(augment_step) => {
+            let result = augment_pipeline(
+                app.clone(),
+                token.to_string(),
+                vec![augment_step.clone()],
+                input,
+                None,
+                None,
+            )
+            .await;
+            (result.image_data, result.error)
+        }
+        PipelineStep::RemoveBackground { feather } => {
+            let result = remove_background(app.clone(), input, None, *feather).await;
+            // The only step that answers with a data URL; keep every
+            // step's output bare base64
+            let image_data = result
+                .image_data
+                .map(|data| match data.split_once(";base64,") {
+                    Some((prefix, base64)) if prefix.starts_with("data:") => base64.to_string(),
+                    _ => data,
+                });
+            (image_data, result.error)
+        }
+        PipelineStep::Convert {
+            target,
+            metadata_embed,
+        } => {
+            let result = convert_image(input, target.clone(), *metadata_embed, None).await;
+            (result.image_data, result.error)
+        }
+    };
+    match error {
+        Some(error) => Err(error),
+        None => image_data.ok_or_else(missing),
+    }
+}
+
+/// Runs `steps` in order, each on the previous step's output (or on `input`
+/// for the first one), e.g. generate → upscale → background removal.
+/// Sends `pipeline_progress` as each step starts and ends. When a step
+/// fails, the rest are marked skipped and the outputs of the steps before
+/// it are still returned.
+#[tauri::command]
+async fn run_pipeline(
+    app: AppHandle,
+    token: String,
+    steps: Vec<pipeline::PipelineStep>,
+    input: Option<ImageSource>,
+) -> pipeline::PipelineResult {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use pipeline::{PipelineProgress, PipelineResult, PipelineStepResult};
+
+    let started = std::time::Instant::now();
+    let mut result = PipelineResult {
+        success: false,
+        image_data: None,
+        steps: Vec::new(),
+        failed_step: None,
+        error: None,
+        duration_ms: 0,
+    };
+    if let Err(e) = pipeline::validate(&steps, input.is_some()) {
+        result.error = Some(e);
+        return result;
+    }
+    let mut image = match input {
+        Some(input) => match input.read().await {
+            Ok(bytes) => Some(STANDARD.encode(bytes)),
+            Err(e) => {
+                result.error = Some(e);
+                return result;
+            }
+        },
+        None => None,
+    };
+
+    let total = steps.len();
+    for (index, step) in steps.iter().enumerate() {
+        let kind = step.kind();
+        if result.failed_step.is_some() {
+            result.steps.push(PipelineStepResult {
+                index,
+                kind,
+                success: false,
+                skipped: true,
+                image_data: None,
+                duration_ms: 0,
+                error: None,
+            });
+            continue;
+        }
+
+        let progress = |status| PipelineProgress {
+            index,
+            total,
+            kind,
+            status,
+        };
+        let _ = app.emit("pipeline_progress", progress("started"));
+        let step_started = std::time::Instant::now();
+        let output = run_pipeline_step(&app, &token, step, image.clone()).await;
+        let duration_ms = step_started.elapsed().as_millis() as u64;
+
+        match output {
+            Ok(output) => {
+                let _ = app.emit("pipeline_progress", progress("done"));
+                image = Some(output.clone());
+                result.steps.push(PipelineStepResult {
+                    index,
+                    kind,
+                    success: true,
+                    skipped: false,
+                    image_data: Some(output),
+                    duration_ms,
+                    error: None,
+                });
+            }
+            Err(e) => {
+                log::warn!("Pipeline step {} ({}) failed: {}", index, kind, e);
+                let _ = app.emit("pipeline_progress", progress("failed"));
+                result.failed_step = Some(index);
+                result.error = Some(e.clone());
+                result.steps.push(PipelineStepResult {
+                    index,
+                    kind,
+                    success: false,
+                    skipped: false,
+                    image_data: None,
+                    duration_ms,
+                    error: Some(e),
+                });
+            }
+        }
+    }
+
+    result.success = result.failed_step.is_none();
+    result.image_data = result
+        .steps
+        .iter()
+        .rev()
+        .find_map(|step| step.image_data.clone());
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+fn read_pipelines(app: &AppHandle) -> Result<Vec<pipeline::SavedPipeline>, String> {
+    let store = app
+        .store(pipeline::PIPELINE_STORE_FILE)
+        .map_err(|e| e.to_string())?;
+    Ok(pipeline::from_store_value(
+        store.get(pipeline::PIPELINE_STORE_KEY),
+    ))
+}
+
+fn write_pipelines(app: &AppHandle, list: &[pipeline::SavedPipeline]) -> Result<(), String> {
+    let store = app
+        .store(pipeline::PIPELINE_STORE_FILE)
+        .map_err(|e| e.to_string())?;
+    let value = serde_json::to_value(list).map_err(|e| e.to_string())?;
+    store.set(pipeline::PIPELINE_STORE_KEY, value);
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Saves a pipeline definition for `run_pipeline`. An existing one with the
+/// same name is only replaced when `overwrite` is true.
+#[tauri::command]
+async fn save_pipeline(
+    app: AppHandle,
+    name: String,
+    steps: Vec<pipeline::PipelineStep>,
+    overwrite: Option<bool>,
+) -> Result<pipeline::SavedPipeline, String> {
+    if steps.is_empty() {
+        return Err("파이프라인에 단계가 없습니다".to_string());
+    }
+    let now = presets::now_millis();
+    let saved = pipeline::SavedPipeline {
+        name: pipeline::validate_name(&name)?,
+        steps,
+        created_at: now,
+        updated_at: now,
+    };
+
+    let mut list = read_pipelines(&app)?;
+    pipeline::upsert(&mut list, saved.clone(), overwrite.unwrap_or(false))?;
+    write_pipelines(&app, &list)?;
+    Ok(list
+        .into_iter()
+        .find(|p| p.name == saved.name)
+        .unwrap_or(saved))
+}
+
+#[tauri::command]
+async fn list_pipelines(app: AppHandle) -> Result<Vec<pipeline::SavedPipeline>, String> {
+    read_pipelines(&app)
+}
+
+/// Returns whether a pipeline was actually removed.
+#[tauri::command]
+async fn delete_pipeline(app: AppHandle, name: String) -> Result<bool, String> {
+    let mut list = read_pipelines(&app)?;
+    let before = list.len();
+    list.retain(|p| p.name != name.trim());
+    if list.len() == before {
+        return Ok(false);
+    }
+    write_pipelines(&app, &list)?;
+    Ok(true)
+}
+
+#[tauri::command]
+async fn parse_metadata(image: ImageSource) -> MetadataResult {
+    let bytes = match image.read().await {
+        Ok(bytes) => bytes,
+        Err(e) => return MetadataResult::failure(e),
+    };
+
+    tauri::async_runtime::spawn_blocking(move || metadata::read_embedded_metadata(&bytes))
+        .await
+        .map(MetadataResult::from)
+        .unwrap_or_else(|e| MetadataResult::failure(e.to_string()))
+}
+
+/// Downloads `url` with the `DownloadHttpClient`, refusing internal
+/// addresses and anything that isn't an image or is larger than
+/// `url_import::MAX_IMAGE_BYTES`. `referer` is the page the image was on,
+/// which some hosts require.
+async fn download_image(
+    client: &reqwest::Client,
+    url: &Url,
+    referer: Option<&str>,
+) -> Result<Vec<u8>, String> {
+    url_import::check_public_host(url)?;
+    let mut request = client.get(url.clone()).timeout(Duration::from_secs(60));
+    if let Some(referer) = referer {
+        request = request.header(reqwest::header::REFERER, referer);
+    }
+    let mut response = request.send().await.map_err(|e| {
+        // Refused by the resolver or the redirect policy
+        let internal = std::iter::successors(Some(&e as &dyn std::error::Error), |e| e.source())
+            .any(|e| e.to_string() == url_import::INTERNAL_ADDRESS_ERROR);
+        match internal {
+            true => url_import::INTERNAL_ADDRESS_ERROR.to_string(),
+            false => format!("이미지 다운로드 오류: {}", e),
+        }
+    })?;
+    if !response.status().is_success() {
+        return Err(url_import::download_error(response.status().as_u16()));
+    }
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+    if is_html {
+        return Err("이미지 대신 웹 페이지가 반환되었습니다 (핫링크 차단?)".to_string());
+    }
+    if response
+        .content_length()
+        .is_some_and(|len| len > url_import::MAX_IMAGE_BYTES)
+    {
+        return Err("이미지가 너무 큽니다".to_string());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("이미지 다운로드 오류: {}", e))?
+    {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() as u64 > url_import::MAX_IMAGE_BYTES {
+            return Err("이미지가 너무 큽니다".to_string());
+        }
+    }
+    Ok(bytes)
+}
+
+/// Looks up a Danbooru post. Failures only cost the tags, so they are
+/// logged rather than returned.
+async fn fetch_danbooru_post(
+    client: &reqwest::Client,
+    post: &url_import::DanbooruPost,
+) -> Option<(url_import::BooruTags, Option<String>)> {
+    let response = client
+        .get(post.api_url())
+        .timeout(Duration::from_secs(20))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    let value = match response {
+        Ok(response) => response.json::<serde_json::Value>().await,
+        Err(e) => Err(e),
+    };
+    match value {
+        Ok(value) => url_import::parse_post(&value),
+        Err(e) => {
+            log::warn!("Danbooru lookup failed for {:?}: {}", post, e);
+            None
+        }
+    }
+}
+
+async fn import_url(
+    app: &AppHandle,
+    url: &str,
+    link_url: Option<&str>,
+    page_url: Option<&str>,
+) -> Result<MetadataResult, String> {
+    let (bytes, booru_tags) = if url.starts_with("data:") {
+        (metadata::decode_image_base64(url)?, None)
+    } else {
+        let image_url = url_import::parse_image_url(url)?;
+        let client = http_client(app);
+        // The image itself names the post most reliably, then the link
+        // around it, then the page it was on
+        let post = std::iter::once(Some(image_url.clone()))
+            .chain([link_url, page_url].map(|u| u.and_then(|u| Url::parse(u).ok())))
+            .flatten()
+            .find_map(|u| url_import::danbooru_post(&u));
+        let found = match &post {
+            Some(post) => fetch_danbooru_post(&client, post).await,
+            None => None,
+        };
+        let (booru_tags, file_url) = found.unzip();
+        // A dragged post link has to be resolved to its file
+        let image_url = if url_import::is_post_page(&image_url) {
+            let file_url = file_url.flatten().ok_or_else(|| {
+                "게시물의 원본 이미지 주소를 찾을 수 없습니다 (로그인이 필요한 게시물?)".to_string()
+            })?;
+            url_import::parse_image_url(&file_url)?
+        } else {
+            image_url
+        };
+        let bytes = download_image(&download_client(app), &image_url, page_url).await?;
+        (bytes, booru_tags)
+    };
+
+    if image::guess_format(&bytes).is_err() {
+        return Err("지원하지 않는 이미지 형식입니다".to_string());
+    }
+    let mut result =
+        tauri::async_runtime::spawn_blocking(move || metadata::read_embedded_metadata(&bytes))
+            .await
+            .map(MetadataResult::from)
+            .map_err(|e| e.to_string())?;
+    result.booru_tags = booru_tags;
+    Ok(result)
+}
+
+/// Reads the settings out of an image on the web, like `parse_metadata`.
+/// For Danbooru images `booru_tags` also holds the post's tags as a prompt,
+/// found from the image, `link_url` or `page_url`. A `url` dragged out of
+/// the embedded browser comes with `embedded-browser-image-dragged`.
+#[tauri::command]
+async fn import_from_url(
+    app: AppHandle,
+    url: String,
+    link_url: Option<String>,
+    page_url: Option<String>,
+) -> MetadataResult {
+    import_url(&app, &url, link_url.as_deref(), page_url.as_deref())
+        .await
+        .unwrap_or_else(MetadataResult::failure)
+}
+
+/// Settings to write into an image with `inject_metadata`.
+#[derive(Debug, Deserialize)]
+pub struct GenerationMetadata {
+    /// In NAI's format (`prompt`, `uc`, `seed`, `steps`, `scale`, ...); the
+    /// ones left out get defaults
+    pub settings: serde_json::Value,
+    /// Keep the image's current settings and only replace the given ones
+    #[serde(default)]
+    pub merge: bool,
+    /// Also write stealth pnginfo into the alpha channel
+    #[serde(default)]
+    pub stealth: bool,
+}
+
+/// Writes generation settings into an image that lost them (screenshots,
+/// edits), as NAI's PNG text chunks and optionally stealth pnginfo. The
+/// result is read back and compared before it is returned as a base64 PNG.
+#[tauri::command]
+async fn inject_metadata(
+    image_base64: ImageSource,
+    metadata: GenerationMetadata,
+) -> Result<String, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let bytes = image_base64.read().await?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let existing = metadata::read_embedded_metadata(&bytes);
+        let fields =
+            nai_settings::metadata_fields(metadata.settings, existing.as_ref(), metadata.merge)?;
+        let png = match image::guess_format(&bytes) {
+            Ok(image::ImageFormat::Png) => bytes,
+            _ => {
+                let decoded = image::load_from_memory(&bytes)
+                    .map_err(|e| format!("이미지 디코딩 오류: {}", e))?;
+                let mut png = Vec::new();
+                decoded
+                    .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                    .map_err(|e| e.to_string())?;
+                png
+            }
+        };
+        let png = metadata::embed_png_metadata(&png, &fields, metadata.stealth)?;
+        metadata::verify_png_metadata(&png, &fields, metadata.stealth)?;
+        Ok(STANDARD.encode(png))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConvertResult {
+    pub success: bool,
+    pub image_data: Option<String>,
+    pub path: Option<String>,
+    pub error: Option<String>,
+}
+
+impl ConvertResult {
+    fn failure(error: String) -> Self {
+        ConvertResult {
+            success: false,
+            image_data: None,
+            path: None,
+            error: Some(error),
+        }
+    }
+}
+
+async fn convert_to_target(
+    image: ImageSource,
+    target: &str,
+    metadata_embed: Option<MetadataEmbed>,
+) -> Result<(Vec<u8>, ConvertTarget), String> {
+    let target = ConvertTarget::parse(target)?;
+    let bytes = image.read().await?;
+    let embed = metadata_embed.unwrap_or_default();
+
+    let converted = tauri::async_runtime::spawn_blocking(move || {
+        metadata::convert_image_bytes(&bytes, target, embed)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok((converted, target))
+}
+
+/// Converts an image to `png`, `jpeg` or `webp-lossless`. For WebP the NAI
+/// metadata is carried over as EXIF, alpha LSB, or both (`metadata_embed`).
+/// With `return_path` the result is written there as-is instead of being
+/// returned as base64.
+#[tauri::command]
+async fn convert_image(
+    image: ImageSource,
+    target: String,
+    metadata_embed: Option<MetadataEmbed>,
+    return_path: Option<String>,
+) -> ConvertResult {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let bytes = match convert_to_target(image, &target, metadata_embed).await {
+        Ok((bytes, _)) => bytes,
+        Err(e) => return ConvertResult::failure(e),
+    };
+    match return_path {
+        Some(path) => match image_source::write_image_file(&path, &bytes).await {
+            Ok(path) => ConvertResult {
+                success: true,
+                image_data: None,
+                path: Some(path),
+                error: None,
+            },
+            Err(e) => ConvertResult::failure(e),
+        },
+        None => ConvertResult {
+            success: true,
+            image_data: Some(STANDARD.encode(&bytes)),
+            path: None,
+            error: None,
+        },
+    }
+}
+
+/// Same as `convert_image`, but writes the result to `path`. The extension is
+/// replaced to match the target format.
+#[tauri::command]
+async fn save_image(
+    image: ImageSource,
+    path: String,
+    target: String,
+    metadata_embed: Option<MetadataEmbed>,
+) -> ConvertResult {
+    let (bytes, target) = match convert_to_target(image, &target, metadata_embed).await {
+        Ok(converted) => converted,
+        Err(e) => return ConvertResult::failure(e),
+    };
+
+    let path = std::path::PathBuf::from(path).with_extension(target.extension());
+    if let Some(parent) = path.parent() {
+        if let Err(e) = tokio::fs::create_dir_all(parent).await {
+            return ConvertResult::failure(format!("폴더 생성 오류: {}", e));
+        }
+    }
+
+    match tokio::fs::write(&path, &bytes).await {
+        Ok(()) => ConvertResult {
+            success: true,
+            image_data: None,
+            path: Some(path.to_string_lossy().to_string()),
+            error: None,
+        },
+        Err(e) => ConvertResult::failure(format!("파일 저장 오류: {}", e)),
+    }
+}
+
+/// Shrinks an image for posting somewhere with an upload limit: at most
+/// `max_dimension` on the long side and `max_bytes` in size, as JPEG (or
+/// lossless WebP when it has transparency). Without `keep_metadata` the NAI
+/// metadata, stealth pnginfo included, is left out. When the limit can't be
+/// met the smallest result is returned with a `warning`.
+#[tauri::command]
+async fn prepare_for_share(
+    image_base64: ImageSource,
+    max_bytes: usize,
+    max_dimension: u32,
+    keep_metadata: bool,
+) -> Result<share::SharedImage, String> {
+    let bytes = image_base64.read().await?;
+    tauri::async_runtime::spawn_blocking(move || {
+        share::prepare(&bytes, max_bytes, max_dimension, keep_metadata)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+const SETTINGS_STORE_FILE: &str = "settings.json";
+const SETTINGS_ORGANIZE_BY_KEY: &str = "save_organize_by";
+
+/// The subfolder rule `save_image_auto` uses when none is passed.
+fn stored_organize_by(app: &AppHandle) -> autosave::OrganizeBy {
+    app.store(SETTINGS_STORE_FILE)
+        .ok()
+        .and_then(|store| store.get(SETTINGS_ORGANIZE_BY_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+/// Saves the default subfolder rule for auto-save: `none`, `date`, `model`
+/// or `date_model`.
+#[tauri::command]
+fn set_save_organization(app: AppHandle, organize_by: String) -> Result<(), String> {
+    let organize_by = autosave::OrganizeBy::parse(&organize_by)?;
+    let store = app.store(SETTINGS_STORE_FILE).map_err(|e| e.to_string())?;
+    store.set(SETTINGS_ORGANIZE_BY_KEY, serde_json::json!(organize_by));
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Auto-save for generation results: writes the image into `directory`,
+/// sorted into date and/or model subfolders per `organize_by` (the stored
+/// default when omitted). Missing folders are created and an existing file
+/// is never overwritten. Without `file_name` the current time is used.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn save_image_auto(
+    app: AppHandle,
+    image: ImageSource,
+    directory: String,
+    model: String,
+    file_name: Option<String>,
+    target: Option<String>,
+    metadata_embed: Option<MetadataEmbed>,
+    organize_by: Option<String>,
+) -> ConvertResult {
+    let organize_by = match organize_by {
+        Some(value) => match autosave::OrganizeBy::parse(&value) {
+            Ok(organize_by) => organize_by,
+            Err(e) => return ConvertResult::failure(e),
+        },
+        None => stored_organize_by(&app),
+    };
+    let target = target.as_deref().unwrap_or("png");
+    let (bytes, target) = match convert_to_target(image, target, metadata_embed).await {
+        Ok(converted) => converted,
+        Err(e) => return ConvertResult::failure(e),
+    };
+
+    let now = chrono::Local::now();
+    let dir = autosave::target_dir(std::path::Path::new(&directory), organize_by, &model, now);
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        return ConvertResult::failure(format!("폴더 생성 오류: {}", e));
+    }
+    let extension = target.extension();
+    let saved = tauri::async_runtime::spawn_blocking(move || {
+        autosave::write_new(&dir, file_name.as_deref(), extension, now, &bytes)
+    })
+    .await
+    .map_err(|e| e.to_string());
+
+    match saved {
+        Ok(Ok(path)) => ConvertResult {
+            success: true,
+            image_data: None,
+            path: Some(path.to_string_lossy().to_string()),
+            error: None,
+        },
+        Ok(Err(e)) => ConvertResult::failure(format!("파일 저장 오류: {}", e)),
+        Err(e) => ConvertResult::failure(e),
+    }
+}
+
+/// Converts `(tag:1.2)`-style weights into NAI emphasis for `model`.
+#[tauri::command]
+fn normalize_prompt_weights(prompt: String, model: String) -> String {
+    prompt::normalize_prompt_weights(&prompt, &model)
+}
+
+/// Rewrites prompt weights from one syntax to another (`a1111`, `nai-v4`,
+/// `nai-v3`). Anything that can't be converted is kept and listed in
+/// `warnings`.
+#[tauri::command]
+fn convert_prompt_weights(
+    prompt: String,
+    from: String,
+    to: String,
+) -> Result<prompt_convert::PromptConversion, String> {
+    let from = prompt_convert::WeightSyntax::parse(&from)?;
+    let to = prompt_convert::WeightSyntax::parse(&to)?;
+    Ok(prompt_convert::convert(&prompt, from, to))
+}
+
+/// Folds line breaks into `, ` the way NAI combines multi-line prompts.
+#[tauri::command]
+fn normalize_prompt_newlines(text: String) -> String {
+    prompt::normalize_prompt_newlines(&text)
+}
+
+/// The models NAIS supports, with their features, defaults and size limits.
+#[tauri::command]
+fn list_models() -> Vec<models::ModelInfo> {
+    models::all().to_vec()
+}
+
+/// Official UC preset text for `model` at index `preset_id`; empty for "None".
+#[tauri::command]
+fn resolve_uc_preset(model: String, preset_id: u32) -> String {
+    uc_presets::resolve_uc_preset(&model, preset_id)
+}
+
+fn read_lint_rules(app: &AppHandle) -> Result<Vec<prompt_lint::LintRule>, String> {
+    let store = app
+        .store(prompt_lint::LINT_STORE_FILE)
+        .map_err(|e| e.to_string())?;
+    match store.get(prompt_lint::LINT_STORE_KEY) {
+        Some(value) => serde_json::from_value(value).map_err(|e| e.to_string()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Warnings about tags in `prompt` and `uc` (contradictions, duplicates,
+/// typos, tags NAI may block), checked locally against the built-in rules
+/// and the user's own.
+#[tauri::command]
+fn lint_prompt(app: AppHandle, prompt: String, uc: String) -> Vec<prompt_lint::PromptLint> {
+    let custom = read_lint_rules(&app).unwrap_or_else(|e| {
+        log::warn!("Ignoring unreadable lint rules: {}", e);
+        Vec::new()
+    });
+    prompt_lint::lint_prompt(&prompt, &uc, &custom)
+}
+
+/// The user's lint rules; with `include_builtin`, the built-in ones first.
+#[tauri::command]
+fn get_lint_rules(
+    app: AppHandle,
+    include_builtin: Option<bool>,
+) -> Result<Vec<prompt_lint::LintRule>, String> {
+    let mut rules = if include_builtin.unwrap_or(false) {
+        prompt_lint::builtin_rules()
+    } else {
+        Vec::new()
+    };
+    rules.extend(read_lint_rules(&app)?);
+    Ok(rules)
+}
+
+/// Replaces the user's lint rules. The built-in rules always apply.
+#[tauri::command]
+fn set_lint_rules(app: AppHandle, rules: Vec<prompt_lint::LintRule>) -> Result<(), String> {
+    prompt_lint::validate_rules(&rules)?;
+    let store = app
+        .store(prompt_lint::LINT_STORE_FILE)
+        .map_err(|e| e.to_string())?;
+    let value = serde_json::to_value(&rules).map_err(|e| e.to_string())?;
+    store.set(prompt_lint::LINT_STORE_KEY, value);
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Presets offered for `model`, in `ucPreset` index order.
+#[tauri::command]
+fn list_uc_presets(model: String) -> Vec<uc_presets::UcPreset> {
+    uc_presets::presets_for_model(&model).to_vec()
+}
+
+/// Packs images into a ZIP at `out_path`, each as PNG with its settings as a
+/// `{name}.json` sidecar and/or an embedded `Comment` chunk. Emits
+/// `export_progress` after every image.
+#[tauri::command]
+async fn export_images_zip(
+    app: AppHandle,
+    items: Vec<ExportItem>,
+    out_path: String,
+    metadata_mode: Option<ExportMetadataMode>,
+    compression_level: Option<i64>,
+) -> Result<ExportZipResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        export::export_images_zip(
+            items,
+            std::path::Path::new(&out_path),
+            metadata_mode.unwrap_or_default(),
+            compression_level,
+            |progress| {
+                if let Err(e) = app.emit("export_progress", progress) {
+                    log::debug!("Failed to emit export progress: {}", e);
+                }
+            },
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Groups visually similar images in `dir_path` by perceptual hash.
+/// `max_distance` is the Hamming distance threshold out of 64 bits.
+#[tauri::command]
+async fn find_duplicates(
+    dir_path: String,
+    max_distance: Option<u32>,
+) -> Result<Vec<duplicates::DuplicateGroup>, String> {
+    let max_distance = max_distance
+        .unwrap_or(duplicates::DEFAULT_MAX_DISTANCE)
+        .min(64);
+    tauri::async_runtime::spawn_blocking(move || {
+        duplicates::find_duplicates(std::path::Path::new(&dir_path), max_distance)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Applies `postprocess::normalize_orientation` to a base64 image, keeping
+/// any `data:` URL prefix. Images without an orientation to fix come back
+/// untouched.
+async fn normalize_orientation_base64(image: String) -> Result<String, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    let bytes = metadata::decode_image_base64(&image)?;
+    let normalized =
+        tauri::async_runtime::spawn_blocking(move || postprocess::normalize_orientation(&bytes))
+            .await
+            .map_err(|e| e.to_string())??;
+
+    match normalized {
+        None => Ok(image),
+        Some(bytes) => {
+            let encoded = STANDARD.encode(bytes);
+            Ok(match image.find(";base64,") {
+                Some(idx) if image.starts_with("data:") => {
+                    format!("{}{}", &image[..idx + ";base64,".len()], encoded)
+                }
+                _ => encoded,
+            })
+        }
+    }
+}
+
+/// Rotates/mirrors an image according to its EXIF Orientation.
+#[tauri::command]
+async fn normalize_orientation(
+    image_base64: ImageSource,
+    return_path: Option<String>,
+) -> Result<String, String> {
+    match (image_base64, return_path) {
+        (ImageSource::Base64(image), None) => normalize_orientation_base64(image).await,
+        (source, return_path) => {
+            let bytes = source.read().await?;
+            let normalized = tauri::async_runtime::spawn_blocking(move || {
+                postprocess::normalize_orientation(&bytes).map(|n| n.unwrap_or(bytes))
+            })
+            .await
+            .map_err(|e| e.to_string())??;
+            image_source::deliver(normalized, return_path).await
+        }
+    }
+}
+
+/// Runs a CPU-heavy image job off the async runtime and returns the result
+/// as base64, or writes it to `return_path` and returns that.
+async fn run_image_job<F>(
+    image: ImageSource,
+    return_path: Option<String>,
+    job: F,
+) -> Result<String, String>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>, String> + Send + 'static,
+{
+    let bytes = image.read().await?;
+    let output = tauri::async_runtime::spawn_blocking(move || job(&bytes))
+        .await
+        .map_err(|e| e.to_string())??;
+    image_source::deliver(output, return_path).await
+}
+
+/// Overlays `text` at `position` (`top-left`, `top-right`, `bottom-left`,
+/// `bottom-right`, `center`) with the given opacity (0.0-1.0).
+#[tauri::command]
+async fn apply_watermark(
+    image_base64: ImageSource,
+    text: String,
+    opacity: f64,
+    position: String,
+    return_path: Option<String>,
+) -> Result<String, String> {
+    let position = postprocess::WatermarkPosition::parse(&position)?;
+    run_image_job(image_base64, return_path, move |bytes| {
+        postprocess::apply_watermark(bytes, &text, opacity, position)
+    })
+    .await
+}
+
+/// Composites a text and/or logo watermark. `position` takes the same values
+/// as `apply_watermark`; `margin` is in pixels. With `preserve_metadata`
+/// (default true) the NAI metadata of the original is kept.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn add_watermark(
+    image_base64: ImageSource,
+    text: Option<String>,
+    logo_base64: Option<ImageSource>,
+    opacity: f64,
+    position: String,
+    margin: Option<u32>,
+    preserve_metadata: Option<bool>,
+    return_path: Option<String>,
+) -> Result<String, String> {
+    let logo = match logo_base64 {
+        Some(logo) => Some(logo.read().await?),
+        None => None,
+    };
+    let mark = postprocess::Watermark {
+        text,
+        logo,
+        opacity: opacity as f32,
+        position: postprocess::WatermarkPosition::parse(&position)?,
+        margin,
+    };
+    let preserve = preserve_metadata.unwrap_or(true);
+    run_image_job(image_base64, return_path, move |bytes| {
+        postprocess::add_watermark(bytes, &mark, preserve)
+    })
+    .await
+}
+
+/// Dominant colors of an image, most common first. `count` defaults to 5
+/// and is capped at 16.
+#[tauri::command]
+async fn extract_palette(
+    image_base64: ImageSource,
+    count: Option<usize>,
+) -> Result<Vec<postprocess::PaletteColor>, String> {
+    let bytes = image_base64.read().await?;
+    let count = count.unwrap_or(postprocess::DEFAULT_PALETTE_SIZE);
+    tauri::async_runtime::spawn_blocking(move || postprocess::extract_palette(&bytes, count))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Dominant colors (`palette_size`, default 5), average brightness and
+/// grayscale/flat-color flags, computed on a downscaled copy.
+#[tauri::command]
+async fn analyze_image(
+    image_base64: ImageSource,
+    palette_size: Option<usize>,
+) -> Result<postprocess::ImageAnalysis, String> {
+    let bytes = image_base64.read().await?;
+    let palette_size = palette_size.unwrap_or(postprocess::DEFAULT_PALETTE_SIZE);
+    tauri::async_runtime::spawn_blocking(move || postprocess::analyze(&bytes, palette_size))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Combines `images` into one contact sheet PNG, `cols` per row, `gap`
+/// pixels apart on an RGBA `bg` background.
+#[tauri::command]
+async fn make_grid(
+    images: Vec<ImageSource>,
+    cols: usize,
+    gap: u32,
+    bg: [u8; 4],
+    return_path: Option<String>,
+) -> Result<String, String> {
+    let mut decoded = Vec::with_capacity(images.len());
+    for image in &images {
+        decoded.push(image.read().await?);
+    }
+    let png = tauri::async_runtime::spawn_blocking(move || {
+        postprocess::make_grid(&decoded, cols, gap, bg)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    image_source::deliver(png, return_path).await
+}
+
+/// Enlarges an image `scale` times on this machine with `lanczos3`,
+/// `nearest` or `bilinear` resampling. Works offline and costs no Anlas,
+/// but is only a resize, not NAI's AI upscale. Results over 8K are refused.
+#[tauri::command]
+async fn local_upscale(
+    image_base64: ImageSource,
+    scale: u32,
+    algorithm: String,
+    return_path: Option<String>,
+) -> Result<String, String> {
+    let algorithm = postprocess::UpscaleAlgorithm::parse(&algorithm)?;
+    run_image_job(image_base64, return_path, move |bytes| {
+        postprocess::local_upscale(bytes, scale, algorithm)
+    })
+    .await
+}
+
+/// Gaussian blur for NSFW previews; a larger `sigma` blurs more.
+#[tauri::command]
+async fn blur_image(
+    image_base64: ImageSource,
+    sigma: f64,
+    return_path: Option<String>,
+) -> Result<String, String> {
+    run_image_job(image_base64, return_path, move |bytes| {
+        postprocess::blur(bytes, sigma)
+    })
+    .await
+}
+
+/// Brightness/contrast/saturation correction. Each value is a factor from 0
+/// to 4 where 1.0 changes nothing; saturation 0 gives grayscale.
+#[tauri::command]
+async fn adjust_image(
+    image_base64: ImageSource,
+    brightness: f64,
+    contrast: f64,
+    saturation: f64,
+    return_path: Option<String>,
+) -> Result<String, String> {
+    run_image_job(image_base64, return_path, move |bytes| {
+        postprocess::adjust(bytes, brightness, contrast, saturation)
+    })
+    .await
+}
+
+fn read_prompt_presets(app: &AppHandle) -> Result<Vec<PromptPreset>, String> {
+    let store = app
+        .store(presets::PRESET_STORE_FILE)
+        .map_err(|e| e.to_string())?;
+    Ok(presets::from_store_value(
+        store.get(presets::PRESET_STORE_KEY),
+    ))
+}
+
+fn write_prompt_presets(app: &AppHandle, list: &[PromptPreset]) -> Result<(), String> {
+    let store = app
+        .store(presets::PRESET_STORE_FILE)
+        .map_err(|e| e.to_string())?;
+    let value = serde_json::to_value(list).map_err(|e| e.to_string())?;
+    store.set(presets::PRESET_STORE_KEY, value);
+    store.save().map_err(|e| e.to_string())
+}
+
+/// Saves a prompt preset. An existing preset with the same name is only
+/// replaced when `overwrite` is true.
+#[tauri::command]
+async fn save_prompt_preset(
+    app: AppHandle,
+    name: String,
+    positive: String,
+    negative: String,
+    params: PresetParams,
+    overwrite: Option<bool>,
+) -> Result<PromptPreset, String> {
+    let now = presets::now_millis();
+    let preset = PromptPreset {
+        name: presets::validate_name(&name)?,
+        positive,
+        negative,
+        params,
+        includes_prompt: true,
+        created_at: now,
+        updated_at: now,
+    };
+
+    let mut list = read_prompt_presets(&app)?;
+    presets::upsert(&mut list, preset.clone(), overwrite.unwrap_or(false))?;
+    write_prompt_presets(&app, &list)?;
+    Ok(list
+        .into_iter()
+        .find(|p| p.name == preset.name)
+        .unwrap_or(preset))
+}
+
+#[tauri::command]
+async fn list_prompt_presets(app: AppHandle) -> Result<Vec<PromptPreset>, String> {
+    read_prompt_presets(&app)
+}
+
+#[tauri::command]
+async fn load_prompt_preset(app: AppHandle, name: String) -> Result<PromptPreset, String> {
+    read_prompt_presets(&app)?
+        .into_iter()
+        .find(|p| p.name == name.trim())
+        .ok_or_else(|| format!("프리셋을 찾을 수 없음: {}", name))
+}
+
+/// Same lookup as `load_prompt_preset`, already shaped for `generate_image`.
+#[tauri::command]
+async fn load_prompt_preset_params(
+    app: AppHandle,
+    name: String,
+) -> Result<GenerationParams, String> {
+    load_prompt_preset(app, name)
+        .await
+        .map(|preset| preset.to_generation_params())
+}
+
+/// Returns whether a preset was actually removed.
+#[tauri::command]
+async fn delete_prompt_preset(app: AppHandle, name: String) -> Result<bool, String> {
+    let mut list = read_prompt_presets(&app)?;
+    let before = list.len();
+    list.retain(|p| p.name != name.trim());
+    if list.len() == before {
+        return Ok(false);
+    }
+    write_prompt_presets(&app, &list)?;
+    Ok(true)
+}
+
+/// Saves the settings of `payload` as a preset. With `include_prompt`
+/// (default false) the prompt and negative prompt are saved too; otherwise
+/// only steps/scale/sampler/resolution and the like.
+#[tauri::command]
+async fn save_preset(
+    app: AppHandle,
+    name: String,
+    payload: GenerationParams,
+    include_prompt: Option<bool>,
+    overwrite: Option<bool>,
+) -> Result<PresetMeta, String> {
+    let preset = PromptPreset::from_generation_params(
+        presets::validate_name(&name)?,
+        &payload,
+        include_prompt.unwrap_or(false),
+        presets::now_millis(),
+    );
+
+    let mut list = read_prompt_presets(&app)?;
+    presets::upsert(&mut list, preset.clone(), overwrite.unwrap_or(false))?;
+    write_prompt_presets(&app, &list)?;
+    Ok(list
+        .iter()
+        .find(|p| p.name == preset.name)
+        .unwrap_or(&preset)
+        .meta())
+}
+
+#[tauri::command]
+async fn list_presets(app: AppHandle) -> Result<Vec<PresetMeta>, String> {
+    Ok(read_prompt_presets(&app)?
+        .iter()
+        .map(PromptPreset::meta)
+        .collect())
+}
+
+/// Settings of a saved preset as `generate_image` params. Prompts are empty
+/// for parameter-only presets (`includes_prompt: false` in `list_presets`).
+#[tauri::command]
+async fn load_preset(app: AppHandle, name: String) -> Result<GenerationParams, String> {
+    load_prompt_preset_params(app, name).await
+}
+
+#[tauri::command]
+async fn delete_preset(app: AppHandle, name: String) -> Result<bool, String> {
+    delete_prompt_preset(app, name).await
+}
+
+/// Writes presets to a JSON file. `names` limits the export; all presets are
+/// exported when it's omitted. Returns the number of presets written.
+#[tauri::command]
+async fn export_prompt_presets(
+    app: AppHandle,
+    path: String,
+    names: Option<Vec<String>>,
+) -> Result<usize, String> {
+    let mut list = read_prompt_presets(&app)?;
+    if let Some(names) = names {
+        list.retain(|p| names.iter().any(|n| n.trim() == p.name));
+    }
+    let count = list.len();
+    let json = presets::export_json(list)?;
+    tokio::fs::write(&path, json)
+        .await
+        .map_err(|e| format!("파일 저장 오류: {}", e))?;
+    Ok(count)
+}
+
+#[tauri::command]
+async fn import_prompt_presets(
+    app: AppHandle,
+    path: String,
+    overwrite: Option<bool>,
+) -> Result<PresetImportResult, String> {
+    let json = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| format!("파일 읽기 오류: {}", e))?;
+    let imported = presets::parse_import(&json)?;
+
+    let mut list = read_prompt_presets(&app)?;
+    let result = presets::merge_import(&mut list, imported, overwrite.unwrap_or(false));
+    if !result.imported.is_empty() {
+        write_prompt_presets(&app, &list)?;
+    }
+    Ok(result)
+}
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::webview::PageLoadEvent;
+use tauri::{
+    AppHandle, Emitter, LogicalPosition, LogicalSize, Manager, RunEvent, Url, WindowEvent,
+};
+use tauri_plugin_shell::{process::CommandChild, ShellExt};
+use tauri_plugin_store::StoreExt;
+
+// Store for tracking tagger sidecar process
+#[derive(Clone)]
+pub struct TaggerState(pub Arc<Mutex<Option<CommandChild>>>);
+
+const DEFAULT_BROWSER_LABEL: &str = "embedded_browser";
+
+fn browser_label(label: Option<String>) -> String {
+    label.unwrap_or_else(|| DEFAULT_BROWSER_LABEL.to_string())
+}
+
+#[derive(Clone, Copy)]
+enum HistoryMove {
+    Back,
+    Forward,
+}
+
+// Webviews don't expose their history, so we mirror it from page load events
+#[derive(Default)]
+struct BrowserHistory {
+    entries: Vec<String>,
+    index: usize,
+    pending: Option<HistoryMove>,
+}
+
+impl BrowserHistory {
+    fn record(&mut self, url: &str) {
+        match self.pending.take() {
+            Some(HistoryMove::Back) if self.can_go_back() => {
+                self.index -= 1;
+                self.entries[self.index] = url.to_string();
+                return;
+            }
+            Some(HistoryMove::Forward) if self.can_go_forward() => {
+                self.index += 1;
+                self.entries[self.index] = url.to_string();
+                return;
+            }
+            _ => {}
+        }
+
+        // Reloads fire a page load for the same URL
+        if self.entries.get(self.index).is_some_and(|u| u == url) {
+            return;
+        }
+
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.index + 1);
+        }
+        self.entries.push(url.to_string());
+        self.index = self.entries.len() - 1;
+    }
+
+    fn can_go_back(&self) -> bool {
+        self.index > 0
+    }
+
+    fn can_go_forward(&self) -> bool {
+        self.index + 1 < self.entries.len()
+    }
+}
+
+// Store for tracking embedded webviews
+struct EmbeddedWebviews {
+    // Label -> whether it should be visible, as last set by show/hide
+    webviews: HashMap<String, bool>,
+    // Everything hidden by `set_browsers_visible(false)`
+    suspended: bool,
+    history: HashMap<String, BrowserHistory>,
+    // Id of the page load each webview is still waiting on
+    pending_loads: HashMap<String, u64>,
+    // Webviews that follow the main window's size, see `set_browser_anchor`
+    anchors: HashMap<String, BrowserAnchor>,
+}
+
+static EMBEDDED_WEBVIEWS: std::sync::LazyLock<Mutex<EmbeddedWebviews>> =
+    std::sync::LazyLock::new(|| {
+        Mutex::new(EmbeddedWebviews {
+            webviews: HashMap::new(),
+            suspended: false,
+            history: HashMap::new(),
+            pending_loads: HashMap::new(),
+            anchors: HashMap::new(),
+        })
+    });
+
+/// A webview's rectangle as fractions (0-1) of the main window's inner size.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct BrowserAnchor {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Moves every anchored webview to its share of the main window. Works in
+/// logical pixels so a DPI change keeps the same layout.
+fn relayout_anchored_browsers(app: &AppHandle) {
+    let Some(window) = app.get_window("main") else {
+        return;
+    };
+    let (Ok(size), Ok(scale)) = (window.inner_size(), window.scale_factor()) else {
+        return;
+    };
+    let size = size.to_logical::<f64>(scale);
+    let anchors: Vec<(String, BrowserAnchor)> = match EMBEDDED_WEBVIEWS.lock() {
+        Ok(store) => store.anchors.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        Err(_) => return,
+    };
+
+    for (label, anchor) in anchors {
+        let Some(webview) = app.get_webview(&label) else {
+            continue;
+        };
+        let position = LogicalPosition::new(anchor.x * size.width, anchor.y * size.height);
+        let rect = LogicalSize::new(anchor.width * size.width, anchor.height * size.height);
+        if let Err(e) = webview
+            .set_position(position)
+            .and_then(|_| webview.set_size(rect))
+        {
+            log::warn!("Failed to relayout {}: {}", label, e);
+        }
+    }
+}
+
+/// Makes the browser `label` follow the main window: `rect_ratio` is its
+/// rectangle as fractions of the window, applied now and on every resize,
+/// move or DPI change. `None` removes the anchor, leaving the browser where
+/// it is. A later `resize_embedded_browser` also removes it.
+#[tauri::command]
+fn set_browser_anchor(
+    app: AppHandle,
+    label: Option<String>,
+    rect_ratio: Option<BrowserAnchor>,
+) -> Result<(), String> {
+    let label = browser_label(label);
+    if let Some(anchor) = rect_ratio {
+        let values = [anchor.x, anchor.y, anchor.width, anchor.height];
+        if values
+            .iter()
+            .any(|v| !v.is_finite() || !(0.0..=1.0).contains(v))
+        {
+            return Err(format!("앵커 비율은 0~1 사이여야 합니다: {:?}", anchor));
+        }
+    }
+
+    {
+        let mut store = EMBEDDED_WEBVIEWS.lock().map_err(|e| e.to_string())?;
+        match rect_ratio {
+            Some(anchor) => store.anchors.insert(label, anchor),
+            None => store.anchors.remove(&label),
+        };
+    }
+    relayout_anchored_browsers(&app);
+    Ok(())
+}
 
-    let client = reqwest::Client::new();
+const BROWSER_LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+// How often the URL is checked for in-page (SPA) route changes
+const BROWSER_ROUTE_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
-    // Use Hugging Face Inference API (free tier available)
-    // Note: For production, consider getting an HF API token
-    let result = client
-        .post("https://router.huggingface.co/hf-inference/models/briaai/RMBG-1.4")
-        .header("Content-Type", "application/octet-stream")
-        .body(image_bytes)
-        .send()
-        .await;
+static BROWSER_LOAD_ID: AtomicU64 = AtomicU64::new(0);
+// Bumped each time a browser is opened so the previous route watcher stops
+static BROWSER_WATCH_GENERATION: AtomicU64 = AtomicU64::new(0);
 
-    match result {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.bytes().await {
-                    Ok(bytes) => {
-                        let base64_result = STANDARD.encode(&bytes);
-                        RemoveBackgroundResult {
-                            success: true,
-                            image_data: Some(format!("data:image/png;base64,{}", base64_result)),
-                            error: None,
-                        }
+/// Payload of `embedded-browser-loaded` and `embedded-browser-route-changed`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserLoaded {
+    pub id: String,
+    pub url: String,
+}
+
+/// Payload of `embedded-browser-load-error`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserLoadError {
+    pub id: String,
+    pub url: String,
+    pub error: String,
+}
+
+fn emit_browser_load_error(app: &AppHandle, id: &str, url: &str, error: String) {
+    let _ = app.emit(
+        "embedded-browser-load-error",
+        BrowserLoadError {
+            id: id.to_string(),
+            url: url.to_string(),
+            error,
+        },
+    );
+}
+
+/// Mirrors history on every finished load and reports it as
+/// `embedded-browser-loaded`. A load that hasn't finished within
+/// `BROWSER_LOAD_TIMEOUT` is reported as `embedded-browser-load-error`.
+fn handle_browser_page_load(
+    webview: &tauri::Webview,
+    payload: &tauri::webview::PageLoadPayload<'_>,
+) {
+    let id = webview.label().to_string();
+    let url = payload.url().to_string();
+    let app = webview.app_handle().clone();
+
+    match payload.event() {
+        PageLoadEvent::Started => {
+            let load_id = BROWSER_LOAD_ID.fetch_add(1, Ordering::SeqCst) + 1;
+            if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
+                store.pending_loads.insert(id.clone(), load_id);
+            }
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(BROWSER_LOAD_TIMEOUT).await;
+                let timed_out = EMBEDDED_WEBVIEWS.lock().is_ok_and(|mut store| {
+                    if store.pending_loads.get(&id) == Some(&load_id) {
+                        store.pending_loads.remove(&id);
+                        true
+                    } else {
+                        false
                     }
-                    Err(e) => RemoveBackgroundResult {
-                        success: false,
-                        image_data: None,
-                        error: Some(format!("응답 읽기 오류: {}", e)),
-                    },
-                }
-            } else {
-                let status = response.status().as_u16();
-                let error_text = response.text().await.unwrap_or_default();
-                RemoveBackgroundResult {
-                    success: false,
-                    image_data: None,
-                    error: Some(format!("API 오류 {}: {}", status, error_text)),
+                });
+                if timed_out {
+                    emit_browser_load_error(
+                        &app,
+                        &id,
+                        &url,
+                        format!(
+                            "{}초 안에 페이지가 로드되지 않았습니다",
+                            BROWSER_LOAD_TIMEOUT.as_secs()
+                        ),
+                    );
                 }
+            });
+        }
+        PageLoadEvent::Finished => {
+            if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
+                store.pending_loads.remove(&id);
+                store.history.entry(id.clone()).or_default().record(&url);
             }
+            let _ = app.emit("embedded-browser-loaded", BrowserLoaded { id, url });
         }
-        Err(e) => RemoveBackgroundResult {
-            success: false,
-            image_data: None,
-            error: Some(format!("네트워크 오류: {}", e)),
-        },
     }
 }
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tauri::{AppHandle, LogicalPosition, LogicalSize, Manager, RunEvent, Url};
-use tauri_plugin_shell::{process::CommandChild, ShellExt};
+/// Page load events don't fire for `history.pushState` routing, so the URL
+/// is polled instead and changes are sent as `embedded-browser-route-changed`.
+/// Stops when the browser is closed or reopened.
+fn watch_browser_routes(app: AppHandle, id: String) {
+    let generation = BROWSER_WATCH_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    tauri::async_runtime::spawn(async move {
+        let mut last_route: Option<String> = None;
+        while BROWSER_WATCH_GENERATION.load(Ordering::SeqCst) == generation {
+            tokio::time::sleep(BROWSER_ROUTE_POLL_INTERVAL).await;
+            let Some(webview) = app.get_webview(&id) else {
+                break;
+            };
+            let Ok(url) = webview.url().map(|u| u.to_string()) else {
+                continue;
+            };
 
-// Store for tracking tagger sidecar process
-#[derive(Clone)]
-pub struct TaggerState(pub Arc<Mutex<Option<CommandChild>>>);
+            let (loading, loaded_url) = match EMBEDDED_WEBVIEWS.lock() {
+                Ok(store) => (
+                    store.pending_loads.contains_key(&id),
+                    store
+                        .history
+                        .get(&id)
+                        .and_then(|h| h.entries.get(h.index).cloned()),
+                ),
+                Err(_) => continue,
+            };
+            // A full load reports itself; only in-page changes are of interest
+            if loading || loaded_url.as_deref() == Some(url.as_str()) {
+                last_route = None;
+                continue;
+            }
+            if last_route.as_deref() != Some(url.as_str()) {
+                last_route = Some(url.clone());
+                let _ = app.emit(
+                    "embedded-browser-route-changed",
+                    BrowserLoaded {
+                        id: id.clone(),
+                        url,
+                    },
+                );
+            }
+        }
+    });
+}
 
-// Store for tracking embedded webviews
-struct EmbeddedWebviews {
-    webviews: HashMap<String, bool>,
+#[derive(Debug, Serialize)]
+pub struct BrowserState {
+    pub label: String,
+    pub url: Option<String>,
+    pub can_go_back: bool,
+    pub can_go_forward: bool,
 }
 
-static EMBEDDED_WEBVIEWS: std::sync::LazyLock<Mutex<EmbeddedWebviews>> =
-    std::sync::LazyLock::new(|| {
-        Mutex::new(EmbeddedWebviews {
-            webviews: HashMap::new(),
+const BROWSER_ALLOWLIST_KEY: &str = "browser_allowlist";
+
+// Cached so navigation checks don't hit the store on every request
+static BROWSER_ALLOWLIST: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// The domains the embedded browser may show: the saved list, or the
+/// defaults when none was saved or it can't be read.
+fn browser_allowlist(app: &AppHandle) -> Vec<String> {
+    let mut cached = BROWSER_ALLOWLIST.lock().unwrap_or_else(|e| e.into_inner());
+    cached
+        .get_or_insert_with(|| {
+            app.store(WEBVIEW_STORE_FILE)
+                .ok()
+                .and_then(|store| store.get(BROWSER_ALLOWLIST_KEY))
+                .and_then(|value| serde_json::from_value::<Vec<String>>(value).ok())
+                .and_then(|domains| browser_allowlist::normalize_list(&domains).ok())
+                .unwrap_or_else(browser_allowlist::default_list)
         })
-    });
+        .clone()
+}
+
+/// Payload of `embedded-browser-blocked`, sent when the page tries to go
+/// somewhere outside the allowlist.
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserBlocked {
+    pub id: String,
+    pub url: String,
+}
+
+fn block_browser_url(app: &AppHandle, id: &str, url: &Url) -> bool {
+    if browser_allowlist::is_allowed(&browser_allowlist(app), url) {
+        return false;
+    }
+    log::warn!("Blocked embedded browser navigation to {}", url);
+    let _ = app.emit(
+        "embedded-browser-blocked",
+        BrowserBlocked {
+            id: id.to_string(),
+            url: url.to_string(),
+        },
+    );
+    true
+}
+
+/// Replaces the domains the embedded browser may open (see
+/// `browser_allowlist` for how they match). `None` restores the defaults.
+/// Returns the list as it will be applied.
+#[tauri::command]
+fn set_browser_allowlist(
+    app: AppHandle,
+    domains: Option<Vec<String>>,
+) -> Result<Vec<String>, String> {
+    let list = match domains {
+        Some(domains) => browser_allowlist::normalize_list(&domains)?,
+        None => browser_allowlist::default_list(),
+    };
+    let store = app.store(WEBVIEW_STORE_FILE).map_err(|e| e.to_string())?;
+    store.set(BROWSER_ALLOWLIST_KEY, serde_json::json!(list));
+    store.save().map_err(|e| e.to_string())?;
+    *BROWSER_ALLOWLIST.lock().map_err(|e| e.to_string())? = Some(list.clone());
+    Ok(list)
+}
+
+#[tauri::command]
+fn get_browser_allowlist(app: AppHandle) -> Vec<String> {
+    browser_allowlist(&app)
+}
 
 #[tauri::command]
 async fn open_embedded_browser(
@@ -350,18 +4912,45 @@ async fn open_embedded_browser(
     let _ = close_embedded_browser(app.clone()).await;
 
     let parsed_url = Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
+    browser_allowlist::check(&browser_allowlist(&app), &parsed_url)?;
 
     // Get the main window (not WebviewWindow, but Window for add_child)
     let window = app.get_window("main").ok_or("Main window not found")?;
 
-    // Create a WebviewBuilder for the embedded browser
+    // Create a WebviewBuilder for the embedded browser. Link clicks and
+    // redirects are held to the same allowlist, and dragged images are
+    // reported through the navigation handler. Popups never get a window of
+    // their own (it would have no navigation handler); allowed ones are
+    // opened in this browser instead.
+    let navigation_app = app.clone();
+    let popup_app = app.clone();
     let webview_builder = tauri::webview::WebviewBuilder::new(
         "embedded_browser",
         tauri::WebviewUrl::External(parsed_url),
-    );
+    )
+    .initialization_script(url_import::drag_script())
+    .on_page_load(|webview, payload| handle_browser_page_load(&webview, &payload))
+    .on_navigation(move |url| {
+        if let Some(dragged) = url_import::parse_drag_report(DEFAULT_BROWSER_LABEL, url) {
+            let _ = navigation_app.emit("embedded-browser-image-dragged", dragged);
+            return false;
+        }
+        !block_browser_url(&navigation_app, DEFAULT_BROWSER_LABEL, url)
+    })
+    .on_new_window(move |url, _| {
+        if !block_browser_url(&popup_app, DEFAULT_BROWSER_LABEL, &url) {
+            let app = popup_app.clone();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = navigate_embedded_browser(app, url.to_string()).await {
+                    log::warn!("Failed to open popup in embedded browser: {}", e);
+                }
+            });
+        }
+        tauri::webview::NewWindowResponse::Deny
+    });
 
     // Add as child webview within the main window
-    window
+    let webview = window
         .add_child(
             webview_builder,
             LogicalPosition::new(x, y),
@@ -369,10 +4958,22 @@ async fn open_embedded_browser(
         )
         .map_err(|e| format!("Failed to create embedded webview: {}", e))?;
 
+    // Restore the zoom level saved for this browser
+    if let Some(factor) = saved_browser_zoom(&app, webview.label()) {
+        apply_browser_zoom(&webview, factor)?;
+    }
+
     // Track the webview
     if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
+        if store.suspended {
+            let _ = webview.hide();
+        }
         store.webviews.insert("embedded_browser".to_string(), true);
+        store
+            .history
+            .insert("embedded_browser".to_string(), BrowserHistory::default());
     }
+    watch_browser_routes(app, webview.label().to_string());
 
     Ok(())
 }
@@ -387,6 +4988,9 @@ async fn close_embedded_browser(app: AppHandle) -> Result<(), String> {
 
     if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
         store.webviews.remove("embedded_browser");
+        store.history.remove("embedded_browser");
+        store.pending_loads.remove("embedded_browser");
+        store.anchors.remove("embedded_browser");
     }
 
     Ok(())
@@ -396,9 +5000,12 @@ async fn close_embedded_browser(app: AppHandle) -> Result<(), String> {
 async fn navigate_embedded_browser(app: AppHandle, url: String) -> Result<(), String> {
     if let Some(webview) = app.get_webview("embedded_browser") {
         let parsed_url = Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
-        webview
-            .navigate(parsed_url)
-            .map_err(|e| format!("Navigation failed: {}", e))?;
+        browser_allowlist::check(&browser_allowlist(&app), &parsed_url)?;
+        if let Err(e) = webview.navigate(parsed_url) {
+            let error = format!("Navigation failed: {}", e);
+            emit_browser_load_error(&app, webview.label(), &url, error.clone());
+            return Err(error);
+        }
     }
     Ok(())
 }
@@ -411,6 +5018,10 @@ async fn resize_embedded_browser(
     width: f64,
     height: f64,
 ) -> Result<(), String> {
+    // An explicit rectangle replaces the anchor
+    if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
+        store.anchors.remove("embedded_browser");
+    }
     if let Some(webview) = app.get_webview("embedded_browser") {
         webview
             .set_position(LogicalPosition::new(x, y))
@@ -425,7 +5036,17 @@ async fn resize_embedded_browser(
 #[tauri::command]
 async fn show_embedded_browser(app: AppHandle) -> Result<(), String> {
     if let Some(webview) = app.get_webview("embedded_browser") {
-        webview.show().map_err(|e| format!("Show failed: {}", e))?;
+        let suspended = match EMBEDDED_WEBVIEWS.lock() {
+            Ok(mut store) => {
+                store.webviews.insert(webview.label().to_string(), true);
+                store.suspended
+            }
+            Err(_) => false,
+        };
+        // While suspended it only comes back with the others
+        if !suspended {
+            webview.show().map_err(|e| format!("Show failed: {}", e))?;
+        }
     }
     Ok(())
 }
@@ -433,11 +5054,47 @@ async fn show_embedded_browser(app: AppHandle) -> Result<(), String> {
 #[tauri::command]
 async fn hide_embedded_browser(app: AppHandle) -> Result<(), String> {
     if let Some(webview) = app.get_webview("embedded_browser") {
+        if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
+            store.webviews.insert(webview.label().to_string(), false);
+        }
         webview.hide().map_err(|e| format!("Hide failed: {}", e))?;
     }
     Ok(())
 }
 
+/// Hides every embedded browser (`visible: false`), e.g. while a generation
+/// preview is shown, or brings them back (`visible: true`). Restoring only
+/// shows the browsers that were visible before; ones hidden on purpose stay
+/// hidden.
+#[tauri::command]
+fn set_browsers_visible(app: AppHandle, visible: bool) -> Result<(), String> {
+    let targets: Vec<String> = {
+        let mut store = EMBEDDED_WEBVIEWS.lock().map_err(|e| e.to_string())?;
+        store.suspended = !visible;
+        store
+            .webviews
+            .iter()
+            .filter(|(_, shown)| **shown)
+            .map(|(label, _)| label.clone())
+            .collect()
+    };
+
+    for label in targets {
+        let Some(webview) = app.get_webview(&label) else {
+            continue;
+        };
+        let result = if visible {
+            webview.show()
+        } else {
+            webview.hide()
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to change visibility of {}: {}", label, e);
+        }
+    }
+    Ok(())
+}
+
 #[tauri::command]
 async fn is_browser_open(app: AppHandle) -> bool {
     app.get_webview("embedded_browser").is_some()
@@ -455,6 +5112,133 @@ async fn zoom_embedded_browser(app: AppHandle, zoom_level: f64) -> Result<(), St
     Ok(())
 }
 
+const WEBVIEW_STORE_FILE: &str = "webview-settings.json";
+const BROWSER_ZOOM_KEY: &str = "browser_zoom";
+const MIN_BROWSER_ZOOM: f64 = 0.25;
+const MAX_BROWSER_ZOOM: f64 = 5.0;
+
+fn saved_browser_zoom(app: &AppHandle, label: &str) -> Option<f64> {
+    let store = app.store(WEBVIEW_STORE_FILE).ok()?;
+    store
+        .get(BROWSER_ZOOM_KEY)?
+        .get(label)?
+        .as_f64()
+        .map(|f| f.clamp(MIN_BROWSER_ZOOM, MAX_BROWSER_ZOOM))
+}
+
+fn apply_browser_zoom(webview: &tauri::Webview, factor: f64) -> Result<(), String> {
+    // Native zoom scales the whole page; fall back to CSS zoom where it's unsupported
+    if let Err(e) = webview.set_zoom(factor) {
+        log::debug!("Native zoom failed, falling back to CSS zoom: {}", e);
+        let js = format!("document.body.style.zoom = '{}';", factor);
+        webview
+            .eval(&js)
+            .map_err(|e| format!("Zoom failed: {}", e))?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_browser_zoom(
+    app: AppHandle,
+    label: Option<String>,
+    factor: f64,
+) -> Result<f64, String> {
+    let label = browser_label(label);
+    if !factor.is_finite() {
+        return Err("Invalid zoom factor".to_string());
+    }
+    let factor = factor.clamp(MIN_BROWSER_ZOOM, MAX_BROWSER_ZOOM);
+
+    if let Some(webview) = app.get_webview(&label) {
+        apply_browser_zoom(&webview, factor)?;
+    }
+
+    let store = app.store(WEBVIEW_STORE_FILE).map_err(|e| e.to_string())?;
+    let mut zooms = store
+        .get(BROWSER_ZOOM_KEY)
+        .and_then(|v| v.as_object().cloned())
+        .unwrap_or_default();
+    zooms.insert(label, serde_json::json!(factor));
+    store.set(BROWSER_ZOOM_KEY, serde_json::Value::Object(zooms));
+    store.save().map_err(|e| e.to_string())?;
+
+    Ok(factor)
+}
+
+fn step_browser_history(app: &AppHandle, label: &str, step: HistoryMove) -> Result<(), String> {
+    let Some(webview) = app.get_webview(label) else {
+        return Ok(());
+    };
+
+    if let Ok(mut store) = EMBEDDED_WEBVIEWS.lock() {
+        let history = store.history.entry(label.to_string()).or_default();
+        let allowed = match step {
+            HistoryMove::Back => history.can_go_back(),
+            HistoryMove::Forward => history.can_go_forward(),
+        };
+        if !allowed {
+            return Ok(());
+        }
+        history.pending = Some(step);
+    }
+
+    let js = match step {
+        HistoryMove::Back => "window.history.back();",
+        HistoryMove::Forward => "window.history.forward();",
+    };
+    webview
+        .eval(js)
+        .map_err(|e| format!("History navigation failed: {}", e))
+}
+
+#[tauri::command]
+async fn browser_back(app: AppHandle, label: Option<String>) -> Result<(), String> {
+    step_browser_history(&app, &browser_label(label), HistoryMove::Back)
+}
+
+#[tauri::command]
+async fn browser_forward(app: AppHandle, label: Option<String>) -> Result<(), String> {
+    step_browser_history(&app, &browser_label(label), HistoryMove::Forward)
+}
+
+#[tauri::command]
+async fn browser_reload(app: AppHandle, label: Option<String>) -> Result<(), String> {
+    if let Some(webview) = app.get_webview(&browser_label(label)) {
+        webview
+            .reload()
+            .map_err(|e| format!("Reload failed: {}", e))?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn browser_state(app: AppHandle, label: Option<String>) -> BrowserState {
+    let label = browser_label(label);
+    let url = app
+        .get_webview(&label)
+        .and_then(|webview| webview.url().ok())
+        .map(|url| url.to_string());
+
+    let (can_go_back, can_go_forward) = EMBEDDED_WEBVIEWS
+        .lock()
+        .ok()
+        .and_then(|store| {
+            store
+                .history
+                .get(&label)
+                .map(|h| (h.can_go_back(), h.can_go_forward()))
+        })
+        .unwrap_or((false, false));
+
+    BrowserState {
+        label,
+        url,
+        can_go_back,
+        can_go_forward,
+    }
+}
+
 #[tauri::command]
 async fn check_tagger_binary() -> bool {
     // Check if tagger-server executable exists in the current working directory or adjacent to the executable
@@ -556,12 +5340,350 @@ async fn start_tagger(app: AppHandle) -> Result<(), String> {
     spawn_tagger_sc(&app)
 }
 
+/// Downloads the tagger-server release for this platform (listed in the
+/// manifest of the latest GitHub release), installs it next to the app
+/// executable after checking its SHA-256, and starts it.
+/// Emits `tagger-download-progress` while downloading. Returns the installed
+/// version.
+#[tauri::command]
+async fn download_tagger(app: AppHandle) -> Result<String, String> {
+    let running = app
+        .state::<TaggerState>()
+        .0
+        .lock()
+        .map(|child| child.is_some())
+        .unwrap_or(false);
+    if running {
+        return Err("태거가 실행 중입니다. 종료한 뒤 다시 시도하세요".to_string());
+    }
+
+    let client = http_client(&app);
+    let (version, release) = tagger::fetch_release(&client).await?;
+
+    let mut dest = std::env::current_exe().map_err(|e| e.to_string())?;
+    dest.pop();
+    dest.push(tagger::BINARY_NAME);
+    log::info!("Downloading tagger {} to {}", version, dest.display());
+    until_exit(tagger::download_verified(
+        &client,
+        &release,
+        &dest,
+        |progress| {
+            let _ = app.emit("tagger-download-progress", progress);
+        },
+    ))
+    .await??;
+
+    spawn_tagger_sc(&app)?;
+    Ok(version)
+}
+
+/// Joins tagger output into a prompt: tags scoring at least `threshold`,
+/// character tags first, duplicates and `exclude`d tags dropped (both on by
+/// default). With `escape_parens` the result reads the same way through
+/// `normalize_prompt_weights`.
+#[tauri::command]
+fn tags_to_prompt(
+    tags: Vec<tagger::Tag>,
+    threshold: f64,
+    underscore_to_space: bool,
+    escape_parens: bool,
+    character_first: Option<bool>,
+    dedupe: Option<bool>,
+    exclude: Option<Vec<String>>,
+) -> String {
+    let options = tagger::PromptOptions {
+        threshold,
+        underscore_to_space,
+        escape_parens,
+        character_first: character_first.unwrap_or(true),
+        dedupe: dedupe.unwrap_or(true),
+        exclude: exclude.unwrap_or_default(),
+    };
+    tagger::tags_to_prompt(&tags, &options)
+}
+
+/// A prompt for one image from the tagger sidecar: danbooru tags in `tags`
+/// mode (the default), character tags first. `caption` mode and any `model`
+/// other than `tagger::SIDECAR_MODEL` return an error, since the sidecar
+/// has neither.
+#[tauri::command]
+async fn caption_image(
+    app: AppHandle,
+    image_base64: ImageSource,
+    model: String,
+    mode: Option<String>,
+) -> Result<String, String> {
+    let mode = tagger::CaptionMode::parse(mode.as_deref().unwrap_or("tags"))?;
+    // Fails before the image is read or the sidecar is started
+    tagger::check_caption_request(mode, &model)?;
+    let path = match &image_base64 {
+        ImageSource::Path(path) => Some(path.clone()),
+        ImageSource::Base64(_) => None,
+    };
+    let bytes = image_base64.read().await?;
+
+    let client = http_client(&app);
+    if let Err(e) = spawn_tagger_sc(&app) {
+        log::warn!("Tagger sidecar not started: {}", e);
+    }
+    if !tagger::wait_until_ready(&client).await {
+        return Err("태거 서버가 응답하지 않습니다".to_string());
+    }
+    tagger::caption_image(&client, path.as_deref(), &bytes, mode, &model).await
+}
+
+/// Tags `paths` through the tagger sidecar, at most
+/// `tagger::MAX_CONCURRENT_TAGS` at a time, emitting `tag_progress` after
+/// each file. Results keep the input order; a failed file only fails its
+/// own entry.
+#[tauri::command]
+async fn tag_images(app: AppHandle, paths: Vec<String>, threshold: f64) -> Vec<tagger::TagResult> {
+    let client = http_client(&app);
+    if let Err(e) = spawn_tagger_sc(&app) {
+        log::warn!("Tagger sidecar not started: {}", e);
+    }
+    if !tagger::wait_until_ready(&client).await {
+        log::warn!("Tagger sidecar is not responding");
+    }
+
+    let total = paths.len();
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(tagger::MAX_CONCURRENT_TAGS));
+    let done = Arc::new(AtomicUsize::new(0));
+    let handles: Vec<_> = paths
+        .iter()
+        .cloned()
+        .map(|path| {
+            let app = app.clone();
+            let client = client.clone();
+            let semaphore = semaphore.clone();
+            let done = done.clone();
+            tauri::async_runtime::spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = tagger::tag_file(&client, path, threshold).await;
+                let current = done.fetch_add(1, Ordering::SeqCst) + 1;
+                let _ = app.emit(
+                    "tag_progress",
+                    tagger::TagProgress {
+                        current,
+                        total,
+                        path: result.path.clone(),
+                    },
+                );
+                result
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(total);
+    for (path, handle) in paths.into_iter().zip(handles) {
+        results.push(handle.await.unwrap_or_else(|e| tagger::TagResult {
+            path,
+            success: false,
+            tags: Vec::new(),
+            error: Some(e.to_string()),
+            cached: false,
+        }));
+    }
+    results
+}
+
+/// An image found in a watched folder, with its metadata already parsed.
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenedFile {
+    pub path: String,
+    pub metadata: MetadataResult,
+}
+
+/// Payload of `second_instance`, sent for a launch without image files.
+#[derive(Debug, Clone, Serialize)]
+pub struct SecondInstance {
+    /// Arguments of the second launch, without the executable path
+    pub args: Vec<String>,
+}
+
+/// An existing file the metadata parser can read.
+fn is_openable_image(path: &str) -> bool {
+    const OPENABLE_EXTENSIONS: [&str; 4] = ["png", "webp", "jpg", "jpeg"];
+
+    let path = std::path::Path::new(path);
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| OPENABLE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Image paths the app was asked to open (launch arguments, "Open with",
+/// macOS `Opened`) before the main window could receive them. They are sent
+/// as `open_file` events once the window has loaded.
+#[derive(Default)]
+pub struct PendingOpenFiles {
+    paths: Mutex<Vec<String>>,
+    ready: AtomicBool,
+}
+
+/// Emits `open_file` for every openable image in `paths`, or buffers them
+/// until the main window is ready. Anything else is ignored.
+fn open_files(app: &AppHandle, paths: impl IntoIterator<Item = String>) {
+    let Some(pending) = app.try_state::<PendingOpenFiles>() else {
+        return;
+    };
+    for path in paths.into_iter().filter(|p| is_openable_image(p)) {
+        if pending.ready.load(Ordering::SeqCst) {
+            let _ = app.emit("open_file", path);
+        } else if let Ok(mut buffered) = pending.paths.lock() {
+            buffered.push(path);
+        }
+    }
+}
+
+/// Called once the main window has loaded; sends everything buffered so far.
+fn flush_open_files(app: &AppHandle) {
+    let Some(pending) = app.try_state::<PendingOpenFiles>() else {
+        return;
+    };
+    pending.ready.store(true, Ordering::SeqCst);
+    let paths = match pending.paths.lock() {
+        Ok(mut buffered) => std::mem::take(&mut *buffered),
+        Err(_) => return,
+    };
+    for path in paths {
+        let _ = app.emit("open_file", path);
+    }
+}
+
+/// A launch argument as a path: relative ones are relative to the
+/// directory the launch was started from, not to this process.
+fn resolve_argument(arg: String, cwd: &std::path::Path) -> String {
+    let path = std::path::Path::new(&arg);
+    if path.is_absolute() {
+        arg
+    } else {
+        cwd.join(path).to_string_lossy().to_string()
+    }
+}
+
+const SETTINGS_WATCHED_FOLDERS_KEY: &str = "watched_folders";
+
+fn store_watched_folders(app: &AppHandle, watch: &folder_watch::FolderWatch) -> Result<(), String> {
+    let folders: Vec<String> = watch
+        .folders()
+        .iter()
+        .map(|folder| folder.to_string_lossy().to_string())
+        .collect();
+    let store = app.store(SETTINGS_STORE_FILE).map_err(|e| e.to_string())?;
+    store.set(SETTINGS_WATCHED_FOLDERS_KEY, serde_json::json!(folders));
+    Ok(())
+}
+
+/// Starts the folder watcher and re-watches the folders saved last session.
+/// Each new image is sent as `image_imported`.
+fn start_folder_watch(app: &AppHandle) {
+    let handle = app.clone();
+    let watch = folder_watch::FolderWatch::start(move |path, bytes| {
+        let imported = OpenedFile {
+            path: path.to_string_lossy().to_string(),
+            metadata: MetadataResult::from(metadata::read_embedded_metadata(&bytes)),
+        };
+        let _ = handle.emit("image_imported", imported);
+    });
+    let watch = match watch {
+        Ok(watch) => watch,
+        Err(e) => {
+            log::warn!("{}", e);
+            return;
+        }
+    };
+
+    let saved: Vec<String> = app
+        .store(SETTINGS_STORE_FILE)
+        .ok()
+        .and_then(|store| store.get(SETTINGS_WATCHED_FOLDERS_KEY))
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default();
+    for folder in saved {
+        if let Err(e) = watch.watch(std::path::Path::new(&folder)) {
+            log::warn!("Failed to restore watched folder {}: {}", folder, e);
+        }
+    }
+    app.manage(watch);
+}
+
+fn folder_watch_state(
+    app: &AppHandle,
+) -> Result<tauri::State<'_, folder_watch::FolderWatch>, String> {
+    app.try_state::<folder_watch::FolderWatch>()
+        .ok_or_else(|| "폴더 감시를 사용할 수 없습니다".to_string())
+}
+
+/// Imports new images saved into `path` from now on (`image_imported`
+/// events). The folder is watched again after a restart.
+#[tauri::command]
+fn watch_folder(app: AppHandle, path: String) -> Result<String, String> {
+    let watch = folder_watch_state(&app)?;
+    let folder = watch.watch(std::path::Path::new(&path))?;
+    store_watched_folders(&app, &watch)?;
+    Ok(folder.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn unwatch_folder(app: AppHandle, path: String) -> Result<(), String> {
+    let watch = folder_watch_state(&app)?;
+    watch.unwatch(std::path::Path::new(&path))?;
+    store_watched_folders(&app, &watch)
+}
+
+#[tauri::command]
+fn get_watched_folders(app: AppHandle) -> Vec<String> {
+    folder_watch_state(&app)
+        .map(|watch| {
+            watch
+                .folders()
+                .iter()
+                .map(|folder| folder.to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Runs in the first instance when the app is launched again: brings the
+/// main window forward and hands over the new launch's arguments. Image
+/// files among them are opened like on a first launch (`open_file`);
+/// anything else is sent as `second_instance`. The second process exits on
+/// its own, so the tagger port and the stores only ever have one owner.
+fn on_second_instance(app: &AppHandle, argv: Vec<String>, cwd: String) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+
+    let cwd = std::path::PathBuf::from(cwd);
+    let args: Vec<String> = argv.into_iter().skip(1).collect();
+    let files: Vec<String> = args
+        .iter()
+        .map(|arg| resolve_argument(arg.clone(), &cwd))
+        .filter(|path| is_openable_image(path))
+        .collect();
+    if files.is_empty() {
+        let _ = app.emit("second_instance", SecondInstance { args });
+    } else {
+        open_files(app, files);
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let tagger_state = TaggerState(Arc::new(Mutex::new(None)));
     let tagger_state_clone = tagger_state.clone();
 
     tauri::Builder::default()
+        // Must be registered first so a second launch exits before anything
+        // else starts up
+        .plugin(tauri_plugin_single_instance::init(|app, argv, cwd| {
+            on_second_instance(app, argv, cwd)
+        }))
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_store::Builder::default().build())
@@ -571,20 +5693,130 @@ pub fn run() {
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_process::init())
         .manage(tagger_state)
+        .manage(PendingOpenFiles::default())
+        .manage(GenerationQueueState::default())
+        .on_page_load(|webview, payload| {
+            if webview.label() == "main" && payload.event() == PageLoadEvent::Finished {
+                flush_open_files(webview.app_handle());
+            }
+        })
+        .on_window_event(|window, event| {
+            if window.label() == "main"
+                && matches!(
+                    event,
+                    WindowEvent::Resized(_)
+                        | WindowEvent::Moved(_)
+                        | WindowEvent::ScaleFactorChanged { .. }
+                )
+            {
+                relayout_anchored_browsers(window.app_handle());
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             verify_token,
             get_anlas_balance,
+            start_anlas_polling,
+            stop_anlas_polling,
+            set_token_check_interval,
+            check_connectivity,
+            set_user_agent,
+            save_token,
+            load_token,
+            delete_token,
             upscale_image,
+            augment_pipeline,
+            supported_upscale_scales,
             remove_background,
+            cancel_remove_background,
+            generate_image,
+            generate_variations,
+            generate_image_stream,
+            verify_reproducibility,
+            snap_character_position,
+            payload_fingerprint,
+            dump_request_schema,
+            set_request_logging,
+            get_request_log_path,
+            get_request_stats,
+            export_session_report,
+            import_nai_settings,
+            params_from_image,
+            export_nai_settings,
+            cancel_generation_stream,
+            enqueue_generation,
+            reorder_queue,
+            set_queue_priority,
+            get_queue_status,
+            run_pipeline,
+            save_pipeline,
+            list_pipelines,
+            delete_pipeline,
+            watch_folder,
+            unwatch_folder,
+            get_watched_folders,
+            normalize_prompt_weights,
+            expand_wildcards,
+            get_wildcard_dir,
+            convert_prompt_weights,
+            normalize_prompt_newlines,
+            list_models,
+            resolve_uc_preset,
+            list_uc_presets,
+            lint_prompt,
+            get_lint_rules,
+            set_lint_rules,
+            parse_metadata,
+            import_from_url,
+            inject_metadata,
+            convert_image,
+            save_image,
+            prepare_for_share,
+            save_image_auto,
+            set_save_organization,
+            export_images_zip,
+            find_duplicates,
+            apply_watermark,
+            add_watermark,
+            blur_image,
+            adjust_image,
+            local_upscale,
+            extract_palette,
+            analyze_image,
+            make_grid,
+            normalize_orientation,
+            save_prompt_preset,
+            list_prompt_presets,
+            load_prompt_preset,
+            load_prompt_preset_params,
+            delete_prompt_preset,
+            export_prompt_presets,
+            import_prompt_presets,
+            save_preset,
+            list_presets,
+            load_preset,
+            delete_preset,
             open_embedded_browser,
+            set_browser_allowlist,
+            get_browser_allowlist,
             close_embedded_browser,
             navigate_embedded_browser,
             resize_embedded_browser,
+            set_browser_anchor,
             show_embedded_browser,
             hide_embedded_browser,
+            set_browsers_visible,
             is_browser_open,
             zoom_embedded_browser,
+            set_browser_zoom,
+            browser_back,
+            browser_forward,
+            browser_reload,
+            browser_state,
             start_tagger,
+            download_tagger,
+            tag_images,
+            caption_image,
+            tags_to_prompt,
             check_tagger_binary
         ])
         .setup(|app| {
@@ -596,6 +5828,29 @@ pub fn run() {
                 )?;
             }
 
+            app.manage(AppHttpClient(Mutex::new(build_http_client(
+                DEFAULT_USER_AGENT,
+            )?)));
+            app.manage(DownloadHttpClient(Mutex::new(build_download_client(
+                DEFAULT_USER_AGENT,
+            )?)));
+
+            if let Err(e) = migrate_settings(app.handle()) {
+                log::warn!("Settings migration skipped: {}", e);
+            }
+
+            // Move a plaintext fallback token into the keyring
+            if let Err(e) = migrate_token_storage(app.handle()) {
+                log::warn!("Token storage migration failed: {}", e);
+            }
+
+            spawn_token_check_worker(app.handle().clone());
+            spawn_queue_worker(app.handle().clone());
+            start_folder_watch(app.handle());
+
+            // Launched through a file association: the image path is an argument
+            open_files(app.handle(), std::env::args().skip(1));
+
             // Auto-start tagger sidecar
             if let Err(e) = spawn_tagger_sc(app.handle()) {
                 eprintln!("Failed to auto-start tagger: {}", e);
@@ -606,7 +5861,19 @@ pub fn run() {
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
         .run(move |_app_handle, event| {
+            // macOS delivers "Open with" as an event instead of arguments
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            if let RunEvent::Opened { urls } = &event {
+                let paths = urls
+                    .iter()
+                    .filter_map(|url| url.to_file_path().ok())
+                    .map(|path| path.to_string_lossy().to_string());
+                open_files(_app_handle, paths);
+            }
+
             if let RunEvent::Exit = event {
+                cancel_requests_for_exit();
+                result_files::clear(_app_handle);
                 if let Ok(mut child) = tagger_state_clone.0.lock() {
                     if let Some(child_process) = child.take() {
                         let _pid = child_process.pid();
@@ -631,3 +5898,73 @@ pub fn run() {
             }
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_known_tier_has_its_name() {
+        let tiers = [(0, "paper"), (1, "tablet"), (2, "scroll"), (3, "opus")];
+        for (tier, name) in tiers {
+            assert_eq!(tier_name(tier), name);
+            assert_eq!(is_opus(tier), tier == 3, "{}", name);
+        }
+        assert_eq!(tier_name(4), "unknown(4)");
+        assert_eq!(tier_name(-1), "unknown(-1)");
+        assert!(!is_opus(4));
+    }
+
+    fn subscription(json: serde_json::Value) -> SubscriptionResponse {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn inactive_or_missing_subscriptions_are_paper() {
+        let inactive = subscription(serde_json::json!({"tier": 3, "active": false}));
+        assert_eq!(inactive.tier_number(), 0);
+        assert!(!inactive.unlimited_generation());
+
+        let missing = subscription(serde_json::json!({}));
+        assert_eq!(missing.tier_number(), 0);
+
+        let opus = subscription(serde_json::json!({"tier": 3, "active": true}));
+        assert_eq!(opus.tier_number(), 3);
+        assert!(opus.unlimited_generation());
+    }
+
+    #[test]
+    fn perks_decide_unlimited_generation() {
+        let perk = subscription(serde_json::json!({
+            "tier": 5,
+            "active": true,
+            "perks": {"unlimitedImageGeneration": true}
+        }));
+        assert!(perk.unlimited_generation());
+        assert_eq!(tier_name(perk.tier_number()), "unknown(5)");
+
+        let no_perk = subscription(serde_json::json!({
+            "tier": 3,
+            "active": true,
+            "perks": {"unlimitedImageGeneration": false}
+        }));
+        assert!(!no_perk.unlimited_generation());
+    }
+
+    #[test]
+    fn snap_command_matches_the_payload() {
+        assert_eq!(snap_character_position(0.42, 1.3, None), (0.5, 0.9));
+        assert_eq!(snap_character_position(0.42, 1.3, Some(false)), (0.5, 0.5));
+    }
+
+    #[test]
+    fn relative_arguments_use_the_launch_directory() {
+        let cwd = std::env::temp_dir();
+        let absolute = cwd.join("a.png").to_string_lossy().to_string();
+        assert_eq!(resolve_argument(absolute.clone(), &cwd), absolute);
+        assert_eq!(
+            resolve_argument("b.png".to_string(), &cwd),
+            cwd.join("b.png").to_string_lossy().to_string()
+        );
+    }
+}
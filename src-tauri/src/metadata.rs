@@ -0,0 +1,1109 @@
+//! NovelAI image metadata.
+//!
+//! NAI embeds its generation settings as PNG text chunks (the `Comment` chunk
+//! holds the JSON payload) and, so that they survive SNS re-encoding, as a
+//! gzip'd copy in the LSB of the alpha channel ("stealth pnginfo"). WebP and
+//! JPEG have no text chunks, so we map the same fields onto EXIF tags
+//! instead.
+//!
+//! JPEGs can't hold either, but other tools put the same JSON in the EXIF
+//! UserComment or ImageDescription, or in an XMP packet, so those are read
+//! too.
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::{Cursor, Read, Write};
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+const STEALTH_SIG_PLAIN: &str = "stealth_pnginfo";
+const STEALTH_SIG_COMPRESSED: &str = "stealth_pngcomp";
+const JPEG_EXIF_HEADER: &[u8] = b"Exif\0\0";
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+// EXIF tags used to carry the NAI text fields in WebP and JPEG
+const TAG_DOCUMENT_NAME: u16 = 0x010D;
+const TAG_IMAGE_DESCRIPTION: u16 = 0x010E;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_SOFTWARE: u16 = 0x0131;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_MAKER_NOTE: u16 = 0x927C;
+const TAG_USER_COMMENT: u16 = 0x9286;
+/// The text field each tag holds. Fields without a tag of their own
+/// (`Generation time`, ...) go into the MakerNote as a JSON object.
+const EXIF_FIELDS: [(u16, &str); 5] = [
+    (TAG_DOCUMENT_NAME, "Title"),
+    (TAG_IMAGE_DESCRIPTION, "Description"),
+    (TAG_MODEL, "Source"),
+    (TAG_SOFTWARE, "Software"),
+    (TAG_USER_COMMENT, "Comment"),
+];
+/// Key in the settings JSON listing what NAIS did to the image after
+/// generation, oldest first.
+pub const POSTPROCESS_KEY: &str = "nais_postprocess";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataSource {
+    TextChunk,
+    StealthAlpha,
+    /// EXIF UserComment (or, for WebP, the EXIF fields we write)
+    Exif,
+    /// JSON in the EXIF ImageDescription of a JPEG
+    ExifDescription,
+    /// JSON in a JPEG's XMP packet
+    Xmp,
+}
+
+/// The raw text fields NAI writes (Title, Description, Software, Source,
+/// Comment, ...), in the order they were found.
+#[derive(Debug, Clone)]
+pub struct EmbeddedMetadata {
+    pub fields: Vec<(String, String)>,
+    pub source: MetadataSource,
+}
+
+impl EmbeddedMetadata {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The generation settings JSON stored in the `Comment` field. Some
+    /// JPEG writers use the description instead.
+    pub fn comment(&self) -> Option<Value> {
+        ["Comment", "parameters", "Description"]
+            .iter()
+            .filter_map(|key| self.get(key))
+            .find_map(json_object)
+    }
+
+    /// NAI stores the model name in the `Source` field.
+    pub fn model(&self) -> Option<&str> {
+        self.get("Source")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataResult {
+    pub success: bool,
+    pub metadata: Option<Value>,
+    pub model: Option<String>,
+    pub source: Option<MetadataSource>,
+    pub fields: Option<Map<String, Value>>,
+    pub error: Option<String>,
+    /// Tags of the Danbooru post the image came from (`import_from_url`)
+    #[serde(default)]
+    pub booru_tags: Option<crate::url_import::BooruTags>,
+}
+
+impl MetadataResult {
+    pub fn failure(error: String) -> Self {
+        MetadataResult {
+            success: false,
+            metadata: None,
+            model: None,
+            source: None,
+            fields: None,
+            error: Some(error),
+            booru_tags: None,
+        }
+    }
+}
+
+impl From<Option<EmbeddedMetadata>> for MetadataResult {
+    fn from(embedded: Option<EmbeddedMetadata>) -> Self {
+        match embedded {
+            Some(meta) => MetadataResult {
+                success: true,
+                metadata: meta.comment(),
+                model: meta.model().map(|s| s.to_string()),
+                source: Some(meta.source),
+                fields: Some(
+                    meta.fields
+                        .iter()
+                        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                        .collect(),
+                ),
+                error: None,
+                booru_tags: None,
+            },
+            None => MetadataResult {
+                success: true,
+                metadata: None,
+                model: None,
+                source: None,
+                fields: None,
+                error: None,
+                booru_tags: None,
+            },
+        }
+    }
+}
+
+/// Decodes a base64 image, accepting an optional `data:image/...;base64,` prefix.
+pub fn decode_image_base64(data: &str) -> Result<Vec<u8>, String> {
+    let payload = match data.find(";base64,") {
+        Some(idx) if data.starts_with("data:") => &data[idx + ";base64,".len()..],
+        _ => data,
+    };
+    STANDARD
+        .decode(payload.trim())
+        .map_err(|e| format!("Base64 디코딩 오류: {}", e))
+}
+
+/// Reads NAI metadata from PNG, WebP or JPEG bytes. Text chunks / EXIF are
+/// checked first since they're cheap; the stealth alpha channel is the
+/// fallback.
+pub fn read_embedded_metadata(bytes: &[u8]) -> Option<EmbeddedMetadata> {
+    let format = image::guess_format(bytes).ok()?;
+    // No alpha channel, so nothing to fall back to
+    if format == ImageFormat::Jpeg {
+        return read_jpeg_metadata(bytes);
+    }
+
+    let fields = match format {
+        ImageFormat::Png => read_png_text_chunks(bytes),
+        _ => read_exif_fields(bytes),
+    };
+    if fields
+        .iter()
+        .any(|(k, _)| k == "Comment" || k == "parameters")
+    {
+        let source = if format == ImageFormat::Png {
+            MetadataSource::TextChunk
+        } else {
+            MetadataSource::Exif
+        };
+        return Some(EmbeddedMetadata { fields, source });
+    }
+
+    let image = image::load_from_memory_with_format(bytes, format).ok()?;
+    if !image.color().has_alpha() {
+        return None;
+    }
+    read_stealth_alpha(&image.to_rgba8()).map(|fields| EmbeddedMetadata {
+        fields,
+        source: MetadataSource::StealthAlpha,
+    })
+}
+
+/// `text` parsed as JSON, if it is a JSON object.
+fn json_object(text: &str) -> Option<Value> {
+    serde_json::from_str::<Value>(text.trim())
+        .ok()
+        .filter(Value::is_object)
+}
+
+/// The EXIF and XMP payloads of a JPEG's APP1 segments, headers stripped.
+fn jpeg_app1_segments(bytes: &[u8]) -> (Option<&[u8]>, Option<&[u8]>) {
+    let (mut exif, mut xmp) = (None, None);
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return (exif, xmp);
+    }
+
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() {
+        if bytes[offset] != 0xFF {
+            break;
+        }
+        let marker = bytes[offset + 1];
+        match marker {
+            // Fill byte before a marker
+            0xFF => {
+                offset += 1;
+                continue;
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                offset += 2;
+                continue;
+            }
+            // Start of scan / end of image: no metadata after this
+            0xDA | 0xD9 => break,
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        let Some(data) = length
+            .checked_sub(2)
+            .and_then(|len| bytes.get(offset + 4..offset + 4 + len))
+        else {
+            break;
+        };
+        if marker == 0xE1 {
+            if let Some(payload) = data.strip_prefix(JPEG_EXIF_HEADER) {
+                exif.get_or_insert(payload);
+            } else if let Some(payload) = data.strip_prefix(JPEG_XMP_HEADER) {
+                xmp.get_or_insert(payload);
+            }
+        }
+        offset += 2 + length;
+    }
+    (exif, xmp)
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&#10;", "\n")
+        .replace("&amp;", "&")
+}
+
+/// The first JSON object in an XMP packet, wherever it sits (element text
+/// or attribute value).
+fn xmp_json(xmp: &[u8]) -> Option<Value> {
+    let text = unescape_xml(&String::from_utf8_lossy(xmp));
+    text.match_indices('{').find_map(|(start, _)| {
+        serde_json::Deserializer::from_str(&text[start..])
+            .into_iter::<Value>()
+            .next()?
+            .ok()
+            .filter(Value::is_object)
+    })
+}
+
+/// EXIF UserComment, then EXIF ImageDescription, then XMP, taking the first
+/// that holds a JSON object. A non-JSON UserComment is still returned as
+/// plain EXIF fields.
+fn read_jpeg_metadata(bytes: &[u8]) -> Option<EmbeddedMetadata> {
+    let (exif, xmp) = jpeg_app1_segments(bytes);
+    let mut fields = exif
+        .map(|exif| exif_to_fields(&parse_exif(exif)))
+        .unwrap_or_default();
+    let field = |fields: &[(String, String)], key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| json_object(v))
+    };
+
+    if field(&fields, "Comment").is_some() {
+        return Some(EmbeddedMetadata {
+            fields,
+            source: MetadataSource::Exif,
+        });
+    }
+    if field(&fields, "Description").is_some() {
+        return Some(EmbeddedMetadata {
+            fields,
+            source: MetadataSource::ExifDescription,
+        });
+    }
+    if let Some(json) = xmp.and_then(xmp_json) {
+        fields.retain(|(k, _)| k != "Comment");
+        fields.push(("Comment".to_string(), json.to_string()));
+        return Some(EmbeddedMetadata {
+            fields,
+            source: MetadataSource::Xmp,
+        });
+    }
+    fields
+        .iter()
+        .any(|(k, _)| k == "Comment")
+        .then_some(EmbeddedMetadata {
+            fields,
+            source: MetadataSource::Exif,
+        })
+}
+
+fn read_png_text_chunks(bytes: &[u8]) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        return fields;
+    }
+
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]) as usize;
+        let kind = &bytes[offset + 4..offset + 8];
+        let data_start = offset + 8;
+        let Some(data) = bytes.get(data_start..data_start + length) else {
+            break;
+        };
+
+        let parsed = match kind {
+            b"tEXt" => parse_text_chunk(data),
+            b"zTXt" => parse_ztxt_chunk(data),
+            b"iTXt" => parse_itxt_chunk(data),
+            b"IEND" => break,
+            _ => None,
+        };
+        if let Some(field) = parsed {
+            fields.push(field);
+        }
+
+        // Skip data and CRC
+        offset = data_start + length + 4;
+    }
+
+    fields
+}
+
+fn split_keyword(data: &[u8]) -> Option<(String, &[u8])> {
+    let nul = data.iter().position(|&b| b == 0)?;
+    let keyword = String::from_utf8_lossy(&data[..nul]).to_string();
+    Some((keyword, &data[nul + 1..]))
+}
+
+fn parse_text_chunk(data: &[u8]) -> Option<(String, String)> {
+    let (keyword, text) = split_keyword(data)?;
+    // tEXt is nominally Latin-1, but NAI writes UTF-8
+    Some((keyword, String::from_utf8_lossy(text).to_string()))
+}
+
+fn parse_ztxt_chunk(data: &[u8]) -> Option<(String, String)> {
+    let (keyword, rest) = split_keyword(data)?;
+    let text = inflate_zlib(rest.get(1..)?)?;
+    Some((keyword, text))
+}
+
+fn parse_itxt_chunk(data: &[u8]) -> Option<(String, String)> {
+    let (keyword, rest) = split_keyword(data)?;
+    let compressed = *rest.first()? == 1;
+    let rest = rest.get(2..)?;
+    // Skip language tag and translated keyword
+    let lang_end = rest.iter().position(|&b| b == 0)?;
+    let rest = &rest[lang_end + 1..];
+    let translated_end = rest.iter().position(|&b| b == 0)?;
+    let text = &rest[translated_end + 1..];
+
+    let text = if compressed {
+        inflate_zlib(text)?
+    } else {
+        String::from_utf8_lossy(text).to_string()
+    };
+    Some((keyword, text))
+}
+
+fn inflate_zlib(data: &[u8]) -> Option<String> {
+    let mut out = String::new();
+    flate2::read::ZlibDecoder::new(data)
+        .read_to_string(&mut out)
+        .ok()?;
+    Some(out)
+}
+
+fn read_exif_fields(bytes: &[u8]) -> Vec<(String, String)> {
+    let exif = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_decoder().ok())
+        .and_then(|mut decoder| decoder.exif_metadata().ok().flatten());
+
+    match exif {
+        Some(exif) => exif_to_fields(&parse_exif(&exif)),
+        None => Vec::new(),
+    }
+}
+
+fn exif_to_fields(tags: &[(u16, String)]) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for (tag, value) in tags {
+        if let Some((_, key)) = EXIF_FIELDS.iter().find(|(t, _)| t == tag) {
+            fields.push((key.to_string(), value.clone()));
+            continue;
+        }
+        // Cameras keep binary data of their own here; only ours is JSON
+        if *tag == TAG_MAKER_NOTE {
+            if let Some(Value::Object(extra)) = json_object(value) {
+                fields.extend(
+                    extra
+                        .into_iter()
+                        .filter_map(|(k, v)| Some((k, v.as_str()?.to_string()))),
+                );
+            }
+        }
+    }
+    fields
+}
+
+/// Parses the string-valued tags of a TIFF/EXIF blob (IFD0 and the Exif
+/// sub-IFD). A leading `Exif\0\0` header is tolerated.
+pub fn parse_exif(data: &[u8]) -> Vec<(u16, String)> {
+    let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+    let little_endian = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return Vec::new(),
+    };
+
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let b = tiff.get(pos..pos + 2)?;
+        Some(if little_endian {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let b = tiff.get(pos..pos + 4)?;
+        Some(if little_endian {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    };
+
+    let mut tags = Vec::new();
+    let mut pending = vec![read_u32(4).unwrap_or(0) as usize];
+    let mut visited = Vec::new();
+
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 || visited.contains(&ifd) {
+            continue;
+        }
+        visited.push(ifd);
+
+        let Some(count) = read_u16(ifd) else {
+            continue;
+        };
+        for i in 0..count as usize {
+            let entry = ifd + 2 + i * 12;
+            let (Some(tag), Some(kind), Some(len)) =
+                (read_u16(entry), read_u16(entry + 2), read_u32(entry + 4))
+            else {
+                break;
+            };
+            let len = len as usize;
+
+            if tag == TAG_EXIF_IFD {
+                if let Some(ptr) = read_u32(entry + 8) {
+                    pending.push(ptr as usize);
+                }
+                continue;
+            }
+
+            // Only ASCII (2) and UNDEFINED (7) carry text
+            if kind != 2 && kind != 7 {
+                continue;
+            }
+            let value_pos = if len <= 4 {
+                entry + 8
+            } else {
+                match read_u32(entry + 8) {
+                    Some(p) => p as usize,
+                    None => continue,
+                }
+            };
+            let Some(raw) = tiff.get(value_pos..value_pos + len) else {
+                continue;
+            };
+
+            let text = if tag == TAG_USER_COMMENT {
+                decode_user_comment(raw, little_endian)
+            } else {
+                String::from_utf8_lossy(raw)
+                    .trim_end_matches('\0')
+                    .to_string()
+            };
+            tags.push((tag, text));
+        }
+
+        if let Some(next) = read_u16(ifd).and_then(|c| read_u32(ifd + 2 + c as usize * 12)) {
+            pending.push(next as usize);
+        }
+    }
+
+    tags
+}
+
+/// UserComment starts with an 8-byte charset prefix (ASCII/UNICODE/JIS/undefined).
+fn decode_user_comment(raw: &[u8], little_endian: bool) -> String {
+    let (prefix, body) = raw.split_at(raw.len().min(8));
+    let text = if prefix.starts_with(b"UNICODE") {
+        // Some writers put UTF-16 in the opposite byte order, so sniff for a BOM first
+        let (body, le) = match body {
+            [0xFF, 0xFE, rest @ ..] => (rest, true),
+            [0xFE, 0xFF, rest @ ..] => (rest, false),
+            _ => (body, little_endian),
+        };
+        let units: Vec<u16> = body
+            .chunks_exact(2)
+            .map(|c| {
+                if le {
+                    u16::from_le_bytes([c[0], c[1]])
+                } else {
+                    u16::from_be_bytes([c[0], c[1]])
+                }
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if prefix.starts_with(b"ASCII") || prefix.iter().all(|&b| b == 0) {
+        String::from_utf8_lossy(body).to_string()
+    } else {
+        String::from_utf8_lossy(raw).to_string()
+    };
+    text.trim_end_matches('\0').to_string()
+}
+
+/// Builds a little-endian EXIF blob carrying the NAI text fields, all of
+/// them (see `EXIF_FIELDS`).
+pub fn build_exif(fields: &[(String, String)]) -> Vec<u8> {
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+
+    // Entries must be in tag order
+    let mut ifd0: Vec<(u16, u16, Vec<u8>)> = Vec::new();
+    for (tag, key) in &EXIF_FIELDS[..4] {
+        if let Some(value) = field(key) {
+            ifd0.push((*tag, 2, nul_terminated(value)));
+        }
+    }
+
+    let mut exif_ifd: Vec<(u16, u16, Vec<u8>)> = Vec::new();
+    let mut extra = Map::new();
+    for (key, value) in fields {
+        if !EXIF_FIELDS.iter().any(|(_, k)| k == key) {
+            extra.insert(key.clone(), Value::String(value.clone()));
+        }
+    }
+    if !extra.is_empty() {
+        let note = Value::Object(extra).to_string().into_bytes();
+        exif_ifd.push((TAG_MAKER_NOTE, 7, note));
+    }
+    if let Some(comment) = field("Comment") {
+        let mut value = b"UNICODE\0".to_vec();
+        for unit in comment.encode_utf16() {
+            value.extend_from_slice(&unit.to_le_bytes());
+        }
+        exif_ifd.push((TAG_USER_COMMENT, 7, value));
+    }
+
+    let ifd_size = |n: usize| 2 + n * 12 + 4;
+    let ifd0_entries = ifd0.len() + usize::from(!exif_ifd.is_empty());
+    let ifd0_offset = 8;
+    let exif_offset = ifd0_offset + ifd_size(ifd0_entries);
+    let mut data_offset = exif_offset
+        + if exif_ifd.is_empty() {
+            0
+        } else {
+            ifd_size(exif_ifd.len())
+        };
+
+    let mut out = b"II*\0".to_vec();
+    out.extend_from_slice(&(ifd0_offset as u32).to_le_bytes());
+
+    let mut heap = Vec::new();
+    let mut write_ifd = |out: &mut Vec<u8>, entries: &[(u16, u16, Vec<u8>)], link: Option<u32>| {
+        let count = entries.len() + usize::from(link.is_some());
+        out.extend_from_slice(&(count as u16).to_le_bytes());
+        for (tag, kind, value) in entries {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            if value.len() <= 4 {
+                let mut inline = value.clone();
+                inline.resize(4, 0);
+                out.extend_from_slice(&inline);
+            } else {
+                out.extend_from_slice(&(data_offset as u32).to_le_bytes());
+                heap.extend_from_slice(value);
+                data_offset += value.len();
+                if value.len() % 2 == 1 {
+                    heap.push(0);
+                    data_offset += 1;
+                }
+            }
+        }
+        if let Some(ptr) = link {
+            out.extend_from_slice(&TAG_EXIF_IFD.to_le_bytes());
+            out.extend_from_slice(&4u16.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            out.extend_from_slice(&ptr.to_le_bytes());
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+    };
+
+    let link = (!exif_ifd.is_empty()).then_some(exif_offset as u32);
+    write_ifd(&mut out, &ifd0, link);
+    if !exif_ifd.is_empty() {
+        write_ifd(&mut out, &exif_ifd, None);
+    }
+    out.extend_from_slice(&heap);
+    out
+}
+
+fn nul_terminated(s: &str) -> Vec<u8> {
+    let mut v = s.as_bytes().to_vec();
+    v.push(0);
+    v
+}
+
+/// Reads stealth pnginfo from the alpha LSBs (column-major, like NAI's reader).
+fn read_stealth_alpha(image: &RgbaImage) -> Option<Vec<(String, String)>> {
+    let (width, height) = image.dimensions();
+    let mut bits = (0..width).flat_map(|x| (0..height).map(move |y| (x, y)));
+    let mut next_bit = || bits.next().map(|(x, y)| image.get_pixel(x, y)[3] & 1);
+
+    let mut read_bytes = |count: usize| -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut byte = 0u8;
+            for _ in 0..8 {
+                byte = (byte << 1) | next_bit()?;
+            }
+            out.push(byte);
+        }
+        Some(out)
+    };
+
+    let signature = read_bytes(STEALTH_SIG_PLAIN.len())?;
+    let compressed = match signature.as_slice() {
+        s if s == STEALTH_SIG_PLAIN.as_bytes() => false,
+        s if s == STEALTH_SIG_COMPRESSED.as_bytes() => true,
+        _ => return None,
+    };
+
+    let len_bytes = read_bytes(4)?;
+    let bit_len = u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]);
+    let payload = read_bytes(bit_len as usize / 8)?;
+
+    let text = if compressed {
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(payload.as_slice())
+            .read_to_string(&mut out)
+            .ok()?;
+        out
+    } else {
+        String::from_utf8(payload).ok()?
+    };
+
+    let Value::Object(map) = serde_json::from_str::<Value>(&text).ok()? else {
+        return None;
+    };
+    Some(
+        map.into_iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => (k, s),
+                other => (k, other.to_string()),
+            })
+            .collect(),
+    )
+}
+
+/// Whether the image carries stealth pnginfo, whatever else it has.
+pub fn has_stealth_metadata(bytes: &[u8]) -> bool {
+    image::load_from_memory(bytes)
+        .ok()
+        .filter(|image| image.color().has_alpha())
+        .is_some_and(|image| read_stealth_alpha(&image.to_rgba8()).is_some())
+}
+
+/// Writes the fields into the alpha LSBs as gzip'd stealth pnginfo.
+pub fn write_stealth_alpha(
+    image: &mut RgbaImage,
+    fields: &[(String, String)],
+) -> Result<(), String> {
+    let json: Map<String, Value> = fields
+        .iter()
+        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+        .collect();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder
+        .write_all(Value::Object(json).to_string().as_bytes())
+        .map_err(|e| e.to_string())?;
+    let payload = encoder.finish().map_err(|e| e.to_string())?;
+
+    let mut data = STEALTH_SIG_COMPRESSED.as_bytes().to_vec();
+    data.extend_from_slice(&((payload.len() * 8) as u32).to_be_bytes());
+    data.extend_from_slice(&payload);
+
+    let (width, height) = image.dimensions();
+    let capacity = width as usize * height as usize;
+    if data.len() * 8 > capacity {
+        return Err(format!(
+            "이미지가 너무 작아 메타데이터를 알파 채널에 담을 수 없습니다 ({} bits > {} px)",
+            data.len() * 8,
+            capacity
+        ));
+    }
+
+    let bits = data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    let coords = (0..width).flat_map(|x| (0..height).map(move |y| (x, y)));
+    for (bit, (x, y)) in bits.zip(coords) {
+        let pixel = image.get_pixel_mut(x, y);
+        pixel[3] = (pixel[3] & !1) | bit;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertTarget {
+    Png,
+    Jpeg,
+    WebpLossless,
+}
+
+impl ConvertTarget {
+    pub fn parse(target: &str) -> Result<Self, String> {
+        match target.to_lowercase().as_str() {
+            "png" => Ok(ConvertTarget::Png),
+            "jpeg" | "jpg" => Ok(ConvertTarget::Jpeg),
+            "webp-lossless" | "webp" => Ok(ConvertTarget::WebpLossless),
+            other => Err(format!("지원하지 않는 변환 형식: {}", other)),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConvertTarget::Png => "png",
+            ConvertTarget::Jpeg => "jpg",
+            ConvertTarget::WebpLossless => "webp",
+        }
+    }
+}
+
+/// Where to put the metadata when the target can't hold PNG text chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataEmbed {
+    #[default]
+    Exif,
+    Alpha,
+    Both,
+    None,
+}
+
+/// Re-encodes an image, carrying its NAI metadata over where the target
+/// format allows it. JPEG output is lossy and has no alpha channel, so its
+/// metadata can only go into EXIF.
+pub fn convert_image_bytes(
+    bytes: &[u8],
+    target: ConvertTarget,
+    embed: MetadataEmbed,
+) -> Result<Vec<u8>, String> {
+    let image = image::load_from_memory(bytes).map_err(|e| format!("이미지 디코딩 오류: {}", e))?;
+    let metadata = read_embedded_metadata(bytes);
+
+    match target {
+        ConvertTarget::Png => {
+            let mut out = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+                .map_err(|e| e.to_string())?;
+            match metadata {
+                Some(meta) => insert_png_text_chunks(&out, &meta.fields),
+                None => Ok(out),
+            }
+        }
+        ConvertTarget::Jpeg => {
+            let fields = metadata.map(|m| m.fields).unwrap_or_default();
+            let use_exif =
+                !fields.is_empty() && matches!(embed, MetadataEmbed::Exif | MetadataEmbed::Both);
+
+            let mut out = Vec::new();
+            let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+            let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 92);
+            if use_exif {
+                encoder
+                    .set_exif_metadata(build_exif(&fields))
+                    .map_err(|e| e.to_string())?;
+            }
+            encoder.encode_image(&rgb).map_err(|e| e.to_string())?;
+            Ok(out)
+        }
+        ConvertTarget::WebpLossless => {
+            let fields = metadata.map(|m| m.fields).unwrap_or_default();
+            let use_alpha =
+                !fields.is_empty() && matches!(embed, MetadataEmbed::Alpha | MetadataEmbed::Both);
+            let use_exif =
+                !fields.is_empty() && matches!(embed, MetadataEmbed::Exif | MetadataEmbed::Both);
+
+            // LSB embedding needs an alpha channel, so RGB sources get promoted to RGBA
+            let image = if use_alpha {
+                let mut rgba = image.to_rgba8();
+                write_stealth_alpha(&mut rgba, &fields)?;
+                DynamicImage::ImageRgba8(rgba)
+            } else if image.color().has_alpha() {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8())
+            };
+
+            let mut out = Vec::new();
+            let mut encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut out);
+            if use_exif {
+                encoder
+                    .set_exif_metadata(build_exif(&fields))
+                    .map_err(|e| e.to_string())?;
+            }
+            encoder
+                .write_image(
+                    image.as_bytes(),
+                    image.width(),
+                    image.height(),
+                    image.color().into(),
+                )
+                .map_err(|e| e.to_string())?;
+            Ok(out)
+        }
+    }
+}
+
+/// Inserts tEXt (or iTXt for non-Latin-1 text) chunks right after IHDR.
+pub fn insert_png_text_chunks(png: &[u8], fields: &[(String, String)]) -> Result<Vec<u8>, String> {
+    if !png.starts_with(&PNG_SIGNATURE) || png.len() < 33 {
+        return Err("PNG 형식이 아닙니다".to_string());
+    }
+    // Signature (8) + IHDR chunk (4 + 4 + 13 + 4)
+    let ihdr_end = 8 + 25;
+
+    let mut out = png[..ihdr_end].to_vec();
+    for (key, value) in fields {
+        let (kind, data) = if value.is_ascii() {
+            let mut data = key.as_bytes().to_vec();
+            data.push(0);
+            data.extend_from_slice(value.as_bytes());
+            (b"tEXt", data)
+        } else {
+            // keyword \0 compression flag, method, empty language \0, empty translation \0
+            let mut data = key.as_bytes().to_vec();
+            data.extend_from_slice(&[0, 0, 0, 0, 0]);
+            data.extend_from_slice(value.as_bytes());
+            (b"iTXt", data)
+        };
+        write_png_chunk(&mut out, kind, &data);
+    }
+    out.extend_from_slice(&png[ihdr_end..]);
+    Ok(out)
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = flate2::Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+/// Metadata fields from a string the frontend kept: either the `fields`
+/// object `parse_metadata` returns, or just the settings JSON (which becomes
+/// the `Comment`).
+pub fn fields_from_json(text: &str) -> Option<Vec<(String, String)>> {
+    let Value::Object(map) = json_object(text)? else {
+        return None;
+    };
+    if map.get("Comment").is_some_and(Value::is_string) {
+        return Some(
+            map.into_iter()
+                .filter_map(|(k, v)| match v {
+                    Value::String(s) => Some((k, s)),
+                    _ => None,
+                })
+                .collect(),
+        );
+    }
+    Some(vec![("Comment".to_string(), text.trim().to_string())])
+}
+
+/// Appends `step` to the post-processing history in the `Comment` JSON and
+/// sets `key` (e.g. `upscaled`) to true. Fields without a JSON `Comment`
+/// are left alone.
+pub fn record_postprocess(fields: &mut [(String, String)], key: &str, step: Value) {
+    let Some((_, comment)) = fields.iter_mut().find(|(k, _)| k == "Comment") else {
+        return;
+    };
+    let Some(Value::Object(mut settings)) = json_object(comment) else {
+        return;
+    };
+    settings.insert(key.to_string(), Value::Bool(true));
+    match settings.get_mut(POSTPROCESS_KEY) {
+        Some(Value::Array(history)) => history.push(step),
+        _ => {
+            settings.insert(POSTPROCESS_KEY.to_string(), Value::Array(vec![step]));
+        }
+    }
+    *comment = Value::Object(settings).to_string();
+}
+
+/// Copy of `png` without its tEXt/zTXt/iTXt chunks.
+fn strip_png_text_chunks(png: &[u8]) -> Vec<u8> {
+    let mut out = png[..PNG_SIGNATURE.len()].to_vec();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= png.len() {
+        let length = u32::from_be_bytes([
+            png[offset],
+            png[offset + 1],
+            png[offset + 2],
+            png[offset + 3],
+        ]) as usize;
+        let end = (offset + 12 + length).min(png.len());
+        if !matches!(&png[offset + 4..offset + 8], b"tEXt" | b"zTXt" | b"iTXt") {
+            out.extend_from_slice(&png[offset..end]);
+        }
+        offset = end;
+    }
+    out
+}
+
+/// Writes `fields` into a PNG as text chunks, replacing any it had, and with
+/// `stealth` also into the alpha LSBs (re-encoding the pixels as RGBA).
+pub fn embed_png_metadata(
+    png: &[u8],
+    fields: &[(String, String)],
+    stealth: bool,
+) -> Result<Vec<u8>, String> {
+    if !png.starts_with(&PNG_SIGNATURE) {
+        return Err("PNG 형식이 아닙니다".to_string());
+    }
+    let png = if stealth {
+        let mut rgba = image::load_from_memory_with_format(png, ImageFormat::Png)
+            .map_err(|e| format!("이미지 디코딩 오류: {}", e))?
+            .to_rgba8();
+        write_stealth_alpha(&mut rgba, fields)?;
+        let mut out = Vec::new();
+        DynamicImage::ImageRgba8(rgba)
+            .write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+            .map_err(|e| e.to_string())?;
+        out
+    } else {
+        strip_png_text_chunks(png)
+    };
+    insert_png_text_chunks(&png, fields)
+}
+
+fn sorted(mut fields: Vec<(String, String)>) -> Vec<(String, String)> {
+    fields.sort();
+    fields
+}
+
+/// Reads `png` back and checks that its text chunks, and with `stealth` its
+/// alpha LSBs on their own, hold exactly `fields`.
+pub fn verify_png_metadata(
+    png: &[u8],
+    fields: &[(String, String)],
+    stealth: bool,
+) -> Result<(), String> {
+    let expected = sorted(fields.to_vec());
+    let check = |bytes: &[u8], source: MetadataSource| match read_embedded_metadata(bytes) {
+        Some(meta) if meta.source == source && sorted(meta.fields.clone()) == expected => Ok(()),
+        Some(_) => Err(format!(
+            "기록된 메타데이터가 입력과 다릅니다 ({:?})",
+            source
+        )),
+        None => Err(format!(
+            "기록한 메타데이터를 읽을 수 없습니다 ({:?})",
+            source
+        )),
+    };
+    check(png, MetadataSource::TextChunk)?;
+    if stealth {
+        check(&strip_png_text_chunks(png), MetadataSource::StealthAlpha)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text chunks of a NAI PNG
+    fn nai_fields() -> Vec<(String, String)> {
+        let comment = serde_json::json!({
+            "prompt": "1girl, 고양이 귀, {{smile}}",
+            "uc": "lowres",
+            "steps": 28,
+            "scale": 5.0,
+            "seed": 1234567890,
+            "sampler": "k_euler_ancestral",
+        });
+        [
+            ("Title", "NovelAI generated image".to_string()),
+            ("Description", "1girl, 고양이 귀, {{smile}}".to_string()),
+            ("Software", "NovelAI".to_string()),
+            ("Source", "NovelAI Diffusion V4.5 4BDE2A90".to_string()),
+            ("Generation time", "4.2".to_string()),
+            ("Comment", comment.to_string()),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect()
+    }
+
+    fn nai_png() -> Vec<u8> {
+        let image = RgbaImage::from_pixel(64, 64, image::Rgba([200, 120, 40, 255]));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        insert_png_text_chunks(&png, &nai_fields()).unwrap()
+    }
+
+    /// What `parse_metadata` reports for `bytes`
+    fn parsed(bytes: &[u8]) -> MetadataResult {
+        MetadataResult::from(read_embedded_metadata(bytes))
+    }
+
+    fn assert_round_trip(target: ConvertTarget, embed: MetadataEmbed, source: MetadataSource) {
+        let original = parsed(&nai_png());
+        let converted = convert_image_bytes(&nai_png(), target, embed).unwrap();
+        let result = parsed(&converted);
+
+        assert_eq!(result.source, Some(source), "{:?} {:?}", target, embed);
+        assert_eq!(result.fields, original.fields, "{:?} {:?}", target, embed);
+        assert_eq!(result.metadata, original.metadata);
+        assert_eq!(result.model, original.model);
+    }
+
+    #[test]
+    fn png_round_trips_through_webp_exif() {
+        assert_round_trip(
+            ConvertTarget::WebpLossless,
+            MetadataEmbed::Exif,
+            MetadataSource::Exif,
+        );
+    }
+
+    #[test]
+    fn png_round_trips_through_webp_alpha() {
+        assert_round_trip(
+            ConvertTarget::WebpLossless,
+            MetadataEmbed::Alpha,
+            MetadataSource::StealthAlpha,
+        );
+    }
+
+    #[test]
+    fn png_round_trips_through_jpeg_exif() {
+        assert_round_trip(
+            ConvertTarget::Jpeg,
+            MetadataEmbed::Exif,
+            MetadataSource::Exif,
+        );
+    }
+
+    #[test]
+    fn png_round_trips_through_png() {
+        assert_round_trip(
+            ConvertTarget::Png,
+            MetadataEmbed::None,
+            MetadataSource::TextChunk,
+        );
+    }
+
+    #[test]
+    fn exif_keeps_every_field() {
+        let fields = exif_to_fields(&parse_exif(&build_exif(&nai_fields())));
+        assert_eq!(sorted(fields), sorted(nai_fields()));
+    }
+}
@@ -0,0 +1,193 @@
+//! The NAI image models NAIS knows, with what each one supports.
+//!
+//! This is the one list of model ids: the frontend gets it from
+//! `list_models`, new settings take their defaults from it, and generation
+//! requests are checked against it before they are sent. Inpainting uses
+//! the `-inpainting` variant of a model, which shares its entry.
+
+use crate::generation::GenerationParams;
+use serde::Serialize;
+
+pub const DEFAULT_MODEL: &str = "nai-diffusion-4-5-full";
+const INPAINTING_SUFFIX: &str = "-inpainting";
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ModelFeatures {
+    pub img2img: bool,
+    pub inpaint: bool,
+    pub vibe_transfer: bool,
+    /// V4 character prompts with positions (`char_captions`)
+    pub character_prompts: bool,
+    /// Character reference images (`director_reference_*`)
+    pub character_reference: bool,
+    /// A separate `variation_seed` for the secondary noise
+    pub variation: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ModelInfo {
+    /// Model id sent to the API
+    pub id: &'static str,
+    pub name: &'static str,
+    /// What NAI writes into an image's `Source` field, minus the hash
+    pub source: &'static str,
+    pub features: ModelFeatures,
+    pub default_sampler: &'static str,
+    pub default_scale: f64,
+    /// Longest side in pixels
+    pub max_side: u32,
+    /// Largest width x height
+    pub max_pixels: u64,
+}
+
+const V4_FEATURES: ModelFeatures = ModelFeatures {
+    img2img: true,
+    inpaint: true,
+    vibe_transfer: true,
+    character_prompts: true,
+    character_reference: false,
+    variation: true,
+};
+
+const V3_FEATURES: ModelFeatures = ModelFeatures {
+    character_prompts: false,
+    variation: false,
+    ..V4_FEATURES
+};
+
+const MAX_SIDE: u32 = 2048;
+const MAX_PIXELS: u64 = 1536 * 2048;
+
+const MODELS: &[ModelInfo] = &[
+    ModelInfo {
+        id: "nai-diffusion-4-5-full",
+        name: "NAI Diffusion V4.5 Full",
+        source: "NovelAI Diffusion V4.5",
+        features: ModelFeatures {
+            character_reference: true,
+            ..V4_FEATURES
+        },
+        default_sampler: "k_euler_ancestral",
+        default_scale: 5.0,
+        max_side: MAX_SIDE,
+        max_pixels: MAX_PIXELS,
+    },
+    ModelInfo {
+        id: "nai-diffusion-4-5-curated",
+        name: "NAI Diffusion V4.5 Curated",
+        source: "NovelAI Diffusion V4.5 Curated",
+        features: V4_FEATURES,
+        default_sampler: "k_euler_ancestral",
+        default_scale: 5.0,
+        max_side: MAX_SIDE,
+        max_pixels: MAX_PIXELS,
+    },
+    ModelInfo {
+        id: "nai-diffusion-4-full",
+        name: "NAI Diffusion V4 Full",
+        source: "NovelAI Diffusion V4",
+        features: V4_FEATURES,
+        default_sampler: "k_euler_ancestral",
+        default_scale: 6.0,
+        max_side: MAX_SIDE,
+        max_pixels: MAX_PIXELS,
+    },
+    ModelInfo {
+        id: "nai-diffusion-4-curated-preview",
+        name: "NAI Diffusion V4 Curated",
+        source: "NovelAI Diffusion V4 Curated",
+        features: V4_FEATURES,
+        default_sampler: "k_euler_ancestral",
+        default_scale: 6.0,
+        max_side: MAX_SIDE,
+        max_pixels: MAX_PIXELS,
+    },
+    ModelInfo {
+        id: "nai-diffusion-3",
+        name: "NAI Diffusion Anime V3",
+        source: "Stable Diffusion XL",
+        features: V3_FEATURES,
+        default_sampler: "k_euler_ancestral",
+        default_scale: 5.0,
+        max_side: MAX_SIDE,
+        max_pixels: MAX_PIXELS,
+    },
+    ModelInfo {
+        id: "nai-diffusion-furry-3",
+        name: "NAI Diffusion Furry V3",
+        source: "Stable Diffusion XL",
+        features: V3_FEATURES,
+        default_sampler: "k_euler_ancestral",
+        default_scale: 5.0,
+        max_side: MAX_SIDE,
+        max_pixels: MAX_PIXELS,
+    },
+];
+
+pub fn all() -> &'static [ModelInfo] {
+    MODELS
+}
+
+/// The entry for `id`, which may also be an `-inpainting` variant.
+pub fn find(id: &str) -> Option<&'static ModelInfo> {
+    let id = id.trim();
+    let id = id.strip_suffix(INPAINTING_SUFFIX).unwrap_or(id);
+    MODELS.iter().find(|model| model.id == id)
+}
+
+pub fn get(id: &str) -> Result<&'static ModelInfo, String> {
+    find(id).ok_or_else(|| {
+        let known: Vec<&str> = MODELS.iter().map(|model| model.id).collect();
+        format!(
+            "알 수 없는 모델입니다: '{}' (지원: {})",
+            id,
+            known.join(", ")
+        )
+    })
+}
+
+pub fn default_model() -> &'static ModelInfo {
+    find(DEFAULT_MODEL).expect("the default model is registered")
+}
+
+/// Checks `params` against what its model supports, before anything is
+/// sent. Character prompts on models without them aren't an error:
+/// `build_payload` leaves them out.
+pub fn validate(params: &GenerationParams) -> Result<(), String> {
+    let model = get(&params.model)?;
+    let unsupported =
+        |feature: &str| format!("{}은(는) {}을(를) 지원하지 않습니다", model.name, feature);
+
+    if params.source_image.is_some() {
+        if params.mask.is_some() && !model.features.inpaint {
+            return Err(unsupported("인페인팅"));
+        }
+        if params.mask.is_none() && !model.features.img2img {
+            return Err(unsupported("img2img"));
+        }
+    }
+    if !params.pre_encoded_vibes.is_empty() && !model.features.vibe_transfer {
+        return Err(unsupported("Vibe Transfer"));
+    }
+    if !params.char_images.is_empty() && !model.features.character_reference {
+        return Err(unsupported("캐릭터 참조"));
+    }
+    if params.variation_seed.is_some() && !model.features.variation {
+        return Err(unsupported("시드 변형"));
+    }
+    if params
+        .variation_strength
+        .is_some_and(|strength| !(0.0..=1.0).contains(&strength))
+    {
+        return Err("variation_strength는 0에서 1 사이여야 합니다".to_string());
+    }
+    if params.width.max(params.height) > model.max_side
+        || params.width as u64 * params.height as u64 > model.max_pixels
+    {
+        return Err(format!(
+            "{}x{}은(는) {}의 최대 해상도를 넘습니다 (한 변 {}px, 총 {}픽셀까지)",
+            params.width, params.height, model.name, model.max_side, model.max_pixels
+        ));
+    }
+    Ok(())
+}
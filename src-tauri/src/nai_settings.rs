@@ -0,0 +1,462 @@
+//! Generation settings in NovelAI's own format.
+//!
+//! The official site exports (and embeds in image metadata) a flat object
+//! with snake_case keys: `prompt`, `uc`, `steps`, `scale`, `noise_schedule`,
+//! `v4_prompt`, ... Request bodies wrap the same keys in `parameters` next
+//! to `input` and `model`; both shapes are read. The mapping to and from
+//! `GenerationParams` lives only here. Keys it doesn't know are kept in
+//! `GenerationParams::extra` and written back on export.
+
+use crate::generation::{self, CharacterPosition, CharacterPrompt, GenerationParams};
+use crate::metadata::{self, EmbeddedMetadata, MetadataSource};
+use crate::models;
+use crate::uc_presets;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+const DEFAULT_WIDTH: u32 = 832;
+const DEFAULT_HEIGHT: u32 = 1216;
+const DEFAULT_STEPS: u32 = 28;
+const DEFAULT_SCHEDULER: &str = "karras";
+
+/// Keys that `build_payload` always derives from other settings. They are
+/// consumed on import so a stale copy in `extra` can't override them.
+const DERIVED_KEYS: &[&str] = &["extra_noise_seed", "negative_prompt", "input", "action"];
+/// NAIS's own markers (see `metadata::record_postprocess`), not settings.
+const HISTORY_KEYS: &[&str] = &["upscaled", metadata::POSTPROCESS_KEY];
+/// Image data that doesn't belong in a settings file.
+const IMAGE_KEYS: &[&str] = &[
+    "image",
+    "mask",
+    "reference_image_multiple",
+    "director_reference_images",
+    "director_reference_descriptions",
+];
+
+#[derive(Debug, Serialize)]
+pub struct NaiSettingsImport {
+    pub params: GenerationParams,
+    /// Settings that were missing and got a default, one line each
+    pub warnings: Vec<String>,
+}
+
+/// Pulls keys out of the settings object, recording which were used.
+struct Reader {
+    map: Map<String, Value>,
+    warnings: Vec<String>,
+}
+
+impl Reader {
+    fn take(&mut self, key: &str) -> Option<Value> {
+        self.map.remove(key).filter(|v| !v.is_null())
+    }
+
+    fn take_str(&mut self, key: &str) -> Option<String> {
+        self.take(key).and_then(|v| v.as_str().map(str::to_string))
+    }
+
+    fn take_f64(&mut self, key: &str) -> Option<f64> {
+        self.take(key).and_then(|v| v.as_f64())
+    }
+
+    fn take_u32(&mut self, key: &str) -> Option<u32> {
+        self.take(key)
+            .and_then(|v| v.as_u64())
+            .and_then(|v| u32::try_from(v).ok())
+    }
+
+    fn take_bool(&mut self, key: &str) -> Option<bool> {
+        self.take(key).and_then(|v| v.as_bool())
+    }
+
+    fn or_default<T: std::fmt::Debug>(&mut self, key: &str, value: Option<T>, default: T) -> T {
+        value.unwrap_or_else(|| {
+            self.warnings
+                .push(format!("'{}' 없음, 기본값 {:?} 사용", key, default));
+            default
+        })
+    }
+}
+
+fn caption_text(prompt: Option<&Value>) -> Option<String> {
+    prompt?
+        .pointer("/caption/base_caption")?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn char_captions(prompt: Option<&Value>) -> Vec<(String, CharacterPosition)> {
+    let Some(captions) = prompt
+        .and_then(|p| p.pointer("/caption/char_captions"))
+        .and_then(Value::as_array)
+    else {
+        return Vec::new();
+    };
+    captions
+        .iter()
+        .map(|c| {
+            let text = c["char_caption"].as_str().unwrap_or_default().to_string();
+            let position = c
+                .pointer("/centers/0")
+                .map(|center| CharacterPosition {
+                    x: center["x"].as_f64().unwrap_or(0.5),
+                    y: center["y"].as_f64().unwrap_or(0.5),
+                })
+                .unwrap_or(generation::DEFAULT_POSITION);
+            (text, position)
+        })
+        .collect()
+}
+
+/// Strips the UC preset text from the front of `negative`, since
+/// `GenerationParams` keeps only the user's own part and adds the preset
+/// back when sending. `None` when `negative` doesn't start with the preset,
+/// i.e. it was never applied (NAIS writes `ucPreset: 0` even without one).
+fn strip_uc_preset(model: &str, preset_id: u32, negative: &str) -> Option<String> {
+    let preset = uc_presets::resolve_uc_preset(model, preset_id);
+    if preset.is_empty() {
+        return Some(negative.to_string());
+    }
+    negative
+        .strip_prefix(preset.as_str())
+        .map(|rest| rest.trim_start_matches(',').trim_start().to_string())
+}
+
+/// NAIS's defaults for the settings NAI always expects, with empty prompts.
+pub fn default_params() -> GenerationParams {
+    let model = models::default_model();
+    GenerationParams {
+        model: model.id.to_string(),
+        width: DEFAULT_WIDTH,
+        height: DEFAULT_HEIGHT,
+        steps: DEFAULT_STEPS,
+        cfg_scale: model.default_scale,
+        sampler: model.default_sampler.to_string(),
+        scheduler: DEFAULT_SCHEDULER.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Reads NAI's settings JSON. Missing settings get NAIS's defaults and a
+/// warning; unknown keys end up in `params.extra`.
+pub fn import(value: Value) -> Result<NaiSettingsImport, String> {
+    let Value::Object(mut map) = value else {
+        return Err("설정 JSON은 객체여야 합니다".to_string());
+    };
+    // Request body shape: `{ input, model, action, parameters: {...} }`
+    if let Some(Value::Object(parameters)) = map.remove("parameters") {
+        let input = map.remove("input");
+        map.extend(parameters);
+        if let Some(input) = input {
+            map.entry("prompt").or_insert(input);
+        }
+    }
+    let mut r = Reader {
+        map,
+        warnings: Vec::new(),
+    };
+
+    let v4_prompt = r.take("v4_prompt");
+    let v4_negative = r.take("v4_negative_prompt");
+
+    let prompt = r
+        .take_str("prompt")
+        .or_else(|| caption_text(v4_prompt.as_ref()));
+    let prompt = r.or_default("prompt", prompt, String::new());
+    let model = r.take_str("model");
+    let model = r.or_default("model", model, models::DEFAULT_MODEL.to_string());
+    // Scale and sampler default to what the site uses for this model
+    let defaults = models::find(&model).unwrap_or_else(models::default_model);
+    let negative = r
+        .take_str("uc")
+        .or_else(|| r.take_str("negative_prompt"))
+        .or_else(|| caption_text(v4_negative.as_ref()))
+        .unwrap_or_default();
+    let (uc_preset, negative_prompt) = match r.take_u32("ucPreset") {
+        Some(id) => match strip_uc_preset(&model, id, &negative) {
+            Some(rest) => (Some(id), rest),
+            None => (None, negative),
+        },
+        None => (None, negative),
+    };
+
+    let width = r.take_u32("width");
+    let width = r.or_default("width", width, DEFAULT_WIDTH);
+    let height = r.take_u32("height");
+    let height = r.or_default("height", height, DEFAULT_HEIGHT);
+    let steps = r.take_u32("steps");
+    let steps = r.or_default("steps", steps, DEFAULT_STEPS);
+    let cfg_scale = r.take_f64("scale");
+    let cfg_scale = r.or_default("scale", cfg_scale, defaults.default_scale);
+    let sampler = r.take_str("sampler");
+    let sampler = r.or_default("sampler", sampler, defaults.default_sampler.to_string());
+    let scheduler = r.take_str("noise_schedule");
+    let scheduler = r.or_default("noise_schedule", scheduler, DEFAULT_SCHEDULER.to_string());
+    // Null means variety is off
+    let variety = r
+        .map
+        .remove("skip_cfg_above_sigma")
+        .is_some_and(|v| !v.is_null());
+
+    let negatives = char_captions(v4_negative.as_ref());
+    let character_prompts = char_captions(v4_prompt.as_ref())
+        .into_iter()
+        .enumerate()
+        .map(|(i, (prompt, position))| CharacterPrompt {
+            prompt,
+            negative: negatives
+                .get(i)
+                .map(|(text, _)| text.clone())
+                .unwrap_or_default(),
+            enabled: true,
+            position,
+        })
+        .collect();
+    let use_coords = r
+        .take_bool("use_coords")
+        .or_else(|| v4_prompt.as_ref().and_then(|p| p["use_coords"].as_bool()));
+
+    let params = GenerationParams {
+        prompt,
+        negative_prompt,
+        model,
+        width,
+        height,
+        steps,
+        cfg_scale,
+        cfg_rescale: r.take_f64("cfg_rescale").unwrap_or(0.0),
+        sampler,
+        scheduler,
+        smea: r.take_bool("sm").unwrap_or(false),
+        smea_dyn: r.take_bool("sm_dyn").unwrap_or(false),
+        variety,
+        seed: r.take("seed").and_then(|v| v.as_i64()),
+        uc_preset,
+        use_coords,
+        n_samples: r.take_u32("n_samples"),
+        character_prompts,
+        strength: r.take_f64("strength"),
+        noise: r.take_f64("noise"),
+        ..Default::default()
+    };
+
+    for key in DERIVED_KEYS.iter().chain(HISTORY_KEYS) {
+        r.map.remove(*key);
+    }
+    Ok(NaiSettingsImport {
+        params: GenerationParams {
+            extra: r.map,
+            ..params
+        },
+        warnings: r.warnings,
+    })
+}
+
+/// Writes `params` in NAI's flat settings format, with `params.extra` put
+/// back for every key NAIS doesn't set itself. Reference and source images
+/// are left out.
+pub fn export(params: &GenerationParams) -> Result<String, String> {
+    let payload = generation::build_payload(params, params.seed.unwrap_or(0));
+    let Some(Value::Object(mut settings)) = payload.get("parameters").cloned() else {
+        return Err("설정 변환 오류".to_string());
+    };
+    for key in IMAGE_KEYS.iter().chain(DERIVED_KEYS) {
+        settings.remove(*key);
+    }
+    if params.seed.is_none() {
+        settings.remove("seed");
+    }
+    settings.insert("prompt".into(), json!(params.prompt));
+    settings.insert("uc".into(), json!(params.final_negative_prompt()));
+    settings.insert("model".into(), json!(params.model));
+
+    for (key, value) in &params.extra {
+        settings.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+    serde_json::to_string_pretty(&Value::Object(settings)).map_err(|e| e.to_string())
+}
+
+// Fields NAI writes besides the settings, which `metadata_fields` sets
+const IMAGE_FIELDS: &[&str] = &["Title", "Description", "Software", "Source", "Comment"];
+
+/// The text fields NAI would have written into an image made with
+/// `settings` (NAI's format; missing settings get defaults). With `merge`,
+/// `settings` is laid over the settings and fields `existing` already has
+/// instead of replacing them.
+pub fn metadata_fields(
+    settings: Value,
+    existing: Option<&EmbeddedMetadata>,
+    merge: bool,
+) -> Result<Vec<(String, String)>, String> {
+    let Value::Object(settings) = settings else {
+        return Err("설정 JSON은 객체여야 합니다".to_string());
+    };
+    let existing = existing.filter(|_| merge);
+    let mut combined = match existing.and_then(EmbeddedMetadata::comment) {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    combined.extend(settings);
+    let params = import(Value::Object(combined))?.params;
+    // NAI writes the settings on one line
+    let comment: Value = serde_json::from_str(&export(&params)?).map_err(|e| e.to_string())?;
+    let source = models::get(&params.model)?.source;
+
+    let mut fields: Vec<(String, String)> = existing
+        .map(|meta| {
+            meta.fields
+                .iter()
+                .filter(|(key, _)| !IMAGE_FIELDS.contains(&key.as_str()))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    fields.extend(
+        [
+            ("Title", "NovelAI generated image".to_string()),
+            ("Description", params.prompt.clone()),
+            ("Software", "NovelAI".to_string()),
+            ("Source", source.to_string()),
+            ("Comment", comment.to_string()),
+        ]
+        .map(|(key, value)| (key.to_string(), value)),
+    );
+    Ok(fields)
+}
+
+/// Model id for the `Source` field NAI writes into images, e.g.
+/// `NovelAI Diffusion V4.5 4BDE2A90`. The field only names the version, so
+/// curated and full can't be told apart; full is assumed.
+fn model_from_source(source: &str) -> Option<&'static str> {
+    let curated = source.to_lowercase().contains("curated");
+    if source.contains("V4.5") {
+        Some(if curated {
+            "nai-diffusion-4-5-curated"
+        } else {
+            "nai-diffusion-4-5-full"
+        })
+    } else if source.contains("V4") {
+        Some(if curated {
+            "nai-diffusion-4-curated-preview"
+        } else {
+            "nai-diffusion-4-full"
+        })
+    } else if source.contains("Stable Diffusion XL") {
+        Some("nai-diffusion-3")
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageParams {
+    pub params: GenerationParams,
+    pub source: MetadataSource,
+    /// Settings the image records but that can't be restored from it
+    pub lost: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Array length of `key` in `extra`, or 0.
+fn extra_len(extra: &Map<String, Value>, key: &str) -> usize {
+    extra.get(key).and_then(Value::as_array).map_or(0, Vec::len)
+}
+
+/// Generation settings recorded in an image, ready for `generate_image`.
+/// Images only keep the strengths of vibe and character references, not
+/// the references themselves, and not the img2img source; those are listed
+/// in `lost` and the generation runs without them.
+pub fn from_metadata(meta: &EmbeddedMetadata) -> Result<ImageParams, String> {
+    let Some(Value::Object(mut settings)) = meta.comment() else {
+        return Err("이미지에 생성 설정이 없습니다".to_string());
+    };
+    // Text-to-image results record a default strength and noise too, so
+    // only the request type or the image itself tell img2img apart
+    let img2img = settings.get("request_type").and_then(Value::as_str) == Some("Img2ImgRequest")
+        || settings.get("image").is_some_and(|image| !image.is_null());
+    let mut warnings = Vec::new();
+    if !settings.contains_key("model") {
+        if let Some((source, model)) = meta
+            .model()
+            .and_then(|source| Some((source, model_from_source(source)?)))
+        {
+            settings.insert("model".into(), json!(model));
+            warnings.push(format!("모델을 '{}'에서 추정했습니다: {}", source, model));
+        }
+    }
+
+    let imported = import(Value::Object(settings))?;
+    let params = imported.params;
+    warnings.extend(imported.warnings);
+
+    let mut lost = Vec::new();
+    let vibes = extra_len(&params.extra, "reference_information_extracted_multiple")
+        .max(extra_len(&params.extra, "reference_strength_multiple"));
+    if vibes > 0 {
+        lost.push(format!("vibe 참조 이미지 {}개", vibes));
+    }
+    let char_refs = extra_len(&params.extra, "director_reference_strength_values").max(extra_len(
+        &params.extra,
+        "director_reference_information_extracted",
+    ));
+    if char_refs > 0 {
+        lost.push(format!("캐릭터 참조 이미지 {}개", char_refs));
+    }
+    if img2img {
+        lost.push("img2img 원본 이미지".to_string());
+    }
+    if params.extra.contains_key("mask") {
+        lost.push("인페인트 마스크".to_string());
+    }
+
+    Ok(ImageParams {
+        params,
+        source: meta.source,
+        lost,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image_with(comment: Value) -> EmbeddedMetadata {
+        EmbeddedMetadata {
+            fields: vec![
+                (
+                    "Source".to_string(),
+                    "NovelAI Diffusion V4.5 4BDE2A90".to_string(),
+                ),
+                ("Comment".to_string(), comment.to_string()),
+            ],
+            source: MetadataSource::TextChunk,
+        }
+    }
+
+    fn lost(comment: Value) -> Vec<String> {
+        from_metadata(&image_with(comment)).unwrap().lost
+    }
+
+    #[test]
+    fn text_to_image_defaults_are_not_img2img() {
+        // What NAI records for a plain text-to-image generation
+        let comment = json!({
+            "prompt": "1girl", "steps": 28, "scale": 5.0, "seed": 1,
+            "strength": 0.7, "noise": 0.0, "request_type": "PromptGenerateRequest",
+        });
+        assert!(lost(comment).is_empty());
+    }
+
+    #[test]
+    fn img2img_is_recognized_by_request_type_or_image() {
+        let by_type = json!({
+            "prompt": "1girl", "strength": 0.5, "noise": 0.1,
+            "request_type": "Img2ImgRequest",
+        });
+        assert_eq!(lost(by_type), ["img2img 원본 이미지"]);
+
+        let by_image = json!({"prompt": "1girl", "image": "iVBORw0KGgo="});
+        assert_eq!(lost(by_image), ["img2img 원본 이미지"]);
+    }
+}
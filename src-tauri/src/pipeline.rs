@@ -0,0 +1,175 @@
+//! Multi-step workflows (generate → upscale → background removal → ...),
+//! where every step works on the image the previous one produced.
+//!
+//! Steps only describe what to do; `run_pipeline` in `lib.rs` runs each one
+//! through the same code as the matching command. Definitions can be saved
+//! by name in the Tauri store and run again later.
+
+use crate::augment::AugmentStep;
+use crate::generation::GenerationParams;
+use crate::metadata::MetadataEmbed;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PIPELINE_STORE_FILE: &str = "pipelines.json";
+pub const PIPELINE_STORE_KEY: &str = "pipelines";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PipelineStep {
+    /// Text to image. With `use_input`, the previous step's image becomes
+    /// the img2img source.
+    Generate {
+        params: Box<GenerationParams>,
+        #[serde(default)]
+        use_input: bool,
+    },
+    Upscale {
+        #[serde(default = "default_scale")]
+        scale: i32,
+    },
+    /// One Director tool (line art, colorize, bg-removal, ...)
+    Augment(AugmentStep),
+    /// Local background removal (RMBG)
+    RemoveBackground {
+        #[serde(default)]
+        feather: Option<f64>,
+    },
+    Convert {
+        target: String,
+        #[serde(default)]
+        metadata_embed: Option<MetadataEmbed>,
+    },
+}
+
+fn default_scale() -> i32 {
+    4
+}
+
+impl PipelineStep {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PipelineStep::Generate { .. } => "generate",
+            PipelineStep::Upscale { .. } => "upscale",
+            PipelineStep::Augment(_) => "augment",
+            PipelineStep::RemoveBackground { .. } => "remove_background",
+            PipelineStep::Convert { .. } => "convert",
+        }
+    }
+
+    /// Whether the step can run without an input image.
+    pub fn starts_from_nothing(&self) -> bool {
+        matches!(
+            self,
+            PipelineStep::Generate {
+                use_input: false,
+                ..
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineStepResult {
+    pub index: usize,
+    pub kind: &'static str,
+    pub success: bool,
+    /// Not run because an earlier step failed
+    pub skipped: bool,
+    /// The step's output image (base64)
+    pub image_data: Option<String>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PipelineResult {
+    pub success: bool,
+    /// Output of the last step that succeeded
+    pub image_data: Option<String>,
+    /// One entry per step, in order; failed and skipped steps included
+    pub steps: Vec<PipelineStepResult>,
+    pub failed_step: Option<usize>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Payload of `pipeline_progress`, sent when a step starts and when it ends.
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineProgress {
+    pub index: usize,
+    pub total: usize,
+    pub kind: &'static str,
+    /// `started`, `done` or `failed`
+    pub status: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPipeline {
+    pub name: String,
+    pub steps: Vec<PipelineStep>,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+/// Checks that the steps can run in this order at all.
+pub fn validate(steps: &[PipelineStep], has_input: bool) -> Result<(), String> {
+    let first = steps
+        .first()
+        .ok_or_else(|| "파이프라인에 단계가 없습니다".to_string())?;
+    if !has_input && !first.starts_from_nothing() {
+        return Err(format!(
+            "입력 이미지가 없으면 첫 단계는 생성이어야 합니다 ({})",
+            first.kind()
+        ));
+    }
+    Ok(())
+}
+
+pub fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("파이프라인 이름이 비어있습니다".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Inserts `pipeline`, replacing a same-named one only when `overwrite` is
+/// set. A replaced pipeline keeps its original `created_at`.
+pub fn upsert(
+    pipelines: &mut Vec<SavedPipeline>,
+    mut pipeline: SavedPipeline,
+    overwrite: bool,
+) -> Result<(), String> {
+    match pipelines.iter_mut().find(|p| p.name == pipeline.name) {
+        Some(_) if !overwrite => Err(format!("이미 존재하는 파이프라인: {}", pipeline.name)),
+        Some(existing) => {
+            pipeline.created_at = existing.created_at;
+            *existing = pipeline;
+            Ok(())
+        }
+        None => {
+            pipelines.push(pipeline);
+            Ok(())
+        }
+    }
+}
+
+/// Reads the stored pipelines, dropping entries that no longer deserialize.
+pub fn from_store_value(value: Option<Value>) -> Vec<SavedPipeline> {
+    let Some(Value::Array(items)) = value else {
+        return Vec::new();
+    };
+    items
+        .into_iter()
+        .filter_map(|item| match serde_json::from_value(item) {
+            Ok(pipeline) => Some(pipeline),
+            Err(e) => {
+                log::warn!("Skipping unreadable pipeline: {}", e);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,934 @@
+//! Local image post-processing (watermarks, blur, ...). Everything here runs
+//! on decoded pixels and needs no network, so it also works offline.
+
+use crate::metadata::{self, ConvertTarget, MetadataEmbed, MetadataSource};
+use ab_glyph::{Font, FontRef, FontVec, PxScale};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, GrayImage, ImageDecoder, ImageEncoder, ImageFormat, Luma, RgbaImage};
+use serde::Serialize;
+use std::io::{Cursor, Write};
+use std::sync::OnceLock;
+
+/// Bundled so watermarks render the same on every OS, even offline.
+static WATERMARK_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+pub fn watermark_font() -> Result<FontRef<'static>, String> {
+    FontRef::try_from_slice(WATERMARK_FONT).map_err(|e| format!("폰트 로드 오류: {}", e))
+}
+
+/// Decodes image bytes, remembering the source format so the result can be
+/// written back in the same format.
+pub fn decode(bytes: &[u8]) -> Result<(DynamicImage, ImageFormat), String> {
+    let format = image::guess_format(bytes).map_err(|e| format!("이미지 형식 오류: {}", e))?;
+    let image = image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| format!("이미지 디코딩 오류: {}", e))?;
+    Ok((image, format))
+}
+
+/// Width and height from the image header alone, without decoding pixels.
+pub fn header_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("이미지 디코딩 오류: {}", e))
+}
+
+/// Fully decodes the image to make sure it isn't truncated or corrupt, and
+/// returns its size.
+pub fn verified_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    let (image, _) = decode(bytes).map_err(|e| format!("손상된 이미지: {}", e))?;
+    Ok((image.width(), image.height()))
+}
+
+/// Encodes `image` as `format`. Unknown formats fall back to PNG; WebP is
+/// written lossless since that's the only WebP encoder available.
+pub fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 95)
+                .encode_image(&rgb)
+                .map_err(|e| e.to_string())?;
+        }
+        ImageFormat::WebP => {
+            let rgba = image.to_rgba8();
+            image::codecs::webp::WebPEncoder::new_lossless(&mut out)
+                .write_image(
+                    rgba.as_raw(),
+                    rgba.width(),
+                    rgba.height(),
+                    image::ExtendedColorType::Rgba8,
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        _ => {
+            image
+                .write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(out)
+}
+
+/// SHA-256 (hex) of the decoded RGBA pixels and size, so two images with the
+/// same pixels match however they were encoded or whatever metadata they
+/// carry.
+pub fn pixel_hash(bytes: &[u8]) -> Result<String, String> {
+    use sha2::{Digest, Sha256};
+
+    let (image, _) = decode(bytes)?;
+    let rgba = image.to_rgba8();
+    let mut hasher = Sha256::new();
+    hasher.update(rgba.width().to_le_bytes());
+    hasher.update(rgba.height().to_le_bytes());
+    hasher.update(rgba.as_raw());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Bakes the EXIF Orientation (all of 1-8, rotations and mirrors) into the
+/// pixels so phone photos aren't fed to img2img sideways. Returns `None`
+/// when there's nothing to do (no EXIF, or already orientation 1). The
+/// re-encoded image carries no EXIF, so it reads as orientation 1 everywhere.
+pub fn normalize_orientation(bytes: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let reader = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let format = reader
+        .format()
+        .ok_or_else(|| "알 수 없는 이미지 형식".to_string())?;
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|e| format!("이미지 디코딩 오류: {}", e))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    if orientation == Orientation::NoTransforms {
+        return Ok(None);
+    }
+
+    let mut image =
+        DynamicImage::from_decoder(decoder).map_err(|e| format!("이미지 디코딩 오류: {}", e))?;
+    image.apply_orientation(orientation);
+    encode(&image, format).map(Some)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    pub fn parse(position: &str) -> Result<Self, String> {
+        match position.to_lowercase().replace('_', "-").as_str() {
+            "top-left" => Ok(WatermarkPosition::TopLeft),
+            "top-right" => Ok(WatermarkPosition::TopRight),
+            "bottom-left" => Ok(WatermarkPosition::BottomLeft),
+            "bottom-right" => Ok(WatermarkPosition::BottomRight),
+            "center" => Ok(WatermarkPosition::Center),
+            other => Err(format!("지원하지 않는 위치: {}", other)),
+        }
+    }
+
+    /// Top-left corner for a `w`x`h` box inside a `canvas_w`x`canvas_h` canvas.
+    pub fn place(self, canvas_w: u32, canvas_h: u32, w: u32, h: u32, margin: u32) -> (i32, i32) {
+        let right = canvas_w.saturating_sub(w + margin) as i32;
+        let bottom = canvas_h.saturating_sub(h + margin) as i32;
+        let margin = margin as i32;
+        match self {
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (right, margin),
+            WatermarkPosition::BottomLeft => (margin, bottom),
+            WatermarkPosition::BottomRight => (right, bottom),
+            WatermarkPosition::Center => (
+                (canvas_w.saturating_sub(w) / 2) as i32,
+                (canvas_h.saturating_sub(h) / 2) as i32,
+            ),
+        }
+    }
+}
+
+/// Blends `color` into `image` wherever `mask` has coverage, scaled by `opacity`.
+fn blend_mask(image: &mut RgbaImage, mask: &GrayImage, color: [u8; 3], opacity: f32) {
+    for (x, y, coverage) in mask.enumerate_pixels() {
+        if coverage[0] == 0 {
+            continue;
+        }
+        let a = coverage[0] as f32 / 255.0 * opacity;
+        let pixel = image.get_pixel_mut(x, y);
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as f32 * (1.0 - a) + color[c] as f32 * a).round() as u8;
+        }
+        // Keep transparent areas from swallowing the mark
+        pixel[3] = pixel[3].max((a * 255.0).round() as u8);
+    }
+}
+
+/// Fonts tried for characters the bundled font lacks (Hangul, kana, CJK).
+/// DejaVu Sans has no CJK glyphs, so these come from the OS; text that no
+/// font here covers is refused rather than drawn as blanks.
+const FALLBACK_FONT_PATHS: &[&str] = &[
+    "C:\\Windows\\Fonts\\malgun.ttf",
+    "C:\\Windows\\Fonts\\gulim.ttc",
+    "/System/Library/Fonts/AppleSDGothicNeo.ttc",
+    "/System/Library/Fonts/Supplemental/AppleGothic.ttf",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/nanum/NanumGothic.ttf",
+];
+
+fn fallback_font() -> Option<&'static FontVec> {
+    static FONT: OnceLock<Option<FontVec>> = OnceLock::new();
+    FONT.get_or_init(|| {
+        FALLBACK_FONT_PATHS.iter().find_map(|path| {
+            let data = std::fs::read(path).ok()?;
+            // Index 0 of a .ttc collection is the Korean/Japanese regular face
+            FontVec::try_from_vec_and_index(data, 0).ok()
+        })
+    })
+    .as_ref()
+}
+
+/// Splits `text` into runs drawable with a single font: the bundled font
+/// where it has the glyph, the system fallback otherwise. Fails when a
+/// character is in neither, since it would come out blank.
+fn font_runs<'a>(
+    primary: &'a FontRef<'static>,
+    text: &str,
+) -> Result<Vec<(&'a dyn FontDyn, String)>, String> {
+    let fallback = fallback_font();
+    let mut runs: Vec<(bool, String)> = Vec::new();
+    let mut missing = String::new();
+    for c in text.chars() {
+        let in_primary = primary.glyph_id(c).0 != 0;
+        let use_fallback = !in_primary && fallback.is_some_and(|f| f.glyph_id(c).0 != 0);
+        if !in_primary && !use_fallback && !c.is_whitespace() && !missing.contains(c) {
+            missing.push(c);
+        }
+        match runs.last_mut() {
+            Some((fb, run)) if *fb == use_fallback => run.push(c),
+            _ => runs.push((use_fallback, c.to_string())),
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "워터마크 글꼴에 없는 문자입니다: {} (한글/일본어 글꼴을 설치하세요)",
+            missing
+        ));
+    }
+    Ok(runs
+        .into_iter()
+        .map(|(use_fallback, run)| {
+            let font: &dyn FontDyn = match (use_fallback, fallback) {
+                (true, Some(f)) => f,
+                _ => primary,
+            };
+            (font, run)
+        })
+        .collect())
+}
+
+/// Object-safe view of the two font types so runs can mix them.
+trait FontDyn {
+    fn text_size(&self, scale: PxScale, text: &str) -> (u32, u32);
+    fn draw(&self, mask: &mut GrayImage, x: i32, y: i32, scale: PxScale, text: &str);
+}
+
+impl<F: Font> FontDyn for F {
+    fn text_size(&self, scale: PxScale, text: &str) -> (u32, u32) {
+        imageproc::drawing::text_size(scale, self, text)
+    }
+
+    fn draw(&self, mask: &mut GrayImage, x: i32, y: i32, scale: PxScale, text: &str) {
+        imageproc::drawing::draw_text_mut(mask, Luma([255]), x, y, scale, self, text);
+    }
+}
+
+/// Renders `text` into a coverage mask of the canvas size at (`x`, `y`).
+fn draw_runs(
+    runs: &[(&dyn FontDyn, String)],
+    width: u32,
+    height: u32,
+    x: i32,
+    y: i32,
+    scale: PxScale,
+) -> GrayImage {
+    let mut mask = GrayImage::new(width, height);
+    let mut cursor = x;
+    for (font, run) in runs {
+        font.draw(&mut mask, cursor, y, scale, run);
+        cursor += font.text_size(scale, run).0 as i32;
+    }
+    mask
+}
+
+fn runs_size(runs: &[(&dyn FontDyn, String)], scale: PxScale) -> (u32, u32) {
+    runs.iter().fold((0, 0), |(w, h), (font, run)| {
+        let (rw, rh) = font.text_size(scale, run);
+        (w + rw, h.max(rh))
+    })
+}
+
+/// Alpha-composites `logo` onto `image` at (`x`, `y`) with extra `opacity`.
+fn overlay_logo(image: &mut RgbaImage, logo: &RgbaImage, x: i32, y: i32, opacity: f32) {
+    for (lx, ly, pixel) in logo.enumerate_pixels() {
+        let (px, py) = (x + lx as i32, y + ly as i32);
+        if px < 0 || py < 0 || px >= image.width() as i32 || py >= image.height() as i32 {
+            continue;
+        }
+        let a = pixel[3] as f32 / 255.0 * opacity;
+        if a <= 0.0 {
+            continue;
+        }
+        let target = image.get_pixel_mut(px as u32, py as u32);
+        for c in 0..3 {
+            target[c] = (target[c] as f32 * (1.0 - a) + pixel[c] as f32 * a).round() as u8;
+        }
+        target[3] = target[3].max((a * 255.0).round() as u8);
+    }
+}
+
+pub struct Watermark {
+    pub text: Option<String>,
+    /// Encoded logo image (PNG with transparency works best)
+    pub logo: Option<Vec<u8>>,
+    /// 0.0-1.0
+    pub opacity: f32,
+    pub position: WatermarkPosition,
+    /// Distance from the edges in pixels; defaults to half the font size
+    pub margin: Option<u32>,
+}
+
+/// Draws the logo and/or semi-transparent white text (with a soft shadow)
+/// side by side. Sizes scale with the image so the mark looks the same at
+/// any resolution.
+pub fn draw_watermark(image: &mut RgbaImage, mark: &Watermark) -> Result<(), String> {
+    let font = watermark_font()?;
+    let (width, height) = image.dimensions();
+    let size = (width.min(height) as f32 / 24.0).max(14.0);
+    let scale = PxScale::from(size);
+    let margin = mark.margin.unwrap_or((size / 2.0) as u32);
+    let shadow = (size / 16.0).ceil().max(1.0) as i32;
+    let opacity = mark.opacity.clamp(0.0, 1.0);
+
+    let runs = match mark.text.as_deref().filter(|t| !t.trim().is_empty()) {
+        Some(text) => font_runs(&font, text)?,
+        None => Vec::new(),
+    };
+    let (text_w, text_h) = runs_size(&runs, scale);
+
+    let logo = match &mark.logo {
+        Some(bytes) => {
+            let logo =
+                image::load_from_memory(bytes).map_err(|e| format!("로고 디코딩 오류: {}", e))?;
+            // At most a sixth of the shorter side, never upscaled
+            let max = (width.min(height) / 6).max(1);
+            let logo = if logo.width() > max || logo.height() > max {
+                logo.resize(max, max, image::imageops::FilterType::Lanczos3)
+            } else {
+                logo
+            };
+            Some(logo.to_rgba8())
+        }
+        None => None,
+    };
+    if logo.is_none() && runs.is_empty() {
+        return Ok(());
+    }
+
+    let gap = if logo.is_some() && !runs.is_empty() {
+        (size / 3.0) as u32
+    } else {
+        0
+    };
+    let (logo_w, logo_h) = logo.as_ref().map_or((0, 0), |l| l.dimensions());
+    let box_w = logo_w + gap + text_w;
+    let box_h = logo_h.max(text_h);
+    let (x, y) = mark.position.place(width, height, box_w, box_h, margin);
+
+    if let Some(logo) = &logo {
+        let ly = y + (box_h - logo_h) as i32 / 2;
+        overlay_logo(image, logo, x, ly, opacity);
+    }
+    if !runs.is_empty() {
+        let tx = x + (logo_w + gap) as i32;
+        let ty = y + (box_h - text_h) as i32 / 2;
+        let shadow_mask = draw_runs(&runs, width, height, tx + shadow, ty + shadow, scale);
+        let text_mask = draw_runs(&runs, width, height, tx, ty, scale);
+        blend_mask(image, &shadow_mask, [0, 0, 0], opacity * 0.5);
+        blend_mask(image, &text_mask, [255, 255, 255], opacity);
+    }
+    Ok(())
+}
+
+/// Applies `mark` and re-encodes in the source format. With
+/// `preserve_metadata`, NAI metadata is carried over: text chunks for PNG,
+/// EXIF for WebP, and the stealth alpha payload is rewritten since drawing
+/// over the image corrupts its LSBs. JPEG output never carries metadata.
+pub fn add_watermark(
+    bytes: &[u8],
+    mark: &Watermark,
+    preserve_metadata: bool,
+) -> Result<Vec<u8>, String> {
+    let (image, format) = decode(bytes)?;
+    let embedded = if preserve_metadata {
+        metadata::read_embedded_metadata(bytes)
+    } else {
+        None
+    };
+    let had_alpha = image.color().has_alpha();
+    let mut rgba = image.to_rgba8();
+    draw_watermark(&mut rgba, mark)?;
+
+    if let Some(meta) = embedded
+        .as_ref()
+        .filter(|m| had_alpha && m.source == MetadataSource::StealthAlpha)
+    {
+        metadata::write_stealth_alpha(&mut rgba, &meta.fields)?;
+    }
+
+    let result = if had_alpha {
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+    };
+
+    let Some(meta) = embedded else {
+        return encode(&result, format);
+    };
+    match format {
+        ImageFormat::Png => {
+            let png = encode(&result, ImageFormat::Png)?;
+            metadata::insert_png_text_chunks(&png, &meta.fields)
+        }
+        ImageFormat::WebP => {
+            // Route through PNG text chunks so the WebP converter writes them as EXIF
+            let png = encode(&result, ImageFormat::Png)?;
+            let png = metadata::insert_png_text_chunks(&png, &meta.fields)?;
+            metadata::convert_image_bytes(&png, ConvertTarget::WebpLossless, MetadataEmbed::Exif)
+        }
+        _ => encode(&result, format),
+    }
+}
+
+pub fn apply_watermark(
+    bytes: &[u8],
+    text: &str,
+    opacity: f64,
+    position: WatermarkPosition,
+) -> Result<Vec<u8>, String> {
+    let mark = Watermark {
+        text: Some(text.to_string()),
+        logo: None,
+        opacity: opacity as f32,
+        position,
+        margin: None,
+    };
+    add_watermark(bytes, &mark, false)
+}
+
+pub fn blur(bytes: &[u8], sigma: f64) -> Result<Vec<u8>, String> {
+    let (image, format) = decode(bytes)?;
+    if !sigma.is_finite() || sigma <= 0.0 {
+        return encode(&image, format);
+    }
+    let sigma = sigma.min(100.0) as f32;
+
+    let result = if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(imageproc::filter::gaussian_blur_f32(
+            &image.to_rgba8(),
+            sigma,
+        ))
+    } else {
+        DynamicImage::ImageRgb8(imageproc::filter::gaussian_blur_f32(
+            &image.to_rgb8(),
+            sigma,
+        ))
+    };
+    encode(&result, format)
+}
+
+// Wider than this just fades the whole cut-out
+const MAX_FEATHER: f64 = 50.0;
+
+/// Cuts the subject out of `original` using the matte from a background
+/// removal model. The model works at its own resolution, so only its alpha
+/// (or, for a plain mask, its gray level) is used: it is scaled back to the
+/// original size, softened by `feather` (Gaussian sigma in pixels, 0 for
+/// none) and multiplied into the original's alpha. Always returns PNG.
+pub fn apply_cutout(original: &[u8], matte: &[u8], feather: f64) -> Result<Vec<u8>, String> {
+    let (image, _) = decode(original)?;
+    let (matte, _) = decode(matte)?;
+    let mut image = image.to_rgba8();
+    let (width, height) = image.dimensions();
+
+    let mask: GrayImage = if matte.color().has_alpha() {
+        let rgba = matte.to_rgba8();
+        GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            Luma([rgba.get_pixel(x, y)[3]])
+        })
+    } else {
+        matte.to_luma8()
+    };
+    let mut mask = if mask.dimensions() == (width, height) {
+        mask
+    } else {
+        image::imageops::resize(&mask, width, height, FilterType::CatmullRom)
+    };
+    if feather.is_finite() && feather > 0.0 {
+        mask = imageproc::filter::gaussian_blur_f32(&mask, feather.min(MAX_FEATHER) as f32);
+    }
+
+    for (pixel, alpha) in image.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = ((pixel[3] as u16 * alpha[0] as u16 + 127) / 255) as u8;
+    }
+    encode(&DynamicImage::ImageRgba8(image), ImageFormat::Png)
+}
+
+// Beyond this every factor just clips the whole image to black or white
+const MAX_ADJUST_FACTOR: f64 = 4.0;
+
+/// Rec. 601 luma, the gray a pixel keeps at saturation 0.
+fn luma(r: f32, g: f32, b: f32) -> f32 {
+    0.299 * r + 0.587 * g + 0.114 * b
+}
+
+/// Brightness, contrast and saturation, each a factor where 1.0 leaves the
+/// image unchanged: brightness scales every channel, contrast stretches
+/// around mid-gray, and saturation moves each pixel away from (or, below
+/// 1.0, toward) its gray. Results are clipped to 0-255 and alpha is kept.
+/// With all three at 1.0 the input comes back byte for byte, metadata
+/// included.
+pub fn adjust(
+    bytes: &[u8],
+    brightness: f64,
+    contrast: f64,
+    saturation: f64,
+) -> Result<Vec<u8>, String> {
+    for (name, value) in [
+        ("brightness", brightness),
+        ("contrast", contrast),
+        ("saturation", saturation),
+    ] {
+        if !value.is_finite() || !(0.0..=MAX_ADJUST_FACTOR).contains(&value) {
+            return Err(format!(
+                "{} 값은 0~{} 사이여야 합니다: {}",
+                name, MAX_ADJUST_FACTOR, value
+            ));
+        }
+    }
+
+    if brightness == 1.0 && contrast == 1.0 && saturation == 1.0 {
+        image::guess_format(bytes).map_err(|e| format!("이미지 형식 오류: {}", e))?;
+        return Ok(bytes.to_vec());
+    }
+
+    let (image, format) = decode(bytes)?;
+
+    // Brightness and contrast are per channel, so they fit in one table
+    let mut table = [0f32; 256];
+    for (v, entry) in table.iter_mut().enumerate() {
+        let bright = v as f64 * brightness;
+        *entry = ((bright - 128.0) * contrast + 128.0) as f32;
+    }
+    let saturation = saturation as f32;
+
+    let has_alpha = image.color().has_alpha();
+    let mut rgba = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b] = [0, 1, 2].map(|i| table[pixel[i] as usize]);
+        let gray = luma(r, g, b);
+        for (i, c) in [r, g, b].into_iter().enumerate() {
+            pixel[i] = (gray + (c - gray) * saturation).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    let result = if has_alpha {
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+    };
+    encode(&result, format)
+}
+
+pub const DEFAULT_PALETTE_SIZE: usize = 5;
+pub const MAX_PALETTE_SIZE: usize = 16;
+// Plenty for a palette and keeps median cut cheap on 4K images
+const PALETTE_SAMPLE_SIZE: u32 = 96;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PaletteColor {
+    pub rgb: [u8; 3],
+    /// Share of the (opaque) pixels in this color's bucket, 0-100
+    pub percent: f64,
+}
+
+/// Index of the channel with the widest range, and that range.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(lo, hi), p| {
+                (lo.min(p[c]), hi.max(p[c]))
+            });
+            (c, max.saturating_sub(min))
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+/// Opaque pixels of a downscaled copy of `bytes`. Transparent pixels (e.g.
+/// after background removal) aren't part of the image.
+fn sample_pixels(bytes: &[u8]) -> Result<Vec<[u8; 3]>, String> {
+    let (image, _) = decode(bytes)?;
+    let small = image
+        .thumbnail(PALETTE_SAMPLE_SIZE, PALETTE_SAMPLE_SIZE)
+        .to_rgba8();
+    Ok(small
+        .pixels()
+        .filter(|p| p[3] >= 128)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect())
+}
+
+/// Dominant colors of an image, most common first.
+pub fn extract_palette(bytes: &[u8], count: usize) -> Result<Vec<PaletteColor>, String> {
+    Ok(median_cut(sample_pixels(bytes)?, count))
+}
+
+/// Median cut: repeatedly splits the bucket with the widest color range at
+/// its median until there are `count` buckets, then averages each bucket.
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<PaletteColor> {
+    let count = count.clamp(1, MAX_PALETTE_SIZE);
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+    let total = pixels.len() as f64;
+
+    let mut buckets = vec![pixels];
+    while buckets.len() < count {
+        let Some((index, channel)) = buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest_channel(b)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(_, (_, range))| *range)
+            .map(|(i, (c, _))| (i, c))
+        else {
+            // Every bucket is a single color already
+            break;
+        };
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_unstable_by_key(|p| p[channel]);
+        let upper = bucket.split_off(bucket.len() / 2);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+
+    let mut palette: Vec<PaletteColor> = buckets
+        .iter()
+        .map(|bucket| {
+            let mut sum = [0u64; 3];
+            for p in bucket {
+                for c in 0..3 {
+                    sum[c] += p[c] as u64;
+                }
+            }
+            let n = bucket.len() as u64;
+            PaletteColor {
+                rgb: [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8],
+                percent: bucket.len() as f64 / total * 100.0,
+            }
+        })
+        .collect();
+    palette.sort_by(|a, b| b.percent.total_cmp(&a.percent));
+    palette
+}
+
+// A pixel counts as colored above this HSV saturation...
+const GRAY_SATURATION: f32 = 0.12;
+// ...unless it's too dark for its hue to mean anything
+const GRAY_MIN_VALUE: u8 = 24;
+// Share of colored pixels an image may have and still count as grayscale,
+// so a stray colored speck or JPEG noise doesn't flip it
+const GRAY_COLORED_SHARE: f64 = 0.02;
+// Luma standard deviation below which an image is basically one flat color
+const FLAT_LUMA_STD_DEV: f64 = 6.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageAnalysis {
+    pub dominant_colors: Vec<PaletteColor>,
+    /// Mean luma, 0 (black) to 255 (white)
+    pub avg_brightness: f64,
+    pub is_grayscale: bool,
+    /// Nearly a single flat color, as failed generations sometimes are
+    pub is_flat: bool,
+}
+
+/// Palette, brightness and grayscale check in one pass over a downscaled
+/// copy, for sorting and filtering the gallery.
+pub fn analyze(bytes: &[u8], palette_size: usize) -> Result<ImageAnalysis, String> {
+    let pixels = sample_pixels(bytes)?;
+    let total = pixels.len().max(1) as f64;
+
+    let lumas: Vec<f64> = pixels
+        .iter()
+        .map(|p| luma(p[0] as f32, p[1] as f32, p[2] as f32) as f64)
+        .collect();
+    let avg_brightness = lumas.iter().sum::<f64>() / total;
+    let variance = lumas
+        .iter()
+        .map(|l| (l - avg_brightness).powi(2))
+        .sum::<f64>()
+        / total;
+
+    let colored = pixels
+        .iter()
+        .filter(|p| {
+            let max = p[0].max(p[1]).max(p[2]);
+            let min = p[0].min(p[1]).min(p[2]);
+            max >= GRAY_MIN_VALUE && (max - min) as f32 / max as f32 > GRAY_SATURATION
+        })
+        .count();
+
+    Ok(ImageAnalysis {
+        dominant_colors: median_cut(pixels, palette_size),
+        avg_brightness,
+        is_grayscale: colored as f64 / total <= GRAY_COLORED_SHARE,
+        is_flat: variance.sqrt() < FLAT_LUMA_STD_DEV,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpscaleAlgorithm {
+    Lanczos3,
+    Nearest,
+    Bilinear,
+}
+
+impl UpscaleAlgorithm {
+    pub fn parse(algorithm: &str) -> Result<Self, String> {
+        match algorithm.to_lowercase().as_str() {
+            "lanczos3" | "lanczos" => Ok(UpscaleAlgorithm::Lanczos3),
+            "nearest" => Ok(UpscaleAlgorithm::Nearest),
+            "bilinear" => Ok(UpscaleAlgorithm::Bilinear),
+            other => Err(format!("지원하지 않는 업스케일 방식: {}", other)),
+        }
+    }
+
+    fn filter(self) -> FilterType {
+        match self {
+            UpscaleAlgorithm::Lanczos3 => FilterType::Lanczos3,
+            UpscaleAlgorithm::Nearest => FilterType::Nearest,
+            UpscaleAlgorithm::Bilinear => FilterType::Triangle,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            UpscaleAlgorithm::Lanczos3 => "Lanczos3",
+            UpscaleAlgorithm::Nearest => "Nearest",
+            UpscaleAlgorithm::Bilinear => "Bilinear",
+        }
+    }
+}
+
+pub const MAX_LOCAL_UPSCALE: u32 = 8;
+// 8K; an RGBA buffer this size is already ~256 MiB
+const MAX_LOCAL_UPSCALE_SIDE: u32 = 8192;
+
+/// Plain resampling, for previewing a larger size without spending Anlas.
+/// Always returns a PNG. Any NAI metadata is carried over, and an `Upscale`
+/// text field records that this is a local resize rather than NAI's AI
+/// upscale, since the two look quite different.
+pub fn local_upscale(
+    bytes: &[u8],
+    scale: u32,
+    algorithm: UpscaleAlgorithm,
+) -> Result<Vec<u8>, String> {
+    if !(1..=MAX_LOCAL_UPSCALE).contains(&scale) {
+        return Err(format!("배율은 1~{} 사이여야 합니다", MAX_LOCAL_UPSCALE));
+    }
+    // Check the size from the header before decoding anything
+    let (w, h) = header_dimensions(bytes)?;
+    let (out_w, out_h) = (w.saturating_mul(scale), h.saturating_mul(scale));
+    if out_w > MAX_LOCAL_UPSCALE_SIDE || out_h > MAX_LOCAL_UPSCALE_SIDE {
+        return Err(format!(
+            "결과 해상도가 너무 큽니다: {}x{} (최대 {}px)",
+            out_w, out_h, MAX_LOCAL_UPSCALE_SIDE
+        ));
+    }
+
+    let (image, _) = decode(bytes)?;
+    let upscaled = image.resize_exact(out_w, out_h, algorithm.filter());
+    let png = encode(&upscaled, ImageFormat::Png)?;
+
+    let mut fields = metadata::read_embedded_metadata(bytes)
+        .map(|m| m.fields)
+        .unwrap_or_default();
+    fields.push((
+        "Upscale".to_string(),
+        format!(
+            "local {} x{} (not NovelAI AI upscale)",
+            algorithm.name(),
+            scale
+        ),
+    ));
+    metadata::insert_png_text_chunks(&png, &fields)
+}
+
+// Keeps a runaway batch from allocating a multi-gigabyte sheet
+const MAX_GRID_SIDE: u64 = 16384;
+
+/// Lays `images` out in a `cols`-wide grid on a `bg` background, `gap`
+/// pixels apart. Every cell is the size of the largest image; smaller ones
+/// are centered (letterboxed) rather than scaled. The PNG is encoded one
+/// grid row at a time, so only that row's images and pixels are in memory.
+pub fn make_grid(
+    images: &[Vec<u8>],
+    cols: usize,
+    gap: u32,
+    bg: [u8; 4],
+) -> Result<Vec<u8>, String> {
+    if images.is_empty() {
+        return Err("합성할 이미지가 없습니다".to_string());
+    }
+    let cols = cols.clamp(1, images.len());
+    let rows = images.len().div_ceil(cols);
+
+    // Header-only pass for the cell size
+    let mut cell_w = 0;
+    let mut cell_h = 0;
+    for bytes in images {
+        let (w, h) = header_dimensions(bytes)?;
+        cell_w = cell_w.max(w);
+        cell_h = cell_h.max(h);
+    }
+
+    let width = cols as u64 * cell_w as u64 + (cols as u64 + 1) * gap as u64;
+    let height = rows as u64 * cell_h as u64 + (rows as u64 + 1) * gap as u64;
+    if width > MAX_GRID_SIDE || height > MAX_GRID_SIDE {
+        return Err(format!(
+            "그리드가 너무 큽니다: {}x{} (최대 {}px)",
+            width, height, MAX_GRID_SIDE
+        ));
+    }
+    let (width, height) = (width as u32, height as u32);
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    let mut stream = writer.stream_writer().map_err(|e| e.to_string())?;
+
+    let background_row: Vec<u8> = bg.repeat(width as usize);
+    let write_gap = |stream: &mut png::StreamWriter<'_, &mut Vec<u8>>| -> Result<(), String> {
+        for _ in 0..gap {
+            stream
+                .write_all(&background_row)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    };
+
+    write_gap(&mut stream)?;
+    for row in images.chunks(cols) {
+        let mut band = RgbaImage::from_pixel(width, cell_h, image::Rgba(bg));
+        for (col, bytes) in row.iter().enumerate() {
+            let (image, _) = decode(bytes)?;
+            let x = gap + col as u32 * (cell_w + gap) + (cell_w - image.width()) / 2;
+            let y = (cell_h - image.height()) / 2;
+            image::imageops::overlay(&mut band, &image.to_rgba8(), x as i64, y as i64);
+        }
+        stream.write_all(band.as_raw()).map_err(|e| e.to_string())?;
+        write_gap(&mut stream)?;
+    }
+
+    stream.finish().map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_with_metadata() -> Vec<u8> {
+        let image = RgbaImage::from_fn(8, 8, |x, y| {
+            image::Rgba([x as u8 * 30, y as u8 * 30, 90, 255])
+        });
+        let encoded = encode(&DynamicImage::ImageRgba8(image), ImageFormat::Png).unwrap();
+        let fields = [("Comment".to_string(), r#"{"seed": 1}"#.to_string())];
+        metadata::insert_png_text_chunks(&encoded, &fields).unwrap()
+    }
+
+    #[test]
+    fn neutral_adjustment_returns_the_input() {
+        let png = png_with_metadata();
+        assert_eq!(adjust(&png, 1.0, 1.0, 1.0).unwrap(), png);
+
+        let jpeg = encode(&decode(&png).unwrap().0, ImageFormat::Jpeg).unwrap();
+        assert_eq!(adjust(&jpeg, 1.0, 1.0, 1.0).unwrap(), jpeg);
+    }
+
+    #[test]
+    fn neutral_adjustment_still_rejects_non_images() {
+        assert!(adjust(b"not an image", 1.0, 1.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn adjustment_changes_pixels() {
+        let png = png_with_metadata();
+        let darker = decode(&adjust(&png, 0.5, 1.0, 1.0).unwrap())
+            .unwrap()
+            .0
+            .to_rgba8();
+        let original = decode(&png).unwrap().0.to_rgba8();
+        assert_eq!(darker.get_pixel(7, 7)[0], original.get_pixel(7, 7)[0] / 2);
+        assert_eq!(darker.get_pixel(7, 7)[3], 255);
+    }
+
+    #[test]
+    fn factors_out_of_range_are_rejected() {
+        let png = png_with_metadata();
+        assert!(adjust(&png, -0.1, 1.0, 1.0).is_err());
+        assert!(adjust(&png, 1.0, f64::NAN, 1.0).is_err());
+        assert!(adjust(&png, 1.0, 1.0, MAX_ADJUST_FACTOR + 1.0).is_err());
+    }
+
+    fn mark(text: &str) -> Watermark {
+        Watermark {
+            text: Some(text.to_string()),
+            logo: None,
+            opacity: 1.0,
+            position: WatermarkPosition::BottomRight,
+            margin: None,
+        }
+    }
+
+    #[test]
+    fn watermark_text_is_drawn() {
+        let mut image = RgbaImage::from_pixel(256, 256, image::Rgba([0, 0, 0, 255]));
+        draw_watermark(&mut image, &mark("NAIS2")).unwrap();
+        assert!(image.pixels().any(|p| p[0] > 0));
+    }
+
+    #[test]
+    fn text_without_glyphs_is_refused() {
+        let mut image = RgbaImage::from_pixel(256, 256, image::Rgba([0, 0, 0, 255]));
+        let err = draw_watermark(&mut image, &mark("NAIS \u{10FFFD}")).unwrap_err();
+        assert!(err.contains('\u{10FFFD}'));
+        assert!(image.pixels().all(|p| p[0] == 0));
+
+        // Hangul only works with a system font that has it
+        let hangul = fallback_font().is_some_and(|f| f.glyph_id('한').0 != 0);
+        assert_eq!(draw_watermark(&mut image, &mark("한글")).is_ok(), hangul);
+    }
+}
@@ -0,0 +1,259 @@
+//! Prompt/UC and generation parameter presets kept in the Tauri store, so
+//! they survive reinstalls that wipe the webview's localStorage. Both kinds
+//! share one list and one export format; parameter-only presets just have
+//! `includes_prompt: false`.
+
+use crate::generation::GenerationParams;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PRESET_STORE_FILE: &str = "prompt-presets.json";
+pub const PRESET_STORE_KEY: &str = "presets";
+const EXPORT_VERSION: u32 = 1;
+
+/// Generation settings saved with a preset. Field names match
+/// `GenerationParams`, so the frontend can spread them straight into a
+/// `generate_image` call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresetParams {
+    pub model: String,
+    pub width: u32,
+    pub height: u32,
+    pub steps: u32,
+    pub cfg_scale: f64,
+    #[serde(default)]
+    pub cfg_rescale: f64,
+    pub sampler: String,
+    pub scheduler: String,
+    #[serde(default)]
+    pub smea: bool,
+    #[serde(default)]
+    pub smea_dyn: bool,
+    #[serde(default)]
+    pub variety: bool,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub uc_preset: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptPreset {
+    pub name: String,
+    pub positive: String,
+    #[serde(default)]
+    pub negative: String,
+    pub params: PresetParams,
+    /// `false` for parameter-only presets, whose prompts are left empty and
+    /// shouldn't replace the user's current prompt when loaded.
+    #[serde(default = "default_true")]
+    pub includes_prompt: bool,
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl From<&GenerationParams> for PresetParams {
+    fn from(params: &GenerationParams) -> Self {
+        PresetParams {
+            model: params.model.clone(),
+            width: params.width,
+            height: params.height,
+            steps: params.steps,
+            cfg_scale: params.cfg_scale,
+            cfg_rescale: params.cfg_rescale,
+            sampler: params.sampler.clone(),
+            scheduler: params.scheduler.clone(),
+            smea: params.smea,
+            smea_dyn: params.smea_dyn,
+            variety: params.variety,
+            seed: params.seed,
+            uc_preset: params.uc_preset,
+        }
+    }
+}
+
+/// List entry for the preset picker, without the full settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresetMeta {
+    pub name: String,
+    pub model: String,
+    pub width: u32,
+    pub height: u32,
+    pub includes_prompt: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl PromptPreset {
+    /// Builds a preset from generation settings. Without `include_prompt`
+    /// only the sampling parameters are kept.
+    pub fn from_generation_params(
+        name: String,
+        params: &GenerationParams,
+        include_prompt: bool,
+        now: u64,
+    ) -> Self {
+        let (positive, negative) = if include_prompt {
+            (params.prompt.clone(), params.negative_prompt.clone())
+        } else {
+            (String::new(), String::new())
+        };
+        PromptPreset {
+            name,
+            positive,
+            negative,
+            params: PresetParams::from(params),
+            includes_prompt: include_prompt,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn meta(&self) -> PresetMeta {
+        PresetMeta {
+            name: self.name.clone(),
+            model: self.params.model.clone(),
+            width: self.params.width,
+            height: self.params.height,
+            includes_prompt: self.includes_prompt,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        }
+    }
+
+    pub fn to_generation_params(&self) -> GenerationParams {
+        let p = &self.params;
+        GenerationParams {
+            prompt: self.positive.clone(),
+            negative_prompt: self.negative.clone(),
+            model: p.model.clone(),
+            width: p.width,
+            height: p.height,
+            steps: p.steps,
+            cfg_scale: p.cfg_scale,
+            cfg_rescale: p.cfg_rescale,
+            sampler: p.sampler.clone(),
+            scheduler: p.scheduler.clone(),
+            smea: p.smea,
+            smea_dyn: p.smea_dyn,
+            variety: p.variety,
+            seed: p.seed,
+            uc_preset: p.uc_preset,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PresetExport {
+    version: u32,
+    presets: Vec<PromptPreset>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PresetImportResult {
+    pub imported: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Reads the stored preset list. Entries that no longer deserialize are
+/// dropped with a warning instead of failing the whole list.
+pub fn from_store_value(value: Option<Value>) -> Vec<PromptPreset> {
+    let Some(Value::Array(items)) = value else {
+        return Vec::new();
+    };
+    items
+        .into_iter()
+        .filter_map(|item| match serde_json::from_value(item) {
+            Ok(preset) => Some(preset),
+            Err(e) => {
+                log::warn!("Skipping unreadable prompt preset: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn validate_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("프리셋 이름이 비어있습니다".to_string());
+    }
+    Ok(name.to_string())
+}
+
+/// Inserts `preset`, replacing a same-named one only when `overwrite` is set.
+/// A replaced preset keeps its original `created_at`.
+pub fn upsert(
+    presets: &mut Vec<PromptPreset>,
+    mut preset: PromptPreset,
+    overwrite: bool,
+) -> Result<(), String> {
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(_) if !overwrite => Err(format!("이미 존재하는 프리셋: {}", preset.name)),
+        Some(existing) => {
+            preset.created_at = existing.created_at;
+            *existing = preset;
+            Ok(())
+        }
+        None => {
+            presets.push(preset);
+            Ok(())
+        }
+    }
+}
+
+pub fn export_json(presets: Vec<PromptPreset>) -> Result<String, String> {
+    serde_json::to_string_pretty(&PresetExport {
+        version: EXPORT_VERSION,
+        presets,
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Accepts both the export wrapper and a bare preset array.
+pub fn parse_import(json: &str) -> Result<Vec<PromptPreset>, String> {
+    let value: Value = serde_json::from_str(json).map_err(|e| format!("JSON 파싱 오류: {}", e))?;
+    let items = match value {
+        Value::Array(_) => value,
+        Value::Object(mut obj) => obj
+            .remove("presets")
+            .ok_or_else(|| "프리셋 목록이 없습니다".to_string())?,
+        _ => return Err("지원하지 않는 프리셋 파일 형식".to_string()),
+    };
+    serde_json::from_value(items).map_err(|e| format!("프리셋 형식 오류: {}", e))
+}
+
+/// Merges imported presets into `presets`, recording which names were
+/// imported and which were skipped because they already exist.
+pub fn merge_import(
+    presets: &mut Vec<PromptPreset>,
+    imported: Vec<PromptPreset>,
+    overwrite: bool,
+) -> PresetImportResult {
+    let mut result = PresetImportResult::default();
+    for mut preset in imported {
+        let Ok(name) = validate_name(&preset.name) else {
+            continue;
+        };
+        preset.name = name.clone();
+        match upsert(presets, preset, overwrite) {
+            Ok(()) => result.imported.push(name),
+            Err(_) => result.skipped.push(name),
+        }
+    }
+    result
+}
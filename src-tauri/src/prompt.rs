@@ -0,0 +1,348 @@
+//! Prompt text normalization, so prompts written for other UIs are combined
+//! the same way NovelAI's own site would send them.
+//!
+//! NAI's emphasis syntax is `{tag}` (x1.05 per brace) and `[tag]` (/1.05 per
+//! bracket). V4 models additionally accept numeric emphasis, `1.2::tag::`.
+//! Parentheses are plain text to NAI (`horror (theme)` is a real tag), so the
+//! only parenthesized form rewritten here is the SD-style `(tag:1.2)`.
+
+pub const BRACE_WEIGHT: f64 = 1.05;
+// Past this many braces the emphasis is already extreme
+const MAX_BRACES: i32 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    /// `{...}` (`emphasis = true`) or `[...]`
+    Group {
+        emphasis: bool,
+        children: Vec<Node>,
+    },
+    /// `(...:weight)`
+    Weighted {
+        weight: f64,
+        children: Vec<Node>,
+    },
+}
+
+impl Node {
+    fn contains_weighted(&self) -> bool {
+        match self {
+            Node::Text(_) => false,
+            Node::Weighted { .. } => true,
+            Node::Group { children, .. } => children.iter().any(Node::contains_weighted),
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match nodes.last_mut() {
+        Some(Node::Text(last)) => last.push_str(text),
+        _ => nodes.push(Node::Text(text.to_string())),
+    }
+}
+
+fn push_char(nodes: &mut Vec<Node>, c: char) {
+    push_text(nodes, c.encode_utf8(&mut [0; 4]));
+}
+
+/// Flattens an unclosed group back into its parent. `opener` is kept as text
+/// for parentheses, which are literal to NAI anyway.
+fn unwrap_into(nodes: &mut Vec<Node>, opener: Option<char>, children: Vec<Node>) {
+    if let Some(c) = opener {
+        push_char(nodes, c);
+    }
+    for child in children {
+        match child {
+            Node::Text(text) => push_text(nodes, &text),
+            other => nodes.push(other),
+        }
+    }
+}
+
+/// Splits a trailing `:1.2` off the last text node of a parenthesized group.
+fn split_weight(children: &mut Vec<Node>) -> Option<f64> {
+    let Some(Node::Text(last)) = children.last_mut() else {
+        return None;
+    };
+    let colon = last.rfind(':')?;
+    let weight: f64 = last[colon + 1..].trim().parse().ok()?;
+    if !weight.is_finite() {
+        return None;
+    }
+    last.truncate(colon);
+    if last.is_empty() {
+        children.pop();
+    }
+    Some(weight)
+}
+
+impl Parser<'_> {
+    /// Parses until `closer` (or the end of input). Returns the nodes and
+    /// whether `closer` was actually found.
+    fn parse_until(&mut self, closer: Option<char>) -> (Vec<Node>, bool) {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.chars.next() {
+            if Some(c) == closer {
+                return (nodes, true);
+            }
+            match c {
+                '\\' => match self.chars.peek().copied() {
+                    // `\(` is SD's literal parenthesis, which NAI reads literally anyway
+                    Some(p @ ('(' | ')')) => {
+                        self.chars.next();
+                        push_char(&mut nodes, p);
+                    }
+                    // NAI has no way to write a literal brace, so escaped ones are dropped
+                    Some('{' | '}' | '[' | ']') => {
+                        self.chars.next();
+                    }
+                    _ => push_char(&mut nodes, c),
+                },
+                '{' | '[' => {
+                    let close = if c == '{' { '}' } else { ']' };
+                    let (children, closed) = self.parse_until(Some(close));
+                    if closed {
+                        nodes.push(Node::Group {
+                            emphasis: c == '{',
+                            children,
+                        });
+                    } else {
+                        // An unclosed brace would weight the rest of the prompt
+                        unwrap_into(&mut nodes, None, children);
+                    }
+                }
+                '(' => {
+                    let (mut children, closed) = self.parse_until(Some(')'));
+                    if !closed {
+                        unwrap_into(&mut nodes, Some('('), children);
+                        continue;
+                    }
+                    match split_weight(&mut children) {
+                        Some(weight) => nodes.push(Node::Weighted { weight, children }),
+                        None => {
+                            unwrap_into(&mut nodes, Some('('), children);
+                            push_char(&mut nodes, ')');
+                        }
+                    }
+                }
+                // Stray or mismatched closing brace/bracket
+                '}' | ']' => {}
+                _ => push_char(&mut nodes, c),
+            }
+        }
+        (nodes, false)
+    }
+}
+
+fn parse(prompt: &str) -> Vec<Node> {
+    let mut parser = Parser {
+        chars: prompt.chars().peekable(),
+    };
+    parser.parse_until(None).0
+}
+
+/// `1.2`, `0.95`, `-1` - at most two decimals, no trailing zeros.
+pub fn format_weight(weight: f64) -> String {
+    let s = format!("{:.2}", weight);
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Nearest number of braces (positive) or brackets (negative) for `weight`.
+pub fn brace_count(weight: f64) -> i32 {
+    if weight <= 0.0 {
+        return -MAX_BRACES;
+    }
+    ((weight.ln() / BRACE_WEIGHT.ln()).round() as i32).clamp(-MAX_BRACES, MAX_BRACES)
+}
+
+/// V3: every weight becomes the nearest number of braces/brackets.
+fn emit_v3(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Group { emphasis, children } => {
+                let (open, close) = if *emphasis { ('{', '}') } else { ('[', ']') };
+                out.push(open);
+                emit_v3(children, out);
+                out.push(close);
+            }
+            Node::Weighted { weight, children } => {
+                let n = brace_count(*weight);
+                let (open, close) = if n >= 0 { ('{', '}') } else { ('[', ']') };
+                let n = n.unsigned_abs() as usize;
+                out.extend(std::iter::repeat(open).take(n));
+                emit_v3(children, out);
+                out.extend(std::iter::repeat(close).take(n));
+            }
+        }
+    }
+}
+
+/// V4: weights become `w::text::` regions. Numeric emphasis doesn't nest, so
+/// nested weights are multiplied out into consecutive regions; brace groups
+/// that wrap a weighted part are folded into the multiplier too.
+fn collect_v4(nodes: &[Node], weight: f64, segments: &mut Vec<(f64, String)>) {
+    let push = |segments: &mut Vec<(f64, String)>, text: &str| match segments.last_mut() {
+        Some((w, s)) if (*w - weight).abs() < 1e-9 => s.push_str(text),
+        _ => segments.push((weight, text.to_string())),
+    };
+
+    for node in nodes {
+        match node {
+            Node::Text(text) => push(segments, text),
+            Node::Group { emphasis, children } if node.contains_weighted() => {
+                let factor = if *emphasis {
+                    BRACE_WEIGHT
+                } else {
+                    1.0 / BRACE_WEIGHT
+                };
+                collect_v4(children, weight * factor, segments);
+            }
+            Node::Group { .. } => {
+                let mut text = String::new();
+                emit_v3(std::slice::from_ref(node), &mut text);
+                push(segments, &text);
+            }
+            Node::Weighted {
+                weight: inner,
+                children,
+            } => collect_v4(children, weight * inner, segments),
+        }
+    }
+}
+
+fn emit_v4(nodes: &[Node]) -> String {
+    let mut segments = Vec::new();
+    collect_v4(nodes, 1.0, &mut segments);
+
+    let mut out = String::new();
+    for (weight, text) in segments {
+        if text.is_empty() {
+            continue;
+        }
+        if format_weight(weight) == "1" {
+            out.push_str(&text);
+        } else {
+            out.push_str(&format!("{}::{}::", format_weight(weight), text));
+        }
+    }
+    out
+}
+
+/// Rewrites weight syntax in `prompt` into the form `model` understands:
+/// `(tag:1.2)` becomes `1.2::tag::` on V4 and the nearest `{{{tag}}}` on V3.
+/// Unclosed or stray braces/brackets are removed so they can't spill
+/// emphasis over the rest of the prompt.
+pub fn normalize_prompt_weights(prompt: &str, model: &str) -> String {
+    let nodes = parse(prompt);
+    if model.contains("diffusion-4") {
+        emit_v4(&nodes)
+    } else {
+        let mut out = String::with_capacity(prompt.len());
+        emit_v3(&nodes, &mut out);
+        out
+    }
+}
+
+/// Normalizes line breaks the way NAI combines a multi-line prompt: CRLF/CR
+/// become LF, lines are trimmed, blank lines dropped, and the remaining lines
+/// joined with `, `. Repeated commas and runs of whitespace collapse too.
+pub fn normalize_prompt_newlines(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    text.split(['\n', ','])
+        .map(|part| part.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Both normalizations, in the order NAI applies them.
+pub fn normalize_prompt(prompt: &str, model: &str) -> String {
+    normalize_prompt_weights(&normalize_prompt_newlines(prompt), model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V3: &str = "nai-diffusion-3";
+    const V4: &str = "nai-diffusion-4-5-full";
+
+    #[test]
+    fn v3_weights_become_braces() {
+        let cases = [
+            ("1girl, (smile:1.1)", "1girl, {{smile}}"),
+            ("(smile:0.9)", "[[smile]]"),
+            ("(smile:1)", "smile"),
+            ("(smile:5)", "{{{{{{{{{{smile}}}}}}}}}}"),
+            ("({smile}:1.05)", "{{smile}}"),
+        ];
+        for (prompt, expected) in cases {
+            assert_eq!(normalize_prompt_weights(prompt, V3), expected, "{}", prompt);
+        }
+    }
+
+    #[test]
+    fn v4_weights_become_numeric_emphasis() {
+        let cases = [
+            ("1girl, (smile:1.2)", "1girl, 1.2::smile::"),
+            ("(smile:0.8), solo", "0.8::smile::, solo"),
+            ("{(smile:1.2)}", "1.26::smile::"),
+            ("(a (b:1.5):2)", "2::a ::3::b::"),
+            ("(smile:1)", "smile"),
+        ];
+        for (prompt, expected) in cases {
+            assert_eq!(normalize_prompt_weights(prompt, V4), expected, "{}", prompt);
+        }
+    }
+
+    #[test]
+    fn nai_syntax_is_kept() {
+        for prompt in [
+            "{{1girl}}, [[blurry]], horror (theme)",
+            "1.2::smile::, -1::hat::",
+            "artist:sample, year 2024",
+        ] {
+            assert_eq!(normalize_prompt_weights(prompt, V3), prompt);
+            assert_eq!(normalize_prompt_weights(prompt, V4), prompt);
+        }
+    }
+
+    #[test]
+    fn unbalanced_braces_are_dropped() {
+        for model in [V3, V4] {
+            assert_eq!(
+                normalize_prompt_weights("{1girl, solo", model),
+                "1girl, solo"
+            );
+            assert_eq!(
+                normalize_prompt_weights("1girl}, solo]", model),
+                "1girl, solo"
+            );
+            assert_eq!(normalize_prompt_weights("(smile:1.2", model), "(smile:1.2");
+        }
+    }
+
+    #[test]
+    fn newlines_are_joined_like_the_site() {
+        // A prompt typed over several lines and what NAI's site sends for it
+        let typed = "masterpiece, best quality\r\n\r\n1girl,\n  solo ,  long   hair\r{smile},,\n\n";
+        assert_eq!(
+            normalize_prompt_newlines(typed),
+            "masterpiece, best quality, 1girl, solo, long hair, {smile}"
+        );
+        assert_eq!(normalize_prompt_newlines("\n \r\n,"), "");
+        assert_eq!(
+            normalize_prompt_newlines("horror (theme)\n1.2::smile::"),
+            "horror (theme), 1.2::smile::"
+        );
+    }
+}
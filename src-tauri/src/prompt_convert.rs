@@ -0,0 +1,413 @@
+//! Converting weight syntax between A1111 (SD WebUI) and NovelAI prompts.
+//!
+//! A1111 weights with parentheses: `(tag)` is x1.1, `[tag]` is /1.1 and
+//! `(tag:1.3)` is explicit; `\(` writes a literal parenthesis and braces are
+//! plain text. NAI weights with braces: `{tag}` is x1.05, `[tag]` is /1.05,
+//! V4 also takes `1.3::tag::`; parentheses are plain text there.
+//!
+//! Both sides are parsed into runs of text with one effective weight each,
+//! so nested weights are multiplied out. Syntax with no counterpart (prompt
+//! editing, alternation, LoRA tags, unclosed groups) is copied as written
+//! and reported in the warnings.
+
+use crate::prompt::{brace_count, format_weight, BRACE_WEIGHT};
+use serde::Serialize;
+
+const A1111_WEIGHT: f64 = 1.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightSyntax {
+    A1111,
+    /// `1.2::tag::`
+    NaiV4,
+    /// `{{tag}}`
+    NaiV3,
+}
+
+impl WeightSyntax {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim().to_lowercase().as_str() {
+            "a1111" | "webui" | "sd" => Ok(WeightSyntax::A1111),
+            "nai" | "nai-v4" | "v4" => Ok(WeightSyntax::NaiV4),
+            "nai-v3" | "v3" => Ok(WeightSyntax::NaiV3),
+            _ => Err(format!("알 수 없는 가중치 형식: {}", name)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromptConversion {
+    pub prompt: String,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Tree {
+    Text(String),
+    /// Copied to the output exactly as written
+    Raw(String),
+    Weighted(f64, Vec<Tree>),
+}
+
+fn push_text(nodes: &mut Vec<Tree>, c: char) {
+    match nodes.last_mut() {
+        Some(Tree::Text(text)) => text.push(c),
+        _ => nodes.push(Tree::Text(c.to_string())),
+    }
+}
+
+fn slice(chars: &[char], start: usize, end: usize) -> String {
+    chars[start..end.min(chars.len())].iter().collect()
+}
+
+/// `(tag:1.3)`: splits the weight off the group's last text node.
+fn split_a1111_weight(children: &mut Vec<Tree>) -> Option<f64> {
+    let Some(Tree::Text(last)) = children.last_mut() else {
+        return None;
+    };
+    let colon = last.rfind(':')?;
+    let weight: f64 = last[colon + 1..].trim().parse().ok()?;
+    if !weight.is_finite() {
+        return None;
+    }
+    last.truncate(colon);
+    if last.is_empty() {
+        children.pop();
+    }
+    Some(weight)
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    warnings: &'a mut Vec<String>,
+}
+
+impl Parser<'_> {
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    /// Keeps `start..pos` as written and records why.
+    fn raw(&mut self, nodes: &mut Vec<Tree>, start: usize, why: &str) {
+        let text = slice(&self.chars, start, self.pos);
+        self.warnings.push(format!("{}: {}", why, text));
+        nodes.push(Tree::Raw(text));
+    }
+
+    /// A1111 syntax up to `closer`. Returns the nodes and whether `closer`
+    /// was found.
+    fn a1111(&mut self, closer: Option<char>) -> (Vec<Tree>, bool) {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.get(self.pos) {
+            self.pos += 1;
+            if Some(c) == closer {
+                return (nodes, true);
+            }
+            match c {
+                '\\' => match self.chars.get(self.pos) {
+                    Some(&next @ ('(' | ')' | '[' | ']' | '\\')) => {
+                        self.pos += 1;
+                        push_text(&mut nodes, next);
+                    }
+                    _ => push_text(&mut nodes, c),
+                },
+                '(' => {
+                    let start = self.pos - 1;
+                    let (mut children, closed) = self.a1111(Some(')'));
+                    if !closed {
+                        self.raw(&mut nodes, start, "닫히지 않은 괄호");
+                        continue;
+                    }
+                    let weight = split_a1111_weight(&mut children).unwrap_or(A1111_WEIGHT);
+                    nodes.push(Tree::Weighted(weight, children));
+                }
+                '[' => {
+                    let start = self.pos - 1;
+                    let (children, closed) = self.a1111(Some(']'));
+                    if !closed {
+                        self.raw(&mut nodes, start, "닫히지 않은 대괄호");
+                        continue;
+                    }
+                    // `[a:b:10]` (prompt editing) and `[a|b]` (alternation)
+                    // only exist in A1111
+                    let special = children
+                        .iter()
+                        .any(|n| matches!(n, Tree::Text(t) if t.contains([':', '|'])));
+                    if special {
+                        self.raw(&mut nodes, start, "변환할 수 없는 구문");
+                    } else {
+                        nodes.push(Tree::Weighted(1.0 / A1111_WEIGHT, children));
+                    }
+                }
+                '<' => {
+                    let start = self.pos - 1;
+                    match self.chars[self.pos..].iter().position(|&c| c == '>') {
+                        Some(len) => {
+                            self.pos += len + 1;
+                            self.raw(&mut nodes, start, "LoRA/확장 구문");
+                        }
+                        None => push_text(&mut nodes, c),
+                    }
+                }
+                _ => push_text(&mut nodes, c),
+            }
+        }
+        (nodes, false)
+    }
+
+    /// `1.2::` at the current position, if the number starts a word.
+    fn nai_weight_prefix(&self) -> Option<(f64, usize)> {
+        if self.pos > 0 && self.chars[self.pos - 1].is_alphanumeric() {
+            return None;
+        }
+        let rest = &self.chars[self.pos..];
+        let len = rest
+            .iter()
+            .enumerate()
+            .take_while(|&(i, &c)| c.is_ascii_digit() || c == '.' || (i == 0 && c == '-'))
+            .count();
+        if len == 0 || rest.get(len..len + 2) != Some(&[':', ':']) {
+            return None;
+        }
+        let weight: f64 = slice(rest, 0, len).parse().ok()?;
+        weight.is_finite().then_some((weight, len + 2))
+    }
+
+    /// NAI syntax up to `closer`, or with `region` set, up to the `::` that
+    /// ends a numeric weight.
+    fn nai(&mut self, closer: Option<char>, region: bool) -> (Vec<Tree>, bool) {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.get(self.pos) {
+            if region && self.starts_with("::") {
+                self.pos += 2;
+                return (nodes, true);
+            }
+            if let Some((weight, len)) = self.nai_weight_prefix() {
+                // A new weight replaces the current one rather than nesting
+                if region {
+                    return (nodes, true);
+                }
+                self.pos += len;
+                // A region left open runs to the end of the prompt
+                let (children, _) = self.nai(closer, true);
+                nodes.push(Tree::Weighted(weight, children));
+                continue;
+            }
+            if Some(c) == closer {
+                // The group's closer also ends a region inside it
+                if !region {
+                    self.pos += 1;
+                }
+                return (nodes, true);
+            }
+            self.pos += 1;
+            match c {
+                '{' | '[' => {
+                    let start = self.pos - 1;
+                    let (close, weight) = if c == '{' {
+                        ('}', BRACE_WEIGHT)
+                    } else {
+                        (']', 1.0 / BRACE_WEIGHT)
+                    };
+                    let (children, closed) = self.nai(Some(close), false);
+                    if closed {
+                        nodes.push(Tree::Weighted(weight, children));
+                    } else {
+                        self.raw(&mut nodes, start, "닫히지 않은 중괄호");
+                    }
+                }
+                _ => push_text(&mut nodes, c),
+            }
+        }
+        (nodes, false)
+    }
+}
+
+struct Run {
+    weight: f64,
+    text: String,
+    raw: bool,
+}
+
+fn flatten(nodes: Vec<Tree>, weight: f64, runs: &mut Vec<Run>) {
+    for node in nodes {
+        let (text, raw) = match node {
+            Tree::Weighted(inner, children) => {
+                flatten(children, weight * inner, runs);
+                continue;
+            }
+            Tree::Text(text) => (text, false),
+            Tree::Raw(text) => (text, true),
+        };
+        match runs.last_mut() {
+            Some(last) if last.raw == raw && (last.weight - weight).abs() < 1e-9 => {
+                last.text.push_str(&text)
+            }
+            _ => runs.push(Run { weight, text, raw }),
+        }
+    }
+}
+
+fn escape(text: &str, to: WeightSyntax, warnings: &mut Vec<String>) -> String {
+    match to {
+        WeightSyntax::A1111 => {
+            let mut out = String::with_capacity(text.len());
+            for c in text.chars() {
+                if matches!(c, '(' | ')' | '[' | ']') {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+            out
+        }
+        // NAI has no way to write a literal brace
+        WeightSyntax::NaiV4 | WeightSyntax::NaiV3 => {
+            if text.contains(['{', '}', '[', ']']) {
+                warnings.push(format!("NAI에서 쓸 수 없는 괄호를 제거했습니다: {}", text));
+                text.replace(['{', '}', '[', ']'], "")
+            } else {
+                text.to_string()
+            }
+        }
+    }
+}
+
+/// Wraps `core` in `to`'s weight syntax.
+fn weighted(core: &str, weight: f64, to: WeightSyntax) -> String {
+    match to {
+        WeightSyntax::A1111 => format!("({}:{})", core, format_weight(weight)),
+        WeightSyntax::NaiV4 => format!("{}::{}::", format_weight(weight), core),
+        WeightSyntax::NaiV3 => {
+            let n = brace_count(weight);
+            let (open, close) = if n >= 0 { ("{", "}") } else { ("[", "]") };
+            let n = n.unsigned_abs() as usize;
+            format!("{}{}{}", open.repeat(n), core, close.repeat(n))
+        }
+    }
+}
+
+/// Converts the weights in `prompt` from one syntax to another. Converting
+/// to the same syntax returns the prompt unchanged.
+pub fn convert(prompt: &str, from: WeightSyntax, to: WeightSyntax) -> PromptConversion {
+    let mut warnings = Vec::new();
+    if from == to {
+        return PromptConversion {
+            prompt: prompt.to_string(),
+            warnings,
+        };
+    }
+
+    let mut parser = Parser {
+        chars: prompt.chars().collect(),
+        pos: 0,
+        warnings: &mut warnings,
+    };
+    let tree = match from {
+        WeightSyntax::A1111 => parser.a1111(None).0,
+        WeightSyntax::NaiV4 | WeightSyntax::NaiV3 => parser.nai(None, false).0,
+    };
+    let mut runs = Vec::new();
+    flatten(tree, 1.0, &mut runs);
+
+    let mut out = String::with_capacity(prompt.len());
+    for run in runs {
+        let text = if run.raw {
+            run.text
+        } else {
+            escape(&run.text, to, &mut warnings)
+        };
+        if run.raw || format_weight(run.weight) == "1" {
+            out.push_str(&text);
+            continue;
+        }
+        // Keep surrounding spaces outside the weight syntax
+        let core = text.trim();
+        if core.is_empty() {
+            out.push_str(&text);
+            continue;
+        }
+        let lead = &text[..text.len() - text.trim_start().len()];
+        let trail = &text[text.trim_end().len()..];
+        out.push_str(lead);
+        out.push_str(&weighted(core, run.weight, to));
+        out.push_str(trail);
+    }
+    PromptConversion {
+        prompt: out,
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use WeightSyntax::{NaiV3, NaiV4, A1111};
+
+    fn converted(prompt: &str, from: WeightSyntax, to: WeightSyntax) -> String {
+        let result = convert(prompt, from, to);
+        assert!(result.warnings.is_empty(), "{:?}", result.warnings);
+        result.prompt
+    }
+
+    #[test]
+    fn explicit_a1111_weights() {
+        assert_eq!(converted("(word:1.1)", A1111, NaiV4), "1.1::word::");
+        assert_eq!(converted("(word:1.1)", A1111, NaiV3), "{{word}}");
+        assert_eq!(
+            converted("1girl, (word:0.5), solo", A1111, NaiV4),
+            "1girl, 0.5::word::, solo"
+        );
+    }
+
+    #[test]
+    fn nested_brackets_are_multiplied_out() {
+        assert_eq!(converted("((word))", A1111, NaiV4), "1.21::word::");
+        assert_eq!(converted("[word]", A1111, NaiV3), "[[word]]");
+        assert_eq!(converted("(a (b:1.5))", A1111, NaiV4), "1.1::a:: 1.65::b::");
+        assert_eq!(converted("{{{tag}}}", NaiV3, NaiV4), "1.16::tag::");
+        assert_eq!(converted("{a [b]}", NaiV3, A1111), "(a:1.05) b");
+    }
+
+    #[test]
+    fn escaped_parentheses_are_literal() {
+        assert_eq!(
+            converted(r"horror \(theme\)", A1111, NaiV4),
+            "horror (theme)"
+        );
+        assert_eq!(
+            converted("horror (theme)", NaiV4, A1111),
+            r"horror \(theme\)"
+        );
+    }
+
+    #[test]
+    fn nai_weights_to_a1111() {
+        assert_eq!(
+            converted("1.2::tag::, {a}", NaiV4, A1111),
+            "(tag:1.2), (a:1.05)"
+        );
+        assert_eq!(converted("-1::hat::", NaiV4, A1111), "(hat:-1)");
+    }
+
+    #[test]
+    fn unconvertible_syntax_is_kept_and_reported() {
+        for prompt in ["[cat:dog:10]", "[cat|dog]", "<lora:style:0.8>", "(unclosed"] {
+            let result = convert(&format!("1girl, {}", prompt), A1111, NaiV4);
+            assert_eq!(result.prompt, format!("1girl, {}", prompt));
+            assert_eq!(result.warnings.len(), 1, "{}", prompt);
+        }
+
+        let result = convert("{unclosed", NaiV3, A1111);
+        assert_eq!(result.prompt, "{unclosed");
+        assert_eq!(result.warnings.len(), 1);
+    }
+
+    #[test]
+    fn same_syntax_is_unchanged() {
+        let prompt = "((word)), [a:b:10]";
+        assert_eq!(converted(prompt, A1111, A1111), prompt);
+    }
+}
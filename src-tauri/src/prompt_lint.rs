@@ -0,0 +1,458 @@
+//! Warnings about a prompt before it is sent: contradicting tags, tags
+//! written twice, likely typos, and tags NAI may refuse.
+//!
+//! Prompts are split into tags at commas and line breaks. Emphasis (`{}`,
+//! `[]`, `1.2::tag::`) is ignored when comparing, as are case and `_` vs
+//! space. Positions are UTF-16 offsets into the text as given (what JS
+//! string indices count), covering the tag without its emphasis, so the
+//! frontend can highlight it in place.
+//!
+//! The built-in rules live in `BUILTIN_RULES`; user rules have the same
+//! shape and are checked alongside them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const LINT_STORE_FILE: &str = "prompt_lint.json";
+pub const LINT_STORE_KEY: &str = "rules";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    /// Tags that don't belong together, or a tag in both prompt and UC
+    Contradiction,
+    Duplicate,
+    Typo,
+    /// Likely to be refused or filtered by NAI
+    Blocked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintField {
+    Prompt,
+    Uc,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptLint {
+    pub kind: LintKind,
+    pub field: LintField,
+    /// UTF-16 offsets of the tag, end exclusive
+    pub start: usize,
+    pub end: usize,
+    pub tag: String,
+    pub message: String,
+    /// Replacement text for the tag; empty means removing it
+    pub suggestion: Option<String>,
+}
+
+/// One rule. With a single tag it fires wherever that tag appears; with
+/// more it fires when all of them are in the prompt, at the one that comes
+/// last. Duplicates aren't rules: every tag is checked for those.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintRule {
+    pub kind: LintKind,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub suggestion: Option<String>,
+    /// Shown instead of the default message for `kind`
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+struct BuiltinRule {
+    kind: LintKind,
+    tags: &'static [&'static str],
+    suggestion: Option<&'static str>,
+}
+
+const fn contradiction(tags: &'static [&'static str]) -> BuiltinRule {
+    BuiltinRule {
+        kind: LintKind::Contradiction,
+        tags,
+        suggestion: None,
+    }
+}
+
+const fn typo(tag: &'static [&'static str], fixed: &'static str) -> BuiltinRule {
+    BuiltinRule {
+        kind: LintKind::Typo,
+        tags: tag,
+        suggestion: Some(fixed),
+    }
+}
+
+const fn blocked(tags: &'static [&'static str]) -> BuiltinRule {
+    BuiltinRule {
+        kind: LintKind::Blocked,
+        tags,
+        suggestion: None,
+    }
+}
+
+const BUILTIN_RULES: &[BuiltinRule] = &[
+    contradiction(&["solo", "2girls"]),
+    contradiction(&["solo", "3girls"]),
+    contradiction(&["solo", "multiple girls"]),
+    contradiction(&["solo", "2boys"]),
+    contradiction(&["solo", "3boys"]),
+    contradiction(&["solo", "multiple boys"]),
+    contradiction(&["day", "night"]),
+    contradiction(&["indoors", "outdoors"]),
+    contradiction(&["open mouth", "closed mouth"]),
+    contradiction(&["closed eyes", "looking at viewer"]),
+    contradiction(&["short hair", "long hair"]),
+    contradiction(&["short hair", "very long hair"]),
+    contradiction(&["standing", "sitting"]),
+    contradiction(&["standing", "lying"]),
+    contradiction(&["upper body", "full body"]),
+    contradiction(&["portrait", "full body"]),
+    contradiction(&["monochrome", "colorful"]),
+    contradiction(&["greyscale", "colorful"]),
+    contradiction(&["smile", "frown"]),
+    contradiction(&["nude", "clothed"]),
+    typo(&["1 girl"], "1girl"),
+    typo(&["1 boy"], "1boy"),
+    typo(&["2 girls"], "2girls"),
+    typo(&["2 boys"], "2boys"),
+    typo(&["masterpeice"], "masterpiece"),
+    typo(&["master piece"], "masterpiece"),
+    typo(&["best qualty"], "best quality"),
+    typo(&["absurd res"], "absurdres"),
+    typo(&["high res"], "highres"),
+    typo(&["looking at veiwer"], "looking at viewer"),
+    typo(&["looking at the viewer"], "looking at viewer"),
+    typo(&["blue eye"], "blue eyes"),
+    typo(&["red eye"], "red eyes"),
+    typo(&["green eye"], "green eyes"),
+    typo(&["long hairs"], "long hair"),
+    typo(&["short hairs"], "short hair"),
+    typo(&["smiling"], "smile"),
+    typo(&["blond hair"], "blonde hair"),
+    typo(&["grey scale"], "greyscale"),
+    typo(&["simple backround"], "simple background"),
+    typo(&["white backround"], "white background"),
+    blocked(&["loli"]),
+    blocked(&["shota"]),
+    blocked(&["toddler"]),
+    blocked(&["child", "nsfw"]),
+    blocked(&["child", "explicit"]),
+    blocked(&["child", "nude"]),
+];
+
+impl LintRule {
+    fn from_builtin(rule: &BuiltinRule) -> Self {
+        LintRule {
+            kind: rule.kind,
+            tags: rule.tags.iter().map(|t| t.to_string()).collect(),
+            suggestion: rule.suggestion.map(str::to_string),
+            message: None,
+        }
+    }
+}
+
+pub fn builtin_rules() -> Vec<LintRule> {
+    BUILTIN_RULES.iter().map(LintRule::from_builtin).collect()
+}
+
+/// Checks user rules before they are saved.
+pub fn validate_rules(rules: &[LintRule]) -> Result<(), String> {
+    for (index, rule) in rules.iter().enumerate() {
+        if rule.kind == LintKind::Duplicate {
+            return Err(format!(
+                "룰 {}: 중복 검사는 룰로 추가할 수 없습니다",
+                index + 1
+            ));
+        }
+        if rule.tags.iter().all(|tag| normalize(tag).is_empty()) {
+            return Err(format!("룰 {}: 태그가 비어있습니다", index + 1));
+        }
+        if rule.kind == LintKind::Contradiction && rule.tags.len() < 2 {
+            return Err(format!(
+                "룰 {}: 모순 룰에는 태그가 두 개 이상 필요합니다",
+                index + 1
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A tag as written, with where its text (minus emphasis) sits in UTF-16
+/// offsets.
+struct Tag {
+    key: String,
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+/// What tags are compared by: lowercase, `_` as space, single spaces.
+fn normalize(tag: &str) -> String {
+    tag.to_lowercase()
+        .replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The length of a `1.2::` / `-1::` weight prefix at the start of `chars`.
+fn weight_prefix_len(chars: &[char]) -> usize {
+    let digits = chars
+        .iter()
+        .take_while(|c| c.is_ascii_digit() || **c == '.' || **c == '-')
+        .count();
+    if digits > 0 && chars[digits..].starts_with(&[':', ':']) {
+        digits + 2
+    } else {
+        0
+    }
+}
+
+fn split_tags(text: &str) -> Vec<Tag> {
+    let chars: Vec<char> = text.chars().collect();
+    // UTF-16 offset of each char, plus the end
+    let utf16: Vec<usize> = std::iter::once(0)
+        .chain(chars.iter().scan(0, |offset, c| {
+            *offset += c.len_utf16();
+            Some(*offset)
+        }))
+        .collect();
+    let mut tags = Vec::new();
+    let mut piece_start = 0;
+    for i in 0..=chars.len() {
+        if i < chars.len() && !matches!(chars[i], ',' | '\n') {
+            continue;
+        }
+        let (mut start, mut end) = (piece_start, i);
+        piece_start = i + 1;
+        loop {
+            let before = (start, end);
+            while start < end && (chars[start].is_whitespace() || "{[".contains(chars[start])) {
+                start += 1;
+            }
+            while end > start && (chars[end - 1].is_whitespace() || "}]".contains(chars[end - 1])) {
+                end -= 1;
+            }
+            start += weight_prefix_len(&chars[start..end]);
+            if chars[start..end].ends_with(&[':', ':']) {
+                end -= 2;
+            }
+            if (start, end) == before {
+                break;
+            }
+        }
+        let tag: String = chars[start..end].iter().collect();
+        let key = normalize(&tag);
+        if !key.is_empty() {
+            tags.push(Tag {
+                key,
+                text: tag,
+                start: utf16[start],
+                end: utf16[end],
+            });
+        }
+    }
+    tags
+}
+
+fn lint(kind: LintKind, field: LintField, tag: &Tag, message: String) -> PromptLint {
+    PromptLint {
+        kind,
+        field,
+        start: tag.start,
+        end: tag.end,
+        tag: tag.text.clone(),
+        message,
+        suggestion: None,
+    }
+}
+
+fn check_field(field: LintField, tags: &[Tag], rules: &[LintRule], out: &mut Vec<PromptLint>) {
+    let mut first_seen: HashMap<&str, usize> = HashMap::new();
+    for (index, tag) in tags.iter().enumerate() {
+        if first_seen.contains_key(tag.key.as_str()) {
+            out.push(PromptLint {
+                suggestion: Some(String::new()),
+                ..lint(
+                    LintKind::Duplicate,
+                    field,
+                    tag,
+                    format!("'{}' 태그가 중복되었습니다", tag.text),
+                )
+            });
+        } else {
+            first_seen.insert(&tag.key, index);
+        }
+    }
+
+    for rule in rules {
+        let keys: Vec<String> = rule
+            .tags
+            .iter()
+            .map(|t| normalize(t))
+            .filter(|k| !k.is_empty())
+            .collect();
+        let hits: Vec<&Tag> = match keys.as_slice() {
+            [] => continue,
+            [key] => tags.iter().filter(|tag| &tag.key == key).collect(),
+            _ => {
+                let found: Option<Vec<usize>> = keys
+                    .iter()
+                    .map(|key| first_seen.get(key.as_str()).copied())
+                    .collect();
+                match found.and_then(|found| found.into_iter().max()) {
+                    Some(last) => vec![&tags[last]],
+                    None => continue,
+                }
+            }
+        };
+        for tag in hits {
+            let others: Vec<&str> = rule
+                .tags
+                .iter()
+                .map(String::as_str)
+                .filter(|t| normalize(t) != tag.key)
+                .collect();
+            let message = rule.message.clone().unwrap_or_else(|| match rule.kind {
+                LintKind::Contradiction => {
+                    format!(
+                        "'{}'와(과) '{}'는 함께 쓰기 어렵습니다",
+                        tag.text,
+                        others.join("', '")
+                    )
+                }
+                LintKind::Typo => format!(
+                    "'{}'는 오타일 수 있습니다{}",
+                    tag.text,
+                    rule.suggestion
+                        .as_ref()
+                        .map(|s| format!(" ('{}'?)", s))
+                        .unwrap_or_default()
+                ),
+                LintKind::Blocked if others.is_empty() => {
+                    format!("'{}'는 NAI에서 차단될 수 있습니다", tag.text)
+                }
+                LintKind::Blocked => format!(
+                    "'{}'와(과) '{}'의 조합은 NAI에서 차단될 수 있습니다",
+                    tag.text,
+                    others.join("', '")
+                ),
+                LintKind::Duplicate => format!("'{}' 태그가 중복되었습니다", tag.text),
+            });
+            out.push(PromptLint {
+                suggestion: rule.suggestion.clone(),
+                ..lint(rule.kind, field, tag, message)
+            });
+        }
+    }
+}
+
+/// Lints `prompt` and `uc` against the built-in rules plus `custom`.
+/// Results are sorted by field, then position.
+pub fn lint_prompt(prompt: &str, uc: &str, custom: &[LintRule]) -> Vec<PromptLint> {
+    let mut rules = builtin_rules();
+    rules.extend(custom.iter().cloned());
+
+    let prompt_tags = split_tags(prompt);
+    let uc_tags = split_tags(uc);
+    let mut lints = Vec::new();
+    check_field(LintField::Prompt, &prompt_tags, &rules, &mut lints);
+    // Only typos make sense in the UC; "solo" there with "2girls" is fine
+    let uc_rules: Vec<LintRule> = rules
+        .iter()
+        .filter(|rule| rule.kind == LintKind::Typo)
+        .cloned()
+        .collect();
+    check_field(LintField::Uc, &uc_tags, &uc_rules, &mut lints);
+
+    for tag in &prompt_tags {
+        if uc_tags.iter().any(|uc_tag| uc_tag.key == tag.key) {
+            lints.push(lint(
+                LintKind::Contradiction,
+                LintField::Prompt,
+                tag,
+                format!("'{}'가 네거티브 프롬프트에도 있습니다", tag.text),
+            ));
+        }
+    }
+
+    lints.sort_by_key(|lint| (lint.field == LintField::Uc, lint.start, lint.end));
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(text: &str) -> Vec<(String, usize, usize)> {
+        split_tags(text)
+            .into_iter()
+            .map(|tag| (tag.text, tag.start, tag.end))
+            .collect()
+    }
+
+    /// What JS `text.slice(start, end)` gives.
+    fn js_slice(text: &str, start: usize, end: usize) -> String {
+        let units: Vec<u16> = text.encode_utf16().collect();
+        String::from_utf16(&units[start..end]).unwrap()
+    }
+
+    #[test]
+    fn emphasis_is_stripped_from_tags() {
+        assert_eq!(
+            spans("{{1girl}}, [solo],1.2::red hair::\n -1::hat::"),
+            vec![
+                ("1girl".to_string(), 2, 7),
+                ("solo".to_string(), 12, 16),
+                ("red hair".to_string(), 23, 31),
+                ("hat".to_string(), 39, 42),
+            ]
+        );
+        assert!(spans(" , {}, [[ ]] ,").is_empty());
+    }
+
+    #[test]
+    fn offsets_are_utf16() {
+        let text = "😀 smile, 1girl, {고양이}";
+        for (tag, start, end) in spans(text) {
+            assert_eq!(js_slice(text, start, end), tag);
+        }
+        assert_eq!(spans(text)[1].1, 10);
+    }
+
+    #[test]
+    fn multi_tag_rules_fire_on_the_last_tag() {
+        let lints = lint_prompt("night, 1girl, day", "", &[]);
+        let contradictions: Vec<&PromptLint> = lints
+            .iter()
+            .filter(|l| l.kind == LintKind::Contradiction)
+            .collect();
+        assert_eq!(contradictions.len(), 1);
+        assert_eq!(contradictions[0].tag, "day");
+        assert_eq!(contradictions[0].start, 14);
+
+        let blocked = lint_prompt("nsfw, child", "", &[]);
+        assert!(blocked
+            .iter()
+            .any(|l| l.kind == LintKind::Blocked && l.tag == "child"));
+        assert!(lint_prompt("child, smile", "", &[]).is_empty());
+    }
+
+    #[test]
+    fn duplicates_typos_and_uc() {
+        let lints = lint_prompt(
+            "Long_Hair, long hair, masterpeice",
+            "long hair, 1 girl",
+            &[],
+        );
+        let kinds: Vec<(LintKind, LintField, &str)> = lints
+            .iter()
+            .map(|l| (l.kind, l.field, l.tag.as_str()))
+            .collect();
+        assert!(kinds.contains(&(LintKind::Duplicate, LintField::Prompt, "long hair")));
+        assert!(kinds.contains(&(LintKind::Typo, LintField::Prompt, "masterpeice")));
+        assert!(kinds.contains(&(LintKind::Typo, LintField::Uc, "1 girl")));
+        assert!(kinds.contains(&(LintKind::Contradiction, LintField::Prompt, "Long_Hair")));
+    }
+}
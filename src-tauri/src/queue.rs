@@ -0,0 +1,257 @@
+//! Generation queue: requests wait here and a single worker (in `lib.rs`)
+//! runs them one at a time.
+//!
+//! Higher `priority` runs first; equal priorities run in the order they were
+//! queued. Moving an item by hand gives it the priority of its new neighbour
+//! and renumbers the order, so the heap stays the single source of truth.
+//! The item being generated has already left the heap and can't be moved.
+
+use crate::generation::GenerationParams;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+pub struct QueueItem {
+    pub id: u64,
+    pub priority: i32,
+    /// Order among equal priorities, lower first
+    seq: u64,
+    pub token: String,
+    pub params: GenerationParams,
+}
+
+impl QueueItem {
+    fn entry(&self, position: usize) -> QueueEntry {
+        QueueEntry {
+            id: self.id,
+            priority: self.priority,
+            position,
+            prompt: self.params.prompt.clone(),
+        }
+    }
+}
+
+// BinaryHeap pops the greatest item: highest priority, then lowest seq
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueEntry {
+    pub id: u64,
+    pub priority: i32,
+    /// Place in line, 0 = next to run. The running item is 0 as well.
+    pub position: usize,
+    pub prompt: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStatus {
+    pub running: Option<QueueEntry>,
+    /// In the order they will run
+    pub pending: Vec<QueueEntry>,
+}
+
+#[derive(Default)]
+pub struct GenerationQueue {
+    heap: BinaryHeap<QueueItem>,
+    running: Option<QueueEntry>,
+    next_id: u64,
+    next_seq: u64,
+}
+
+impl GenerationQueue {
+    pub fn push(&mut self, token: String, params: GenerationParams, priority: i32) -> u64 {
+        self.next_id += 1;
+        self.next_seq += 1;
+        self.heap.push(QueueItem {
+            id: self.next_id,
+            priority,
+            seq: self.next_seq,
+            token,
+            params,
+        });
+        self.next_id
+    }
+
+    /// Takes the next item and marks it as running.
+    pub fn start_next(&mut self) -> Option<QueueItem> {
+        let item = self.heap.pop()?;
+        self.running = Some(item.entry(0));
+        Some(item)
+    }
+
+    pub fn finish(&mut self) {
+        self.running = None;
+    }
+
+    fn check_pending(&self, id: u64) -> Result<(), String> {
+        if self.running.as_ref().is_some_and(|r| r.id == id) {
+            return Err("이미 생성 중인 항목은 변경할 수 없습니다".to_string());
+        }
+        if !self.heap.iter().any(|item| item.id == id) {
+            return Err(format!("대기열에 없는 항목입니다: {}", id));
+        }
+        Ok(())
+    }
+
+    pub fn set_priority(&mut self, id: u64, priority: i32) -> Result<(), String> {
+        self.check_pending(id)?;
+        let mut items = std::mem::take(&mut self.heap).into_vec();
+        for item in items.iter_mut().filter(|item| item.id == id) {
+            item.priority = priority;
+        }
+        self.heap = items.into();
+        Ok(())
+    }
+
+    /// Moves a waiting item to `new_index` in the run order (clamped).
+    pub fn reorder(&mut self, id: u64, new_index: usize) -> Result<(), String> {
+        self.check_pending(id)?;
+        let mut items = std::mem::take(&mut self.heap).into_sorted_vec();
+        items.reverse();
+        let from = items.iter().position(|item| item.id == id).unwrap_or(0);
+        let mut item = items.remove(from);
+        let to = new_index.min(items.len());
+
+        // Same priority as the item it lands behind (or, at the front, the
+        // one it lands before), so it sorts exactly there
+        let neighbour = if to > 0 {
+            items.get(to - 1)
+        } else {
+            items.first()
+        };
+        if let Some(neighbour) = neighbour {
+            item.priority = neighbour.priority;
+        }
+        items.insert(to, item);
+        for item in &mut items {
+            self.next_seq += 1;
+            item.seq = self.next_seq;
+        }
+        self.heap = items.into();
+        Ok(())
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let mut pending: Vec<&QueueItem> = self.heap.iter().collect();
+        pending.sort_by(|a, b| b.cmp(a));
+        QueueStatus {
+            running: self.running.clone(),
+            pending: pending
+                .into_iter()
+                .enumerate()
+                .map(|(position, item)| item.entry(position))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(prompt: &str) -> GenerationParams {
+        GenerationParams {
+            prompt: prompt.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Queues `(prompt, priority)` pairs and returns their ids.
+    fn queue_of(items: &[(&str, i32)]) -> (GenerationQueue, Vec<u64>) {
+        let mut queue = GenerationQueue::default();
+        let ids = items
+            .iter()
+            .map(|(prompt, priority)| queue.push(String::new(), params(prompt), *priority))
+            .collect();
+        (queue, ids)
+    }
+
+    fn order(queue: &GenerationQueue) -> Vec<String> {
+        queue
+            .status()
+            .pending
+            .into_iter()
+            .map(|e| e.prompt)
+            .collect()
+    }
+
+    #[test]
+    fn equal_priorities_run_first_in_first_out() {
+        let (mut queue, _) = queue_of(&[("a", 0), ("b", 0), ("c", 0)]);
+        assert_eq!(order(&queue), ["a", "b", "c"]);
+        let started: Vec<String> = std::iter::from_fn(|| queue.start_next())
+            .map(|item| item.params.prompt)
+            .collect();
+        assert_eq!(started, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn higher_priority_overtakes() {
+        let (mut queue, ids) = queue_of(&[("a", 0), ("b", 0), ("urgent", 5)]);
+        assert_eq!(order(&queue), ["urgent", "a", "b"]);
+
+        queue.set_priority(ids[1], 10).unwrap();
+        assert_eq!(order(&queue), ["b", "urgent", "a"]);
+    }
+
+    #[test]
+    fn reorder_moves_and_takes_the_neighbours_priority() {
+        let (mut queue, ids) = queue_of(&[("a", 5), ("b", 0), ("c", 0)]);
+
+        queue.reorder(ids[2], 0).unwrap();
+        assert_eq!(order(&queue), ["c", "a", "b"]);
+        assert_eq!(queue.status().pending[0].priority, 5);
+
+        queue.reorder(ids[2], 2).unwrap();
+        assert_eq!(order(&queue), ["a", "b", "c"]);
+        assert_eq!(queue.status().pending[2].priority, 0);
+
+        // Past the end is clamped to the back
+        queue.reorder(ids[0], 99).unwrap();
+        assert_eq!(order(&queue), ["b", "c", "a"]);
+        assert_eq!(queue.status().pending[2].priority, 0);
+
+        assert!(queue.reorder(999, 0).is_err());
+    }
+
+    #[test]
+    fn running_item_cannot_be_changed() {
+        let (mut queue, ids) = queue_of(&[("a", 0), ("b", 0)]);
+        let running = queue.start_next().unwrap();
+        assert_eq!(running.id, ids[0]);
+        assert!(queue.set_priority(ids[0], 3).is_err());
+        assert!(queue.reorder(ids[0], 0).is_err());
+
+        let status = queue.status();
+        assert_eq!(status.running.map(|r| r.id), Some(ids[0]));
+        assert_eq!(status.pending.len(), 1);
+    }
+
+    #[test]
+    fn status_positions_follow_the_run_order() {
+        let (queue, ids) = queue_of(&[("a", 0), ("b", 1), ("c", 0)]);
+        let pending = queue.status().pending;
+        let positions: Vec<(u64, usize)> = pending.iter().map(|e| (e.id, e.position)).collect();
+        assert_eq!(positions, [(ids[1], 0), (ids[0], 1), (ids[2], 2)]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Optional log of the requests NAIS sends to NAI, for comparing against
+//! the official site's requests in bug reports.
+//!
+//! While enabled (`set_request_logging`), every exchange is appended to
+//! `nai-requests.jsonl` in the app log folder as separate lines sharing an
+//! `id`: `request` (URL, headers, body), `response` (status, headers),
+//! `body` (size and SHA-256, plus the text for small non-binary bodies) or
+//! `error`. The token is masked, and long strings in request bodies (images,
+//! vibes) are replaced by their length and hash.
+
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+pub const FILE_NAME: &str = "nai-requests.jsonl";
+// Longer request strings are images or encoded vibes
+const MAX_LOGGED_STRING: usize = 512;
+// Response bodies up to this size are logged as text when they aren't binary
+const MAX_LOGGED_BODY: usize = 4096;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+// Keeps lines from concurrent requests from interleaving
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Ties the lines of one exchange together. Stored in the response's
+/// extensions so whoever reads the body can log it.
+#[derive(Debug, Clone, Copy)]
+pub struct ExchangeId(u64);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn path(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_log_dir().map_err(|e| e.to_string())?;
+    Ok(dir.join(FILE_NAME))
+}
+
+fn append(app: &AppHandle, id: ExchangeId, event: &str, mut entry: Map<String, Value>) {
+    entry.insert("id".into(), json!(id.0));
+    entry.insert("event".into(), json!(event));
+    entry.insert("time".into(), json!(chrono::Local::now().to_rfc3339()));
+
+    let result = path(app).and_then(|path| {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        writeln!(file, "{}", Value::Object(entry)).map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        log::warn!("Failed to write request log: {}", e);
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// `pst-abcd…wxyz`: enough to tell tokens apart, not enough to use one.
+fn mask_token(token: &str) -> String {
+    let chars: Vec<char> = token.chars().collect();
+    if chars.len() <= 12 {
+        return "***".to_string();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+fn summarize(value: &Value) -> Value {
+    match value {
+        Value::String(s) if s.len() > MAX_LOGGED_STRING => {
+            json!(format!(
+                "<{} chars, sha256 {}>",
+                s.len(),
+                sha256_hex(s.as_bytes())
+            ))
+        }
+        Value::Array(items) => Value::Array(items.iter().map(summarize).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), summarize(value)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn headers_json(headers: &reqwest::header::HeaderMap) -> Map<String, Value> {
+    headers
+        .iter()
+        .filter(|(name, _)| *name != reqwest::header::SET_COOKIE)
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            let value = match value.strip_prefix("Bearer ") {
+                Some(token) if name == reqwest::header::AUTHORIZATION => {
+                    format!("Bearer {}", mask_token(token))
+                }
+                _ => value.to_string(),
+            };
+            (name.to_string(), json!(value))
+        })
+        .collect()
+}
+
+/// Logs an outgoing request. Returns `None` while logging is off, which
+/// makes the other calls no-ops.
+pub fn request(app: &AppHandle, request: &reqwest::Request) -> Option<ExchangeId> {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return None;
+    }
+    let id = ExchangeId(NEXT_ID.fetch_add(1, Ordering::SeqCst));
+    let body =
+        request.body().and_then(|b| b.as_bytes()).map(|bytes| {
+            match serde_json::from_slice::<Value>(bytes) {
+                Ok(value) => summarize(&value),
+                Err(_) => json!(format!(
+                    "<{} bytes, sha256 {}>",
+                    bytes.len(),
+                    sha256_hex(bytes)
+                )),
+            }
+        });
+    let mut entry = Map::new();
+    entry.insert("method".into(), json!(request.method().as_str()));
+    entry.insert("url".into(), json!(request.url().as_str()));
+    entry.insert(
+        "headers".into(),
+        Value::Object(headers_json(request.headers())),
+    );
+    entry.insert("body".into(), body.unwrap_or(Value::Null));
+    append(app, id, "request", entry);
+    Some(id)
+}
+
+/// Logs the status and headers, and tags `response` so `body` can be
+/// logged under the same id when it is read.
+pub fn response(app: &AppHandle, id: Option<ExchangeId>, response: &mut reqwest::Response) {
+    let Some(id) = id else {
+        return;
+    };
+    let mut entry = Map::new();
+    entry.insert("status".into(), json!(response.status().as_u16()));
+    entry.insert(
+        "headers".into(),
+        Value::Object(headers_json(response.headers())),
+    );
+    append(app, id, "response", entry);
+    response.extensions_mut().insert(id);
+}
+
+/// The id `response` stored on a response, if it was logged.
+pub fn id_of(response: &reqwest::Response) -> Option<ExchangeId> {
+    response.extensions().get::<ExchangeId>().copied()
+}
+
+/// Logs a response body: size and hash always, the text too when it is
+/// small and not binary (ZIPs and images never are).
+pub fn body(app: &AppHandle, id: Option<ExchangeId>, bytes: &[u8]) {
+    let Some(id) = id else {
+        return;
+    };
+    let mut entry = Map::new();
+    entry.insert("size".into(), json!(bytes.len()));
+    entry.insert("sha256".into(), json!(sha256_hex(bytes)));
+    if bytes.len() <= MAX_LOGGED_BODY {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            entry.insert("text".into(), json!(text));
+        }
+    }
+    append(app, id, "body", entry);
+}
+
+pub fn error(app: &AppHandle, id: Option<ExchangeId>, message: &str) {
+    let Some(id) = id else {
+        return;
+    };
+    let mut entry = Map::new();
+    entry.insert("message".into(), json!(message));
+    append(app, id, "error", entry);
+}
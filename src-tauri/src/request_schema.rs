@@ -0,0 +1,124 @@
+//! Example request bodies for bug reports.
+//!
+//! Each dump is built by the same code that builds real requests, from
+//! NAIS's default settings, with placeholders in place of prompts, images
+//! and the token. `fields` lists every key in the body with the JSON type
+//! NAIS sends for it, so the dump can be held against a request captured
+//! from the official site.
+
+use crate::augment::{self, AugmentStep, AugmentTool};
+use crate::{generation, nai_settings};
+use serde_json::{json, Map, Value};
+
+const GENERATE_URL: &str = "https://image.novelai.net/ai/generate-image";
+const UPSCALE_URL: &str = "https://api.novelai.net/ai/upscale";
+const IMAGE_PLACEHOLDER: &str = "<base64 PNG>";
+const MASK_PLACEHOLDER: &str = "<base64 PNG mask>";
+
+pub const ACTIONS: [&str; 5] = ["generate", "img2img", "inpaint", "upscale", "augment"];
+
+fn type_name(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "boolean".to_string(),
+        Value::Number(n) if n.is_f64() => "number".to_string(),
+        Value::Number(_) => "integer".to_string(),
+        Value::String(s) if s == IMAGE_PLACEHOLDER || s == MASK_PLACEHOLDER => {
+            "string (base64)".to_string()
+        }
+        Value::String(_) => "string".to_string(),
+        Value::Array(items) => match items.first() {
+            Some(first) => format!("array<{}>", type_name(first)),
+            None => "array".to_string(),
+        },
+        Value::Object(_) => "object".to_string(),
+    }
+}
+
+/// Records the type of every key under `value`, with dotted paths and `[]`
+/// for array elements.
+fn describe(value: &Value, path: &str, fields: &mut Map<String, Value>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let child_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                fields.insert(child_path.clone(), json!(type_name(child)));
+                describe(child, &child_path, fields);
+            }
+        }
+        Value::Array(items) => {
+            if let Some(first) = items.first() {
+                describe(first, &format!("{}[]", path), fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn generation_body(source_image: bool, mask: bool) -> Value {
+    let params = generation::GenerationParams {
+        prompt: "<prompt>".to_string(),
+        negative_prompt: "<negative prompt>".to_string(),
+        source_image: source_image.then(|| IMAGE_PLACEHOLDER.to_string()),
+        mask: mask.then(|| MASK_PLACEHOLDER.to_string()),
+        ..nai_settings::default_params()
+    };
+    generation::build_payload(&params, 0)
+}
+
+/// Pretty-printed dump for `action` (one of `ACTIONS`): endpoint, headers
+/// with the token left out, an example body and its field types.
+pub fn dump(action: &str) -> Result<String, String> {
+    let (url, body) = match action.trim().to_lowercase().as_str() {
+        "generate" => (GENERATE_URL, generation_body(false, false)),
+        "img2img" => (GENERATE_URL, generation_body(true, false)),
+        "inpaint" => (GENERATE_URL, generation_body(true, true)),
+        "upscale" => {
+            let params = nai_settings::default_params();
+            let body = json!({
+                "image": IMAGE_PLACEHOLDER,
+                "width": params.width,
+                "height": params.height,
+                "scale": 4,
+            });
+            (UPSCALE_URL, body)
+        }
+        "augment" => {
+            let params = nai_settings::default_params();
+            let step = AugmentStep {
+                tool: AugmentTool::Colorize,
+                prompt: Some("<prompt>".to_string()),
+                defry: Some(0),
+            };
+            let body =
+                augment::build_payload(&step, IMAGE_PLACEHOLDER, params.width, params.height);
+            (augment::AUGMENT_URL, body)
+        }
+        _ => {
+            return Err(format!(
+                "알 수 없는 요청 종류: {} (가능한 값: {})",
+                action,
+                ACTIONS.join(", ")
+            ))
+        }
+    };
+
+    let mut fields = Map::new();
+    describe(&body, "", &mut fields);
+    let dump = json!({
+        "action": action.trim().to_lowercase(),
+        "method": "POST",
+        "endpoint": url,
+        "headers": {
+            "Authorization": "Bearer <token>",
+            "Content-Type": "application/json",
+        },
+        "body": body,
+        "fields": fields,
+    });
+    serde_json::to_string_pretty(&dump).map_err(|e| e.to_string())
+}
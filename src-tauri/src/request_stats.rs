@@ -0,0 +1,169 @@
+//! In-memory counts of the requests sent to NAI, per token, to see how
+//! close one is running to NAI's rate limit (429).
+//!
+//! `send_logged` records every NAI exchange here. Only the last hour is
+//! kept, and nothing survives a restart. Tokens are stored as hashes.
+
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60 * 60);
+const MINUTE: Duration = Duration::from_secs(60);
+// Far above what NAI allows in an hour; bounds memory if something loops
+const MAX_ENTRIES: usize = 20_000;
+// Suggested wait after a 429 without `Retry-After`, doubled for each
+// further 429 within a minute
+const BASE_WAIT: Duration = Duration::from_secs(5);
+const MAX_WAIT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    RateLimited,
+    /// Any other error status, or no response at all
+    Failed,
+}
+
+struct Entry {
+    at: Instant,
+    token: u64,
+    outcome: Outcome,
+}
+
+struct Stats {
+    entries: VecDeque<Entry>,
+    /// Since the app started, unlike the windowed counts
+    rate_limited_total: HashMap<u64, u64>,
+    /// Per token: when the last 429 came and the wait NAI asked for
+    last_rate_limit: HashMap<u64, (Instant, Option<Duration>)>,
+}
+
+static STATS: Mutex<Option<Stats>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct WindowStats {
+    pub total: u64,
+    pub success: u64,
+    pub rate_limited: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestStats {
+    pub last_minute: WindowStats,
+    pub last_hour: WindowStats,
+    /// 429s since the app started
+    pub rate_limited_count: u64,
+    /// How long to hold off after a recent 429, in seconds. `None` when no
+    /// wait is needed.
+    pub suggested_wait_secs: Option<u64>,
+}
+
+fn token_key(token: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn with_stats<T>(f: impl FnOnce(&mut Stats) -> T) -> T {
+    let mut guard = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = guard.get_or_insert_with(|| Stats {
+        entries: VecDeque::new(),
+        rate_limited_total: HashMap::new(),
+        last_rate_limit: HashMap::new(),
+    });
+    let now = Instant::now();
+    while stats
+        .entries
+        .front()
+        .is_some_and(|entry| now.duration_since(entry.at) > WINDOW)
+    {
+        stats.entries.pop_front();
+    }
+    f(stats)
+}
+
+/// Records one request made with `token`. `retry_after` is NAI's
+/// `Retry-After`, if a 429 came with one.
+pub fn record(token: &str, outcome: Outcome, retry_after: Option<Duration>) {
+    let token = token_key(token);
+    with_stats(|stats| {
+        if stats.entries.len() >= MAX_ENTRIES {
+            stats.entries.pop_front();
+        }
+        let now = Instant::now();
+        stats.entries.push_back(Entry {
+            at: now,
+            token,
+            outcome,
+        });
+        if outcome == Outcome::RateLimited {
+            *stats.rate_limited_total.entry(token).or_default() += 1;
+            stats.last_rate_limit.insert(token, (now, retry_after));
+        }
+    });
+}
+
+/// Counts for `token`, or for all tokens together when `None`.
+pub fn get(token: Option<&str>) -> RequestStats {
+    let token = token.map(token_key);
+    with_stats(|stats| {
+        let now = Instant::now();
+        let mut last_minute = WindowStats::default();
+        let mut last_hour = WindowStats::default();
+        let mut recent_429s = 0u32;
+        for entry in stats
+            .entries
+            .iter()
+            .filter(|entry| token.map_or(true, |t| entry.token == t))
+        {
+            let recent = now.duration_since(entry.at) <= MINUTE;
+            for window in [Some(&mut last_hour), recent.then_some(&mut last_minute)]
+                .into_iter()
+                .flatten()
+            {
+                window.total += 1;
+                match entry.outcome {
+                    Outcome::Success => window.success += 1,
+                    Outcome::RateLimited => window.rate_limited += 1,
+                    Outcome::Failed => window.failed += 1,
+                }
+            }
+            if recent && entry.outcome == Outcome::RateLimited {
+                recent_429s += 1;
+            }
+        }
+
+        let rate_limited_count = stats
+            .rate_limited_total
+            .iter()
+            .filter(|(key, _)| token.map_or(true, |t| **key == t))
+            .map(|(_, count)| count)
+            .sum();
+        let last = stats
+            .last_rate_limit
+            .iter()
+            .filter(|(key, _)| token.map_or(true, |t| **key == t))
+            .map(|(_, last)| *last)
+            .max_by_key(|(at, _)| *at);
+        let suggested_wait_secs = last.and_then(|(at, retry_after)| {
+            let wait = retry_after.unwrap_or_else(|| {
+                (BASE_WAIT * 2u32.pow(recent_429s.saturating_sub(1).min(8))).min(MAX_WAIT)
+            });
+            let remaining = wait.checked_sub(now.duration_since(at))?;
+            // Round up so a pending wait never shows as 0
+            Some(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0))
+        });
+
+        RequestStats {
+            last_minute,
+            last_hour,
+            rate_limited_count,
+            suggested_wait_secs,
+        }
+    })
+}
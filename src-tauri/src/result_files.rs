@@ -0,0 +1,88 @@
+//! Results returned as file paths instead of base64 (`return_mode: "file"`).
+//!
+//! Large images are expensive to push through IPC as base64, so they can be
+//! written to `results/` in the app cache folder instead and loaded by the
+//! frontend through `convertFileSrc`. The asset protocol scope in
+//! `tauri.conf.json` covers exactly that folder. Only the newest
+//! `MAX_FILES` are kept, and the folder is emptied when the app exits.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::{AppHandle, Manager};
+
+const DIR_NAME: &str = "results";
+const MAX_FILES: usize = 200;
+
+static NEXT_FILE: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnMode {
+    #[default]
+    Base64,
+    File,
+}
+
+impl ReturnMode {
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value.map(|v| v.trim().to_lowercase()).as_deref() {
+            None | Some("" | "base64") => Ok(ReturnMode::Base64),
+            Some("file") => Ok(ReturnMode::File),
+            Some(other) => Err(format!("알 수 없는 반환 방식: {}", other)),
+        }
+    }
+}
+
+pub fn dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let cache = app.path().app_cache_dir().map_err(|e| e.to_string())?;
+    Ok(cache.join(DIR_NAME))
+}
+
+/// Deletes the oldest files so that one more still fits under `MAX_FILES`.
+fn prune(dir: &std::path::Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(std::time::SystemTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((meta.modified().ok()?, entry.path()))
+        })
+        .collect();
+    if files.len() < MAX_FILES {
+        return;
+    }
+    files.sort();
+    for (_, path) in &files[..=files.len() - MAX_FILES] {
+        if let Err(e) = std::fs::remove_file(path) {
+            log::warn!("Failed to remove old result {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// A fresh path for the next result, making room for it first.
+pub fn next_path(app: &AppHandle) -> Result<String, String> {
+    let dir = dir(app)?;
+    prune(&dir);
+    let name = format!(
+        "{}_{}.png",
+        chrono::Local::now().format("%Y%m%d_%H%M%S_%3f"),
+        NEXT_FILE.fetch_add(1, Ordering::SeqCst)
+    );
+    Ok(dir.join(name).to_string_lossy().to_string())
+}
+
+/// Removes every result file. Called on exit; paths handed out before are
+/// invalid afterwards.
+pub fn clear(app: &AppHandle) {
+    let Ok(dir) = dir(app) else {
+        return;
+    };
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Failed to clear {}: {}", dir.display(), e);
+        }
+    }
+}
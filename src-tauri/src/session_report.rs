@@ -0,0 +1,168 @@
+//! Summary of what was generated since the app started, for keeping track
+//! of a day's work or pasting into a GitHub issue.
+//!
+//! Generation commands record each request here; 429s come from
+//! `request_stats`. Everything is in memory, and nothing that identifies the
+//! user (tokens, prompts, paths) is kept.
+
+use crate::api_error::NaiErrorKind;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Session {
+    started_at: Option<chrono::DateTime<chrono::Local>>,
+    requests: u64,
+    succeeded: u64,
+    failed: u64,
+    anlas: u64,
+    /// Time spent on requests that produced images
+    succeeded_time: Duration,
+    succeeded_requests: u64,
+    models: BTreeMap<String, u64>,
+    failures: BTreeMap<String, u64>,
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// One request to NAI and what came of it.
+pub struct Generation<'a> {
+    pub model: &'a str,
+    /// Images returned
+    pub succeeded: u32,
+    /// Images that failed, or 1 for a request that failed as a whole
+    pub failed: u32,
+    pub error_code: Option<NaiErrorKind>,
+    pub duration: Duration,
+    /// Estimated cost, 0 when nothing was charged
+    pub anlas: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    pub app_version: String,
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// When the first request was recorded, RFC 3339
+    pub started_at: Option<String>,
+    pub generated_at: String,
+    pub requests: u64,
+    /// Images, successful and failed
+    pub total: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub estimated_anlas: u64,
+    pub rate_limited: u64,
+    /// Per successful request
+    pub average_duration_ms: Option<u64>,
+    /// Requests per model
+    pub models: BTreeMap<String, u64>,
+    /// Failed requests per error kind
+    pub failures: BTreeMap<String, u64>,
+}
+
+fn with_session<T>(f: impl FnOnce(&mut Session) -> T) -> T {
+    let mut guard = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    f(guard.get_or_insert_with(Session::default))
+}
+
+pub fn record(generation: Generation) {
+    with_session(|session| {
+        session.started_at.get_or_insert_with(chrono::Local::now);
+        session.requests += 1;
+        session.succeeded += u64::from(generation.succeeded);
+        session.failed += u64::from(generation.failed);
+        session.anlas += u64::from(generation.anlas);
+        if generation.succeeded > 0 {
+            session.succeeded_time += generation.duration;
+            session.succeeded_requests += 1;
+        }
+        *session
+            .models
+            .entry(generation.model.to_string())
+            .or_default() += 1;
+        if generation.failed > 0 {
+            let kind = generation
+                .error_code
+                .and_then(|code| serde_json::to_value(code).ok())
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_else(|| "other".to_string());
+            *session.failures.entry(kind).or_default() += 1;
+        }
+    })
+}
+
+pub fn report(app_version: String, rate_limited: u64) -> SessionReport {
+    with_session(|session| SessionReport {
+        app_version,
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        started_at: session.started_at.map(|at| at.to_rfc3339()),
+        generated_at: chrono::Local::now().to_rfc3339(),
+        requests: session.requests,
+        total: session.succeeded + session.failed,
+        succeeded: session.succeeded,
+        failed: session.failed,
+        estimated_anlas: session.anlas,
+        rate_limited,
+        average_duration_ms: (session.succeeded_requests > 0).then(|| {
+            (session.succeeded_time.as_millis() / u128::from(session.succeeded_requests)) as u64
+        }),
+        models: session.models.clone(),
+        failures: session.failures.clone(),
+    })
+}
+
+pub fn to_markdown(report: &SessionReport) -> String {
+    let mut out = String::new();
+    // Writing to a String can't fail
+    let _ = writeln!(out, "## NAIS2 세션 리포트\n");
+    let _ = writeln!(
+        out,
+        "- 앱 버전: {} ({} {})",
+        report.app_version, report.os, report.arch
+    );
+    let _ = writeln!(
+        out,
+        "- 세션 시작: {}",
+        report.started_at.as_deref().unwrap_or("-")
+    );
+    let _ = writeln!(out, "- 리포트 생성: {}\n", report.generated_at);
+
+    let average = report
+        .average_duration_ms
+        .map_or("-".to_string(), |ms| format!("{:.1}초", ms as f64 / 1000.0));
+    let _ = writeln!(out, "| 항목 | 값 |\n|---|---|");
+    for (label, value) in [
+        ("요청 수", report.requests.to_string()),
+        ("총 생성", report.total.to_string()),
+        ("성공", report.succeeded.to_string()),
+        ("실패", report.failed.to_string()),
+        ("소비 추정 Anlas", report.estimated_anlas.to_string()),
+        ("429 (요청 과다)", report.rate_limited.to_string()),
+        ("평균 생성 시간", average),
+    ] {
+        let _ = writeln!(out, "| {} | {} |", label, value);
+    }
+
+    for (title, column, counts) in [
+        ("모델", "요청 수", &report.models),
+        ("실패 원인", "횟수", &report.failures),
+    ] {
+        if counts.is_empty() {
+            continue;
+        }
+        let _ = writeln!(
+            out,
+            "\n### {}\n\n| {} | {} |\n|---|---|",
+            title, title, column
+        );
+        for (name, count) in counts {
+            let _ = writeln!(out, "| `{}` | {} |", name, count);
+        }
+    }
+    out
+}
@@ -0,0 +1,192 @@
+//! Versioned layout of the Tauri store files.
+//!
+//! `settings.json` holds `schema_version`. On startup the stores are read
+//! into plain JSON maps, every migration from the stored version up to
+//! `CURRENT_VERSION` is applied in order, and the result is written back.
+//! A version newer than this build knows is left alone. Stores from before
+//! versioning count as version 1; a fresh install starts at the current one.
+//!
+//! A migration only ever sees these maps, so adding one is a matter of a
+//! function and an entry in `MIGRATIONS`.
+
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+pub const VERSION_KEY: &str = "schema_version";
+/// The file holding `VERSION_KEY`
+pub const VERSION_FILE: &str = "settings.json";
+/// Every store file that is versioned, and backed up before a migration
+pub const FILES: [&str; 3] = [VERSION_FILE, "webview-settings.json", "prompt-presets.json"];
+
+/// Contents of the store files by name. Files that don't exist are missing.
+pub type Stores = BTreeMap<String, Map<String, Value>>;
+
+/// A description of what it does, and the function doing it.
+type Migration = (&'static str, fn(&mut Stores));
+
+/// `MIGRATIONS[i]` takes the stores from version `i + 1` to `i + 2`.
+const MIGRATIONS: &[Migration] = &[];
+
+pub const CURRENT_VERSION: u32 = 1 + MIGRATIONS.len() as u32;
+
+/// The version `stores` are at.
+pub fn stored_version(stores: &Stores) -> u32 {
+    version_at(stores, CURRENT_VERSION)
+}
+
+/// The version `stores` are at, for a build whose newest is `current`.
+fn version_at(stores: &Stores, current: u32) -> u32 {
+    let version = stores
+        .get(VERSION_FILE)
+        .and_then(|settings| settings.get(VERSION_KEY))
+        .and_then(Value::as_u64);
+    match version {
+        Some(version) => version.min(u32::MAX as u64) as u32,
+        None if stores.values().all(Map::is_empty) => current,
+        None => 1,
+    }
+}
+
+/// The stored version, or an error for one from a newer build, which must
+/// not be touched.
+fn checked_version(stores: &Stores, current: u32) -> Result<u32, String> {
+    let version = version_at(stores, current);
+    if version > current {
+        return Err(format!(
+            "설정 스키마 버전 {}은(는) 이 버전의 앱({})보다 새롭습니다. 설정을 변경하지 않았습니다",
+            version, current
+        ));
+    }
+    Ok(version)
+}
+
+/// Whether `migrate` has anything to do. Errors for a version from a newer
+/// build.
+pub fn needs_migration(stores: &Stores) -> Result<bool, String> {
+    let version = checked_version(stores, CURRENT_VERSION)?;
+    Ok(version < CURRENT_VERSION
+        || stores
+            .get(VERSION_FILE)
+            .map_or(true, |settings| !settings.contains_key(VERSION_KEY)))
+}
+
+/// Brings `stores` up to `CURRENT_VERSION` and records it. Returns what was
+/// done, in order.
+pub fn migrate(stores: &mut Stores) -> Result<Vec<&'static str>, String> {
+    apply_migrations(stores, MIGRATIONS)
+}
+
+/// `migrate` with the given migrations in place of `MIGRATIONS`.
+fn apply_migrations(
+    stores: &mut Stores,
+    migrations: &[Migration],
+) -> Result<Vec<&'static str>, String> {
+    let current = 1 + migrations.len() as u32;
+    let version = checked_version(stores, current)?;
+    let mut applied = Vec::new();
+    for (description, apply) in migrations.iter().skip(version.saturating_sub(1) as usize) {
+        apply(stores);
+        applied.push(*description);
+    }
+    stores
+        .entry(VERSION_FILE.to_string())
+        .or_default()
+        .insert(VERSION_KEY.into(), Value::from(current));
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stores(files: &[(&str, Value)]) -> Stores {
+        files
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.as_object().unwrap().clone()))
+            .collect()
+    }
+
+    fn version_of(stores: &Stores) -> Option<&Value> {
+        stores[VERSION_FILE].get(VERSION_KEY)
+    }
+
+    fn mark(stores: &mut Stores, step: &str) {
+        let settings = stores.entry(VERSION_FILE.to_string()).or_default();
+        let steps = settings.entry("steps").or_insert_with(|| json!([]));
+        steps.as_array_mut().unwrap().push(json!(step));
+    }
+
+    const STEPS: &[Migration] = &[
+        ("to 2", |stores| mark(stores, "2")),
+        ("to 3", |stores| mark(stores, "3")),
+        ("to 4", |stores| mark(stores, "4")),
+    ];
+
+    #[test]
+    fn fresh_install_starts_at_the_current_version() {
+        let mut fresh = Stores::new();
+        assert_eq!(stored_version(&fresh), CURRENT_VERSION);
+        assert!(needs_migration(&fresh).unwrap());
+        assert!(migrate(&mut fresh).unwrap().is_empty());
+        assert_eq!(version_of(&fresh), Some(&json!(CURRENT_VERSION)));
+        assert!(!needs_migration(&fresh).unwrap());
+
+        let mut fresh = stores(&[(VERSION_FILE, json!({}))]);
+        assert!(apply_migrations(&mut fresh, STEPS).unwrap().is_empty());
+        assert_eq!(version_of(&fresh), Some(&json!(4)));
+    }
+
+    #[test]
+    fn unversioned_stores_are_version_one() {
+        let mut old = stores(&[
+            (VERSION_FILE, json!({})),
+            ("prompt-presets.json", json!({ "presets": [] })),
+        ]);
+        assert_eq!(stored_version(&old), 1);
+        assert!(needs_migration(&old).unwrap());
+        migrate(&mut old).unwrap();
+        assert_eq!(version_of(&old), Some(&json!(CURRENT_VERSION)));
+        assert_eq!(old["prompt-presets.json"]["presets"], json!([]));
+
+        let mut old = stores(&[("webview-settings.json", json!({ "zoom": 1 }))]);
+        assert_eq!(
+            apply_migrations(&mut old, STEPS).unwrap(),
+            ["to 2", "to 3", "to 4"]
+        );
+        assert_eq!(old[VERSION_FILE]["steps"], json!(["2", "3", "4"]));
+    }
+
+    #[test]
+    fn migrations_run_from_the_stored_version() {
+        let mut at_3 = stores(&[(VERSION_FILE, json!({ VERSION_KEY: 3 }))]);
+        assert_eq!(apply_migrations(&mut at_3, STEPS).unwrap(), ["to 4"]);
+        assert_eq!(at_3[VERSION_FILE]["steps"], json!(["4"]));
+        assert_eq!(version_of(&at_3), Some(&json!(4)));
+
+        let mut current = stores(&[(VERSION_FILE, json!({ VERSION_KEY: 4 }))]);
+        assert!(apply_migrations(&mut current, STEPS).unwrap().is_empty());
+        assert!(current[VERSION_FILE].get("steps").is_none());
+    }
+
+    #[test]
+    fn newer_versions_are_left_alone() {
+        let newer = stores(&[
+            (
+                VERSION_FILE,
+                json!({ VERSION_KEY: CURRENT_VERSION + 1, "theme": "dark" }),
+            ),
+            ("prompt-presets.json", json!({ "presets": [1] })),
+        ]);
+        let mut stores = newer.clone();
+        assert!(needs_migration(&stores).is_err());
+        assert!(migrate(&mut stores).is_err());
+        assert_eq!(stores, newer);
+
+        let mut stores = newer.clone();
+        stores.get_mut(VERSION_FILE).unwrap()[VERSION_KEY] = json!(5);
+        let before = stores.clone();
+        assert!(apply_migrations(&mut stores, STEPS).is_err());
+        assert_eq!(stores, before);
+    }
+}
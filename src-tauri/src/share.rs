@@ -0,0 +1,228 @@
+//! Shrinking images to fit upload limits (Discord's 8 MB and the like).
+//!
+//! The image is first scaled to fit `max_dimension`. Opaque images become
+//! JPEG: the best quality that fits is found by binary search between
+//! `MIN_QUALITY` and `MAX_QUALITY`, and only when even the lowest doesn't
+//! fit is the image scaled down further. Images with real transparency
+//! become lossless WebP, which only gets smaller by scaling. When nothing
+//! fits, the smallest attempt is returned with a warning.
+
+use crate::metadata;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageEncoder};
+use serde::Serialize;
+
+const MAX_QUALITY: u8 = 92;
+const MIN_QUALITY: u8 = 50;
+/// Each further downscale keeps this share of the width and height
+const SCALE_STEP: f64 = 0.8;
+const MAX_SCALE_STEPS: usize = 10;
+const MIN_SIDE: u32 = 64;
+
+#[derive(Debug, Serialize)]
+pub struct SharedImage {
+    /// Base64 of the result
+    pub image_data: String,
+    /// `jpeg` or `webp`
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub size: usize,
+    /// JPEG quality used; `None` for lossless WebP
+    pub quality: Option<u8>,
+    /// Set when the result is still over `max_bytes`
+    pub warning: Option<String>,
+}
+
+struct Attempt {
+    bytes: Vec<u8>,
+    width: u32,
+    height: u32,
+    quality: Option<u8>,
+}
+
+/// Whether any pixel is actually see-through. Stealth metadata only touches
+/// the lowest alpha bit, so 254 still counts as opaque.
+fn has_transparency(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().any(|p| p[3] < 254)
+}
+
+fn encode_jpeg(image: &DynamicImage, quality: u8, exif: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let rgb = image.to_rgb8();
+    let mut out = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, quality);
+    if let Some(exif) = exif {
+        encoder
+            .set_exif_metadata(exif.to_vec())
+            .map_err(|e| e.to_string())?;
+    }
+    encoder
+        .write_image(
+            rgb.as_raw(),
+            rgb.width(),
+            rgb.height(),
+            image::ExtendedColorType::Rgb8,
+        )
+        .map_err(|e| format!("JPEG 인코딩 오류: {}", e))?;
+    Ok(out)
+}
+
+fn encode_webp(image: &DynamicImage, exif: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let mut rgba = image.to_rgba8();
+    if exif.is_none() {
+        // Without metadata the stealth bits have to go too
+        for pixel in rgba.pixels_mut() {
+            if pixel[3] >= 254 {
+                pixel[3] = 255;
+            }
+        }
+    }
+    let mut out = Vec::new();
+    let mut encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut out);
+    if let Some(exif) = exif {
+        encoder
+            .set_exif_metadata(exif.to_vec())
+            .map_err(|e| e.to_string())?;
+    }
+    encoder
+        .write_image(
+            rgba.as_raw(),
+            rgba.width(),
+            rgba.height(),
+            image::ExtendedColorType::Rgba8,
+        )
+        .map_err(|e| format!("WebP 인코딩 오류: {}", e))?;
+    Ok(out)
+}
+
+/// The best JPEG of `image` within `max_bytes`, if any quality fits.
+fn best_jpeg(
+    image: &DynamicImage,
+    max_bytes: usize,
+    exif: Option<&[u8]>,
+    smallest: &mut Option<Attempt>,
+) -> Result<Option<Attempt>, String> {
+    let attempt = |quality: u8| -> Result<Attempt, String> {
+        Ok(Attempt {
+            bytes: encode_jpeg(image, quality, exif)?,
+            width: image.width(),
+            height: image.height(),
+            quality: Some(quality),
+        })
+    };
+    let best = attempt(MAX_QUALITY)?;
+    if best.bytes.len() <= max_bytes {
+        return Ok(Some(best));
+    }
+    let worst = attempt(MIN_QUALITY)?;
+    if worst.bytes.len() > max_bytes {
+        if smallest
+            .as_ref()
+            .map_or(true, |s| worst.bytes.len() < s.bytes.len())
+        {
+            *smallest = Some(worst);
+        }
+        return Ok(None);
+    }
+
+    // `fits` always fits, `low..high` is still open
+    let (mut fits, mut low, mut high) = (worst, MIN_QUALITY + 1, MAX_QUALITY - 1);
+    while low <= high {
+        let quality = low + (high - low) / 2;
+        let candidate = attempt(quality)?;
+        if candidate.bytes.len() <= max_bytes {
+            fits = candidate;
+            low = quality + 1;
+        } else {
+            high = quality - 1;
+        }
+    }
+    Ok(Some(fits))
+}
+
+pub fn prepare(
+    bytes: &[u8],
+    max_bytes: usize,
+    max_dimension: u32,
+    keep_metadata: bool,
+) -> Result<SharedImage, String> {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+    if max_bytes == 0 || max_dimension == 0 {
+        return Err("크기 제한은 0보다 커야 합니다".to_string());
+    }
+    let image = image::load_from_memory(bytes).map_err(|e| format!("이미지 디코딩 오류: {}", e))?;
+    let exif = keep_metadata
+        .then(|| metadata::read_embedded_metadata(bytes))
+        .flatten()
+        .filter(|meta| !meta.fields.is_empty())
+        .map(|meta| metadata::build_exif(&meta.fields));
+    let transparent = has_transparency(&image);
+
+    let longest = image.width().max(image.height());
+    let mut scale = (max_dimension as f64 / longest as f64).min(1.0);
+    let mut smallest: Option<Attempt> = None;
+    let mut result = None;
+    for _ in 0..=MAX_SCALE_STEPS {
+        let width = ((image.width() as f64 * scale).round() as u32).max(1);
+        let height = ((image.height() as f64 * scale).round() as u32).max(1);
+        let resized = if (width, height) == (image.width(), image.height()) {
+            image.clone()
+        } else {
+            image.resize_exact(width, height, FilterType::Lanczos3)
+        };
+
+        if transparent {
+            let attempt = Attempt {
+                bytes: encode_webp(&resized, exif.as_deref())?,
+                width,
+                height,
+                quality: None,
+            };
+            if attempt.bytes.len() <= max_bytes {
+                result = Some(attempt);
+                break;
+            }
+            if smallest
+                .as_ref()
+                .map_or(true, |s| attempt.bytes.len() < s.bytes.len())
+            {
+                smallest = Some(attempt);
+            }
+        } else if let Some(attempt) =
+            best_jpeg(&resized, max_bytes, exif.as_deref(), &mut smallest)?
+        {
+            result = Some(attempt);
+            break;
+        }
+
+        if width.min(height) as f64 * SCALE_STEP < MIN_SIDE as f64 {
+            break;
+        }
+        scale *= SCALE_STEP;
+    }
+
+    let (attempt, warning) = match result {
+        Some(attempt) => (attempt, None),
+        None => {
+            let attempt = smallest.ok_or_else(|| "이미지를 인코딩하지 못했습니다".to_string())?;
+            let warning = format!(
+                "{}바이트 이하로 줄이지 못했습니다. 가장 작은 결과({}바이트, {}x{})를 반환합니다",
+                max_bytes,
+                attempt.bytes.len(),
+                attempt.width,
+                attempt.height
+            );
+            (attempt, Some(warning))
+        }
+    };
+    Ok(SharedImage {
+        image_data: STANDARD.encode(&attempt.bytes),
+        format: if transparent { "webp" } else { "jpeg" },
+        width: attempt.width,
+        height: attempt.height,
+        size: attempt.bytes.len(),
+        quality: attempt.quality,
+        warning,
+    })
+}
@@ -0,0 +1,184 @@
+//! Parsing for `generate-image-stream`.
+//!
+//! With `"stream": "sse"` the V4 models answer with server-sent events
+//! instead of a ZIP. Each event carries a JSON object: `intermediate` events
+//! hold a JPEG preview of the current denoising step, and the single `final`
+//! event holds the finished PNG. Both images are already base64.
+
+use serde::Deserialize;
+
+/// Models that support streaming. Older models only have the ZIP endpoint.
+pub fn supports_streaming(model: &str) -> bool {
+    model.contains("diffusion-4")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// Preview after step `step` (1-based)
+    Intermediate {
+        step: u32,
+        image: String,
+    },
+    Final {
+        image: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct RawEvent {
+    event_type: String,
+    #[serde(default)]
+    step_ix: Option<u32>,
+    #[serde(default)]
+    image: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl RawEvent {
+    fn into_event(self) -> Option<StreamEvent> {
+        match self.event_type.as_str() {
+            "intermediate" => Some(StreamEvent::Intermediate {
+                step: self.step_ix.unwrap_or(0) + 1,
+                image: self.image?,
+            }),
+            "final" => Some(StreamEvent::Final { image: self.image? }),
+            "error" => Some(StreamEvent::Error {
+                message: self.message.unwrap_or_default(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the raw byte chunks of the response into events. Chunks can split
+/// an event anywhere, so incomplete data stays buffered until the blank line
+/// that ends it arrives.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    /// How much of `buffer` has already been searched for an event end, so
+    /// a large event arriving in many chunks isn't rescanned every time
+    scanned: usize,
+}
+
+impl SseParser {
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<StreamEvent> {
+        // Line endings may be CRLF; neither JSON nor base64 contains a CR
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+
+        let mut events = Vec::new();
+        let mut start = 0;
+        // The blank line may straddle the previous chunk boundary
+        let mut from = self.scanned.saturating_sub(1);
+        while let Some(offset) = self.buffer[from..].windows(2).position(|w| w == b"\n\n") {
+            let end = from + offset;
+            if let Some(event) = parse_block(&self.buffer[start..end]) {
+                events.push(event);
+            }
+            start = end + 2;
+            from = start;
+        }
+        self.buffer.drain(..start);
+        self.scanned = self.buffer.len();
+        events
+    }
+
+    /// Parses whatever is left once the stream has ended without a trailing
+    /// blank line.
+    pub fn finish(&mut self) -> Option<StreamEvent> {
+        self.scanned = 0;
+        let block = std::mem::take(&mut self.buffer);
+        parse_block(&block)
+    }
+}
+
+fn parse_block(block: &[u8]) -> Option<StreamEvent> {
+    let text = String::from_utf8_lossy(block);
+    let data: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    if data.is_empty() {
+        return None;
+    }
+
+    match serde_json::from_str::<RawEvent>(&data.join("\n")) {
+        Ok(raw) => raw.into_event(),
+        Err(e) => {
+            log::warn!("Unreadable stream event: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREVIEW: &str = "event: intermediate\ndata: {\"event_type\":\"intermediate\",\"step_ix\":0,\"image\":\"AAAA\"}\n\n";
+    const FINAL: &str =
+        "event: final\r\ndata: {\"event_type\":\"final\",\"image\":\"BBBB\"}\r\n\r\n";
+
+    fn feed_in_pieces(input: &str, size: usize) -> Vec<StreamEvent> {
+        let mut parser = SseParser::default();
+        let mut events: Vec<StreamEvent> = input
+            .as_bytes()
+            .chunks(size)
+            .flat_map(|chunk| parser.feed(chunk))
+            .collect();
+        events.extend(parser.finish());
+        events
+    }
+
+    #[test]
+    fn events_split_across_chunks() {
+        let input = format!("{}{}", PREVIEW, FINAL);
+        let expected = vec![
+            StreamEvent::Intermediate {
+                step: 1,
+                image: "AAAA".to_string(),
+            },
+            StreamEvent::Final {
+                image: "BBBB".to_string(),
+            },
+        ];
+        for size in [1, 2, 3, 7, input.len()] {
+            assert_eq!(
+                feed_in_pieces(&input, size),
+                expected,
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn unterminated_event_is_parsed_at_the_end() {
+        let input = FINAL.trim_end();
+        assert_eq!(
+            feed_in_pieces(input, 5),
+            vec![StreamEvent::Final {
+                image: "BBBB".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn error_event() {
+        let events = feed_in_pieces(
+            "data: {\"event_type\":\"error\",\"message\":\"busy\"}\n\n",
+            4,
+        );
+        assert_eq!(
+            events,
+            vec![StreamEvent::Error {
+                message: "busy".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,723 @@
+//! Client for the local WD14 tagger sidecar (`tagger-server`, port 8002).
+//!
+//! `POST /tag?threshold=` takes a multipart form with `file` and answers
+//! `{ "tags": [{ "label": ..., "score": ... }] }` or `{ "error": ... }`.
+//! The sidecar runs one fixed model (SmilingWolf/wd-v1-4-convnext-tagger-v2)
+//! and has no other endpoints.
+//!
+//! The sidecar binary can also be downloaded: a JSON manifest published with
+//! each release lists a download URL and SHA-256 per platform. Only the
+//! manifest at `MANIFEST_URL` is used, and only binaries attached to this
+//! repository's releases are accepted from it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+const TAGGER_URL: &str = "http://127.0.0.1:8002/tag";
+const HEALTH_URL: &str = "http://127.0.0.1:8002/health";
+// The sidecar loads its model on startup, which can take a while
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// The sidecar runs the model on one device, so more parallel requests only
+/// queue up inside it and use more memory.
+pub const MAX_CONCURRENT_TAGS: usize = 2;
+const MULTIPART_BOUNDARY: &str = "----nais2-tagger-boundary";
+
+pub const MANIFEST_URL: &str =
+    "https://github.com/sunanakgo/NAIS2/releases/latest/download/tagger-manifest.json";
+/// Where the binaries a manifest lists must come from
+const RELEASE_DOWNLOAD_PREFIX: &str = "https://github.com/sunanakgo/NAIS2/releases/download/";
+#[cfg(target_os = "windows")]
+pub const BINARY_NAME: &str = "tagger-server.exe";
+#[cfg(not(target_os = "windows"))]
+pub const BINARY_NAME: &str = "tagger-server";
+// Progress is emitted at most once per this many bytes
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub label: String,
+    pub score: f64,
+    /// WD14's category number: 0 general, `CATEGORY_CHARACTER` or
+    /// `CATEGORY_RATING`, when the sidecar sends it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<u8>,
+}
+
+pub const CATEGORY_CHARACTER: u8 = 4;
+pub const CATEGORY_RATING: u8 = 9;
+
+#[derive(Debug, Serialize)]
+pub struct TagResult {
+    pub path: String,
+    pub success: bool,
+    pub tags: Vec<Tag>,
+    pub error: Option<String>,
+    /// Served from the cache without asking the sidecar
+    pub cached: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagProgress {
+    pub current: usize,
+    pub total: usize,
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TagResponse {
+    #[serde(default)]
+    tags: Vec<Tag>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Path, modification time and threshold bits; an edited file or a different
+/// threshold is a cache miss.
+type CacheKey = (String, SystemTime, u64);
+/// Same scheme for `caption_image`, with the mode and model in place of the
+/// threshold.
+type CaptionKey = (String, SystemTime, String);
+/// Prompts kept by `caption_image`; the oldest go first
+const MAX_CAPTION_CACHE: usize = 512;
+
+fn tag_cache() -> &'static Mutex<HashMap<CacheKey, Vec<Tag>>> {
+    static CACHE: OnceLock<Mutex<HashMap<CacheKey, Vec<Tag>>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+#[derive(Default)]
+struct CaptionCache {
+    prompts: HashMap<CaptionKey, String>,
+    order: VecDeque<CaptionKey>,
+}
+
+impl CaptionCache {
+    fn insert(&mut self, key: CaptionKey, prompt: String) {
+        if !self.prompts.contains_key(&key) {
+            if self.order.len() >= MAX_CAPTION_CACHE {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.prompts.remove(&oldest);
+                }
+            }
+            self.order.push_back(key.clone());
+        }
+        self.prompts.insert(key, prompt);
+    }
+}
+
+fn caption_cache() -> &'static Mutex<CaptionCache> {
+    static CACHE: OnceLock<Mutex<CaptionCache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// What the caches know an image by: its path and modification time, or for
+/// an image passed as data, a hash of the bytes.
+fn image_id(path: Option<&str>, bytes: &[u8]) -> Option<(String, SystemTime)> {
+    use sha2::{Digest, Sha256};
+
+    match path {
+        Some(path) => {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+            Some((path.to_string(), modified))
+        }
+        None => Some((
+            format!("sha256:{:x}", Sha256::digest(bytes)),
+            SystemTime::UNIX_EPOCH,
+        )),
+    }
+}
+
+fn cache_key(path: &str, threshold: f64) -> Option<CacheKey> {
+    let (id, modified) = image_id(Some(path), &[])?;
+    Some((id, modified, threshold.to_bits()))
+}
+
+/// reqwest's multipart support needs an extra feature; the forms here are
+/// just one file, so they're written out by hand.
+fn multipart_body(file_name: &str, bytes: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(bytes.len() + 256);
+    body.extend_from_slice(
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{f}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            b = MULTIPART_BOUNDARY,
+            f = file_name.replace('"', "_"),
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY).as_bytes());
+    body
+}
+
+/// `file` as a multipart form for the sidecar. FastAPI reads the endpoints'
+/// other parameters from the query string, not from the form.
+fn form_request(
+    client: &reqwest::Client,
+    url: &str,
+    query: &[(&str, String)],
+    file_name: &str,
+    bytes: &[u8],
+) -> reqwest::RequestBuilder {
+    client
+        .post(url)
+        .query(query)
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY),
+        )
+        .body(multipart_body(file_name, bytes))
+}
+
+async fn post_form(
+    client: &reqwest::Client,
+    url: &str,
+    query: &[(&str, String)],
+    file_name: &str,
+    bytes: &[u8],
+) -> Result<reqwest::Response, String> {
+    form_request(client, url, query, file_name, bytes)
+        .send()
+        .await
+        .map_err(|e| format!("태거 서버 연결 오류: {}", e))
+}
+
+async fn tags_for_bytes(
+    client: &reqwest::Client,
+    file_name: &str,
+    bytes: &[u8],
+    query: &[(&str, String)],
+) -> Result<Vec<Tag>, String> {
+    let response = post_form(client, TAGGER_URL, query, file_name, bytes).await?;
+    if !response.status().is_success() {
+        return Err(format!("태거 서버 오류: {}", response.status()));
+    }
+    let data: TagResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("응답 파싱 오류: {}", e))?;
+    match data.error {
+        Some(error) => Err(format!("태깅 오류: {}", error)),
+        None => Ok(data.tags),
+    }
+}
+
+fn file_name_of(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "image".to_string())
+}
+
+async fn request_tags(
+    client: &reqwest::Client,
+    path: &str,
+    threshold: f64,
+) -> Result<Vec<Tag>, String> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("파일 읽기 오류: {}", e))?;
+    tags_for_bytes(
+        client,
+        &file_name_of(path),
+        &bytes,
+        &[("threshold", threshold.to_string())],
+    )
+    .await
+}
+
+/// Waits for the sidecar to answer `/health`, e.g. right after it was
+/// spawned. Returns `false` on timeout.
+pub async fn wait_until_ready(client: &reqwest::Client) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < STARTUP_TIMEOUT {
+        if client
+            .get(HEALTH_URL)
+            .send()
+            .await
+            .is_ok_and(|r| r.status().is_success())
+        {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    false
+}
+
+/// Tags one file, using the cache when the same file was already tagged at
+/// the same threshold. Failures are reported in the result, never returned.
+pub async fn tag_file(client: &reqwest::Client, path: String, threshold: f64) -> TagResult {
+    let key = cache_key(&path, threshold);
+    if let Some(tags) = key
+        .as_ref()
+        .and_then(|k| tag_cache().lock().ok()?.get(k).cloned())
+    {
+        return TagResult {
+            path,
+            success: true,
+            tags,
+            error: None,
+            cached: true,
+        };
+    }
+
+    match request_tags(client, &path, threshold).await {
+        Ok(tags) => {
+            if let (Some(key), Ok(mut cache)) = (key, tag_cache().lock()) {
+                cache.insert(key, tags.clone());
+            }
+            TagResult {
+                path,
+                success: true,
+                tags,
+                error: None,
+                cached: false,
+            }
+        }
+        Err(e) => TagResult {
+            path,
+            success: false,
+            tags: Vec::new(),
+            error: Some(e),
+            cached: false,
+        },
+    }
+}
+
+/// `{ "version": "1.2.0", "platforms": { "windows-x86_64": { "url": ...,
+/// "sha256": ... } } }`, keyed by `platform_key()`.
+#[derive(Debug, Deserialize)]
+pub struct TaggerManifest {
+    pub version: String,
+    pub platforms: HashMap<String, TaggerRelease>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TaggerRelease {
+    pub url: String,
+    /// Hex SHA-256 of the binary
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgress {
+    pub downloaded: u64,
+    /// `None` when the server didn't send a length
+    pub total: Option<u64>,
+}
+
+/// e.g. `windows-x86_64`, `macos-aarch64`, `linux-x86_64`.
+pub fn platform_key() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Looks up this platform's release in the manifest at `MANIFEST_URL`.
+pub async fn fetch_release(client: &reqwest::Client) -> Result<(String, TaggerRelease), String> {
+    let response = client
+        .get(MANIFEST_URL)
+        .send()
+        .await
+        .map_err(|e| format!("매니페스트 다운로드 오류: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("매니페스트 다운로드 오류: {}", response.status()));
+    }
+    let manifest: TaggerManifest = response
+        .json()
+        .await
+        .map_err(|e| format!("매니페스트 파싱 오류: {}", e))?;
+
+    let platform = platform_key();
+    match manifest.platforms.get(&platform) {
+        Some(release) if !release.url.starts_with(RELEASE_DOWNLOAD_PREFIX) => Err(format!(
+            "매니페스트의 다운로드 주소가 NAIS2 릴리스가 아닙니다: {}",
+            release.url
+        )),
+        Some(release) => Ok((manifest.version, release.clone())),
+        None => Err(format!(
+            "이 플랫폼({})용 태거가 없습니다 (버전 {})",
+            platform, manifest.version
+        )),
+    }
+}
+
+/// Deletes the file when dropped, if it still exists.
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Downloads `release` to a temporary file next to `dest` and only moves it
+/// into place once the SHA-256 matches, so an interrupted or corrupted
+/// download never replaces a working binary. On Unix the file is made
+/// executable.
+pub async fn download_verified(
+    client: &reqwest::Client,
+    release: &TaggerRelease,
+    dest: &Path,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> Result<(), String> {
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncWriteExt;
+
+    let partial = PathBuf::from(format!("{}.download", dest.display()));
+    // Also cleans up when the download is dropped halfway, e.g. on app exit
+    let _cleanup = RemoveOnDrop(partial.clone());
+    let mut response = client
+        .get(&release.url)
+        .send()
+        .await
+        .map_err(|e| format!("태거 다운로드 오류: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("태거 다운로드 오류: {}", response.status()));
+    }
+    let total = response.content_length();
+
+    let mut file = tokio::fs::File::create(&partial).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            format!("설치 폴더에 쓸 권한이 없습니다: {}", partial.display())
+        } else {
+            format!("임시 파일 생성 오류: {}", e)
+        }
+    })?;
+    let mut hasher = Sha256::new();
+    let mut downloaded = 0u64;
+    let mut reported = 0u64;
+    let result: Result<(), String> = async {
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("태거 다운로드 오류: {}", e))?
+        {
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("파일 쓰기 오류: {}", e))?;
+            downloaded += chunk.len() as u64;
+            if downloaded - reported >= PROGRESS_STEP {
+                reported = downloaded;
+                on_progress(DownloadProgress { downloaded, total });
+            }
+        }
+        file.flush()
+            .await
+            .map_err(|e| format!("파일 쓰기 오류: {}", e))?;
+        on_progress(DownloadProgress { downloaded, total });
+
+        let actual = format!("{:x}", hasher.finalize());
+        if !actual.eq_ignore_ascii_case(release.sha256.trim()) {
+            return Err(format!(
+                "체크섬 불일치: 예상 {}, 실제 {}",
+                release.sha256.trim(),
+                actual
+            ));
+        }
+        Ok(())
+    }
+    .await;
+    drop(file);
+
+    result?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))
+            .await
+            .map_err(|e| format!("실행 권한 설정 오류: {}", e))?;
+    }
+    tokio::fs::rename(&partial, dest)
+        .await
+        .map_err(|e| format!("태거 설치 오류: {}", e))
+}
+
+// WD14's rating labels; they describe the image, not what to draw
+const RATING_LABELS: [&str; 4] = ["general", "sensitive", "questionable", "explicit"];
+// Emoticon tags whose underscores are part of the face
+pub const KAOMOJI: [&str; 10] = [
+    "^_^", ">_<", "0_0", "o_o", "x_x", "u_u", "=_=", "._.", "@_@", "<o>_<o>",
+];
+
+#[derive(Debug, Clone)]
+pub struct PromptOptions {
+    pub threshold: f64,
+    pub underscore_to_space: bool,
+    /// Writes `(` as `\(` so `normalize_prompt_weights` doesn't read it
+    /// as a weight
+    pub escape_parens: bool,
+    /// Character tags before general ones
+    pub character_first: bool,
+    pub dedupe: bool,
+    /// Tags left out, compared ignoring case and `_` vs space
+    pub exclude: Vec<String>,
+}
+
+fn tag_key(label: &str) -> String {
+    label.trim().to_lowercase().replace('_', " ")
+}
+
+fn is_rating(tag: &Tag) -> bool {
+    match tag.category {
+        Some(category) => category == CATEGORY_RATING,
+        None => RATING_LABELS.contains(&tag.label.as_str()),
+    }
+}
+
+/// Tags at or above the threshold as a comma-separated prompt, highest
+/// score first (within each category when `character_first` is set).
+/// Rating tags are always left out.
+pub fn tags_to_prompt(tags: &[Tag], options: &PromptOptions) -> String {
+    let excluded: Vec<String> = options.exclude.iter().map(|t| tag_key(t)).collect();
+    let mut picked: Vec<&Tag> = tags
+        .iter()
+        .filter(|t| t.score >= options.threshold && !is_rating(t))
+        .filter(|t| !excluded.contains(&tag_key(&t.label)))
+        .collect();
+    picked.sort_by(|a, b| {
+        let character_rank =
+            |t: &Tag| options.character_first && t.category != Some(CATEGORY_CHARACTER);
+        character_rank(a)
+            .cmp(&character_rank(b))
+            .then(b.score.total_cmp(&a.score))
+    });
+
+    let mut seen = Vec::new();
+    let mut parts = Vec::with_capacity(picked.len());
+    for tag in picked {
+        if options.dedupe {
+            let key = tag_key(&tag.label);
+            if seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+        }
+        let mut text = tag.label.trim().to_string();
+        if options.underscore_to_space && !KAOMOJI.contains(&text.as_str()) {
+            text = text.replace('_', " ");
+        }
+        if options.escape_parens {
+            text = text.replace('(', "\\(").replace(')', "\\)");
+        }
+        parts.push(text);
+    }
+    parts.join(", ")
+}
+
+/// Score cutoff for `CaptionMode::Tags`
+const CAPTION_TAG_THRESHOLD: f64 = 0.35;
+/// The one model the sidecar runs
+pub const SIDECAR_MODEL: &str = "SmilingWolf/wd-v1-4-convnext-tagger-v2";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionMode {
+    /// Danbooru tags from `/tag`, joined into a prompt
+    Tags,
+    /// A natural-language caption, which the bundled sidecar can't write
+    Caption,
+}
+
+impl CaptionMode {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "tags" | "tag" | "danbooru" => Ok(CaptionMode::Tags),
+            "caption" | "natural" | "natural_language" => Ok(CaptionMode::Caption),
+            _ => Err(format!("알 수 없는 캡션 모드: {}", value)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            CaptionMode::Tags => "tags",
+            CaptionMode::Caption => "caption",
+        }
+    }
+}
+
+/// Refuses what the sidecar can't do: `Caption` mode, and a `model` other
+/// than `SIDECAR_MODEL` (its full name, the name without `SmilingWolf/`, or
+/// empty for the default).
+pub fn check_caption_request(mode: CaptionMode, model: &str) -> Result<(), String> {
+    if mode == CaptionMode::Caption {
+        return Err(
+            "이 태거 서버는 캡션 모드를 지원하지 않습니다. 태그 모드를 사용하세요".to_string(),
+        );
+    }
+    let model = model.trim();
+    let short = SIDECAR_MODEL.rsplit('/').next().unwrap_or(SIDECAR_MODEL);
+    if model.is_empty()
+        || model.eq_ignore_ascii_case(SIDECAR_MODEL)
+        || model.eq_ignore_ascii_case(short)
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "이 태거 서버는 {} 모델만 지원합니다 (요청: {})",
+            SIDECAR_MODEL, model
+        ))
+    }
+}
+
+/// A prompt for the image: its tags joined the way `tags_to_prompt` does.
+/// Requests `check_caption_request` refuses fail before anything is sent. `path` is the file the bytes came from, if any;
+/// results are cached per file (or image data), mode and model.
+pub async fn caption_image(
+    client: &reqwest::Client,
+    path: Option<&str>,
+    bytes: &[u8],
+    mode: CaptionMode,
+    model: &str,
+) -> Result<String, String> {
+    check_caption_request(mode, model)?;
+
+    let key = image_id(path, bytes)
+        .map(|(id, modified)| (id, modified, format!("{}:{}", mode.name(), model.trim())));
+    if let Some(prompt) = key
+        .as_ref()
+        .and_then(|k| caption_cache().lock().ok()?.prompts.get(k).cloned())
+    {
+        return Ok(prompt);
+    }
+
+    let file_name = path
+        .map(file_name_of)
+        .unwrap_or_else(|| "image".to_string());
+    let query = [("threshold", CAPTION_TAG_THRESHOLD.to_string())];
+    let tags = tags_for_bytes(client, &file_name, bytes, &query).await?;
+    let options = PromptOptions {
+        threshold: CAPTION_TAG_THRESHOLD,
+        underscore_to_space: true,
+        escape_parens: false,
+        character_first: true,
+        dedupe: true,
+        exclude: Vec::new(),
+    };
+    let prompt = tags_to_prompt(&tags, &options);
+
+    if let (Some(key), Ok(mut cache)) = (key, caption_cache().lock()) {
+        cache.insert(key, prompt.clone());
+    }
+    Ok(prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A `/tag` response as `tagger_server.py` sends it
+    const SIDECAR_RESPONSE: &str = r#"{"tags": [
+        {"label": "1girl", "score": 0.9912, "category": 0},
+        {"label": "general", "score": 0.8731, "category": 9},
+        {"label": "hatsune_miku", "score": 0.9524, "category": 4},
+        {"label": "long_hair", "score": 0.8815, "category": 0},
+        {"label": "^_^", "score": 0.5012, "category": 0}
+    ]}"#;
+
+    fn options() -> PromptOptions {
+        PromptOptions {
+            threshold: 0.35,
+            underscore_to_space: true,
+            escape_parens: false,
+            character_first: true,
+            dedupe: true,
+            exclude: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_sidecar_response() {
+        let response: TagResponse = serde_json::from_str(SIDECAR_RESPONSE).unwrap();
+        assert!(response.error.is_none());
+        assert_eq!(response.tags.len(), 5);
+        assert_eq!(response.tags[1].category, Some(CATEGORY_RATING));
+        assert_eq!(response.tags[2].category, Some(CATEGORY_CHARACTER));
+    }
+
+    #[test]
+    fn character_tags_come_first_and_ratings_are_dropped() {
+        let response: TagResponse = serde_json::from_str(SIDECAR_RESPONSE).unwrap();
+        assert_eq!(
+            tags_to_prompt(&response.tags, &options()),
+            "hatsune miku, 1girl, long hair, ^_^"
+        );
+    }
+
+    #[test]
+    fn ratings_are_recognized_by_label_without_category() {
+        let tags = vec![
+            Tag {
+                label: "sensitive".to_string(),
+                score: 0.9,
+                category: None,
+            },
+            Tag {
+                label: "smile".to_string(),
+                score: 0.8,
+                category: None,
+            },
+        ];
+        assert_eq!(tags_to_prompt(&tags, &options()), "smile");
+    }
+
+    #[test]
+    fn threshold_is_sent_in_the_query() {
+        let request = form_request(
+            &reqwest::Client::new(),
+            TAGGER_URL,
+            &[("threshold", "0.5".to_string())],
+            "a.png",
+            b"png",
+        )
+        .build()
+        .unwrap();
+        assert_eq!(request.url().query(), Some("threshold=0.5"));
+
+        let body = request.body().and_then(|b| b.as_bytes()).unwrap();
+        let body = String::from_utf8_lossy(body);
+        assert!(body.contains("name=\"file\"; filename=\"a.png\""));
+        assert!(!body.contains("threshold"));
+    }
+
+    #[test]
+    fn caption_cache_drops_the_oldest() {
+        let key = |n: usize| {
+            (
+                format!("image{}", n),
+                SystemTime::UNIX_EPOCH,
+                "tags:".to_string(),
+            )
+        };
+        let mut cache = CaptionCache::default();
+        for n in 0..MAX_CAPTION_CACHE + 10 {
+            cache.insert(key(n), n.to_string());
+        }
+        assert_eq!(cache.prompts.len(), MAX_CAPTION_CACHE);
+        assert!(!cache.prompts.contains_key(&key(9)));
+        assert_eq!(cache.prompts.get(&key(10)).map(String::as_str), Some("10"));
+
+        // Updating an entry doesn't count twice
+        cache.insert(key(10), "new".to_string());
+        assert_eq!(cache.order.len(), MAX_CAPTION_CACHE);
+    }
+
+    #[test]
+    fn caption_mode_and_model() {
+        assert_eq!(CaptionMode::parse("Tags").unwrap(), CaptionMode::Tags);
+        assert_eq!(CaptionMode::parse("natural").unwrap(), CaptionMode::Caption);
+        assert!(CaptionMode::parse("poem").is_err());
+
+        for model in ["", SIDECAR_MODEL, "wd-v1-4-convnext-tagger-v2"] {
+            assert!(
+                check_caption_request(CaptionMode::Tags, model).is_ok(),
+                "{}",
+                model
+            );
+        }
+        assert!(check_caption_request(CaptionMode::Tags, "wd-eva02-large-tagger-v3").is_err());
+        assert!(check_caption_request(CaptionMode::Caption, "").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Official Undesired Content presets, per model family.
+//!
+//! The generate-image API does not expand `ucPreset` by itself; NovelAI's own
+//! site prepends the preset text to the user's negative prompt before sending
+//! it. This table is the single copy of those strings, so every caller gets
+//! exactly the text the site would send. The order of each list is the
+//! `ucPreset` index.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct UcPreset {
+    pub name: &'static str,
+    pub text: &'static str,
+}
+
+const fn preset(name: &'static str, text: &'static str) -> UcPreset {
+    UcPreset { name, text }
+}
+
+const NONE: UcPreset = preset("None", "");
+
+const V3: &[UcPreset] = &[
+    preset(
+        "Heavy",
+        "lowres, {bad}, error, fewer, extra, missing, worst quality, jpeg artifacts, bad quality, watermark, unfinished, displeasing, chromatic aberration, signature, extra digits, artistic error, username, scan, [abstract]",
+    ),
+    preset(
+        "Light",
+        "lowres, jpeg artifacts, worst quality, watermark, blurry, very displeasing",
+    ),
+    preset(
+        "Human Focus",
+        "lowres, {bad}, error, fewer, extra, missing, worst quality, jpeg artifacts, bad quality, watermark, unfinished, displeasing, chromatic aberration, signature, extra digits, artistic error, username, scan, [abstract], bad anatomy, bad hands, @_@, mismatched pupils, heart-shaped pupils, glowing eyes",
+    ),
+    NONE,
+];
+
+const FURRY_V3: &[UcPreset] = &[
+    preset(
+        "Heavy",
+        "{{worst quality}}, [displeasing], {unusual pupils}, guide lines, {{unfinished}}, {bad}, url, artist name, {{tall image}}, mosaic, {sketch page}, comic panel, impact (font), [dated], {logo}, ych, {what}, {where is your god now}, {distorted text}, repeated text, {floating head}, {1994}, {widescreen}, absolutely everyone, sequence, {compression artifacts}, hard translated, {cropped}, {commissioner name}, unknown text, high contrast",
+    ),
+    preset(
+        "Light",
+        "{worst quality}, guide lines, unfinished, bad, url, tall image, widescreen, compression artifacts, unknown text",
+    ),
+    NONE,
+];
+
+const V4_CURATED: &[UcPreset] = &[
+    preset(
+        "Heavy",
+        "blurry, lowres, error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, logo, dated, signature, multiple views, gigantic breasts, white blank page, blank page",
+    ),
+    preset(
+        "Light",
+        "blurry, lowres, error, worst quality, bad quality, jpeg artifacts, very displeasing, logo, dated, signature, white blank page, blank page",
+    ),
+    NONE,
+];
+
+const V4_FULL: &[UcPreset] = &[
+    preset(
+        "Heavy",
+        "blurry, lowres, error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, multiple views, logo, too many watermarks, white blank page, blank page",
+    ),
+    preset(
+        "Light",
+        "blurry, lowres, error, worst quality, bad quality, jpeg artifacts, very displeasing, white blank page, blank page",
+    ),
+    NONE,
+];
+
+const V45_CURATED: &[UcPreset] = &[
+    preset(
+        "Heavy",
+        "blurry, lowres, upscaled, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, halftone, multiple views, logo, too many watermarks, negative space, blank page",
+    ),
+    preset(
+        "Light",
+        "blurry, lowres, upscaled, artistic error, scan artifacts, jpeg artifacts, logo, too many watermarks, negative space, blank page",
+    ),
+    preset(
+        "Human Focus",
+        "blurry, lowres, upscaled, artistic error, film grain, scan artifacts, bad anatomy, bad hands, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, halftone, multiple views, logo, too many watermarks, @_@, mismatched pupils, glowing eyes, negative space, blank page",
+    ),
+    NONE,
+];
+
+const V45_FULL: &[UcPreset] = &[
+    preset(
+        "Heavy",
+        "nsfw, lowres, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, dithering, halftone, screentone, multiple views, logo, too many watermarks, negative space, blank page",
+    ),
+    preset(
+        "Light",
+        "nsfw, lowres, artistic error, scan artifacts, worst quality, bad quality, jpeg artifacts, multiple views, very displeasing, too many watermarks, negative space, blank page",
+    ),
+    preset(
+        "Furry Focus",
+        "nsfw, {worst quality}, distracting watermark, unfinished, bad quality, {widescreen}, upscale, {sequence}, {{grandfathered content}}, blurred foreground, chromatic aberration, sketch, everyone, [sketch background], simple, [flat colors], ych (character), outline, multiple scenes, [[horror (theme)]], comic",
+    ),
+    preset(
+        "Human Focus",
+        "nsfw, lowres, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, dithering, halftone, screentone, multiple views, logo, too many watermarks, negative space, blank page, @_@, mismatched pupils, glowing eyes, bad anatomy",
+    ),
+    NONE,
+];
+
+/// Presets available for `model`, in `ucPreset` index order. Inpainting
+/// variants share the presets of their base model.
+pub fn presets_for_model(model: &str) -> &'static [UcPreset] {
+    if model.contains("diffusion-4-5-full") {
+        V45_FULL
+    } else if model.contains("diffusion-4-5-curated") {
+        V45_CURATED
+    } else if model.contains("diffusion-4-full") {
+        V4_FULL
+    } else if model.contains("diffusion-4-curated") {
+        V4_CURATED
+    } else if model.contains("furry-3") {
+        FURRY_V3
+    } else if model.contains("diffusion-3") {
+        V3
+    } else {
+        &[]
+    }
+}
+
+/// Preset text for `preset_id`. "None", unknown models and out-of-range
+/// indices all resolve to an empty string.
+pub fn resolve_uc_preset(model: &str, preset_id: u32) -> String {
+    presets_for_model(model)
+        .get(preset_id as usize)
+        .map(|p| p.text.to_string())
+        .unwrap_or_default()
+}
+
+/// Prepends the preset to the user's negative prompt the same way the
+/// NovelAI site does: `"{preset}, {negative}"`, or whichever one is
+/// non-empty.
+pub fn apply_uc_preset(model: &str, preset_id: u32, negative: &str) -> String {
+    let preset = resolve_uc_preset(model, preset_id);
+    match (preset.is_empty(), negative.is_empty()) {
+        (true, _) => negative.to_string(),
+        (false, true) => preset,
+        (false, false) => format!("{}, {}", preset, negative),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The presets as the NovelAI site sends them, copied from its requests
+    // rather than from the table above
+    const V3_HEAVY: &str = "lowres, {bad}, error, fewer, extra, missing, worst quality, jpeg artifacts, bad quality, watermark, unfinished, displeasing, chromatic aberration, signature, extra digits, artistic error, username, scan, [abstract]";
+    const OFFICIAL: &[(&str, &[(&str, &str)])] = &[
+        (
+            "nai-diffusion-3",
+            &[
+                ("Heavy", V3_HEAVY),
+                ("Light", "lowres, jpeg artifacts, worst quality, watermark, blurry, very displeasing"),
+                ("Human Focus", "lowres, {bad}, error, fewer, extra, missing, worst quality, jpeg artifacts, bad quality, watermark, unfinished, displeasing, chromatic aberration, signature, extra digits, artistic error, username, scan, [abstract], bad anatomy, bad hands, @_@, mismatched pupils, heart-shaped pupils, glowing eyes"),
+                ("None", ""),
+            ],
+        ),
+        (
+            "nai-diffusion-furry-3",
+            &[
+                ("Heavy", "{{worst quality}}, [displeasing], {unusual pupils}, guide lines, {{unfinished}}, {bad}, url, artist name, {{tall image}}, mosaic, {sketch page}, comic panel, impact (font), [dated], {logo}, ych, {what}, {where is your god now}, {distorted text}, repeated text, {floating head}, {1994}, {widescreen}, absolutely everyone, sequence, {compression artifacts}, hard translated, {cropped}, {commissioner name}, unknown text, high contrast"),
+                ("Light", "{worst quality}, guide lines, unfinished, bad, url, tall image, widescreen, compression artifacts, unknown text"),
+                ("None", ""),
+            ],
+        ),
+        (
+            "nai-diffusion-4-curated-preview",
+            &[
+                ("Heavy", "blurry, lowres, error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, logo, dated, signature, multiple views, gigantic breasts, white blank page, blank page"),
+                ("Light", "blurry, lowres, error, worst quality, bad quality, jpeg artifacts, very displeasing, logo, dated, signature, white blank page, blank page"),
+                ("None", ""),
+            ],
+        ),
+        (
+            "nai-diffusion-4-full",
+            &[
+                ("Heavy", "blurry, lowres, error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, multiple views, logo, too many watermarks, white blank page, blank page"),
+                ("Light", "blurry, lowres, error, worst quality, bad quality, jpeg artifacts, very displeasing, white blank page, blank page"),
+                ("None", ""),
+            ],
+        ),
+        (
+            "nai-diffusion-4-5-curated",
+            &[
+                ("Heavy", "blurry, lowres, upscaled, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, halftone, multiple views, logo, too many watermarks, negative space, blank page"),
+                ("Light", "blurry, lowres, upscaled, artistic error, scan artifacts, jpeg artifacts, logo, too many watermarks, negative space, blank page"),
+                ("Human Focus", "blurry, lowres, upscaled, artistic error, film grain, scan artifacts, bad anatomy, bad hands, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, halftone, multiple views, logo, too many watermarks, @_@, mismatched pupils, glowing eyes, negative space, blank page"),
+                ("None", ""),
+            ],
+        ),
+        (
+            "nai-diffusion-4-5-full",
+            &[
+                ("Heavy", "nsfw, lowres, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, dithering, halftone, screentone, multiple views, logo, too many watermarks, negative space, blank page"),
+                ("Light", "nsfw, lowres, artistic error, scan artifacts, worst quality, bad quality, jpeg artifacts, multiple views, very displeasing, too many watermarks, negative space, blank page"),
+                ("Furry Focus", "nsfw, {worst quality}, distracting watermark, unfinished, bad quality, {widescreen}, upscale, {sequence}, {{grandfathered content}}, blurred foreground, chromatic aberration, sketch, everyone, [sketch background], simple, [flat colors], ych (character), outline, multiple scenes, [[horror (theme)]], comic"),
+                ("Human Focus", "nsfw, lowres, artistic error, film grain, scan artifacts, worst quality, bad quality, jpeg artifacts, very displeasing, chromatic aberration, dithering, halftone, screentone, multiple views, logo, too many watermarks, negative space, blank page, @_@, mismatched pupils, glowing eyes, bad anatomy"),
+                ("None", ""),
+            ],
+        ),
+    ];
+
+    #[test]
+    fn every_preset_matches_the_site() {
+        for (model, expected) in OFFICIAL {
+            for inpainting in [false, true] {
+                let model = if inpainting {
+                    format!("{}-inpainting", model)
+                } else {
+                    model.to_string()
+                };
+                let presets = presets_for_model(&model);
+                assert_eq!(presets.len(), expected.len(), "{}", model);
+                for (index, (name, text)) in expected.iter().enumerate() {
+                    assert_eq!(presets[index].name, *name, "{} #{}", model, index);
+                    assert_eq!(
+                        resolve_uc_preset(&model, index as u32),
+                        *text,
+                        "{} {}",
+                        model,
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn none_unknown_and_out_of_range_are_empty() {
+        for (model, expected) in OFFICIAL {
+            assert_eq!(resolve_uc_preset(model, expected.len() as u32 - 1), "");
+            assert_eq!(resolve_uc_preset(model, expected.len() as u32), "");
+        }
+        assert_eq!(resolve_uc_preset("nai-diffusion-2", 0), "");
+    }
+
+    #[test]
+    fn preset_is_prepended_like_the_site() {
+        let model = "nai-diffusion-3";
+        assert_eq!(
+            apply_uc_preset(model, 0, "bad feet"),
+            format!("{}, bad feet", V3_HEAVY)
+        );
+        assert_eq!(apply_uc_preset(model, 0, ""), V3_HEAVY);
+        assert_eq!(apply_uc_preset(model, 3, "bad feet"), "bad feet");
+    }
+}
@@ -0,0 +1,319 @@
+//! Importing settings from an image on the web, such as one dragged out of
+//! the embedded browser.
+//!
+//! The embedded browser's pages can't call commands, so `drag_script`
+//! reports a dragged image by navigating to a URL on `DRAG_REPORT_HOST`,
+//! which the navigation handler catches and cancels (`parse_drag_report`).
+//!
+//! Danbooru posts are also looked up through its JSON API, found from the
+//! post page or from the MD5 in the image's file name, so their tags can be
+//! offered as a prompt.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::IpAddr;
+use tauri::Url;
+
+/// Never resolves (`.invalid` is reserved), so nothing leaves the webview
+pub const DRAG_REPORT_HOST: &str = "nais-image-drag.invalid";
+pub const MAX_IMAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+const DANBOORU_HOSTS: [&str; 2] = ["danbooru.donmai.us", "safebooru.donmai.us"];
+const DANBOORU_API: &str = "https://danbooru.donmai.us";
+
+/// Injected into every page of the embedded browser. Reports the image (and
+/// the link around it) when a drag that started on it ends somewhere.
+pub fn drag_script() -> String {
+    format!(
+        r#"(() => {{
+  let dragged = null;
+  document.addEventListener('dragstart', (e) => {{
+    const target = e.target instanceof Element ? e.target : null;
+    const img = target && (target.tagName === 'IMG' ? target : target.querySelector('img'));
+    const link = target && target.closest('a');
+    dragged = img ? {{ src: img.currentSrc || img.src, link: link ? link.href : '' }} : null;
+  }}, true);
+  document.addEventListener('dragend', (e) => {{
+    const image = dragged;
+    dragged = null;
+    if (!image || (e.dataTransfer && e.dataTransfer.dropEffect === 'none')) return;
+    const report = new URL('https://{}/');
+    report.searchParams.set('src', image.src);
+    report.searchParams.set('link', image.link);
+    report.searchParams.set('page', location.href);
+    location.href = report.href;
+  }}, true);
+}})();"#,
+        DRAG_REPORT_HOST
+    )
+}
+
+/// Payload of `embedded-browser-image-dragged`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DraggedImage {
+    pub id: String,
+    /// The image's `src`; may be a `blob:` or `data:` URL
+    pub url: String,
+    /// The link around the image, if any (a post page on booru listings)
+    pub link_url: Option<String>,
+    pub page_url: String,
+}
+
+/// The dragged image `url` reports, or `None` for a real navigation.
+pub fn parse_drag_report(id: &str, url: &Url) -> Option<DraggedImage> {
+    if url.host_str() != Some(DRAG_REPORT_HOST) {
+        return None;
+    }
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .filter(|value| !value.is_empty())
+    };
+    Some(DraggedImage {
+        id: id.to_string(),
+        url: param("src")?,
+        link_url: param("link"),
+        page_url: param("page").unwrap_or_default(),
+    })
+}
+
+/// Checks a URL `import_from_url` can download. `data:` URLs are read
+/// without a request and aren't passed here.
+pub fn parse_image_url(url: &str) -> Result<Url, String> {
+    if url.starts_with("blob:") {
+        return Err(
+            "페이지 안에서만 존재하는 이미지(blob)는 가져올 수 없습니다. 이미지를 저장한 뒤 파일로 불러오세요"
+                .to_string(),
+        );
+    }
+    let parsed = Url::parse(url.trim()).map_err(|_| format!("올바른 URL이 아닙니다: {}", url))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(parsed),
+        scheme => Err(format!("지원하지 않는 URL 형식입니다: {}", scheme)),
+    }
+}
+
+/// Shown when a download would reach this machine or the local network
+pub const INTERNAL_ADDRESS_ERROR: &str = "내부 네트워크 주소의 이미지는 가져올 수 없습니다";
+
+/// Whether `ip` is on the public internet: not loopback, private,
+/// link-local, shared (CGNAT), multicast or unspecified.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7 unique local, fe80::/10 link-local
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Refuses URLs whose host is written as an internal address or is
+/// `localhost`. Host names are checked again when they are resolved.
+pub fn check_public_host(url: &Url) -> Result<(), String> {
+    let host = url
+        .host_str()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let internal = match host.parse::<IpAddr>() {
+        Ok(ip) => !is_public_ip(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain.is_empty() || domain == "localhost" || domain.ends_with(".localhost")
+        }
+    };
+    match internal {
+        true => Err(INTERNAL_ADDRESS_ERROR.to_string()),
+        false => Ok(()),
+    }
+}
+
+/// What a download's status means for the user. Image hosts that block
+/// hotlinking answer 403 (sometimes 401 or 429) to requests from elsewhere.
+pub fn download_error(status: u16) -> String {
+    match status {
+        401 | 403 => {
+            "이미지 서버가 외부 앱의 접근을 차단했습니다 (핫링크 차단). 이미지를 저장한 뒤 파일로 불러오세요"
+                .to_string()
+        }
+        404 | 410 => "이미지를 찾을 수 없습니다 (삭제되었거나 주소가 바뀌었습니다)".to_string(),
+        429 => "이미지 서버의 요청 제한에 걸렸습니다. 잠시 후 다시 시도하세요".to_string(),
+        _ => format!("이미지 다운로드 실패 (HTTP {})", status),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DanbooruPost {
+    Id(u64),
+    Md5(String),
+}
+
+impl DanbooruPost {
+    pub fn api_url(&self) -> String {
+        match self {
+            DanbooruPost::Id(id) => format!("{}/posts/{}.json", DANBOORU_API, id),
+            DanbooruPost::Md5(md5) => {
+                format!("{}/posts.json?tags=md5:{}&limit=1", DANBOORU_API, md5)
+            }
+        }
+    }
+}
+
+/// The post `url` points at: a post page (`/posts/123`) or a file on
+/// Danbooru's CDN, whose name carries the MD5 (`.../sample-<md5>.jpg`).
+pub fn danbooru_post(url: &Url) -> Option<DanbooruPost> {
+    let host = url.host_str()?;
+    if DANBOORU_HOSTS.contains(&host) {
+        let mut segments = url.path_segments()?;
+        if segments.next() != Some("posts") {
+            return None;
+        }
+        let id = segments.next()?;
+        return id.parse().ok().map(DanbooruPost::Id);
+    }
+    if host != "donmai.us" && !host.ends_with(".donmai.us") {
+        return None;
+    }
+    let file = url.path_segments()?.next_back()?;
+    let stem = file.split('.').next()?;
+    let md5 = stem.strip_prefix("sample-").unwrap_or(stem);
+    (md5.len() == 32 && md5.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| DanbooruPost::Md5(md5.to_ascii_lowercase()))
+}
+
+/// Whether `url` is a post page rather than the image itself.
+pub fn is_post_page(url: &Url) -> bool {
+    url.host_str()
+        .is_some_and(|host| DANBOORU_HOSTS.contains(&host))
+}
+
+/// A Danbooru post's tags, grouped like on the site.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BooruTags {
+    pub post_url: String,
+    pub artist: Vec<String>,
+    pub copyright: Vec<String>,
+    pub character: Vec<String>,
+    pub general: Vec<String>,
+    /// `g`, `s`, `q` or `e`
+    pub rating: Option<String>,
+    /// Character, copyright and general tags as a NAI prompt
+    pub prompt: String,
+}
+
+fn prompt_tag(tag: &str) -> String {
+    if crate::tagger::KAOMOJI.contains(&tag) {
+        tag.to_string()
+    } else {
+        tag.replace('_', " ")
+    }
+}
+
+/// Reads a post from `/posts/<id>.json`, or the first of `/posts.json`.
+/// Also returns the URL of the original file, which login-only posts lack.
+pub fn parse_post(value: &Value) -> Option<(BooruTags, Option<String>)> {
+    let post = match value {
+        Value::Array(posts) => posts.first()?,
+        post => post,
+    };
+    let id = post.get("id")?.as_u64()?;
+    let tags = |key: &str| -> Vec<String> {
+        post.get(key)
+            .and_then(Value::as_str)
+            .map(|s| s.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default()
+    };
+    let mut booru = BooruTags {
+        post_url: format!("{}/posts/{}", DANBOORU_API, id),
+        artist: tags("tag_string_artist"),
+        copyright: tags("tag_string_copyright"),
+        character: tags("tag_string_character"),
+        general: tags("tag_string_general"),
+        rating: post
+            .get("rating")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        prompt: String::new(),
+    };
+    booru.prompt = booru
+        .character
+        .iter()
+        .chain(&booru.copyright)
+        .chain(&booru.general)
+        .map(|tag| prompt_tag(tag))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let file_url = post
+        .get("file_url")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    Some((booru, file_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.0.10",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "104.16.0.1", "2606:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn internal_hosts_are_refused() {
+        for url in [
+            "http://127.0.0.1/a.png",
+            "http://[::1]:8002/a.png",
+            "http://localhost/a.png",
+            "http://app.localhost./a.png",
+            "http://192.168.1.1/a.png",
+        ] {
+            assert!(
+                check_public_host(&Url::parse(url).unwrap()).is_err(),
+                "{}",
+                url
+            );
+        }
+        assert!(
+            check_public_host(&Url::parse("https://danbooru.donmai.us/a.png").unwrap()).is_ok()
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! Wildcards in prompts, expanded the same way for the same seed.
+//!
+//! - `{red|blue|green}` picks one option. Braces without a `|` at their own
+//!   level are NAI emphasis and stay as they are, so `{{red|blue}}` becomes
+//!   `{red}` or `{blue}`.
+//! - `{2::red|blue}` weights an option (red is picked twice as often). An
+//!   option that also ends in `::` is V4 emphasis (`{1.2::red::|blue}`), not
+//!   a weight.
+//! - `__name__` picks a line of `name.txt` in the wildcard folder. Blank
+//!   lines and lines starting with `#` are skipped; `name` may contain `/`
+//!   for subfolders.
+//!
+//! Options and file lines may contain wildcards themselves. Choices come
+//! from a small fixed PRNG seeded with `seed`, so results don't depend on
+//! the platform or on crate versions.
+
+use std::path::Path;
+
+/// Deeper than anyone writes by hand; stops files that include themselves
+const MAX_DEPTH: usize = 16;
+const FILE_EXTENSION: &str = "txt";
+
+/// SplitMix64
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u32) -> Self {
+        Rng(u64::from(seed) ^ 0x5DEE_CE66_D1CE_4E5B)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn pick(&mut self, weights: &[f64]) -> usize {
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return (self.next_u64() % weights.len() as u64) as usize;
+        }
+        let mut target = self.next_f64() * total;
+        for (index, weight) in weights.iter().enumerate() {
+            if target < *weight {
+                return index;
+            }
+            target -= weight;
+        }
+        weights.len() - 1
+    }
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    /// `{...}` split at its own `|`s
+    Group(Vec<Vec<Node>>),
+    /// A `{` that is never closed, kept as written
+    Unclosed(Vec<Vec<Node>>),
+}
+
+fn parse_seq(chars: &[char], pos: &mut usize, nested: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    while *pos < chars.len() {
+        let c = chars[*pos];
+        if nested && (c == '|' || c == '}') {
+            break;
+        }
+        *pos += 1;
+        if c == '{' {
+            if !text.is_empty() {
+                nodes.push(Node::Text(std::mem::take(&mut text)));
+            }
+            nodes.push(parse_group(chars, pos));
+        } else {
+            text.push(c);
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    nodes
+}
+
+/// Parses after a `{` up to and including its `}`.
+fn parse_group(chars: &[char], pos: &mut usize) -> Node {
+    let mut options = Vec::new();
+    loop {
+        options.push(parse_seq(chars, pos, true));
+        match chars.get(*pos) {
+            Some('|') => *pos += 1,
+            Some('}') => {
+                *pos += 1;
+                return Node::Group(options);
+            }
+            _ => return Node::Unclosed(options),
+        }
+    }
+}
+
+/// `2::a` → (2, "a"). Options ending in `::` are V4 emphasis, not weighted.
+fn split_weight(option: &[Node]) -> Option<(f64, String)> {
+    let Some(Node::Text(first)) = option.first() else {
+        return None;
+    };
+    let ends_with_emphasis =
+        matches!(option.last(), Some(Node::Text(last)) if last.trim_end().ends_with("::"));
+    let (weight, rest) = first.trim_start().split_once("::")?;
+    if ends_with_emphasis || weight.is_empty() {
+        return None;
+    }
+    let weight: f64 = weight
+        .parse()
+        .ok()
+        .filter(|w: &f64| w.is_finite() && *w >= 0.0)?;
+    Some((weight, rest.to_string()))
+}
+
+struct Expander<'a> {
+    rng: Rng,
+    load_file: &'a mut dyn FnMut(&str) -> Result<Vec<String>, String>,
+}
+
+impl Expander<'_> {
+    fn render(&mut self, nodes: &[Node], depth: usize, out: &mut String) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.render_text(text, depth, out)?,
+                Node::Group(options) if options.len() == 1 => {
+                    out.push('{');
+                    self.render(&options[0], depth, out)?;
+                    out.push('}');
+                }
+                Node::Group(options) => {
+                    let split: Vec<Option<(f64, String)>> =
+                        options.iter().map(|o| split_weight(o)).collect();
+                    let weights: Vec<f64> = split
+                        .iter()
+                        .map(|s| s.as_ref().map_or(1.0, |(w, _)| *w))
+                        .collect();
+                    let index = self.rng.pick(&weights);
+                    let mut chosen = String::new();
+                    match &split[index] {
+                        // The weight prefix was in the first text node
+                        Some((_, rest)) => {
+                            self.render_text(rest, depth, &mut chosen)?;
+                            self.render(&options[index][1..], depth, &mut chosen)?;
+                        }
+                        None => self.render(&options[index], depth, &mut chosen)?,
+                    }
+                    out.push_str(chosen.trim());
+                }
+                Node::Unclosed(options) => {
+                    out.push('{');
+                    for (i, option) in options.iter().enumerate() {
+                        if i > 0 {
+                            out.push('|');
+                        }
+                        self.render(option, depth, out)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Plain text, with `__name__` file references replaced.
+    fn render_text(&mut self, text: &str, depth: usize, out: &mut String) -> Result<(), String> {
+        let mut rest = text;
+        while let Some(start) = rest.find("__") {
+            let after = &rest[start + 2..];
+            let name = after.find("__").map(|end| &after[..end]).filter(|name| {
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/' | ' '))
+            });
+            let Some(name) = name else {
+                out.push_str(&rest[..start + 2]);
+                rest = after;
+                continue;
+            };
+            out.push_str(&rest[..start]);
+            let lines = (self.load_file)(name)?;
+            if lines.is_empty() {
+                return Err(format!("와일드카드 파일이 비어있습니다: {}", name));
+            }
+            let line = &lines[self.rng.pick(&vec![1.0; lines.len()])];
+            self.expand(line, depth + 1, out)?;
+            rest = &after[name.len() + 2..];
+        }
+        out.push_str(rest);
+        Ok(())
+    }
+
+    fn expand(&mut self, text: &str, depth: usize, out: &mut String) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err("와일드카드가 너무 깊게 중첩되었습니다 (순환 참조?)".to_string());
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let nodes = parse_seq(&chars, &mut 0, false);
+        self.render(&nodes, depth, out)
+    }
+}
+
+/// Expands every wildcard in `prompt`. `load_file` gets a `__name__` and
+/// returns the file's options (see `read_file`).
+pub fn expand(
+    prompt: &str,
+    seed: u32,
+    load_file: &mut dyn FnMut(&str) -> Result<Vec<String>, String>,
+) -> Result<String, String> {
+    let mut expander = Expander {
+        rng: Rng::new(seed),
+        load_file,
+    };
+    let mut out = String::new();
+    expander.expand(prompt, 0, &mut out)?;
+    Ok(out)
+}
+
+/// The options in wildcard file `name` under `dir`.
+pub fn read_file(dir: &Path, name: &str) -> Result<Vec<String>, String> {
+    let relative = Path::new(name);
+    let safe = relative
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)));
+    if !safe {
+        return Err(format!("잘못된 와일드카드 이름: {}", name));
+    }
+    let path = dir.join(format!("{}.{}", name, FILE_EXTENSION));
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| format!("와일드카드 파일을 읽을 수 없습니다 ({}): {}", name, e))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_files(name: &str) -> Result<Vec<String>, String> {
+        Err(format!("unexpected file: {}", name))
+    }
+
+    fn expand_plain(prompt: &str, seed: u32) -> String {
+        expand(prompt, seed, &mut no_files).unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_prompt() {
+        let prompt = "1girl, {red|blue|green|black} hair, {smile|frown}, {{a|b}}";
+        for seed in [0, 1, 42, u32::MAX] {
+            assert_eq!(expand_plain(prompt, seed), expand_plain(prompt, seed));
+        }
+        let results: std::collections::HashSet<String> =
+            (0..64).map(|seed| expand_plain(prompt, seed)).collect();
+        assert!(results.len() > 1);
+    }
+
+    #[test]
+    fn fixed_choices_do_not_change() {
+        // Guards the PRNG: a change here changes every saved seed's prompt
+        let picks: Vec<String> = (0..8).map(|seed| expand_plain("{a|b|c|d}", seed)).collect();
+        assert_eq!(picks, ["c", "b", "d", "b", "b", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn emphasis_braces_are_kept() {
+        assert_eq!(expand_plain("{{red}}, {solo}", 7), "{{red}}, {solo}");
+        let picked = expand_plain("{{red|blue}}", 7);
+        assert!(picked == "{red}" || picked == "{blue}", "{}", picked);
+        assert_eq!(expand_plain("{unclosed|x", 7), "{unclosed|x");
+    }
+
+    #[test]
+    fn weights() {
+        for seed in 0..32 {
+            assert_eq!(expand_plain("{0::a|1::b}", seed), "b");
+        }
+        // Trailing `::` is V4 emphasis, not a weight
+        let picked = expand_plain("{1.2::red::|0::blue}", 3);
+        assert!(picked == "1.2::red::" || picked == "0::blue", "{}", picked);
+    }
+
+    #[test]
+    fn file_references() {
+        let mut load = |name: &str| -> Result<Vec<String>, String> {
+            match name {
+                "color" => Ok(vec!["red".to_string(), "{pale|dark} __shade__".to_string()]),
+                "shade" => Ok(vec!["blue".to_string()]),
+                "self" => Ok(vec!["__self__".to_string()]),
+                _ => Err(format!("missing: {}", name)),
+            }
+        };
+        for seed in 0..16 {
+            let first = expand("__color__ hair", seed, &mut load).unwrap();
+            let again = expand("__color__ hair", seed, &mut load).unwrap();
+            assert_eq!(first, again);
+            assert!(
+                ["red hair", "pale blue hair", "dark blue hair"].contains(&first.as_str()),
+                "{}",
+                first
+            );
+        }
+        assert!(expand("__self__", 0, &mut load).is_err());
+        assert!(expand("__missing__", 0, &mut load).is_err());
+        assert_eq!(expand("a__b", 0, &mut load).unwrap(), "a__b");
+    }
+
+    #[test]
+    fn file_names_stay_in_the_folder() {
+        let dir = std::env::temp_dir();
+        assert!(read_file(&dir, "../secret").is_err());
+        assert!(read_file(&dir, "/etc/passwd").is_err());
+    }
+}
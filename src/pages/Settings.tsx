@@ -37,7 +37,7 @@ import { Slider } from '@/components/ui/slider'
 import { cn } from '@/lib/utils'
 import { useThemeStore } from '@/stores/theme-store'
 import { useAuthStore } from '@/stores/auth-store'
-import { useSettingsStore } from '@/stores/settings-store'
+import { useSettingsStore, type SaveOrganizeBy } from '@/stores/settings-store'
 import { toast } from '@/components/ui/use-toast'
 import NovelAILogo from '@/assets/novelai_logo.svg'
 import GeminiIcon from '@/assets/gemini-color.svg'
@@ -66,7 +66,7 @@ export default function Settings() {
     const { t, i18n } = useTranslation()
     const { theme, setTheme } = useThemeStore()
     const { token, isVerified, anlas, isLoading, verifyAndSave } = useAuthStore()
-    const { savePath, autoSave, setSavePath, setAutoSave, promptFontSize, setPromptFontSize, useStreaming, setUseStreaming, generationDelay, setGenerationDelay, geminiApiKey, setGeminiApiKey, useAbsolutePath, libraryPath, useAbsoluteLibraryPath, setLibraryPath } = useSettingsStore()
+    const { savePath, autoSave, setSavePath, setAutoSave, saveOrganizeBy, setSaveOrganizeBy, promptFontSize, setPromptFontSize, useStreaming, setUseStreaming, generationDelay, setGenerationDelay, geminiApiKey, setGeminiApiKey, useAbsolutePath, libraryPath, useAbsoluteLibraryPath, setLibraryPath } = useSettingsStore()
     const [localGeminiKey, setLocalGeminiKey] = useState(geminiApiKey)
 
     const [activeSection, setActiveSection] = useState<SettingsSection>('general')
@@ -648,6 +648,27 @@ export default function Settings() {
                                         onChange={(e) => setAutoSave(e.target.checked)}
                                     />
                                 </div>
+
+                                <div className="flex items-center justify-between pt-4 border-t border-border/30">
+                                    <div className="space-y-0.5">
+                                        <label className="text-sm font-medium">{t('settingsPage.save.organizeBy')}</label>
+                                        <p className="text-xs text-muted-foreground">
+                                            {t('settingsPage.save.organizeByHelp')}
+                                        </p>
+                                    </div>
+                                    <Select value={saveOrganizeBy} onValueChange={(v) => setSaveOrganizeBy(v as SaveOrganizeBy)}>
+                                        <SelectTrigger className="w-40">
+                                            <SelectValue />
+                                        </SelectTrigger>
+                                        <SelectContent>
+                                            {(['none', 'date', 'model', 'date_model'] as const).map((value) => (
+                                                <SelectItem key={value} value={value}>
+                                                    {t(`settingsPage.save.organize.${value}`)}
+                                                </SelectItem>
+                                            ))}
+                                        </SelectContent>
+                                    </Select>
+                                </div>
                             </div>
 
                             {/* Library Path Setting */}
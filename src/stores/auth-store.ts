@@ -1,5 +1,6 @@
 import { create } from 'zustand'
 import { persist } from 'zustand/middleware'
+import { invoke } from '@tauri-apps/api/core'
 import { getUserInfo, verifyToken, type AnlasInfo } from '@/services/novelai-api'
 
 interface AuthState {
@@ -8,6 +9,8 @@ interface AuthState {
     tier: string | null
     anlas: AnlasInfo | null
     isLoading: boolean
+    /** A plaintext token from an older version that isn't in the keyring yet */
+    legacyTokenPending: boolean
 
     setToken: (token: string) => void
     verifyAndSave: (token: string) => Promise<boolean>
@@ -23,6 +26,7 @@ export const useAuthStore = create<AuthState>()(
             tier: null,
             anlas: null,
             isLoading: false,
+            legacyTokenPending: false,
 
             setToken: (token) => set({ token }),
 
@@ -33,6 +37,9 @@ export const useAuthStore = create<AuthState>()(
 
                 if (result.valid) {
                     set({ token, isVerified: true, tier: result.tier || null })
+                    await invoke('save_token', { token })
+                        .then(() => set({ legacyTokenPending: false }))
+                        .catch((e) => console.warn('Failed to save token:', e))
 
                     // Fetch Anlas balance
                     const userInfo = await getUserInfo(token)
@@ -58,20 +65,45 @@ export const useAuthStore = create<AuthState>()(
                 }
             },
 
-            clearToken: () => set({
-                token: '',
-                isVerified: false,
-                tier: null,
-                anlas: null,
-            }),
+            clearToken: () => {
+                set({
+                    token: '',
+                    isVerified: false,
+                    tier: null,
+                    anlas: null,
+                    legacyTokenPending: false,
+                })
+                invoke('delete_token').catch((e) => console.warn('Failed to delete token:', e))
+            },
         }),
         {
             name: 'nais2-auth',
+            // The token itself lives in the OS keyring (save_token/load_token).
+            // A legacy token stays here until the keyring has accepted it
             partialize: (state) => ({
-                token: state.token,
                 isVerified: state.isVerified,
                 tier: state.tier,
+                ...(state.legacyTokenPending && { token: state.token }),
             }),
+            onRehydrateStorage: () => (state) => {
+                const legacyToken = state?.token
+                if (legacyToken) {
+                    // Older versions kept the token here in plaintext; move it
+                    // to the keyring. It stays in storage until that works, so
+                    // a failed save retries on the next start instead of
+                    // losing the token
+                    useAuthStore.setState({ token: legacyToken, legacyTokenPending: true })
+                    invoke('save_token', { token: legacyToken })
+                        .then(() => useAuthStore.setState({ legacyTokenPending: false }))
+                        .catch((e) => console.warn('Failed to migrate token:', e))
+                    return
+                }
+                invoke<string | null>('load_token')
+                    .then((token) => {
+                        if (token) useAuthStore.setState({ token })
+                    })
+                    .catch((e) => console.warn('Failed to load token:', e))
+            },
         }
     )
 )
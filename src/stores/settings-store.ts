@@ -1,5 +1,14 @@
 import { create } from 'zustand'
 import { persist } from 'zustand/middleware'
+import { invoke } from '@tauri-apps/api/core'
+
+// Subfolders for auto-saved images, see src-tauri/src/autosave.rs
+export type SaveOrganizeBy = 'none' | 'date' | 'model' | 'date_model'
+
+const syncSaveOrganization = (organizeBy: SaveOrganizeBy) =>
+    invoke('set_save_organization', { organizeBy }).catch((e) =>
+        console.warn('Failed to save organization setting:', e)
+    )
 
 export interface CustomResolution {
     id: string
@@ -13,6 +22,7 @@ interface SettingsState {
     savePath: string
     useAbsolutePath: boolean  // If true, savePath is absolute path; if false, relative to Pictures folder
     autoSave: boolean
+    saveOrganizeBy: SaveOrganizeBy
 
     // Custom resolution presets
     customResolutions: CustomResolution[]
@@ -34,6 +44,7 @@ interface SettingsState {
     // Actions
     setSavePath: (path: string, useAbsolute?: boolean) => void
     setAutoSave: (autoSave: boolean) => void
+    setSaveOrganizeBy: (organizeBy: SaveOrganizeBy) => void
     addCustomResolution: (resolution: Omit<CustomResolution, 'id'>) => void
     removeCustomResolution: (id: string) => void
     setPromptFontSize: (size: number) => void
@@ -49,6 +60,7 @@ export const useSettingsStore = create<SettingsState>()(
             savePath: 'NAIS_Output',
             useAbsolutePath: false,  // Default: relative to Pictures folder
             autoSave: true,
+            saveOrganizeBy: 'none',
             customResolutions: [],
             promptFontSize: 16, // Default text-base equivalent approximately
             useStreaming: true, // Default: enabled
@@ -62,6 +74,10 @@ export const useSettingsStore = create<SettingsState>()(
                 useAbsolutePath: useAbsolute ?? false
             }),
             setAutoSave: (autoSave) => set({ autoSave }),
+            setSaveOrganizeBy: (saveOrganizeBy) => {
+                set({ saveOrganizeBy })
+                syncSaveOrganization(saveOrganizeBy)
+            },
 
             addCustomResolution: (resolution) => set((state) => ({
                 customResolutions: [
@@ -84,6 +100,10 @@ export const useSettingsStore = create<SettingsState>()(
         }),
         {
             name: 'nais2-settings',
+            // The backend reads this from its own store for save_image_auto
+            onRehydrateStorage: () => (state) => {
+                if (state) syncSaveOrganization(state.saveOrganizeBy)
+            },
         }
     )
 )